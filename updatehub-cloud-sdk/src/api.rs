@@ -2,6 +2,7 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use chrono::{DateTime, Utc};
 use serde::Serialize;
 use std::{collections::BTreeMap, fs, path::Path};
 
@@ -21,7 +22,34 @@ pub struct UpdatePackage {
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Signature(Vec<u8>);
 
-#[derive(Serialize)]
+/// State transition of an update, kept to be reported later on along
+/// with other ones.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub state: String,
+    pub package_uid: String,
+    pub previous_state: Option<String>,
+    /// Machine readable code of the error, along with its message.
+    pub error_code: Option<String>,
+    pub error_message: Option<String>,
+    pub current_log: Option<String>,
+    /// Time spent on the states of the update, sent along with the
+    /// reports of its outcome.
+    pub state_timings: Vec<StateTiming>,
+}
+
+/// Time spent on a state of the update.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct StateTiming {
+    pub state: String,
+    pub entered_at: DateTime<Utc>,
+    pub left_at: DateTime<Utc>,
+    /// Time, in milliseconds, spent on the state.
+    pub duration_ms: u64,
+}
+
+#[derive(Clone, Copy, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct FirmwareMetadata<'a> {
     pub product_uid: &'a str,
@@ -31,6 +59,7 @@ pub struct FirmwareMetadata<'a> {
     pub device_attributes: MetadataValue<'a>,
 }
 
+#[derive(Clone, Copy)]
 pub struct MetadataValue<'a>(pub &'a BTreeMap<String, Vec<String>>);
 
 impl<'a> serde::ser::Serialize for MetadataValue<'a> {
@@ -72,10 +101,26 @@ impl Signature {
         Ok(Signature(openssl::base64::decode_block(bytes)?.to_vec()))
     }
 
+    pub fn to_base64_string(&self) -> String {
+        openssl::base64::encode_block(&self.0)
+    }
+
+    /// Validates the signature over the metadata exactly as it has been
+    /// received from the server. RSA keys validate a signature of the
+    /// metadata's SHA256 digest while Ed25519 keys validate a signature of
+    /// the metadata itself.
     pub fn validate(&self, key: &Path, package: &UpdatePackage) -> crate::Result<()> {
-        use openssl::{hash::MessageDigest, pkey::PKey, rsa::Rsa, sign::Verifier};
-        let key = PKey::from_rsa(Rsa::public_key_from_pem(&fs::read(key)?)?)?;
-        if Verifier::new(MessageDigest::sha256(), &key)?.verify_oneshot(&self.0, &package.raw)? {
+        use openssl::{
+            hash::MessageDigest,
+            pkey::{Id, PKey},
+            sign::Verifier,
+        };
+        let key = PKey::public_key_from_pem(&fs::read(key)?)?;
+        let mut verifier = match key.id() {
+            Id::ED25519 => Verifier::new_without_digest(&key)?,
+            _ => Verifier::new(MessageDigest::sha256(), &key)?,
+        };
+        if verifier.verify_oneshot(&self.0, &package.raw)? {
             return Ok(());
         }
         Err(crate::Error::InvalidSignature)
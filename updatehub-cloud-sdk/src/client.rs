@@ -2,37 +2,55 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{api, Error, Result};
+use crate::{api, Error, RateLimiter, Result};
 use reqwest::{header, StatusCode};
-use slog_scope::{debug, error};
+use slog_scope::{debug, error, warn};
 use std::{
     convert::{TryFrom, TryInto},
+    io::Write,
+    net::{IpAddr, SocketAddr},
     path::Path,
+    time::Duration,
 };
 use tokio::{fs, io};
 
+/// Size, in bytes, reports must exceed to be compressed, as smaller ones
+/// gain too little from it.
+const REPORT_COMPRESSION_THRESHOLD: usize = 1024;
+
 pub struct Client<'a> {
     client: reqwest::Client,
+    identity: Option<ClientIdentity>,
+    proxy: Option<Proxy>,
     server: &'a str,
+    headers: header::HeaderMap,
+    rate_limiter: Option<RateLimiter>,
+    probe_timeout: Option<Duration>,
+    download_timeout: Option<Duration>,
+    compress_reports: bool,
+    strict_content_length: bool,
 }
 
-pub async fn get<W>(url: &str, handle: &mut W) -> Result<()>
-where
-    W: io::AsyncWrite + Unpin,
-{
-    let url = reqwest::Url::parse(url)?;
-    save_body_to(reqwest::get(url).await?, handle).await
-}
+/// Called with the amount of bytes written after each received chunk.
+pub type ProgressHandler<'a> = &'a (dyn Fn(u64) + Sync);
 
-async fn save_body_to<W>(mut resp: reqwest::Response, handle: &mut W) -> Result<()>
+async fn save_body_to<W>(
+    mut resp: reqwest::Response,
+    handle: &mut W,
+    rate_limiter: Option<&RateLimiter>,
+    idle_timeout: Option<Duration>,
+    progress: Option<ProgressHandler<'_>>,
+) -> Result<()>
 where
     W: io::AsyncWrite + Unpin,
 {
     use io::AsyncWriteExt;
     use std::str::FromStr;
 
-    if !resp.status().is_success() {
-        return Err(Error::InvalidStatusResponse(resp.status()));
+    match resp.status() {
+        StatusCode::UNAUTHORIZED => return Err(Error::Unauthorized),
+        s if !s.is_success() => return Err(Error::InvalidStatusResponse(s)),
+        _ => {}
     }
 
     let mut written: f32 = 0.;
@@ -42,9 +60,33 @@ where
         None => 0,
     };
 
-    while let Some(chunk) = resp.chunk().await? {
+    loop {
+        // The timeout applies to each chunk, so slow downloads are kept
+        // while they make progress.
+        let chunk = match idle_timeout {
+            Some(timeout) => {
+                tokio::time::timeout(timeout, resp.chunk()).await.map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("no data received in {} seconds", timeout.as_secs()),
+                    )
+                })??
+            }
+            None => resp.chunk().await?,
+        };
+        let chunk = match chunk {
+            Some(chunk) => chunk,
+            None => break,
+        };
+
         let read = chunk.len();
+        if let Some(rate_limiter) = rate_limiter {
+            rate_limiter.consume(read).await;
+        }
         handle.write_all(&chunk).await?;
+        if let Some(progress) = progress {
+            progress(read as u64);
+        }
         if length > 0 {
             written += read as f32 / (length as f32 / 100.);
             if written as usize >= threshold {
@@ -57,23 +99,219 @@ where
     Ok(())
 }
 
-impl<'a> Client<'a> {
-    pub fn new(server: &'a str) -> Self {
+/// TLS client certificate, and its private key, presented to the server
+/// on every request.
+#[derive(Clone)]
+pub struct ClientIdentity(reqwest::Identity);
+
+impl ClientIdentity {
+    /// Loads the PEM encoded certificate and PKCS#8 private key.
+    pub fn from_pem_files(certificate: &Path, key: &Path) -> Result<Self> {
+        let certificate = std::fs::read(certificate).map_err(|e| {
+            error!("fail to read client certificate {:?}, error: {}", certificate, e);
+            e
+        })?;
+        let key = std::fs::read(key).map_err(|e| {
+            error!("fail to read client key {:?}, error: {}", key, e);
+            e
+        })?;
+
+        Ok(ClientIdentity(
+            reqwest::Identity::from_pkcs8_pem(&certificate, &key)
+                .map_err(Error::InvalidClientIdentity)?,
+        ))
+    }
+}
+
+/// HTTP proxy every request is sent through. When none is given, the
+/// proxy set by the `HTTP_PROXY` and `HTTPS_PROXY` environment variables
+/// is used.
+#[derive(Clone)]
+pub struct Proxy(reqwest::Proxy);
+
+impl Proxy {
+    /// Parses the proxy URL. Hosts matching the comma separated `no_proxy`
+    /// list are reached directly.
+    pub fn new(url: &str, no_proxy: Option<&str>) -> Result<Self> {
+        let proxy = reqwest::Proxy::all(url).map_err(|e| {
+            error!("invalid proxy {}, error: {}", url, e);
+            Error::InvalidProxy(e)
+        })?;
+
+        Ok(Proxy(proxy.no_proxy(no_proxy.and_then(reqwest::NoProxy::from_string))))
+    }
+}
+
+/// Headers sent on every request to the server, on top of the default
+/// ones, which they override.
+#[derive(Clone, Debug, Default)]
+pub struct Headers(header::HeaderMap);
+
+impl Headers {
+    /// Validates the extra headers and the `User-Agent` replacing the
+    /// default one, if any.
+    pub fn new<'b>(
+        user_agent: Option<&str>,
+        extra: impl IntoIterator<Item = (&'b str, &'b str)>,
+    ) -> Result<Self> {
+        let header = |name: &str, value: &str| {
+            let name = header::HeaderName::from_bytes(name.as_bytes());
+            let value = header::HeaderValue::from_str(value);
+            match (name, value) {
+                (Ok(name), Ok(value)) => Ok((name, value)),
+                (Err(e), _) => Err(e.to_string()),
+                (_, Err(e)) => Err(e.to_string()),
+            }
+            .map_err(|e| {
+                error!("invalid header {}: {}, error: {}", name, value, e);
+                Error::InvalidHeader(format!("{}: {}", name, e))
+            })
+        };
+
         let mut headers = header::HeaderMap::new();
-        headers.insert(header::USER_AGENT, header::HeaderValue::from_static("updatehub/2.0 Linux"));
-        headers.insert(header::CONTENT_TYPE, header::HeaderValue::from_static("application/json"));
-        headers.insert(
-            "api-content-type",
-            header::HeaderValue::from_static("application/vnd.updatehub-v1+json"),
-        );
+        for (name, value) in extra {
+            let (name, value) = header(name, value)?;
+            headers.insert(name, value);
+        }
+        if let Some(user_agent) = user_agent {
+            let (name, value) = header(header::USER_AGENT.as_str(), user_agent)?;
+            headers.insert(name, value);
+        }
+
+        Ok(Headers(headers))
+    }
+
+    /// Sends `authorization` as the `Authorization` header, overriding
+    /// the one in the extra headers, if any.
+    pub fn with_authorization(mut self, authorization: &str) -> Result<Self> {
+        let mut value = header::HeaderValue::from_str(authorization).map_err(|e| {
+            error!("invalid authorization header: {}", e);
+            Error::InvalidHeader(format!("{}: {}", header::AUTHORIZATION, e))
+        })?;
+        value.set_sensitive(true);
+        self.0.insert(header::AUTHORIZATION, value);
+        Ok(self)
+    }
+}
+
+/// Addresses hosts are connected to instead of resolving their names. TLS
+/// is still validated against the hostnames, and other hosts are
+/// resolved as usual.
+#[derive(Clone, Debug, Default)]
+pub struct HostAliases(Vec<(String, SocketAddr)>);
+
+impl HostAliases {
+    /// Parses the IP address each hostname is mapped to.
+    pub fn new<'b>(aliases: impl IntoIterator<Item = (&'b str, &'b str)>) -> Result<Self> {
+        aliases
+            .into_iter()
+            .map(|(host, ip)| {
+                let ip = ip.parse::<IpAddr>().map_err(|e| {
+                    error!("invalid address {} for host {}, error: {}", ip, host, e);
+                    Error::InvalidHostAlias(format!("{}: {}", host, e))
+                })?;
+                // The port is taken from the URL, so any is fine here
+                Ok((host.to_owned(), SocketAddr::new(ip, 0)))
+            })
+            .collect::<Result<_>>()
+            .map(HostAliases)
+    }
 
-        let client = reqwest::Client::builder()
-            .connect_timeout(std::time::Duration::from_secs(10))
-            .default_headers(headers)
-            .build()
-            .unwrap();
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 
-        Self { server, client }
+    fn apply(&self, builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        self.0.iter().fold(builder, |builder, (host, addr)| builder.resolve(host, *addr))
+    }
+}
+
+fn build_client(
+    identity: Option<&ClientIdentity>,
+    proxy: Option<&Proxy>,
+    host_aliases: &HostAliases,
+) -> reqwest::Client {
+    let mut headers = header::HeaderMap::new();
+    headers.insert(header::USER_AGENT, header::HeaderValue::from_static("updatehub/2.0 Linux"));
+    headers.insert(header::CONTENT_TYPE, header::HeaderValue::from_static("application/json"));
+    headers.insert(
+        "api-content-type",
+        header::HeaderValue::from_static("application/vnd.updatehub-v1+json"),
+    );
+
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(std::time::Duration::from_secs(10))
+        .default_headers(headers);
+    if let Some(identity) = identity {
+        builder = builder.identity(identity.0.clone());
+    }
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(proxy.0.clone());
+    }
+    host_aliases.apply(builder).build().unwrap()
+}
+
+impl<'a> Client<'a> {
+    pub fn new(server: &'a str, identity: Option<&ClientIdentity>, proxy: Option<&Proxy>) -> Self {
+        Self {
+            server,
+            client: build_client(identity, proxy, &HostAliases::default()),
+            identity: identity.cloned(),
+            proxy: proxy.cloned(),
+            headers: header::HeaderMap::new(),
+            rate_limiter: None,
+            probe_timeout: None,
+            download_timeout: None,
+            compress_reports: false,
+            strict_content_length: true,
+        }
+    }
+
+    /// Sends the headers on every request of this client.
+    pub fn with_headers(self, headers: &Headers) -> Self {
+        Self { headers: headers.0.clone(), ..self }
+    }
+
+    /// Connects to the hosts of this client, as the server and the object
+    /// mirrors, through the given addresses instead of resolving them.
+    pub fn with_host_aliases(self, host_aliases: &HostAliases) -> Self {
+        if host_aliases.is_empty() {
+            return self;
+        }
+        let client = build_client(self.identity.as_ref(), self.proxy.as_ref(), host_aliases);
+        Self { client, ..self }
+    }
+
+    /// Limits the throughput of the objects downloaded by this client. The
+    /// limiter may be shared with other clients so the limit applies to
+    /// all of them together.
+    pub fn with_rate_limiter(self, rate_limiter: Option<RateLimiter>) -> Self {
+        Self { rate_limiter, ..self }
+    }
+
+    /// Limits the time a probe may take as a whole, so an unresponsive
+    /// server is given up on quickly.
+    pub fn with_probe_timeout(self, probe_timeout: Option<Duration>) -> Self {
+        Self { probe_timeout, ..self }
+    }
+
+    /// Limits the time an object download may go without receiving any
+    /// data. The download may take longer as long as it makes progress.
+    pub fn with_download_timeout(self, download_timeout: Option<Duration>) -> Self {
+        Self { download_timeout, ..self }
+    }
+
+    /// Sends the reports of this client gzip compressed, when they are
+    /// large enough to benefit from it.
+    pub fn with_report_compression(self, compress_reports: bool) -> Self {
+        Self { compress_reports, ..self }
+    }
+
+    /// Fails the downloads whose announced Content Length disagrees with
+    /// the expected size, which is the default. Otherwise, the mismatch
+    /// is only logged.
+    pub fn with_strict_content_length(self, strict_content_length: bool) -> Self {
+        Self { strict_content_length, ..self }
     }
 
     pub async fn probe(
@@ -83,16 +321,33 @@ impl<'a> Client<'a> {
     ) -> Result<api::ProbeResponse> {
         reqwest::Url::parse(self.server)?;
 
-        let response = self
+        let mut headers = self.headers.clone();
+        for (name, value) in
+            [("api-product-uid", firmware.product_uid), ("api-firmware-version", firmware.version)]
+        {
+            if !headers.contains_key(name) {
+                headers.insert(
+                    name,
+                    header::HeaderValue::from_str(value)
+                        .map_err(|e| Error::InvalidHeader(format!("{}: {}", name, e)))?,
+                );
+            }
+        }
+
+        let mut request = self
             .client
             .post(&format!("{}/upgrades", &self.server))
+            .headers(headers)
             .header("api-retries", num_retries.to_string())
-            .json(&firmware)
-            .send()
-            .await?;
+            .json(&firmware);
+        if let Some(timeout) = self.probe_timeout {
+            request = request.timeout(timeout);
+        }
+        let response = request.send().await?;
 
         match response.status() {
             StatusCode::NOT_FOUND => Ok(api::ProbeResponse::NoUpdate),
+            StatusCode::UNAUTHORIZED => Err(Error::Unauthorized),
             StatusCode::OK => {
                 match response
                     .headers()
@@ -124,14 +379,83 @@ impl<'a> Client<'a> {
         package_uid: &str,
         download_dir: &Path,
         object: &str,
+        size: u64,
+        progress: Option<ProgressHandler<'_>>,
     ) -> Result<()> {
         validate_url(self.server)?;
 
+        self.download_object_from(
+            &self.object_url(product_uid, package_uid, object),
+            download_dir,
+            object,
+            size,
+            progress,
+        )
+        .await
+    }
+
+    /// Gets the headers sent to the url. The configured headers, which
+    /// may hold the device credentials, are only sent to the server, never
+    /// to other hosts as the object mirrors.
+    fn headers_for(&self, url: &str) -> header::HeaderMap {
+        let origin = |url| url::Url::parse(url).map(|url| url.origin());
+        match (origin(url), origin(self.server)) {
+            (Ok(url), Ok(server)) if url == server => self.headers.clone(),
+            _ => header::HeaderMap::new(),
+        }
+    }
+
+    /// Ensures the length announced by the server matches the amount of
+    /// data we expect to receive, so a misbehaving proxy or cache is caught
+    /// before anything gets written to disk. Responses without a Content
+    /// Length (e.g: chunked transfers) are accepted as is.
+    fn check_content_length(&self, resp: &reqwest::Response, expected: u64) -> Result<()> {
+        if !resp.status().is_success() {
+            return Ok(());
+        }
+
+        match resp.content_length() {
+            Some(received) if received != expected && self.strict_content_length => {
+                error!(
+                    "server announced a content length of {} bytes but {} bytes were expected",
+                    received, expected
+                );
+                Err(Error::UnexpectedContentLength { expected, received })
+            }
+            Some(received) if received != expected => {
+                warn!(
+                    "server announced a content length of {} bytes but {} bytes were expected, \
+                     carrying on",
+                    received, expected
+                );
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Gets the url the object is downloaded from the server.
+    pub fn object_url(&self, product_uid: &str, package_uid: &str, object: &str) -> String {
         // FIXME: Discuss the need of packages inside the route
-        let mut request = self.client.get(format!(
+        format!(
             "{}/products/{}/packages/{}/objects/{}",
             &self.server, product_uid, package_uid, object
-        ));
+        )
+    }
+
+    /// Downloads the object from the url, as the server does, resuming a
+    /// partial download. Used for objects hosted apart from the server.
+    pub async fn download_object_from(
+        &self,
+        url: &str,
+        download_dir: &Path,
+        object: &str,
+        size: u64,
+        progress: Option<ProgressHandler<'_>>,
+    ) -> Result<()> {
+        validate_url(url)?;
+
+        let mut request = self.client.get(url).headers(self.headers_for(url));
 
         if !download_dir.exists() {
             fs::create_dir_all(download_dir).await.map_err(|e| {
@@ -141,16 +465,93 @@ impl<'a> Client<'a> {
         }
 
         let file = download_dir.join(object);
+        let mut offset = 0;
         if file.exists() {
-            request = request
-                .header("RANGE", format!("bytes={}-", file.metadata()?.len().saturating_sub(1)));
+            offset = file.metadata()?.len().saturating_sub(1);
+            request = request.header("RANGE", format!("bytes={}-", offset));
         }
 
+        let resp = request.send().await?;
+        self.check_content_length(&resp, size.saturating_sub(offset))?;
+
         let mut file = fs::OpenOptions::new().create(true).append(true).open(&file).await?;
+        if resp.status().is_success() {
+            // The last byte downloaded is requested again, so it's dropped
+            // instead of being duplicated
+            file.set_len(offset).await?;
+        }
+
+        save_body_to(resp, &mut file, self.rate_limiter.as_ref(), self.download_timeout, progress)
+            .await
+    }
+
+    /// Gets the url into the handle, as it's received. The configured
+    /// headers are only sent when the url belongs to the server.
+    pub async fn get<W>(
+        &self,
+        url: &str,
+        handle: &mut W,
+        progress: Option<ProgressHandler<'_>>,
+    ) -> Result<()>
+    where
+        W: io::AsyncWrite + Unpin,
+    {
+        validate_url(url)?;
 
-        save_body_to(request.send().await?, &mut file).await
+        let resp = self.client.get(url).headers(self.headers_for(url)).send().await?;
+        save_body_to(resp, handle, self.rate_limiter.as_ref(), self.download_timeout, progress)
+            .await
     }
 
+    /// Posts the payload, as JSON, to the url, failing when it isn't
+    /// accepted. The configured headers are only sent when the url belongs
+    /// to the server.
+    pub async fn post<T: serde::Serialize>(
+        &self,
+        url: &str,
+        payload: &T,
+        timeout: Duration,
+    ) -> Result<()> {
+        validate_url(url)?;
+
+        let response = self
+            .client
+            .post(url)
+            .headers(self.headers_for(url))
+            .timeout(timeout)
+            .json(payload)
+            .send()
+            .await?;
+        match response.status() {
+            s if s.is_success() => Ok(()),
+            StatusCode::UNAUTHORIZED => Err(Error::Unauthorized),
+            s => Err(Error::InvalidStatusResponse(s)),
+        }
+    }
+
+    /// Downloads the object from the url straight into the handle, as it's
+    /// received, without staging it on disk.
+    pub async fn stream_object_from<W>(
+        &self,
+        url: &str,
+        size: u64,
+        handle: &mut W,
+        progress: Option<ProgressHandler<'_>>,
+    ) -> Result<()>
+    where
+        W: io::AsyncWrite + Unpin,
+    {
+        validate_url(url)?;
+
+        let resp = self.client.get(url).headers(self.headers_for(url)).send().await?;
+        self.check_content_length(&resp, size)?;
+
+        save_body_to(resp, handle, self.rate_limiter.as_ref(), self.download_timeout, progress)
+            .await
+    }
+
+    /// Sends a single report. Reports carrying an error code, or state
+    /// timings, are sent by [`Client::send_report`].
     pub async fn report(
         &self,
         state: &str,
@@ -159,31 +560,106 @@ impl<'a> Client<'a> {
         previous_state: Option<&str>,
         error_message: Option<String>,
         current_log: Option<String>,
+    ) -> Result<()> {
+        self.send_report(
+            firmware,
+            &api::Report {
+                state: state.to_owned(),
+                package_uid: package_uid.to_owned(),
+                previous_state: previous_state.map(str::to_owned),
+                error_code: None,
+                error_message,
+                current_log,
+                state_timings: Vec::new(),
+            },
+        )
+        .await
+    }
+
+    /// Sends a single report, along with the state timings it holds.
+    pub async fn send_report(
+        &self,
+        firmware: api::FirmwareMetadata<'_>,
+        report: &api::Report,
     ) -> Result<()> {
         validate_url(self.server)?;
 
-        #[derive(serde::Serialize)]
-        #[serde(rename_all = "kebab-case")]
-        struct Payload<'a> {
-            #[serde(rename = "status")]
-            state: &'a str,
-            #[serde(flatten)]
-            firmware: api::FirmwareMetadata<'a>,
-            package_uid: &'a str,
-            #[serde(skip_serializing_if = "Option::is_none")]
-            previous_state: Option<&'a str>,
-            #[serde(skip_serializing_if = "Option::is_none")]
-            error_message: Option<String>,
-            #[serde(skip_serializing_if = "Option::is_none")]
-            current_log: Option<String>,
+        let response = self.report_request(&ReportPayload::new(firmware, report))?.send().await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Err(Error::Unauthorized);
         }
+        Ok(())
+    }
+
+    /// Sends several reports on a single request, with an array payload.
+    pub async fn report_batch(
+        &self,
+        firmware: api::FirmwareMetadata<'_>,
+        reports: &[api::Report],
+    ) -> Result<()> {
+        validate_url(self.server)?;
 
         let payload =
-            Payload { state, firmware, package_uid, previous_state, error_message, current_log };
+            reports.iter().map(|report| ReportPayload::new(firmware, report)).collect::<Vec<_>>();
 
-        self.client.post(&format!("{}/report", &self.server)).json(&payload).send().await?;
+        let response = self.report_request(&payload)?.send().await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Err(Error::Unauthorized);
+        }
         Ok(())
     }
+
+    /// Builds the request sending the report payload, compressing it
+    /// when enabled and the payload is large enough.
+    fn report_request<T: serde::Serialize>(&self, payload: &T) -> Result<reqwest::RequestBuilder> {
+        let body = serde_json::to_vec(payload)?;
+        let request =
+            self.client.post(&format!("{}/report", &self.server)).headers(self.headers.clone());
+        if !self.compress_reports || body.len() <= REPORT_COMPRESSION_THRESHOLD {
+            return Ok(request.body(body));
+        }
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&body)?;
+        let compressed = encoder.finish()?;
+        debug!("report compressed from {} to {} bytes", body.len(), compressed.len());
+        Ok(request.header(header::CONTENT_ENCODING, "gzip").body(compressed))
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ReportPayload<'a> {
+    #[serde(rename = "status")]
+    state: &'a str,
+    #[serde(flatten)]
+    firmware: api::FirmwareMetadata<'a>,
+    package_uid: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_state: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_message: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    current_log: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    state_timings: &'a [api::StateTiming],
+}
+
+impl<'a> ReportPayload<'a> {
+    fn new(firmware: api::FirmwareMetadata<'a>, report: &'a api::Report) -> Self {
+        ReportPayload {
+            state: &report.state,
+            firmware,
+            package_uid: &report.package_uid,
+            previous_state: report.previous_state.as_deref(),
+            error_code: report.error_code.as_deref(),
+            error_message: report.error_message.as_deref(),
+            current_log: report.current_log.as_deref(),
+            state_timings: &report.state_timings,
+        }
+    }
 }
 
 impl TryFrom<&header::HeaderValue> for api::Signature {
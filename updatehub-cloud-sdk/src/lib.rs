@@ -4,8 +4,10 @@
 
 pub mod api;
 mod client;
+mod rate_limit;
 
-pub use client::{get, Client};
+pub use client::{Client, ClientIdentity, Headers, HostAliases, ProgressHandler, Proxy};
+pub use rate_limit::RateLimiter;
 
 use derive_more::{Display, Error, From};
 
@@ -15,8 +17,19 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     #[display(fmt = "Package's signature validation has failed")]
     InvalidSignature,
+    #[display(fmt = "Server has refused the device authorization")]
+    Unauthorized,
     #[display(fmt = "Http response is missing Content Length")]
     MissingContentLength,
+    #[display(
+        fmt = "Http response Content Length ({}) does not match the expected size ({})",
+        received,
+        expected
+    )]
+    UnexpectedContentLength {
+        expected: u64,
+        received: u64,
+    },
 
     Io(std::io::Error),
     JsonParsing(serde_json::Error),
@@ -24,10 +37,49 @@ pub enum Error {
     ParseInt(std::num::ParseIntError),
 
     Http(reqwest::Error),
+    #[display(fmt = "Invalid client certificate or key: {}", _0)]
+    #[from(ignore)]
+    InvalidClientIdentity(reqwest::Error),
+    #[display(fmt = "Invalid proxy: {}", _0)]
+    #[from(ignore)]
+    InvalidProxy(reqwest::Error),
     #[display(fmt = "Invalid status response: {}", _0)]
     InvalidStatusResponse(#[error(not(source))] reqwest::StatusCode),
     #[display(fmt = "Invalid header value: {}", _0)]
     HeaderParse(reqwest::header::ToStrError),
+    #[display(fmt = "Invalid header: {}", _0)]
+    #[from(ignore)]
+    InvalidHeader(#[error(not(source))] String),
+    #[display(fmt = "Invalid host alias: {}", _0)]
+    #[from(ignore)]
+    InvalidHostAlias(#[error(not(source))] String),
     #[display(fmt = "Invalid url: {}", _0)]
     UrlParse(url::ParseError),
 }
+
+impl Error {
+    /// Whether the error is likely transient, such as timeouts, server
+    /// errors and dropped connections, so the request may be retried.
+    pub fn is_retryable(&self) -> bool {
+        use std::io::ErrorKind;
+
+        match self {
+            Error::Http(e) => {
+                e.is_timeout()
+                    || e.is_connect()
+                    || e.is_body()
+                    || e.status().map_or(false, |s| s.is_server_error())
+            }
+            Error::InvalidStatusResponse(status) => status.is_server_error(),
+            Error::Io(e) => matches!(
+                e.kind(),
+                ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::TimedOut
+                    | ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+}
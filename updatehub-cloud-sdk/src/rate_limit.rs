@@ -0,0 +1,67 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    num::NonZeroU64,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Token bucket used to limit the throughput of downloads.
+///
+/// Cloning the limiter shares the same bucket, so the limit applies to
+/// the aggregated throughput of every transfer holding a clone of it.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    bytes_per_second: f64,
+    available: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing `bytes_per_second` to be consumed, with
+    /// a burst capacity of one second worth of bytes. The rate can't be
+    /// zero, as no byte would ever be let through.
+    pub fn new(bytes_per_second: NonZeroU64) -> Self {
+        let bytes_per_second = bytes_per_second.get() as f64;
+        RateLimiter {
+            bucket: Arc::new(Mutex::new(Bucket {
+                bytes_per_second,
+                available: bytes_per_second,
+                last_refill: Instant::now(),
+            })),
+        }
+    }
+
+    /// Takes `amount` bytes from the bucket, waiting as long as needed for
+    /// them to be available.
+    pub async fn consume(&self, amount: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.available =
+                (bucket.available + elapsed * bucket.bytes_per_second).min(bucket.bytes_per_second);
+            bucket.last_refill = now;
+
+            // The bucket is allowed to go negative, making following
+            // consumers wait for the debt to be paid off as well.
+            bucket.available -= amount as f64;
+            if bucket.available < 0. {
+                Duration::from_secs_f64(-bucket.available / bucket.bytes_per_second)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
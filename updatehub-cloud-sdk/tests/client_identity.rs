@@ -0,0 +1,166 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// The server certificate is trusted through SSL_CERT_FILE, which is
+// process-wide, so these tests have their own test binary.
+
+use updatehub_cloud_sdk as sdk;
+
+use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    net::TcpListener,
+    path::Path,
+};
+
+/// Writes a self-signed certificate for `name`, valid for 127.0.0.1,
+/// and its private key.
+fn write_identity(dir: &Path, name: &str) -> (std::path::PathBuf, std::path::PathBuf) {
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        hash::MessageDigest,
+        pkey::PKey,
+        rsa::Rsa,
+        x509::{extension::SubjectAlternativeName, X509},
+    };
+
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut subject = openssl::x509::X509NameBuilder::new().unwrap();
+    subject.append_entry_by_text("CN", name).unwrap();
+    let subject = subject.build();
+
+    let mut cert = X509::builder().unwrap();
+    cert.set_version(2).unwrap();
+    cert.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap()).unwrap();
+    cert.set_subject_name(&subject).unwrap();
+    cert.set_issuer_name(&subject).unwrap();
+    cert.set_pubkey(&key).unwrap();
+    cert.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+    cert.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+    let alt_name =
+        SubjectAlternativeName::new().ip("127.0.0.1").build(&cert.x509v3_context(None, None));
+    cert.append_extension(alt_name.unwrap()).unwrap();
+    cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+    let cert_path = dir.join(format!("{}.crt", name));
+    let key_path = dir.join(format!("{}.key", name));
+    std::fs::write(&cert_path, cert.build().to_pem().unwrap()).unwrap();
+    std::fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+    (cert_path, key_path)
+}
+
+/// Serves each of the responses, in order, over TLS requiring a client
+/// certificate. Returns the common name of the client certificates.
+fn serve_tls(
+    cert: &Path,
+    key: &Path,
+    responses: &'static [&'static [u8]],
+) -> (String, std::thread::JoinHandle<Vec<Option<String>>>) {
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    acceptor.set_private_key_file(key, SslFiletype::PEM).unwrap();
+    acceptor.set_certificate_chain_file(cert).unwrap();
+    acceptor
+        .set_verify_callback(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT, |_, _| {
+            true
+        });
+    let acceptor = acceptor.build();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("https://{}", listener.local_addr().unwrap());
+
+    let server = std::thread::spawn(move || {
+        responses
+            .iter()
+            .map(|response| {
+                let mut stream = acceptor.accept(listener.accept().unwrap().0).unwrap();
+                let peer = stream.ssl().peer_certificate().map(|cert| {
+                    let name =
+                        cert.subject_name().entries().next().unwrap().data().as_utf8().unwrap();
+                    name.to_string()
+                });
+
+                // Reads the whole request, body included, before replying
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                let end = loop {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break pos + 4;
+                    }
+                };
+                let head = String::from_utf8_lossy(&request[..end]).to_lowercase();
+                let body_len = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .map_or(0, |len| len.trim().parse().unwrap());
+                while request.len() < end + body_len {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+
+                stream.write_all(response).unwrap();
+                peer
+            })
+            .collect()
+    });
+
+    (url, server)
+}
+
+#[tokio::test]
+async fn client_identity() {
+    let dir = tempfile::tempdir().unwrap();
+    let (cert, key) = write_identity(dir.path(), "device");
+    let (server_cert, server_key) = write_identity(dir.path(), "server");
+    // The server certificate is self-signed, so it's trusted directly
+    std::env::set_var("SSL_CERT_FILE", &server_cert);
+
+    let (url, server) = serve_tls(
+        &server_cert,
+        &server_key,
+        &[
+            b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\ncontent-length: 4\r\nconnection: close\r\n\r\n1234",
+        ],
+    );
+
+    let identity = sdk::ClientIdentity::from_pem_files(&cert, &key).unwrap();
+    let client = sdk::Client::new(&url, Some(&identity), None);
+
+    let metadata = BTreeMap::default();
+    let firmware = sdk::api::FirmwareMetadata {
+        product_uid: "229ffd7e08721d716163fc81a2dbaf6c90d449f0a3b009b6a2defe8a0b0d7381",
+        version: "1.1",
+        hardware: "board",
+        device_identity: sdk::api::MetadataValue(&metadata),
+        device_attributes: sdk::api::MetadataValue(&metadata),
+    };
+    match client.probe(0, firmware).await {
+        Ok(sdk::api::ProbeResponse::NoUpdate) => {}
+        r => panic!("Unexpected result: {:?}", r.map(|_| ())),
+    }
+
+    let mut output = Vec::new();
+    client.get(&format!("{}/package", url), &mut output, None).await.unwrap();
+    assert_eq!(output, b"1234");
+
+    let device = Some(String::from("device"));
+    assert_eq!(server.join().unwrap(), vec![device.clone(), device]);
+}
+
+#[tokio::test]
+async fn invalid_client_identity() {
+    let dir = tempfile::tempdir().unwrap();
+    let (cert, _) = write_identity(dir.path(), "device");
+    let invalid_key = dir.path().join("invalid.key");
+    std::fs::write(&invalid_key, "not a key").unwrap();
+
+    match sdk::ClientIdentity::from_pem_files(&cert, &invalid_key) {
+        Err(sdk::Error::InvalidClientIdentity(_)) => {}
+        r => panic!("Unexpected result: {:?}", r.map(|_| ())),
+    }
+    assert!(sdk::ClientIdentity::from_pem_files(&cert, &dir.path().join("missing.key")).is_err());
+}
@@ -5,16 +5,20 @@
 use updatehub_cloud_sdk as sdk;
 
 use serde_json::json;
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, io::Write};
 
 enum FakeServer {
     NoUpdate,
     HasUpdate,
     ExtraPoll,
     WithRetry,
+    WithHeaders,
     ReportSuccess,
     ReportError,
+    ReportBatch,
     DownloadInParts,
+    DownloadWrongSize,
+    DownloadStalled,
 }
 
 fn create_mock_server(mode: FakeServer) -> (mockito::ServerGuard, mockito::Mock) {
@@ -94,6 +98,14 @@ fn create_mock_server(mode: FakeServer) -> (mockito::ServerGuard, mockito::Mock)
             .match_body(reply_body)
             .with_status(404)
             .create(),
+        FakeServer::WithHeaders => server.mock("POST", "/upgrades")
+            .match_header("User-Agent", "fleet-agent/1.0")
+            .match_header("X-Fleet", "edge")
+            .match_header("Api-Product-Uid", "overridden")
+            .match_header("Api-Firmware-Version", "1.1")
+            .match_body(reply_body)
+            .with_status(404)
+            .create(),
         FakeServer::ReportSuccess => server.mock("POST", "/report")
             .match_header("Content-Type", "application/json")
             .match_header("Api-Content-Type", "application/vnd.updatehub-v1+json")
@@ -140,6 +152,48 @@ fn create_mock_server(mode: FakeServer) -> (mockito::ServerGuard, mockito::Mock)
             )))
             .with_status(200)
             .create(),
+        FakeServer::ReportBatch => server.mock("POST", "/report")
+            .match_header("Content-Type", "application/json")
+            .match_header("Api-Content-Type", "application/vnd.updatehub-v1+json")
+            .match_body(Matcher::Json(json!(
+                [
+                    {
+                        "product-uid": "229ffd7e08721d716163fc81a2dbaf6c90d449f0a3b009b6a2defe8a0b0d7381",
+                        "version": "1.1",
+                        "hardware": "board",
+                        "device-identity": {
+                            "id1": "value1",
+                            "id2": "value2"
+                        },
+                        "device-attributes": {
+                            "attr1": "attrvalue1",
+                            "attr2": "attrvalue2"
+                        },
+                        "status": "downloading",
+                        "package-uid": "package-uid",
+                    },
+                    {
+                        "product-uid": "229ffd7e08721d716163fc81a2dbaf6c90d449f0a3b009b6a2defe8a0b0d7381",
+                        "version": "1.1",
+                        "hardware": "board",
+                        "device-identity": {
+                            "id1": "value1",
+                            "id2": "value2"
+                        },
+                        "device-attributes": {
+                            "attr1": "attrvalue1",
+                            "attr2": "attrvalue2"
+                        },
+                        "status": "error",
+                        "package-uid": "package-uid",
+                        "error-code": "errorCode",
+                        "error-message": "errorMessage",
+                        "previous-state": "downloading"
+                    }
+                ]
+            )))
+            .with_status(200)
+            .create(),
         FakeServer::DownloadInParts => {
             server.mock(
                 "GET",
@@ -151,8 +205,10 @@ fn create_mock_server(mode: FakeServer) -> (mockito::ServerGuard, mockito::Mock)
             )
                 .match_header("Content-Type", "application/json")
                 .match_header("Api-Content-Type", "application/vnd.updatehub-v1+json")
+                .match_header("Range", Matcher::Missing)
                 .with_status(200)
-                .with_body("1234")
+                // The transfer is cut short, without announcing its length
+                .with_chunked_body(|w| w.write_all(b"1234"))
                 .create();
             server.mock(
                 "GET",
@@ -165,10 +221,39 @@ fn create_mock_server(mode: FakeServer) -> (mockito::ServerGuard, mockito::Mock)
                 .match_header("Content-Type", "application/json")
                 .match_header("Api-Content-Type", "application/vnd.updatehub-v1+json")
                 .match_header("Range", "bytes=3-")
-                .with_status(200)
-                .with_body("567890")
+                .with_status(206)
+                .with_body("4567890")
                 .create()
         }
+        FakeServer::DownloadStalled => server.mock(
+            "GET",
+            format!(
+                "/products/{}/packages/{}/objects/{}",
+                FakeMetadata::PRODUCT_UID, "package_id", "object"
+            )
+                .as_str(),
+        )
+            .with_status(200)
+            .with_chunked_body(|w| {
+                w.write_all(b"1234")?;
+                w.flush()?;
+                std::thread::sleep(std::time::Duration::from_secs(2));
+                w.write_all(b"567890")
+            })
+            .create(),
+        FakeServer::DownloadWrongSize => server.mock(
+            "GET",
+            format!(
+                "/products/{}/packages/{}/objects/{}",
+                FakeMetadata::PRODUCT_UID, "package_id", "object"
+            )
+                .as_str(),
+        )
+            .match_header("Content-Type", "application/json")
+            .match_header("Api-Content-Type", "application/vnd.updatehub-v1+json")
+            .with_status(200)
+            .with_body("1234")
+            .create(),
     };
 
     (server, mocks)
@@ -206,36 +291,140 @@ impl FakeMetadata {
 
 #[tokio::test]
 async fn direct_get_invalid_url() {
-    let res = sdk::get("http://foo.bar:---", &mut tokio::io::sink()).await;
+    let url = "http://foo.bar:---";
+    let res = sdk::Client::new(url, None, None).get(url, &mut tokio::io::sink(), None).await;
     assert!(res.is_err());
 }
 
+#[tokio::test]
+async fn rate_limiter_delays_consumption() {
+    let limiter = sdk::RateLimiter::new(std::num::NonZeroU64::new(1000).unwrap());
+    let start = std::time::Instant::now();
+
+    // The initial burst is served right away while the excess has to wait
+    // for the bucket to be refilled.
+    limiter.consume(1000).await;
+    assert!(start.elapsed() < std::time::Duration::from_millis(100));
+    limiter.clone().consume(500).await;
+    assert!(start.elapsed() >= std::time::Duration::from_millis(450));
+}
+
+#[tokio::test]
+async fn probe_through_proxy() {
+    // The server address does not resolve so the request can only reach
+    // the proxy, which is asked for the server's host.
+    let mut proxy = mockito::Server::new();
+    let forwarded = proxy
+        .mock("POST", "/upgrades")
+        .match_header("host", "updatehub.invalid")
+        .with_status(404)
+        .expect(1)
+        .create();
+    let proxy_url = proxy.url();
+
+    let through_proxy = sdk::Proxy::new(&proxy_url, None).unwrap();
+    sdk::Client::new("http://updatehub.invalid", None, Some(&through_proxy))
+        .probe(0, FakeMetadata::new().get())
+        .await
+        .unwrap();
+
+    // Hosts skipping the proxy are reached directly, failing to resolve
+    let no_proxy = sdk::Proxy::new(&proxy_url, Some("updatehub.invalid")).unwrap();
+    assert!(sdk::Client::new("http://updatehub.invalid", None, Some(&no_proxy))
+        .probe(0, FakeMetadata::new().get())
+        .await
+        .is_err());
+    forwarded.assert();
+}
+
+#[tokio::test]
+async fn probe_through_host_alias() {
+    // The server host does not resolve so the request can only reach the
+    // mock server through its alias.
+    let (server, mocks) = create_mock_server(FakeServer::NoUpdate);
+    let server_address = server.url().replace("127.0.0.1", "updatehub.invalid");
+    let host_aliases = sdk::HostAliases::new([("updatehub.invalid", "127.0.0.1")]).unwrap();
+    sdk::Client::new(&server_address, None, None)
+        .with_host_aliases(&host_aliases)
+        .probe(0, FakeMetadata::new().get())
+        .await
+        .unwrap();
+    mocks.assert();
+
+    match sdk::HostAliases::new([("updatehub.invalid", "updatehub.io")]) {
+        Err(sdk::Error::InvalidHostAlias(_)) => {}
+        r => panic!("Unexpected result: {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn invalid_proxy() {
+    match sdk::Proxy::new("http://[invalid", None) {
+        Err(sdk::Error::InvalidProxy(_)) => {}
+        r => panic!("Unexpected result: {:?}", r.map(|_| ())),
+    }
+}
+
 #[tokio::test]
 async fn probe_requirements() {
     let (server, mocks) = create_mock_server(FakeServer::NoUpdate);
-    sdk::Client::new(&server.url()).probe(0, FakeMetadata::new().get()).await.unwrap();
+    sdk::Client::new(&server.url(), None, None).probe(0, FakeMetadata::new().get()).await.unwrap();
     mocks.assert();
 }
 
 #[tokio::test]
 async fn probe_invalid_url() {
-    let res = sdk::Client::new("http://foo.bar:---").probe(0, FakeMetadata::new().get()).await;
+    let res = sdk::Client::new("http://foo.bar:---", None, None)
+        .probe(0, FakeMetadata::new().get())
+        .await;
     assert!(res.is_err());
 }
 
 #[tokio::test]
 async fn probe_with_retry() {
     let (server, mocks) = create_mock_server(FakeServer::WithRetry);
-    sdk::Client::new(&server.url()).probe(1, FakeMetadata::new().get()).await.unwrap();
+    sdk::Client::new(&server.url(), None, None).probe(1, FakeMetadata::new().get()).await.unwrap();
     mocks.assert();
 }
 
+#[tokio::test]
+async fn probe_with_headers() {
+    let (server, mocks) = create_mock_server(FakeServer::WithHeaders);
+    let headers = sdk::Headers::new(
+        Some("fleet-agent/1.0"),
+        [("X-Fleet", "edge"), ("Api-Product-Uid", "overridden")],
+    )
+    .unwrap();
+    sdk::Client::new(&server.url(), None, None)
+        .with_headers(&headers)
+        .probe(0, FakeMetadata::new().get())
+        .await
+        .unwrap();
+    mocks.assert();
+}
+
+#[tokio::test]
+async fn invalid_headers() {
+    for (user_agent, extra) in [
+        (None, [("invalid name", "value")]),
+        (None, [("X-Fleet", "line\nbreak")]),
+        (Some("\n"), [("X-Fleet", "edge")]),
+    ] {
+        match sdk::Headers::new(user_agent, extra) {
+            Err(sdk::Error::InvalidHeader(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+}
+
 #[tokio::test]
 async fn probe_response_with_signature() {
     use sdk::api::ProbeResponse;
     let (server, mocks) = create_mock_server(FakeServer::HasUpdate);
-    let response =
-        sdk::Client::new(&server.url()).probe(0, FakeMetadata::new().get()).await.unwrap();
+    let response = sdk::Client::new(&server.url(), None, None)
+        .probe(0, FakeMetadata::new().get())
+        .await
+        .unwrap();
     match response {
         ProbeResponse::Update(_, Some(signature)) => assert_eq!(
             signature,
@@ -252,8 +441,10 @@ async fn probe_response_with_signature() {
 async fn probe_response_with_extra_poll() {
     use sdk::api::ProbeResponse;
     let (server, mocks) = create_mock_server(FakeServer::ExtraPoll);
-    let response =
-        sdk::Client::new(&server.url()).probe(0, FakeMetadata::new().get()).await.unwrap();
+    let response = sdk::Client::new(&server.url(), None, None)
+        .probe(0, FakeMetadata::new().get())
+        .await
+        .unwrap();
     match response {
         ProbeResponse::ExtraPoll(n) => assert_eq!(n, 10),
         r => panic!("Unexpected probe response: {:?}", r),
@@ -261,10 +452,32 @@ async fn probe_response_with_extra_poll() {
     mocks.assert();
 }
 
+#[tokio::test]
+async fn probe_unauthorized() {
+    let mut server = mockito::Server::new();
+    let mock = server
+        .mock("POST", "/upgrades")
+        .match_header("Authorization", "Bearer expired")
+        .with_status(401)
+        .create();
+    let headers =
+        sdk::Headers::new(None, []).unwrap().with_authorization("Bearer expired").unwrap();
+
+    let res = sdk::Client::new(&server.url(), None, None)
+        .with_headers(&headers)
+        .probe(0, FakeMetadata::new().get())
+        .await;
+    match res {
+        Err(e @ sdk::Error::Unauthorized) => assert!(!e.is_retryable()),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    mock.assert();
+}
+
 #[tokio::test]
 async fn report_success() {
     let (server, mocks) = create_mock_server(FakeServer::ReportSuccess);
-    sdk::Client::new(&server.url())
+    sdk::Client::new(&server.url(), None, None)
         .report("state", FakeMetadata::new().get(), "package-uid", None, None, None)
         .await
         .unwrap();
@@ -274,7 +487,7 @@ async fn report_success() {
 #[tokio::test]
 async fn report_error() {
     let (server, mocks) = create_mock_server(FakeServer::ReportError);
-    sdk::Client::new(&server.url())
+    sdk::Client::new(&server.url(), None, None)
         .report(
             "state",
             FakeMetadata::new().get(),
@@ -288,6 +501,170 @@ async fn report_error() {
     mocks.assert();
 }
 
+/// Serves a single request, answering it with `200 OK`, and returns its
+/// lowercased headers and its body.
+fn capture_request() -> (String, std::thread::JoinHandle<(String, Vec<u8>)>) {
+    use std::io::{BufRead, BufReader, Read};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut headers = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            headers.push_str(&line.to_lowercase());
+        }
+        let len = headers
+            .lines()
+            .find_map(|l| l.strip_prefix("content-length:"))
+            .map_or(0, |l| l.trim().parse().unwrap());
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).unwrap();
+        reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n").unwrap();
+        (headers, body)
+    });
+
+    (url, server)
+}
+
+#[tokio::test]
+async fn report_compressed() {
+    use std::io::Read;
+
+    let log = "log line\n".repeat(512);
+    let (url, server) = capture_request();
+    sdk::Client::new(&url, None, None)
+        .with_report_compression(true)
+        .send_report(
+            FakeMetadata::new().get(),
+            &sdk::api::Report {
+                state: "error".to_owned(),
+                package_uid: "package-uid".to_owned(),
+                previous_state: Some("previous-state".to_owned()),
+                error_code: Some("errorCode".to_owned()),
+                error_message: Some("errorMessage".to_owned()),
+                current_log: Some(log.clone()),
+                state_timings: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+    let (headers, body) = server.join().unwrap();
+    assert!(headers.contains("content-encoding: gzip"), "{}", headers);
+    assert!(body.len() < log.len());
+
+    let mut json = String::new();
+    flate2::read::GzDecoder::new(&body[..]).read_to_string(&mut json).unwrap();
+    let payload: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(payload["status"], "error");
+    assert_eq!(payload["package-uid"], "package-uid");
+    assert_eq!(payload["error-code"], "errorCode");
+    assert_eq!(payload["current-log"], log.as_str());
+
+    // Small reports aren't worth compressing
+    let (url, server) = capture_request();
+    sdk::Client::new(&url, None, None)
+        .with_report_compression(true)
+        .report("state", FakeMetadata::new().get(), "package-uid", None, None, None)
+        .await
+        .unwrap();
+    let (headers, body) = server.join().unwrap();
+    assert!(!headers.contains("content-encoding"), "{}", headers);
+    let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(payload["status"], "state");
+}
+
+#[tokio::test]
+async fn report_state_timings() {
+    use chrono::TimeZone;
+
+    let entered_at = chrono::Utc.with_ymd_and_hms(2026, 1, 1, 12, 0, 0).unwrap();
+    let report = sdk::api::Report {
+        state: "installed".to_owned(),
+        package_uid: "package-uid".to_owned(),
+        previous_state: None,
+        error_code: None,
+        error_message: None,
+        current_log: None,
+        state_timings: vec![sdk::api::StateTiming {
+            state: "download".to_owned(),
+            entered_at,
+            left_at: entered_at + chrono::Duration::milliseconds(1500),
+            duration_ms: 1500,
+        }],
+    };
+
+    let (url, server) = capture_request();
+    sdk::Client::new(&url, None, None)
+        .send_report(FakeMetadata::new().get(), &report)
+        .await
+        .unwrap();
+    let (_, body) = server.join().unwrap();
+    let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(payload["status"], "installed");
+    assert_eq!(
+        payload["state-timings"],
+        json!([{
+            "state": "download",
+            "entered-at": "2026-01-01T12:00:00Z",
+            "left-at": "2026-01-01T12:00:01.500Z",
+            "duration-ms": 1500
+        }])
+    );
+}
+
+#[tokio::test]
+async fn download_stalled() {
+    let (server, mocks) = create_mock_server(FakeServer::DownloadStalled);
+    let dir = tempfile::tempdir().unwrap();
+
+    let res = sdk::Client::new(&server.url(), None, None)
+        .with_download_timeout(Some(std::time::Duration::from_millis(500)))
+        .download_object(FakeMetadata::PRODUCT_UID, "package_id", dir.path(), "object", 10, None)
+        .await;
+    match res {
+        Err(e) => assert!(e.is_retryable(), "a stalled download should be retried: {}", e),
+        Ok(_) => panic!("download should have timed out"),
+    }
+    mocks.assert();
+}
+
+#[tokio::test]
+async fn report_batch() {
+    let (server, mocks) = create_mock_server(FakeServer::ReportBatch);
+    let reports = [
+        sdk::api::Report {
+            state: "downloading".to_string(),
+            package_uid: "package-uid".to_string(),
+            previous_state: None,
+            error_code: None,
+            error_message: None,
+            current_log: None,
+            state_timings: Vec::new(),
+        },
+        sdk::api::Report {
+            state: "error".to_string(),
+            package_uid: "package-uid".to_string(),
+            previous_state: Some("downloading".to_string()),
+            error_code: Some("errorCode".to_string()),
+            error_message: Some("errorMessage".to_string()),
+            current_log: None,
+            state_timings: Vec::new(),
+        },
+    ];
+    sdk::Client::new(&server.url(), None, None)
+        .report_batch(FakeMetadata::new().get(), &reports)
+        .await
+        .unwrap();
+    mocks.assert();
+}
+
 #[tokio::test]
 async fn download_object() {
     use tokio::fs;
@@ -296,9 +673,11 @@ async fn download_object() {
     let dir = tempfile::tempdir().unwrap();
     let file_path = dir.path().join("object");
 
+    let client = sdk::Client::new(&server.url(), None, None);
+
     // Download the object.
-    sdk::Client::new(&server.url())
-        .download_object(FakeMetadata::PRODUCT_UID, "package_id", dir.path(), "object")
+    client
+        .download_object(FakeMetadata::PRODUCT_UID, "package_id", dir.path(), "object", 10, None)
         .await
         .unwrap();
 
@@ -306,8 +685,8 @@ async fn download_object() {
     assert_eq!(fs::read_to_string(&file_path).await.unwrap(), "1234".to_string());
 
     // Download the remaining bytes of the object.
-    sdk::Client::new(&server.url())
-        .download_object(FakeMetadata::PRODUCT_UID, "package_id", dir.path(), "object")
+    client
+        .download_object(FakeMetadata::PRODUCT_UID, "package_id", dir.path(), "object", 10, None)
         .await
         .unwrap();
 
@@ -316,3 +695,107 @@ async fn download_object() {
     mocks.assert();
     dir.close().unwrap();
 }
+
+#[tokio::test]
+async fn download_object_headers_only_sent_to_server() {
+    use mockito::Matcher;
+
+    let headers = sdk::Headers::new(None, [("X-Fleet", "edge")])
+        .unwrap()
+        .with_authorization("Bearer token")
+        .unwrap();
+    let mut host = mockito::Server::new();
+    let url = format!("{}/object", host.url());
+
+    // The object is downloaded from the server itself
+    let dir = tempfile::tempdir().unwrap();
+    let mock = host
+        .mock("GET", "/object")
+        .match_header("Authorization", "Bearer token")
+        .match_header("X-Fleet", "edge")
+        .with_body("1234567890")
+        .create();
+    sdk::Client::new(&host.url(), None, None)
+        .with_headers(&headers)
+        .download_object_from(&url, dir.path(), "object", 10, None)
+        .await
+        .unwrap();
+    mock.assert();
+
+    // The object is downloaded from a mirror on another host
+    let dir = tempfile::tempdir().unwrap();
+    let mock = host
+        .mock("GET", "/object")
+        .match_header("Authorization", Matcher::Missing)
+        .match_header("X-Fleet", Matcher::Missing)
+        .with_body("1234567890")
+        .create();
+    sdk::Client::new("https://api.updatehub.io", None, None)
+        .with_headers(&headers)
+        .download_object_from(&url, dir.path(), "object", 10, None)
+        .await
+        .unwrap();
+    mock.assert();
+}
+
+#[tokio::test]
+async fn download_object_with_unexpected_content_length() {
+    let (server, mocks) = create_mock_server(FakeServer::DownloadWrongSize);
+    let dir = tempfile::tempdir().unwrap();
+
+    let res = sdk::Client::new(&server.url(), None, None)
+        .download_object(FakeMetadata::PRODUCT_UID, "package_id", dir.path(), "object", 10, None)
+        .await;
+
+    match res {
+        Err(sdk::Error::UnexpectedContentLength { expected: 10, received: 4 }) => {}
+        r => panic!("Unexpected download result: {:?}", r),
+    }
+    assert!(!dir.path().join("object").exists());
+    mocks.assert();
+
+    // The mismatch can be left to the object checksum instead
+    sdk::Client::new(&server.url(), None, None)
+        .with_strict_content_length(false)
+        .download_object(FakeMetadata::PRODUCT_UID, "package_id", dir.path(), "object", 10, None)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(dir.path().join("object")).unwrap(), b"1234");
+    dir.close().unwrap();
+}
+
+#[tokio::test]
+async fn download_object_retryable_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    for (status, retryable) in [(503, true), (500, true), (404, false), (403, false)] {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock(
+                "GET",
+                format!(
+                    "/products/{}/packages/{}/objects/{}",
+                    FakeMetadata::PRODUCT_UID,
+                    "package_id",
+                    "object"
+                )
+                .as_str(),
+            )
+            .with_status(status)
+            .create();
+
+        let err = sdk::Client::new(&server.url(), None, None)
+            .download_object(
+                FakeMetadata::PRODUCT_UID,
+                "package_id",
+                dir.path(),
+                "object",
+                10,
+                None,
+            )
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.is_retryable(), retryable, "status: {}, error: {}", status, err);
+        mock.assert();
+    }
+}
@@ -0,0 +1,56 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::definitions::HashAlgorithm;
+use serde::Deserialize;
+
+#[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Command {
+    pub filename: String,
+    pub size: u64,
+    pub sha256sum: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub urls: Vec<String>,
+
+    /// Command line run to install the object, where `{file}` is
+    /// replaced by the path of the verified object.
+    pub command: String,
+    /// Seconds the command is allowed to run before being killed.
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    #[serde(default)]
+    pub install_order: Option<i32>,
+}
+
+#[test]
+fn deserialize() {
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    assert_eq!(
+        super::Object::Command(Box::new(Command {
+            filename: "install.sh".to_string(),
+            size: 1024,
+            sha256sum: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+                .to_string(),
+            hash_algorithm: HashAlgorithm::default(),
+            urls: Vec::new(),
+            command: "sh {file} --verbose".to_string(),
+            timeout: Some(60),
+            install_order: None,
+        })),
+        serde_json::from_value::<super::Object>(json!({
+            "mode": "command",
+            "filename": "install.sh",
+            "size": 1024,
+            "sha256sum": "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            "command": "sh {file} --verbose",
+            "timeout": 60,
+        }))
+        .unwrap()
+    );
+}
@@ -3,7 +3,8 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::definitions::{
-    Filesystem, InstallIfDifferent, TargetFormat, TargetPermissions, TargetType,
+    Compression, Filesystem, HashAlgorithm, InstallIfDifferent, TargetFormat, TargetPermissions,
+    TargetType,
 };
 use serde::Deserialize;
 use std::path::PathBuf;
@@ -15,6 +16,10 @@ pub struct Copy {
     pub filesystem: Filesystem,
     pub size: u64,
     pub sha256sum: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub urls: Vec<String>,
     #[serde(flatten)]
     pub target_type: TargetType,
     pub target_path: PathBuf,
@@ -25,11 +30,25 @@ pub struct Copy {
     #[serde(default)]
     pub compressed: bool,
     #[serde(default)]
+    pub compression: Option<Compression>,
+    /// Checksum of the uncompressed content, verified as it is written.
+    /// When not given, only the checksum of the compressed object is
+    /// verified.
+    #[serde(default)]
+    pub uncompressed_sha256sum: Option<String>,
+    #[serde(default)]
     pub required_uncompressed_size: u64,
     #[serde(flatten, default)]
     pub target_format: TargetFormat,
     #[serde(default)]
     pub mount_options: String,
+    /// Writes the content straight into the target file, instead of
+    /// renaming a fully written temporary file over it. Required when
+    /// the target is not a regular file, as a FIFO.
+    #[serde(default)]
+    pub write_in_place: bool,
+    #[serde(default)]
+    pub install_order: Option<i32>,
 }
 
 #[test]
@@ -50,9 +69,15 @@ fn deserialize() {
             install_if_different: Some(InstallIfDifferent::CheckSum),
             target_permissions: TargetPermissions::default(),
             compressed: false,
+            compression: None,
+            uncompressed_sha256sum: None,
             required_uncompressed_size: 0,
             target_format: TargetFormat::default(),
             mount_options: String::default(),
+            write_in_place: false,
+            install_order: None,
+            hash_algorithm: HashAlgorithm::default(),
+            urls: Vec::new(),
         })),
         serde_json::from_value::<super::Object>(json!({
             "mode": "copy",
@@ -0,0 +1,29 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::Deserialize;
+
+/// Hint of the algorithm used to compress the object. When not
+/// provided, the format is auto detected.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn deserialize() {
+        assert_eq!(Compression::Zstd, serde_json::from_value(json!("zstd")).unwrap());
+        assert_eq!(Compression::Gzip, serde_json::from_value(json!("gzip")).unwrap());
+        assert!(serde_json::from_value::<Compression>(json!("lz4")).is_err());
+    }
+}
@@ -0,0 +1,29 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::Deserialize;
+
+/// Algorithm used to compute the object's checksum. When not provided,
+/// SHA256 is assumed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn deserialize() {
+        assert_eq!(HashAlgorithm::Sha256, serde_json::from_value(json!("sha256")).unwrap());
+        assert_eq!(HashAlgorithm::Blake3, serde_json::from_value(json!("blake3")).unwrap());
+        assert!(serde_json::from_value::<HashAlgorithm>(json!("md5")).is_err());
+    }
+}
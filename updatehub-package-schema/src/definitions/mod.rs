@@ -3,9 +3,12 @@
 // SPDX-License-Identifier: Apache-2.0
 
 mod chunk_size;
+mod compression;
 mod count;
 mod filesystem;
+mod hash_algorithm;
 pub mod install_if_different;
+mod post_install;
 mod skip;
 mod target_format;
 pub mod target_permissions;
@@ -13,9 +16,12 @@ mod target_type;
 mod truncate;
 
 pub use chunk_size::ChunkSize;
+pub use compression::Compression;
 pub use count::Count;
 pub use filesystem::Filesystem;
+pub use hash_algorithm::HashAlgorithm;
 pub use install_if_different::InstallIfDifferent;
+pub use post_install::PostInstallAction;
 pub use skip::Skip;
 pub use target_format::TargetFormat;
 pub use target_permissions::TargetPermissions;
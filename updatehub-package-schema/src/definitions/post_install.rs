@@ -0,0 +1,29 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::Deserialize;
+use std::fmt;
+
+/// Action run against the filesystem an object holds once it has been
+/// written into its target.
+#[derive(Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum PostInstallAction {
+    /// Checks the filesystem, repairing it when needed.
+    Fsck,
+    /// Grows the filesystem to fill its target, once it has been checked.
+    Resize,
+}
+
+impl fmt::Display for PostInstallAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(
+            match self {
+                PostInstallAction::Fsck => "fsck",
+                PostInstallAction::Resize => "resize",
+            },
+            f,
+        )
+    }
+}
@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use serde::{de, Deserialize, Deserializer};
+use std::collections::BTreeMap;
 
 /// Options to set permissions after installing on target.
 #[derive(Clone, PartialEq, Eq, Debug, Deserialize, Default)]
@@ -13,6 +14,12 @@ pub struct TargetPermissions {
     pub target_mode: Option<u32>,
     pub target_gid: Option<Gid>,
     pub target_uid: Option<Uid>,
+    /// Extended attributes set on the target, as `security.selinux`
+    /// labels.
+    pub target_xattrs: BTreeMap<String, String>,
+    /// Fails the installation when an extended attribute can't be set,
+    /// instead of only warning about it.
+    pub strict_xattrs: bool,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug, Deserialize)]
@@ -54,11 +61,18 @@ fn deserialize() {
             target_mode: Some(0o0777),
             target_gid: Some(Gid::Name("wheel".to_string())),
             target_uid: Some(Uid::Name("user".to_string())),
+            target_xattrs: BTreeMap::from([(
+                "security.selinux".to_string(),
+                "system_u:object_r:etc_t:s0".to_string()
+            )]),
+            strict_xattrs: true,
         },
         serde_json::from_value::<TargetPermissions>(json!({
             "target-mode": "0777",
             "target-uid": "user",
-            "target-gid": "wheel"
+            "target-gid": "wheel",
+            "target-xattrs": { "security.selinux": "system_u:object_r:etc_t:s0" },
+            "strict-xattrs": true,
         }))
         .unwrap()
     );
@@ -68,6 +82,7 @@ fn deserialize() {
             target_mode: None,
             target_gid: Some(Gid::Number(1000)),
             target_uid: Some(Uid::Number(1000)),
+            ..TargetPermissions::default()
         },
         serde_json::from_value::<TargetPermissions>(json!({
             "target-uid": 1000,
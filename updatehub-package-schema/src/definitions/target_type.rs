@@ -12,6 +12,9 @@ pub enum TargetType {
     Device(PathBuf),
     UBIVolume(String),
     MTDName(String),
+    File(PathBuf),
+    #[serde(rename = "gpt-label")]
+    GptLabel(String),
 }
 
 #[cfg(test)]
@@ -38,5 +41,21 @@ mod test {
             }))
             .unwrap()
         );
+        assert_eq!(
+            TargetType::File(PathBuf::from("/mnt/data/app.img")),
+            serde_json::from_value::<TargetType>(json!({
+                "target-type": "file",
+                "target": "/mnt/data/app.img",
+            }))
+            .unwrap()
+        );
+        assert_eq!(
+            TargetType::GptLabel("rootfs-a".to_string()),
+            serde_json::from_value::<TargetType>(json!({
+                "target-type": "gpt-label",
+                "target": "rootfs-a",
+            }))
+            .unwrap()
+        );
     }
 }
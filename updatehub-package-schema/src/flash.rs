@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::definitions::{InstallIfDifferent, TargetType};
+use crate::definitions::{HashAlgorithm, InstallIfDifferent, TargetType};
 use serde::Deserialize;
 
 #[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
@@ -11,10 +11,16 @@ pub struct Flash {
     pub filename: String,
     pub size: u64,
     pub sha256sum: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub urls: Vec<String>,
     #[serde(flatten)]
     pub target: TargetType,
 
     pub install_if_different: Option<InstallIfDifferent>,
+    #[serde(default)]
+    pub install_order: Option<i32>,
 }
 
 #[test]
@@ -31,6 +37,9 @@ fn deserialize() {
             target: TargetType::Device(std::path::PathBuf::from("/dev/sda")),
 
             install_if_different: None,
+            install_order: None,
+            hash_algorithm: HashAlgorithm::default(),
+            urls: Vec::new(),
         })),
         serde_json::from_value::<super::Object>(json!({
             "mode": "flash",
@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::definitions::InstallIfDifferent;
+use crate::definitions::{HashAlgorithm, InstallIfDifferent};
 use serde::Deserialize;
 use std::path::PathBuf;
 
@@ -11,6 +11,10 @@ pub struct Imxkobs {
     pub filename: String,
     pub size: u64,
     pub sha256sum: String,
+    #[serde(default, rename = "hash-algorithm")]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub urls: Vec<String>,
 
     pub install_if_different: Option<InstallIfDifferent>,
     #[serde(rename = "1k_padding")]
@@ -22,6 +26,8 @@ pub struct Imxkobs {
     pub chip_0_device_path: Option<PathBuf>,
     #[serde(default)]
     pub chip_1_device_path: Option<PathBuf>,
+    #[serde(default, rename = "install-order")]
+    pub install_order: Option<i32>,
 }
 
 #[test]
@@ -41,6 +47,9 @@ fn deserialize() {
             search_exponent: 2,
             chip_0_device_path: Some(PathBuf::from("/dev/sda1")),
             chip_1_device_path: Some(PathBuf::from("/dev/sda2")),
+            install_order: None,
+            hash_algorithm: HashAlgorithm::default(),
+            urls: Vec::new(),
         })),
         serde_json::from_value::<super::Object>(json!({
             "mode": "imxkobs",
@@ -2,10 +2,12 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+mod command;
 mod copy;
 mod flash;
 mod imxkobs;
 mod mender;
+mod patch;
 mod raw;
 mod raw_delta;
 mod tarball;
@@ -21,8 +23,9 @@ pub mod definitions;
 /// Objects representing each possible install mode
 pub mod objects {
     pub use crate::{
-        copy::Copy, flash::Flash, imxkobs::Imxkobs, mender::Mender, raw::Raw, raw_delta::RawDelta,
-        tarball::Tarball, test::Test, ubifs::Ubifs, uboot_env::UbootEnv, zephyr::Zephyr,
+        command::Command, copy::Copy, flash::Flash, imxkobs::Imxkobs, mender::Mender, patch::Patch,
+        raw::Raw, raw_delta::RawDelta, tarball::Tarball, test::Test, ubifs::Ubifs,
+        uboot_env::UbootEnv, zephyr::Zephyr,
     };
 }
 pub use update_package::{SupportedHardware, UpdatePackage};
@@ -34,10 +37,12 @@ use serde::Deserialize;
 #[serde(tag = "mode")]
 #[serde(rename_all = "lowercase")]
 pub enum Object {
+    Command(Box<objects::Command>),
     Copy(Box<objects::Copy>),
     Flash(Box<objects::Flash>),
     Imxkobs(Box<objects::Imxkobs>),
     Mender(Box<objects::Mender>),
+    Patch(Box<objects::Patch>),
     Raw(Box<objects::Raw>),
     #[serde(rename = "raw-delta")]
     RawDelta(Box<objects::RawDelta>),
@@ -0,0 +1,69 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::definitions::{HashAlgorithm, TargetType};
+use serde::Deserialize;
+
+#[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Patch {
+    pub filename: String,
+    pub size: u64,
+    pub sha256sum: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub urls: Vec<String>,
+    #[serde(flatten)]
+    pub target: TargetType,
+
+    /// Checksum of the content the patch applies to, read from the start
+    /// of the target.
+    pub base_sha256sum: String,
+    pub base_size: u64,
+    /// Checksum of the content produced by the patch, which is written
+    /// into the target.
+    pub patched_sha256sum: String,
+    pub patched_size: u64,
+    #[serde(default)]
+    pub install_order: Option<i32>,
+}
+
+#[test]
+fn deserialize() {
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    assert_eq!(
+        super::Object::Patch(Box::new(Patch {
+            filename: "rootfs.bspatch".to_string(),
+            size: 1024,
+            sha256sum: "cfe2be1c64b0387500853de0f48303e3de7b1c6f1508dc719eeafa0d41c36722"
+                .to_string(),
+            hash_algorithm: HashAlgorithm::default(),
+            urls: Vec::new(),
+            target: TargetType::Device(std::path::PathBuf::from("/dev/sda1")),
+            base_sha256sum: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+                .to_string(),
+            base_size: 4096,
+            patched_sha256sum: "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+                .to_string(),
+            patched_size: 8192,
+            install_order: None,
+        })),
+        serde_json::from_value::<super::Object>(json!({
+            "mode": "patch",
+            "filename": "rootfs.bspatch",
+            "size": 1024,
+            "sha256sum": "cfe2be1c64b0387500853de0f48303e3de7b1c6f1508dc719eeafa0d41c36722",
+            "target-type": "device",
+            "target": "/dev/sda1",
+            "base-sha256sum": "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            "base-size": 4096,
+            "patched-sha256sum": "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03",
+            "patched-size": 8192,
+        }))
+        .unwrap()
+    );
+}
@@ -2,7 +2,10 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::definitions::{ChunkSize, Count, InstallIfDifferent, Skip, TargetType, Truncate};
+use crate::definitions::{
+    ChunkSize, Compression, Count, Filesystem, HashAlgorithm, InstallIfDifferent,
+    PostInstallAction, Skip, TargetType, Truncate,
+};
 use serde::Deserialize;
 
 #[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
@@ -11,13 +14,26 @@ pub struct Raw {
     pub filename: String,
     pub size: u64,
     pub sha256sum: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub urls: Vec<String>,
     #[serde(flatten)]
     pub target_type: TargetType,
+    #[serde(default)]
+    pub additional_targets: Vec<TargetType>,
 
     pub install_if_different: Option<InstallIfDifferent>,
     #[serde(default)]
     pub compressed: bool,
     #[serde(default)]
+    pub compression: Option<Compression>,
+    /// Checksum of the uncompressed content, verified as it is written.
+    /// When not given, only the checksum of the compressed object is
+    /// verified.
+    #[serde(default)]
+    pub uncompressed_sha256sum: Option<String>,
+    #[serde(default)]
     pub required_uncompressed_size: u64,
     #[serde(default)]
     pub chunk_size: ChunkSize,
@@ -25,10 +41,36 @@ pub struct Raw {
     pub skip: Skip,
     #[serde(default)]
     pub seek: u64,
+    /// Offset, in bytes, the object is written at within its targets,
+    /// added to `seek`.
+    #[serde(default)]
+    pub target_offset: Option<u64>,
+    /// Size, in bytes, of the region reserved for the object within its
+    /// targets, starting at the offset.
+    #[serde(default)]
+    pub target_size: Option<u64>,
     #[serde(default)]
     pub count: Count,
     #[serde(default)]
     pub truncate: Truncate,
+    /// Filesystem held by the image, required by the post-install
+    /// actions.
+    #[serde(default)]
+    pub filesystem: Option<Filesystem>,
+    /// Actions run against each target once the image has been written.
+    #[serde(default)]
+    pub post_install: Vec<PostInstallAction>,
+    /// Checksum of the content expanded from a sparse image, verified as
+    /// it is written. When not given, only the checksum of the sparse
+    /// image itself is verified.
+    #[serde(default)]
+    pub expanded_sha256sum: Option<String>,
+    /// Reads back the written region of every target once the object is
+    /// written, checking it holds the expected content.
+    #[serde(default)]
+    pub verify_after_write: bool,
+    #[serde(default)]
+    pub install_order: Option<i32>,
 }
 
 #[test]
@@ -44,26 +86,45 @@ fn deserialize() {
             sha256sum: "cfe2be1c64b0387500853de0f48303e3de7b1c6f1508dc719eeafa0d41c36722"
                 .to_string(),
             target_type: TargetType::Device(PathBuf::from("/dev/sdb")),
+            additional_targets: vec![TargetType::Device(PathBuf::from("/dev/sdc"))],
 
             install_if_different: Some(InstallIfDifferent::CheckSum),
             compressed: true,
+            compression: None,
+            uncompressed_sha256sum: None,
             required_uncompressed_size: 2048,
             chunk_size: ChunkSize::default(),
             skip: Skip::default(),
             seek: u64::default(),
+            target_offset: Some(512),
+            target_size: None,
             count: Count::default(),
             truncate: Truncate::default(),
+            filesystem: Some(Filesystem::Ext4),
+            post_install: vec![PostInstallAction::Fsck, PostInstallAction::Resize],
+            expanded_sha256sum: None,
+            verify_after_write: true,
+            install_order: None,
+            hash_algorithm: HashAlgorithm::Blake3,
+            urls: vec!["https://cdn.example.com/etc/passwd".to_string()],
         })),
         serde_json::from_value::<super::Object>(json!({
             "mode": "raw",
             "filename": "etc/passwd",
             "size": 1024,
             "sha256sum": "cfe2be1c64b0387500853de0f48303e3de7b1c6f1508dc719eeafa0d41c36722",
+            "hash-algorithm": "blake3",
+            "urls": ["https://cdn.example.com/etc/passwd"],
             "install-if-different": "sha256sum",
             "target-type": "device",
             "target": "/dev/sdb",
+            "additional-targets": [{ "target-type": "device", "target": "/dev/sdc" }],
             "compressed": true,
-            "required-uncompressed-size": 2048
+            "required-uncompressed-size": 2048,
+            "target-offset": 512,
+            "filesystem": "ext4",
+            "post-install": ["fsck", "resize"],
+            "verify-after-write": true
         }))
         .unwrap()
     );
@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::definitions::{ChunkSize, TargetType};
+use crate::definitions::{ChunkSize, HashAlgorithm, TargetType};
 use serde::Deserialize;
 
 #[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
@@ -10,6 +10,10 @@ use serde::Deserialize;
 pub struct RawDelta {
     pub filename: String,
     pub sha256sum: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub urls: Vec<String>,
     #[serde(flatten)]
     pub target: TargetType,
     pub size: u64,
@@ -17,6 +21,8 @@ pub struct RawDelta {
     pub chunk_size: ChunkSize,
     #[serde(default)]
     pub seek: u64,
+    #[serde(default)]
+    pub install_order: Option<i32>,
 }
 
 #[test]
@@ -33,6 +39,9 @@ fn deserialize() {
             chunk_size: ChunkSize::default(),
             seek: 0,
             size: 1024,
+            install_order: None,
+            hash_algorithm: HashAlgorithm::default(),
+            urls: Vec::new(),
         },
         serde_json::from_value::<RawDelta>(json!({
             "filename": "etc/passwd",
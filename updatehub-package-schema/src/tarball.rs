@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::definitions::{Filesystem, TargetFormat, TargetType};
+use crate::definitions::{Filesystem, HashAlgorithm, TargetFormat, TargetType};
 use serde::Deserialize;
 use std::path::PathBuf;
 
@@ -13,6 +13,10 @@ pub struct Tarball {
     pub filesystem: Filesystem,
     pub size: u64,
     pub sha256sum: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub urls: Vec<String>,
     #[serde(flatten)]
     pub target: TargetType,
     pub target_path: PathBuf,
@@ -25,6 +29,8 @@ pub struct Tarball {
     pub target_format: TargetFormat,
     #[serde(default)]
     pub mount_options: String,
+    #[serde(default)]
+    pub install_order: Option<i32>,
 }
 
 #[test]
@@ -46,6 +52,9 @@ fn deserialize() {
             required_uncompressed_size: 0,
             target_format: TargetFormat::default(),
             mount_options: String::default(),
+            install_order: None,
+            hash_algorithm: HashAlgorithm::default(),
+            urls: Vec::new(),
         })),
         serde_json::from_value::<super::Object>(json!({
             "mode": "tarball",
@@ -2,6 +2,7 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::definitions::HashAlgorithm;
 use serde::Deserialize;
 
 #[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
@@ -9,7 +10,13 @@ use serde::Deserialize;
 pub struct Test {
     pub filename: String,
     pub sha256sum: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub urls: Vec<String>,
     pub target: String,
     pub size: u64,
     pub force_check_requirements_fail: bool,
+    #[serde(default)]
+    pub install_order: Option<i32>,
 }
@@ -2,8 +2,9 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::definitions::TargetType;
+use crate::definitions::{Compression, HashAlgorithm, TargetType};
 use serde::Deserialize;
+use std::path::PathBuf;
 
 #[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
 #[serde(rename_all = "kebab-case")]
@@ -11,13 +12,31 @@ pub struct Ubifs {
     pub filename: String,
     pub size: u64,
     pub sha256sum: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub urls: Vec<String>,
     #[serde(flatten)]
     pub target: TargetType,
 
     #[serde(default)]
     pub compressed: bool,
     #[serde(default)]
+    pub compression: Option<Compression>,
+    #[serde(default)]
     pub required_uncompressed_size: u64,
+    #[serde(default)]
+    pub install_order: Option<i32>,
+
+    /// Size, in bytes, the volume is resized to before installing.
+    #[serde(default)]
+    pub volume_size: Option<u64>,
+    /// Creates the volume on `ubi_device` when it doesn't exist yet.
+    #[serde(default)]
+    pub create_volume: bool,
+    /// UBI device the volume is created on, `/dev/ubi0` by default.
+    #[serde(default)]
+    pub ubi_device: Option<PathBuf>,
 }
 
 #[test]
@@ -34,7 +53,14 @@ fn deserialize() {
             target: TargetType::UBIVolume("home".to_string()),
 
             compressed: true,
+            compression: None,
             required_uncompressed_size: 2048,
+            install_order: None,
+            hash_algorithm: HashAlgorithm::default(),
+            urls: Vec::new(),
+            volume_size: Some(4096),
+            create_volume: true,
+            ubi_device: Some(PathBuf::from("/dev/ubi1")),
         })),
         serde_json::from_value::<super::Object>(json!({
             "mode": "ubifs",
@@ -44,7 +70,10 @@ fn deserialize() {
             "target-type": "ubivolume",
             "target": "home",
             "compressed": true,
-            "required-uncompressed-size": 2048
+            "required-uncompressed-size": 2048,
+            "volume-size": 4096,
+            "create-volume": true,
+            "ubi-device": "/dev/ubi1"
         }))
         .unwrap()
     );
@@ -2,7 +2,9 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::definitions::HashAlgorithm;
 use serde::Deserialize;
+use std::collections::BTreeMap;
 
 #[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
 #[serde(rename_all = "kebab-case")]
@@ -10,6 +12,17 @@ pub struct UbootEnv {
     pub filename: String,
     pub size: u64,
     pub sha256sum: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub urls: Vec<String>,
+    /// Variables set on the environment once the object is installed.
+    /// Their values can't be empty, as that deletes the variable, nor
+    /// start with whitespace, which the environment drops.
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    #[serde(default)]
+    pub install_order: Option<i32>,
 }
 
 #[test]
@@ -23,12 +36,23 @@ fn deserialize() {
             size: 1024,
             sha256sum: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
                 .to_string(),
+            install_order: None,
+            hash_algorithm: HashAlgorithm::default(),
+            urls: Vec::new(),
+            variables: BTreeMap::from([
+                ("boot_part".to_string(), "2".to_string()),
+                ("upgrade_available".to_string(), "1".to_string()),
+            ]),
         })),
         serde_json::from_value::<super::Object>(json!({
             "mode": "uboot-env",
             "filename": "updatehub.defenv",
             "size": 1024,
-            "sha256sum": "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+            "sha256sum": "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            "variables": {
+                "boot_part": "2",
+                "upgrade_available": "1"
+            }
         }))
         .unwrap()
     );
@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use serde::Deserialize;
+use std::collections::BTreeMap;
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
 pub struct UpdatePackage {
@@ -12,6 +13,14 @@ pub struct UpdatePackage {
     #[serde(default, rename = "supported-hardware")]
     pub supported_hardware: SupportedHardware,
     pub objects: (Vec<crate::Object>, Vec<crate::Object>),
+    /// Filenames of the objects to be installed, skipping the others. All
+    /// the objects are installed when empty.
+    #[serde(default, rename = "install-subset")]
+    pub install_subset: Vec<String>,
+    /// Filenames of the objects each object requires to be installed
+    /// along with it, keyed by its filename.
+    #[serde(default, rename = "object-dependencies")]
+    pub object_dependencies: BTreeMap<String, Vec<String>>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
@@ -67,4 +76,22 @@ mod tests {
                 .unwrap()
         );
     }
+
+    #[test]
+    fn install_subset() {
+        let package = serde_json::from_value::<UpdatePackage>(json!({
+            "product": "0123456789",
+            "version": "1.0",
+            "objects": [[], []],
+            "install-subset": ["app.ext4", "app.conf"],
+            "object-dependencies": { "app.ext4": ["app.conf"] }
+        }))
+        .unwrap();
+
+        assert_eq!(package.install_subset, ["app.ext4", "app.conf"]);
+        assert_eq!(
+            package.object_dependencies,
+            BTreeMap::from([("app.ext4".to_owned(), vec!["app.conf".to_owned()])])
+        );
+    }
 }
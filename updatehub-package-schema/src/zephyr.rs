@@ -2,6 +2,7 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::definitions::HashAlgorithm;
 use serde::Deserialize;
 
 #[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
@@ -9,6 +10,12 @@ pub struct Zephyr {
     pub filename: String,
     pub size: u64,
     pub sha256sum: String,
+    #[serde(default, rename = "hash-algorithm")]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub urls: Vec<String>,
+    #[serde(default, rename = "install-order")]
+    pub install_order: Option<i32>,
 }
 
 #[test]
@@ -22,6 +29,9 @@ fn deserialize() {
             size: 1024,
             sha256sum: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
                 .to_string(),
+            install_order: None,
+            hash_algorithm: HashAlgorithm::default(),
+            urls: Vec::new(),
         })),
         serde_json::from_value::<super::Object>(json!({
             "mode": "zephyr",
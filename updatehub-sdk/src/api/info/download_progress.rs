@@ -0,0 +1,34 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+/// Progress of the download being currently handled by the agent.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DownloadProgress {
+    /// Sum of the size of all objects being downloaded, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_bytes: Option<u64>,
+    /// Amount of bytes already downloaded
+    pub downloaded_bytes: u64,
+    /// Index, starting at 1, of the last object whose download has started
+    pub current_object: usize,
+    /// Number of objects being downloaded
+    pub total_objects: usize,
+    /// Progress of each object being downloaded
+    pub objects: Vec<ObjectProgress>,
+}
+
+/// Progress of a single object download.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObjectProgress {
+    pub filename: String,
+    /// Object size, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    pub downloaded_bytes: u64,
+    pub completed: bool,
+}
@@ -0,0 +1,35 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+/// Progress of the install being currently handled by the agent.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InstallProgress {
+    /// Index, starting at 1, of the last object whose install has started
+    pub current_object: usize,
+    /// Number of objects being installed
+    pub total_objects: usize,
+    /// Status of each object, in the order they are installed
+    pub objects: Vec<ObjectInstallStatus>,
+}
+
+/// Install status of a single object.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObjectInstallStatus {
+    pub filename: String,
+    pub mode: String,
+    pub status: InstallStatus,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallStatus {
+    Pending,
+    Installing,
+    Done,
+    Failed,
+}
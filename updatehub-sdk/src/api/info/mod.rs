@@ -2,9 +2,12 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+pub mod download_progress;
 pub mod firmware;
+pub mod install_progress;
 pub mod runtime_settings;
 pub mod settings;
 
@@ -16,4 +19,45 @@ pub struct Response {
     pub config: settings::Settings,
     pub firmware: firmware::Metadata,
     pub runtime_settings: runtime_settings::RuntimeSettings,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_progress: Option<download_progress::DownloadProgress>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub install_progress: Option<install_progress::InstallProgress>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_installation_set: Option<runtime_settings::InstallationSet>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_installation_set: Option<runtime_settings::InstallationSet>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_install_time: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_update_result: Option<runtime_settings::UpdateResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_update_time: Option<DateTime<Utc>>,
+    /// Number of packages refused, since the agent started, for not
+    /// supporting the device's hardware.
+    #[serde(default)]
+    pub rejected_packages: u64,
+    /// Name of the state the agent is paused before, when stopped at a
+    /// debugging breakpoint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breakpoint: Option<String>,
+    /// Download rate limit, in bytes per second, applied to downloads
+    /// started now.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_rate_limit: Option<u64>,
+    /// Time spent on the states of the current update cycle, when
+    /// `report_state_timings` is enabled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub state_timings: Vec<StateTiming>,
+}
+
+/// Time spent on a state of the update cycle.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StateTiming {
+    pub state: String,
+    pub entered_at: DateTime<Utc>,
+    pub left_at: DateTime<Utc>,
+    /// Time, in milliseconds, spent on the state.
+    pub duration_ms: u64,
 }
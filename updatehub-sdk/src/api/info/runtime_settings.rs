@@ -2,7 +2,8 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use chrono::{DateTime, Utc};
+use crate::serde_helpers;
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -22,6 +23,33 @@ pub struct RuntimePolling {
     pub retries: usize,
     pub now: bool,
     pub server_address: ServerAddress,
+    /// Polling interval set through the agent API, overriding the
+    /// configured one. Zero when not overridden.
+    #[serde(
+        with = "serde_helpers::duration",
+        default = "Duration::zero",
+        skip_serializing_if = "Duration::is_zero"
+    )]
+    pub interval: Duration,
+    /// Summary of the last probe response received from the server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_probe: Option<ProbeSummary>,
+    /// Last configured server which answered a probe, tried first on the
+    /// next one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_server_address: Option<String>,
+}
+
+/// Summary of a probe response, telling if the server offered an update
+/// or deferred the device.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProbeSummary {
+    pub timestamp: DateTime<Utc>,
+    pub update_available: bool,
+    /// Seconds the server asked the device to wait before probing again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extra_poll: Option<i64>,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
@@ -38,6 +66,68 @@ pub struct RuntimeUpdate {
     pub upgrade_to_installation: Option<InstallationSet>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub applied_package_uid: Option<String>,
+    #[serde(default)]
+    pub boot_count: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_update_result: Option<UpdateResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_update_time: Option<DateTime<Utc>>,
+    /// Update package selected by a probe which has not been installed
+    /// yet, so it can be resumed after the agent restarts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_update: Option<PendingUpdate>,
+    /// Last update attempts, oldest first, bounded by the `history_size`
+    /// setting.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<UpdateAttempt>,
+    /// Number of times in a row the installation has failed validation
+    /// and been rolled back.
+    #[serde(default)]
+    pub rollback_attempts: u32,
+    /// Whether the rollback attempts are exhausted, so the agent has
+    /// stopped swapping the active installation set.
+    #[serde(default)]
+    pub rollback_exhausted: bool,
+}
+
+/// Update package received from the server, kept as it was sent so its
+/// signature can still be validated.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PendingUpdate {
+    pub package: String,
+    /// Base64 encoded signature of the package.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Outcome of the last update attempt.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateResult {
+    pub status: UpdateStatus,
+    pub package_uid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Update attempt kept in the history.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateAttempt {
+    pub timestamp: DateTime<Utc>,
+    pub package_uid: String,
+    pub status: UpdateStatus,
+    /// Machine readable code of the error which has failed the update.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateStatus {
+    Success,
+    Failure,
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
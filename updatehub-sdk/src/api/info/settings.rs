@@ -3,9 +3,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::serde_helpers;
-use chrono::Duration;
+use chrono::{Duration, NaiveTime};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{collections::BTreeMap, fmt, path::PathBuf};
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 #[serde(deny_unknown_fields)]
@@ -21,13 +21,365 @@ pub struct Settings {
 #[serde(deny_unknown_fields)]
 pub struct Firmware {
     pub metadata: PathBuf,
+    /// Public key used to validate the update package signature. When it
+    /// is a directory, a signature verified by any of the keys in it is
+    /// accepted, so keys can be rotated. By default, the `key.pub` file
+    /// inside the metadata directory is used, if present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key_path: Option<PathBuf>,
+    /// Maximum size, in bytes, of the in-memory log served by the agent
+    /// and sent on error reports. Older entries are dropped once it is
+    /// full.
+    #[serde(default = "default_log_buffer_size")]
+    pub log_buffer_size: usize,
+    /// Command run after booting into a new installation to check the
+    /// system health before confirming it. By default, no health check
+    /// is run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<String>,
+    /// Number of times the health check is run, until it succeeds,
+    /// before rolling back the installation.
+    #[serde(default = "default_health_check_attempts")]
+    pub health_check_attempts: usize,
+    /// Delay between health check attempts.
+    #[serde(with = "serde_helpers::duration", default = "default_health_check_interval")]
+    pub health_check_interval: Duration,
+    /// Installation set, 0 or 1, updates are always installed into. By
+    /// default, updates are installed into the inactive set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_installation_set: Option<u8>,
+    /// Command run to reboot the device after an update is installed.
+    #[serde(default = "default_reboot_command")]
+    pub reboot_command: String,
+    /// Delay before running the reboot command, giving time for logs
+    /// and reports to be flushed. By default, the reboot is immediate.
+    #[serde(
+        with = "serde_helpers::duration",
+        default = "Duration::zero",
+        skip_serializing_if = "Duration::is_zero"
+    )]
+    pub reboot_delay: Duration,
+    /// Action taken when booting into a new installation, that is, when
+    /// the active set is the one in the runtime settings'
+    /// `upgrade_to_installation`, and the firmware has no validate
+    /// callback. By default, the installation is rolled back.
+    #[serde(default)]
+    pub validate_on_missing_callback: MissingCallbackPolicy,
+    /// Devices of each installation set, by name, as `[set 0, set 1]`.
+    /// Object targets written as `{name}` are installed into the device
+    /// of the set being updated, and no object is allowed to target the
+    /// devices of the active set.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub installation_set_devices: BTreeMap<String, [PathBuf; 2]>,
+    /// Scheme used to compare the package version against the running
+    /// firmware version. By default, versions are not compared.
+    #[serde(default)]
+    pub version_scheme: VersionScheme,
+    /// Installs packages whose version is equal to or older than the
+    /// running firmware version, when `version_scheme` compares them.
+    #[serde(default)]
+    pub allow_downgrade: bool,
+    /// Time the system must have been up for before an update is
+    /// installed, so a device restarting in a loop doesn't keep
+    /// installing. By default, installs are not held back.
+    #[serde(
+        with = "serde_helpers::duration",
+        default = "Duration::zero",
+        skip_serializing_if = "Duration::is_zero"
+    )]
+    pub minimum_uptime_before_install: Duration,
+    /// File the in-memory log is saved to whenever the error state is
+    /// reached, so failures are kept even when the report doesn't reach
+    /// the server. The logs of previous failures are kept as `.1` to
+    /// `.4` suffixed files. By default, the log is not saved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_log_path: Option<PathBuf>,
+    /// Number of times in a row an installation failing validation is
+    /// rolled back. Once exceeded, as when both installation sets keep
+    /// failing, the agent stops swapping the active set and rebooting,
+    /// and parks reporting the situation.
+    #[serde(default = "default_rollback_attempts")]
+    pub rollback_attempts: u32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingCallbackPolicy {
+    /// Confirms the installation as if the callback had succeeded.
+    Validate,
+    /// Swaps back to the previous installation and reboots.
+    Rollback,
+    /// Leaves the installation unconfirmed, keeping
+    /// `upgrade_to_installation` set so it is checked again on the next
+    /// start.
+    Error,
+}
+
+impl Default for MissingCallbackPolicy {
+    fn default() -> Self {
+        MissingCallbackPolicy::Rollback
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionScheme {
+    /// Versions are not compared, any package version is installed.
+    None,
+    /// Dot separated numbers, with optional pre-release and build
+    /// metadata, as in semantic versioning.
+    Semver,
+    /// Plain string comparison.
+    Lexical,
+    /// The `version-compare-callback` of the firmware metadata directory
+    /// is run with the running and the package versions, printing
+    /// `newer`, `equal` or `older` for the package version.
+    Callback,
+}
+
+impl Default for VersionScheme {
+    fn default() -> Self {
+        VersionScheme::None
+    }
+}
+
+fn default_log_buffer_size() -> usize {
+    256 * 1024
+}
+
+fn default_health_check_attempts() -> usize {
+    3
+}
+
+fn default_rollback_attempts() -> u32 {
+    3
+}
+
+fn default_health_check_interval() -> Duration {
+    Duration::seconds(10)
+}
+
+fn default_reboot_command() -> String {
+    "reboot".to_owned()
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Network {
     pub server_address: String,
+    /// Address the agent API listens on, either as `host:port` or, on Unix
+    /// systems, as `unix:<path>` for a Unix domain socket.
     pub listen_socket: String,
+    /// Maximum number of objects downloaded at the same time. By
+    /// default, objects are downloaded one at a time.
+    #[serde(default = "default_max_concurrent_downloads")]
+    pub max_concurrent_downloads: usize,
+    /// Maximum throughput, in bytes per second, shared by all downloads.
+    /// By default, downloads are not throttled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_rate_limit: Option<u64>,
+    /// PEM encoded certificate presented to the server for TLS client
+    /// authentication. It must be set along with `client_key`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_certificate: Option<PathBuf>,
+    /// PEM encoded PKCS#8 private key of `client_certificate`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<PathBuf>,
+    /// Number of times an object download is retried after a transient
+    /// failure. By default, downloads are not retried.
+    #[serde(default)]
+    pub download_retries: usize,
+    /// Delay before the first download retry, doubled on each following
+    /// attempt.
+    #[serde(with = "serde_helpers::duration", default = "default_download_retry_backoff")]
+    pub download_retry_backoff: Duration,
+    /// Format used for the log output. By default, logs are written as
+    /// plain text.
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// HTTP proxy used to reach the server. By default, the proxy set by
+    /// the `HTTP_PROXY` and `HTTPS_PROXY` environment variables is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Comma separated list of hosts which are reached without the proxy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<String>,
+    /// Number of state reports sent together to the server. Reports are
+    /// queued until the batch is full, the update fails or the agent
+    /// goes idle. By default, every report is sent right away.
+    #[serde(default = "default_report_batch_size")]
+    pub report_batch_size: usize,
+    /// Maximum time a probe may take before it is given up on. By
+    /// default, probes have no time limit.
+    #[serde(
+        with = "serde_helpers::duration",
+        default = "Duration::zero",
+        skip_serializing_if = "Duration::is_zero"
+    )]
+    pub probe_timeout: Duration,
+    /// Maximum time an object download may go without receiving any
+    /// data before it fails. By default, downloads have no time limit.
+    #[serde(
+        with = "serde_helpers::duration",
+        default = "Duration::zero",
+        skip_serializing_if = "Duration::is_zero"
+    )]
+    pub download_timeout: Duration,
+    /// `User-Agent` sent on the requests to the server. By default,
+    /// `updatehub/2.0 Linux` is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Headers sent on every request to the server, overriding the
+    /// default ones. By default, no extra headers are sent.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra_headers: BTreeMap<String, String>,
+    /// Maximum size, in bytes, of the objects of a package for it to be
+    /// downloaded. By default, packages of any size are downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_package_size: Option<u64>,
+    /// Servers probed, in order, when `server_address` doesn't respond.
+    /// By default, only `server_address` is used.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_servers: Vec<String>,
+    /// Checks the connectivity, with a TCP connection, before probing the
+    /// server, deferring the probe while it's not reachable.
+    #[serde(default)]
+    pub connectivity_check: bool,
+    /// `host:port` connected to by the connectivity check. By default, the
+    /// server host is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connectivity_check_target: Option<String>,
+    /// Permissions of the Unix domain socket the agent API listens on,
+    /// when `listen_socket` is one. By default, only the owner and its
+    /// group can access it.
+    #[serde(default = "default_listen_socket_mode")]
+    pub listen_socket_mode: u32,
+    /// Maximum number of API requests handled at the same time, further
+    /// clients are refused until one of them is answered. By default, it is
+    /// unlimited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_api_connections: Option<usize>,
+    /// Enables the debugging of the agent: the diagnostics of its
+    /// internals are served on `/debug/channels`, and it pauses at the
+    /// `update.debug_breakpoints`. Meant for maintainers and support only.
+    /// By default, it is disabled.
+    #[serde(default)]
+    pub enable_debug_api: bool,
+    /// Local time ranges with the download rate limit applied in them,
+    /// picked when a download starts. The first range holding the
+    /// current time is used, and outside all of them
+    /// `download_rate_limit` applies. By default, the rate limit doesn't
+    /// depend on the time.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub download_rate_schedule: Vec<RateSchedule>,
+    /// Sends the reports gzip compressed, when they are large enough to
+    /// benefit from it, as the ones holding the error logs. By default,
+    /// reports are not compressed.
+    #[serde(default)]
+    pub compress_reports: bool,
+    /// Records the time spent on each state of the update, sending them
+    /// along with the reports of its outcome. By default, the states are
+    /// not timed.
+    #[serde(default)]
+    pub report_state_timings: bool,
+    /// `Authorization` header sent on the requests to the server, such
+    /// as `Bearer <token>`. It's never served by the agent API. By
+    /// default, no authorization is sent.
+    #[serde(default, skip_serializing)]
+    pub authorization: Option<String>,
+    /// File holding the `Authorization` header sent on the requests to
+    /// the server. It's read again for every request, so the credentials
+    /// can be rotated without restarting the agent, and the requests fail
+    /// while it can't be read. It cannot be set along with
+    /// `authorization`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authorization_file: Option<PathBuf>,
+    /// URLs notified, with a `POST` of a JSON event, when an update is
+    /// available, has been downloaded, and has been installed or has
+    /// failed. They are reached through the same proxy, host aliases and
+    /// client certificate as the server. Failures to notify them are only
+    /// logged and never affect the update. By default, no webhook is
+    /// notified.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<String>,
+    /// Secret sent on the `X-UpdateHub-Webhook-Secret` header of the
+    /// webhook requests, so their receivers can authenticate them. It's
+    /// never served by the agent API. By default, no secret is sent.
+    #[serde(default, skip_serializing)]
+    pub webhook_secret: Option<String>,
+    /// IP addresses the hosts, as the server and the object mirrors, are
+    /// connected to instead of resolving their names. TLS is still
+    /// validated against the hostnames. By default, every host is
+    /// resolved.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub host_aliases: BTreeMap<String, String>,
+    /// Token the agent API requests changing the installation sets, or
+    /// rolling back, must send, as `Authorization: Bearer <token>`. It's
+    /// never served by the agent API. By default, no token is set and
+    /// such requests are refused.
+    #[serde(default, skip_serializing)]
+    pub api_token: Option<String>,
+    /// Action taken when the server, or a proxy on the way, announces a
+    /// `Content-Length` other than the expected size of the object being
+    /// downloaded. By default, the download fails.
+    #[serde(default)]
+    pub content_length_mismatch: ContentLengthMismatchPolicy,
+}
+
+/// Download rate limit applied during a time range.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateSchedule {
+    pub window: UpdateWindow,
+    /// Maximum throughput, in bytes per second, shared by all downloads.
+    /// By default, downloads are not throttled within the range.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentLengthMismatchPolicy {
+    /// Fails the download, trying the next mirror of the object, if any.
+    Fail,
+    /// Logs the mismatch and carries on with the download, leaving it to
+    /// the object checksum to catch a corrupted transfer.
+    Warn,
+}
+
+impl Default for ContentLengthMismatchPolicy {
+    fn default() -> Self {
+        ContentLengthMismatchPolicy::Fail
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Plain,
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Plain
+    }
+}
+
+fn default_max_concurrent_downloads() -> usize {
+    1
+}
+
+fn default_download_retry_backoff() -> Duration {
+    Duration::seconds(1)
+}
+
+fn default_report_batch_size() -> usize {
+    1
+}
+
+fn default_listen_socket_mode() -> u32 {
+    0o660
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
@@ -36,6 +388,85 @@ pub struct Polling {
     #[serde(with = "serde_helpers::duration")]
     pub interval: Duration,
     pub enabled: bool,
+    /// Local time ranges in which updates are allowed to be installed.
+    /// By default, updates can be installed at any time.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub update_window: Vec<UpdateWindow>,
+    /// Maximum random delay added to each poll, spreading the probes of
+    /// devices which would otherwise poll at the same time. A new delay
+    /// is picked on every poll cycle.
+    #[serde(
+        with = "serde_helpers::duration",
+        default = "Duration::zero",
+        skip_serializing_if = "Duration::is_zero"
+    )]
+    pub jitter: Duration,
+    /// Maximum delay between probes after consecutive failed ones. The
+    /// delay doubles on each failure, starting from 1 second, and is
+    /// reset on the next successful probe. By default, it is capped to
+    /// 1 hour.
+    #[serde(with = "serde_helpers::duration", default = "default_max_backoff")]
+    pub max_backoff: Duration,
+    /// Probes the server as soon as the agent starts, when polling is
+    /// enabled, instead of waiting for the next poll computed from the
+    /// last one. By default, it waits for the next poll.
+    #[serde(default)]
+    pub probe_on_startup: bool,
+}
+
+fn default_max_backoff() -> Duration {
+    Duration::hours(1)
+}
+
+/// Time range, in the `HH:MM-HH:MM` format, which may cross midnight.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct UpdateWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl UpdateWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+impl TryFrom<String> for UpdateWindow {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let parse = |t: &str| {
+            NaiveTime::parse_from_str(t.trim(), "%H:%M")
+                .map_err(|e| format!("invalid update window '{}': {}", s, e))
+        };
+
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("invalid update window '{}': expected HH:MM-HH:MM", s))?;
+        let (start, end) = (parse(start)?, parse(end)?);
+        if start == end {
+            return Err(format!("invalid update window '{}': it cannot be empty", s));
+        }
+
+        Ok(UpdateWindow { start, end })
+    }
+}
+
+impl From<UpdateWindow> for String {
+    fn from(w: UpdateWindow) -> Self {
+        w.to_string()
+    }
+}
+
+impl fmt::Display for UpdateWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start.format("%H:%M"), self.end.format("%H:%M"))
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
@@ -48,6 +479,50 @@ pub struct Storage {
     /// those are stored in
     /// `/var/lib/updatehub/runtime_settings.conf`.
     pub runtime_settings: PathBuf,
+    /// Go through the whole update process without writing the objects
+    /// to their targets nor rebooting the device. By default, dry-run
+    /// mode is disabled.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Size, in bytes, of the buffers used to write the objects into
+    /// their targets. It must be a power of two between 512 bytes and
+    /// 64 MiB. By default, 128 KiB buffers are used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_chunk_size: Option<usize>,
+    /// Write uncompressed `raw` objects straight into their targets as
+    /// they are downloaded, without staging them on the download dir.
+    /// The checksum is verified once the object is written, so a
+    /// corrupted download leaves the targets dirty. By default, objects
+    /// are downloaded before being installed.
+    #[serde(default)]
+    pub stream_install: bool,
+    /// Keep the objects of an installed package on the download dir. By
+    /// default, they are removed once the package is installed.
+    #[serde(default)]
+    pub keep_downloads: bool,
+    /// Number of threads used to decompress objects hinted as
+    /// xz-compressed, which requires the `xz` tool. Other formats are
+    /// always decompressed in a single thread. By default, a single
+    /// thread is used.
+    #[serde(default = "default_decompress_threads")]
+    pub decompress_threads: usize,
+    /// Where runtime settings are persisted on read-only mode, as on a
+    /// tmpfs or an overlay. It is seeded from `runtime_settings` on its
+    /// first use. By default, runtime settings are not persisted on
+    /// read-only mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime_settings_overlay: Option<PathBuf>,
+    /// Maximum memory, in bytes, the decompression of an object may use.
+    /// Objects requiring more fail to install instead of exhausting the
+    /// device memory. It bounds zstd-compressed objects, and the ones
+    /// hinted as xz-compressed, which then require the `xz` tool. By
+    /// default, the decompressor's own limit is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decompress_memory_limit: Option<u64>,
+}
+
+fn default_decompress_threads() -> usize {
+    1
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
@@ -55,4 +530,94 @@ pub struct Storage {
 pub struct Update {
     pub download_dir: PathBuf,
     pub supported_install_modes: Vec<String>,
+    /// Free space, in bytes, kept on the download dir filesystem on top
+    /// of the objects being downloaded. By default, no margin is kept.
+    #[serde(default)]
+    pub download_space_margin: u64,
+    /// Maximum time the agent waits, when stopped, for the running
+    /// update step to reach a safe point before exiting.
+    #[serde(with = "serde_helpers::duration", default = "default_shutdown_timeout")]
+    pub shutdown_timeout: Duration,
+    /// Probes the server before resuming an update interrupted by a
+    /// restart, instead of resuming it right away, so a revoked or
+    /// changed package is not installed.
+    #[serde(default)]
+    pub revalidate_on_resume: bool,
+    /// Maximum time each state may take to be handled, by state name,
+    /// overriding the default ones. A zero duration disables the limit.
+    #[serde(
+        with = "serde_helpers::duration_map",
+        default,
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub state_timeouts: BTreeMap<String, Duration>,
+    /// Accept update metadata injected through the agent API, installed
+    /// as if it had been sent by the server. Meant for QA only, as it
+    /// bypasses the server. The signature is still validated when the
+    /// device has a public key. By default, injection is refused.
+    #[serde(default)]
+    pub allow_metadata_injection: bool,
+    /// Waits for the install of a downloaded update to be confirmed
+    /// through the agent API. By default, updates are installed as soon
+    /// as they are downloaded.
+    #[serde(default)]
+    pub require_install_confirmation: bool,
+    /// Maximum time to wait for the install to be confirmed, after which
+    /// `install_confirmation_timeout_action` is taken. By default, it
+    /// waits until the install is confirmed or the download aborted.
+    #[serde(
+        with = "serde_helpers::duration",
+        default = "Duration::zero",
+        skip_serializing_if = "Duration::is_zero"
+    )]
+    pub install_confirmation_timeout: Duration,
+    /// Action taken when the install is not confirmed in time. By
+    /// default, the update is aborted.
+    #[serde(default)]
+    pub install_confirmation_timeout_action: ConfirmationTimeoutAction,
+    /// Names of the states the agent pauses before handling, until it is
+    /// continued through the agent API. They are only honored when
+    /// `network.enable_debug_api` is set, as the agent otherwise waits
+    /// forever.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub debug_breakpoints: Vec<String>,
+    /// Number of update attempts kept in the runtime settings history,
+    /// the oldest ones are dropped once it is full.
+    #[serde(default = "default_history_size")]
+    pub history_size: usize,
+    /// Number of times an update failing for a transient cause, as a
+    /// network error, is retried right away before waiting for the next
+    /// poll. By default, it waits for the next poll.
+    #[serde(default)]
+    pub error_retries: u32,
+    /// Delay before each immediate retry, with up to 50% of jitter added.
+    #[serde(with = "serde_helpers::duration", default = "default_error_retry_delay")]
+    pub error_retry_delay: Duration,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfirmationTimeoutAction {
+    /// Installs the update as if it had been confirmed.
+    Install,
+    /// Aborts the update as failed, removing the downloaded objects.
+    Abort,
+}
+
+impl Default for ConfirmationTimeoutAction {
+    fn default() -> Self {
+        ConfirmationTimeoutAction::Abort
+    }
+}
+
+fn default_history_size() -> usize {
+    10
+}
+
+fn default_error_retry_delay() -> Duration {
+    Duration::seconds(10)
+}
+
+fn default_shutdown_timeout() -> Duration {
+    Duration::seconds(30)
 }
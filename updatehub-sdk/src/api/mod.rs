@@ -8,6 +8,22 @@
 /// Body of `info` response.
 pub mod info;
 
+/// Body of `version` response.
+pub mod version {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Response {
+        /// Version of the agent, as described by git.
+        pub version: String,
+        /// Git commit the agent was built from, or `unknown`.
+        pub git_commit: String,
+        pub build_time: DateTime<Utc>,
+    }
+}
+
 /// Body of `probe` request and response.
 pub mod probe {
     use serde::{Deserialize, Serialize};
@@ -18,6 +34,19 @@ pub mod probe {
         pub custom_server: String,
     }
 
+    /// Body of `probe/metadata` request, injecting update metadata as if
+    /// it had been sent by the server. It's only accepted when the
+    /// `allow_metadata_injection` setting is enabled.
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct MetadataRequest {
+        /// Update package metadata, as sent by the server.
+        pub metadata: String,
+        /// Base64 encoded signature of the metadata.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub signature: Option<String>,
+    }
+
     #[derive(Clone, Debug, Deserialize, Serialize)]
     #[serde(rename_all = "snake_case")]
     pub enum Response {
@@ -35,6 +64,14 @@ pub mod local_install {
     #[serde(deny_unknown_fields)]
     pub struct Request {
         pub file: std::path::PathBuf,
+        /// Expected sha256sum of the package file, verified before it's
+        /// extracted. It can't be given for an already extracted package.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub sha256sum: Option<String>,
+        /// Filenames of the package objects to be installed, skipping the
+        /// others. All the objects are installed when empty.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub objects: Vec<String>,
     }
 }
 
@@ -62,6 +99,8 @@ pub mod state {
         Probe,
         Validation,
         Download,
+        PausedDownload,
+        AwaitingApproval,
         Install,
         Reboot,
         DirectDownload,
@@ -70,7 +109,21 @@ pub mod state {
     }
 }
 
-/// Body of `abort_download` response.
+/// Body of each `events` stream entry.
+pub mod events {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Event {
+        pub state: String,
+        pub timestamp: DateTime<Utc>,
+    }
+}
+
+/// Body of `abort_download` response. The other requests on the update
+/// being handled reply with a [`message`] body.
 ///
 /// # Successful case
 ///
@@ -81,13 +134,62 @@ pub mod state {
 ///
 /// On a failed request, the body of response is a struct
 /// called `Refused` with a error message.
+///
+/// # Query
+///
+/// The abort and cancel requests accept the `Query` parameters, to
+/// remove the files staged by the aborted download or canceled update. The
+/// number of removed files is then confirmed in the `purged` field of the
+/// response.
 pub mod abort_download {
     use serde::{Deserialize, Serialize};
 
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Query {
+        #[serde(default)]
+        pub purge: bool,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Response {
+        pub message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub purged: Option<usize>,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Refused {
+        pub error: String,
+    }
+}
+
+/// Body of the `cancel_update`, `pause_download`, `resume_download`,
+/// `confirm_install` and `debug/continue` responses, and of the refused
+/// `polling` and `probe/metadata` requests.
+///
+/// # Successful case
+///
+/// On a successful request, the body of response is a struct
+/// called `Response` with a successful message. Canceling an update with
+/// the `purge` query parameter also confirms the number of removed files
+/// in its `purged` field.
+///
+/// # Failed case
+///
+/// On a failed request, the body of response is a struct
+/// called `Refused` with a error message.
+pub mod message {
+    use serde::{Deserialize, Serialize};
+
     #[derive(Clone, Debug, Deserialize, Serialize)]
     #[serde(deny_unknown_fields)]
     pub struct Response {
         pub message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub purged: Option<usize>,
     }
 
     #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -97,6 +199,185 @@ pub mod abort_download {
     }
 }
 
+/// Body of `installation_set` request and response.
+///
+/// # Request
+///
+/// The `PUT` request body is the action to be performed over the
+/// installation sets, either swapping the active one or validating the
+/// currently active set.
+///
+/// # Response
+///
+/// Both `GET` and a successful `PUT` respond with the `Response` struct
+/// describing the current state of the installation sets.
+pub mod installation_set {
+    use super::info::runtime_settings::InstallationSet;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Request {
+        Swap,
+        Validate,
+    }
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Response {
+        pub active: InstallationSet,
+        pub inactive: InstallationSet,
+        pub validated: bool,
+    }
+}
+
+/// Body of `rollback` response.
+///
+/// # Success case
+///
+/// On a successful request, the body of response is the `Response`
+/// struct with the installation set which will be active after the
+/// reboot.
+///
+/// # Failed case
+///
+/// On a failed request, the body of response is the current state of
+/// the agent.
+pub mod rollback {
+    use super::info::runtime_settings::InstallationSet;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Response {
+        pub active: InstallationSet,
+    }
+}
+
+/// Body of `polling` request and response.
+///
+/// # Request
+///
+/// The `POST` request body holds the polling interval to be used,
+/// overriding the configured one. Requesting the configured interval
+/// removes the override.
+///
+/// # Response
+///
+/// Both `GET` and a successful `POST` respond with the `Response` struct
+/// holding the effective polling interval. On a refused request, the
+/// body of response is the `abort_download::Refused` struct with the
+/// reason.
+pub mod polling {
+    use crate::serde_helpers;
+    use chrono::Duration;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Request {
+        #[serde(with = "serde_helpers::duration")]
+        pub interval: Duration,
+    }
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Response {
+        #[serde(with = "serde_helpers::duration")]
+        pub interval: Duration,
+        /// Whether the interval has been set through the agent API.
+        pub overridden: bool,
+    }
+}
+
+/// Body of `history` response.
+pub mod history {
+    use crate::api::info::runtime_settings::UpdateAttempt;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Response {
+        /// Last update attempts, oldest first.
+        pub entries: Vec<UpdateAttempt>,
+    }
+}
+
+/// Body of `debug/channels` response.
+pub mod debug {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Response {
+        /// Requests queued for the state machine.
+        pub communication: Channel,
+        /// Wake ups queued for the state machine.
+        pub waker: Channel,
+        /// Seconds since the state machine has last handled a state
+        /// successfully.
+        pub seconds_since_last_transition: i64,
+    }
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Channel {
+        /// Number of queued messages.
+        pub len: usize,
+        /// Maximum number of queued messages, if bounded.
+        pub capacity: Option<usize>,
+    }
+}
+
+/// Body of `selftest` request and response.
+///
+/// # Request
+///
+/// The `POST` request body holds the targets to be checked, using the same
+/// `target-type` and `target` fields of the update package objects. The
+/// body may be omitted, checking only the callbacks and directories.
+///
+/// # Response
+///
+/// The `Response` struct holds the outcome of each check run, and whether
+/// all of them have passed. Running a self-test never changes the state of
+/// the agent.
+pub mod selftest {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Request {
+        #[serde(default)]
+        pub targets: Vec<Target>,
+    }
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Target {
+        #[serde(rename = "target-type")]
+        pub target_type: String,
+        pub target: String,
+    }
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Response {
+        pub passed: bool,
+        pub checks: Vec<Check>,
+    }
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Check {
+        pub name: String,
+        pub passed: bool,
+        /// Reason of the failure, when the check hasn't passed.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub error: Option<String>,
+    }
+}
+
 /// Body of `log` response.
 pub mod log {
     use serde::{Deserialize, Serialize};
@@ -122,6 +403,15 @@ pub mod log {
         Trace,
     }
 
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Query {
+        /// Number of newest entries to return, instead of the whole
+        /// buffer.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub tail: Option<usize>,
+    }
+
     #[derive(Clone, Debug, Deserialize, Serialize)]
     #[serde(deny_unknown_fields)]
     pub struct Log {
@@ -11,6 +11,7 @@ use std::path::Path;
 pub struct Client {
     server_address: String,
     client: reqwest::Client,
+    api_token: Option<String>,
 }
 
 impl Default for Client {
@@ -18,6 +19,7 @@ impl Default for Client {
         Client {
             server_address: "http://localhost:8080".to_string(),
             client: reqwest::Client::new(),
+            api_token: None,
         }
     }
 }
@@ -28,6 +30,12 @@ impl Client {
         Client { server_address: format!("http://{}", server_address), ..Self::default() }
     }
 
+    /// Sends the token to the agent on the requests which require it, as
+    /// the ones changing the installation sets or rolling back.
+    pub fn with_api_token(self, api_token: &str) -> Self {
+        Client { api_token: Some(api_token.to_owned()), ..self }
+    }
+
     /// Get the current state of the agent.
     /// # Example
     ///
@@ -51,6 +59,29 @@ impl Client {
         }
     }
 
+    /// Get the version of the agent and when it was built.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.version().await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address or
+    /// cannot parse the body json as a `version::Response`.
+    pub async fn version(&self) -> Result<api::version::Response> {
+        let response = self.client.get(&format!("{}/version", self.server_address)).send().await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
     /// Probe the agent for update.
     /// # Example
     ///
@@ -83,9 +114,53 @@ impl Client {
         .send()
         .await?;
 
+        match response.status() {
+            StatusCode::OK | StatusCode::ACCEPTED => Ok(response.json().await?),
+            StatusCode::CONFLICT => Err(Error::AgentIsBusy(response.json().await?)),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Request agent to install an update from the given metadata, as if
+    /// it had been sent by the server. The agent must have the
+    /// `allow_metadata_injection` setting enabled.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let metadata = std::fs::read_to_string("/tmp/metadata.json")?;
+    ///
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.probe_with_metadata(&metadata, None).await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address,
+    /// the agent is busy, the metadata is refused or cannot parse the body
+    /// json as a `probe::Response`.
+    pub async fn probe_with_metadata(
+        &self,
+        metadata: &str,
+        signature: Option<&str>,
+    ) -> Result<api::probe::Response> {
+        let response = self
+            .client
+            .post(format!("{}/probe/metadata", self.server_address))
+            .json(&api::probe::MetadataRequest {
+                metadata: metadata.to_owned(),
+                signature: signature.map(str::to_owned),
+            })
+            .send()
+            .await?;
+
         match response.status() {
             StatusCode::OK => Ok(response.json().await?),
             StatusCode::NOT_ACCEPTABLE => Err(Error::AgentIsBusy(response.json().await?)),
+            StatusCode::BAD_REQUEST | StatusCode::FORBIDDEN => {
+                Err(Error::MetadataInjectionRefused(response.json().await?))
+            }
             s => Err(Error::UnexpectedResponse(s)),
         }
     }
@@ -108,10 +183,87 @@ impl Client {
     /// This method fails when cannot complete the request at the address or
     /// cannot parse the body json as a `state::Response`.
     pub async fn local_install(&self, file: &Path) -> Result<api::state::Response> {
+        self.request_local_install(api::local_install::Request {
+            file: file.to_owned(),
+            sha256sum: None,
+            objects: Vec::new(),
+        })
+        .await
+    }
+
+    /// Request agent to install a local package, verifying the whole file
+    /// against the expected sha256sum before extracting it.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let path = std::path::Path::new("/tmp/my-update-package.uhupkg");
+    /// let sha256sum = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    ///
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.local_install_with_checksum(path, sha256sum).await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address or
+    /// cannot parse the body json as a `state::Response`.
+    pub async fn local_install_with_checksum(
+        &self,
+        file: &Path,
+        sha256sum: &str,
+    ) -> Result<api::state::Response> {
+        self.request_local_install(api::local_install::Request {
+            file: file.to_owned(),
+            sha256sum: Some(sha256sum.to_owned()),
+            objects: Vec::new(),
+        })
+        .await
+    }
+
+    /// Request agent to install only some of the objects of a local
+    /// package, skipping the others, verifying the whole file against the
+    /// expected sha256sum, if given, before extracting it.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let path = std::path::Path::new("/tmp/my-update-package.uhupkg");
+    ///
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.local_install_objects(path, None, &["app.ext4"]).await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address or
+    /// cannot parse the body json as a `state::Response`.
+    pub async fn local_install_objects(
+        &self,
+        file: &Path,
+        sha256sum: Option<&str>,
+        objects: &[&str],
+    ) -> Result<api::state::Response> {
+        self.request_local_install(api::local_install::Request {
+            file: file.to_owned(),
+            sha256sum: sha256sum.map(str::to_owned),
+            objects: objects.iter().map(|o| (*o).to_owned()).collect(),
+        })
+        .await
+    }
+
+    async fn request_local_install(
+        &self,
+        request: api::local_install::Request,
+    ) -> Result<api::state::Response> {
         let response = self
             .client
             .post(&format!("{}/local_install", self.server_address))
-            .json(&api::local_install::Request { file: file.to_owned() })
+            .json(&request)
             .send()
             .await?;
 
@@ -178,6 +330,438 @@ impl Client {
         }
     }
 
+    /// Tells agent to abort the current download, removing the objects
+    /// it has staged, even partially. The response confirms how many
+    /// files were removed.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.abort_download_and_purge().await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address or
+    /// cannot parse the body json as a `abort_download::Response`.
+    pub async fn abort_download_and_purge(&self) -> Result<api::abort_download::Response> {
+        let response = self
+            .client
+            .post(&format!("{}/update/download/abort", self.server_address))
+            .query(&api::abort_download::Query { purge: true })
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            StatusCode::NOT_ACCEPTABLE => Err(Error::AbortDownloadRefused(response.json().await?)),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Tells agent to cancel the update which has been downloaded but
+    /// not installed yet, going back to idle.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.cancel_update().await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address or
+    /// cannot parse the body json as a `message::Response`.
+    pub async fn cancel_update(&self) -> Result<api::message::Response> {
+        let response =
+            self.client.post(&format!("{}/update/cancel", self.server_address)).send().await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            StatusCode::NOT_ACCEPTABLE => Err(Error::CancelUpdateRefused(response.json().await?)),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Tells agent to cancel the update which has been downloaded but
+    /// not installed yet, removing the objects it has staged. The
+    /// response confirms how many files were removed.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.cancel_update_and_purge().await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address or
+    /// cannot parse the body json as a `message::Response`.
+    pub async fn cancel_update_and_purge(&self) -> Result<api::message::Response> {
+        let response = self
+            .client
+            .post(&format!("{}/update/cancel", self.server_address))
+            .query(&api::abort_download::Query { purge: true })
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            StatusCode::NOT_ACCEPTABLE => Err(Error::CancelUpdateRefused(response.json().await?)),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Tells agent to pause the current download. Objects already
+    /// downloaded, even partially, are kept so the download can be resumed.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.pause_download().await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address or
+    /// cannot parse the body json as a `message::Response`.
+    pub async fn pause_download(&self) -> Result<api::message::Response> {
+        let response = self
+            .client
+            .post(&format!("{}/update/download/pause", self.server_address))
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            StatusCode::NOT_ACCEPTABLE => Err(Error::PauseDownloadRefused(response.json().await?)),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Tells agent to resume a paused download.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.resume_download().await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address or
+    /// cannot parse the body json as a `message::Response`.
+    pub async fn resume_download(&self) -> Result<api::message::Response> {
+        let response = self
+            .client
+            .post(&format!("{}/update/download/resume", self.server_address))
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            StatusCode::NOT_ACCEPTABLE => Err(Error::ResumeDownloadRefused(response.json().await?)),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Tells agent to install the downloaded update which is awaiting
+    /// confirmation.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.confirm_install().await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address or
+    /// cannot parse the body json as a `message::Response`.
+    pub async fn confirm_install(&self) -> Result<api::message::Response> {
+        let response = self
+            .client
+            .post(&format!("{}/update/install/confirm", self.server_address))
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            StatusCode::NOT_ACCEPTABLE => Err(Error::ConfirmInstallRefused(response.json().await?)),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Tells agent to continue from the debugging breakpoint it is paused
+    /// at.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.debug_continue().await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address or
+    /// cannot parse the body json as a `message::Response`.
+    pub async fn debug_continue(&self) -> Result<api::message::Response> {
+        let response =
+            self.client.post(&format!("{}/debug/continue", self.server_address)).send().await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            StatusCode::NOT_ACCEPTABLE => Err(Error::DebugContinueRefused(response.json().await?)),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Get the current active and inactive installation sets.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.installation_set().await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address or
+    /// cannot parse the body json as a `installation_set::Response`.
+    pub async fn installation_set(&self) -> Result<api::installation_set::Response> {
+        let response =
+            self.client.get(&format!("{}/installation-set", self.server_address)).send().await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Request agent to swap the active installation set or to validate
+    /// the current one.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// use updatehub_sdk::api::installation_set::Request;
+    ///
+    /// let client = updatehub_sdk::Client::default().with_api_token("token");
+    /// let response = client.set_installation_set(Request::Swap).await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address,
+    /// the API token is missing or invalid, the agent is busy or cannot
+    /// parse the body json as a `installation_set::Response`.
+    pub async fn set_installation_set(
+        &self,
+        request: api::installation_set::Request,
+    ) -> Result<api::installation_set::Response> {
+        let mut request =
+            self.client.put(&format!("{}/installation-set", self.server_address)).json(&request);
+        if let Some(api_token) = &self.api_token {
+            request = request.bearer_auth(api_token);
+        }
+        let response = request.send().await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(Error::Unauthorized),
+            StatusCode::NOT_ACCEPTABLE => Err(Error::AgentIsBusy(response.json().await?)),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Request agent to roll back to the inactive installation set and
+    /// reboot into it.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let client = updatehub_sdk::Client::default().with_api_token("token");
+    /// let response = client.rollback().await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address,
+    /// the API token is missing or invalid, the agent is busy or cannot
+    /// parse the body json as a `rollback::Response`.
+    pub async fn rollback(&self) -> Result<api::rollback::Response> {
+        let mut request = self.client.post(&format!("{}/rollback", self.server_address));
+        if let Some(api_token) = &self.api_token {
+            request = request.bearer_auth(api_token);
+        }
+        let response = request.send().await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(Error::Unauthorized),
+            StatusCode::NOT_ACCEPTABLE => Err(Error::AgentIsBusy(response.json().await?)),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Get the effective polling interval of the agent.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.polling().await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address or
+    /// cannot parse the body json as a `polling::Response`.
+    pub async fn polling(&self) -> Result<api::polling::Response> {
+        let response = self.client.get(&format!("{}/polling", self.server_address)).send().await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Get the last update attempts recorded by the agent.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.history().await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address or
+    /// cannot parse the body json as a `history::Response`.
+    pub async fn history(&self) -> Result<api::history::Response> {
+        let response = self.client.get(&format!("{}/history", self.server_address)).send().await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Get the depth of the agent internal channels and the time since its
+    /// last state transition, when the debug API is enabled.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.debug_channels().await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address, the
+    /// debug API is disabled or cannot parse the body json as a
+    /// `debug::Response`.
+    pub async fn debug_channels(&self) -> Result<api::debug::Response> {
+        let response =
+            self.client.get(&format!("{}/debug/channels", self.server_address)).send().await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Request agent to use a new polling interval, overriding the
+    /// configured one.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.set_polling(chrono::Duration::hours(6)).await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address,
+    /// the interval is refused or cannot parse the body json as a
+    /// `polling::Response`.
+    pub async fn set_polling(&self, interval: chrono::Duration) -> Result<api::polling::Response> {
+        let response = self
+            .client
+            .post(&format!("{}/polling", self.server_address))
+            .json(&api::polling::Request { interval })
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            StatusCode::BAD_REQUEST => Err(Error::PollingIntervalRefused(response.json().await?)),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Request agent to run the commissioning self-test, checking the
+    /// given targets along with the firmware callbacks and the
+    /// directories used during an update.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// use updatehub_sdk::api::selftest::Target;
+    ///
+    /// let client = updatehub_sdk::Client::default();
+    /// let target =
+    ///     Target { target_type: "device".to_owned(), target: "/dev/mmcblk0p2".to_owned() };
+    /// let response = client.selftest(vec![target]).await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address or
+    /// cannot parse the body json as a `selftest::Response`.
+    pub async fn selftest(
+        &self,
+        targets: Vec<api::selftest::Target>,
+    ) -> Result<api::selftest::Response> {
+        let response = self
+            .client
+            .post(&format!("{}/selftest", self.server_address))
+            .json(&api::selftest::Request { targets })
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
+
     /// Get the available log entries for the last update.
     /// # Example
     ///
@@ -200,4 +784,32 @@ impl Client {
             s => Err(Error::UnexpectedResponse(s)),
         }
     }
+
+    /// Get only the newest `lines` log entries for the last update.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> updatehub_sdk::Result<()> {
+    /// let client = updatehub_sdk::Client::default();
+    /// let response = client.log_tail(100).await?;
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This method fails when cannot complete the request at the address or
+    /// cannot parse the body json as a `log::Log`.
+    pub async fn log_tail(&self, lines: usize) -> Result<api::log::Log> {
+        let response = self
+            .client
+            .get(&format!("{}/log", self.server_address))
+            .query(&api::log::Query { tail: Some(lines) })
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json().await?),
+            s => Err(Error::UnexpectedResponse(s)),
+        }
+    }
 }
@@ -16,6 +16,37 @@ pub enum Error {
     #[display(fmt = "Abort download was refused: {:?}", _0)]
     AbortDownloadRefused(#[error(not(source))] crate::api::abort_download::Refused),
 
+    #[display(fmt = "Update cancel was refused: {:?}", _0)]
+    #[from(ignore)]
+    CancelUpdateRefused(#[error(not(source))] crate::api::message::Refused),
+
+    #[display(fmt = "Pause download was refused: {:?}", _0)]
+    #[from(ignore)]
+    PauseDownloadRefused(#[error(not(source))] crate::api::message::Refused),
+
+    #[display(fmt = "Resume download was refused: {:?}", _0)]
+    #[from(ignore)]
+    ResumeDownloadRefused(#[error(not(source))] crate::api::message::Refused),
+
+    #[display(fmt = "Install confirmation was refused: {:?}", _0)]
+    #[from(ignore)]
+    ConfirmInstallRefused(#[error(not(source))] crate::api::message::Refused),
+
+    #[display(fmt = "Debug continue was refused: {:?}", _0)]
+    #[from(ignore)]
+    DebugContinueRefused(#[error(not(source))] crate::api::message::Refused),
+
+    #[display(fmt = "Polling interval was refused: {:?}", _0)]
+    #[from(ignore)]
+    PollingIntervalRefused(#[error(not(source))] crate::api::message::Refused),
+
+    #[display(fmt = "Metadata injection was refused: {:?}", _0)]
+    #[from(ignore)]
+    MetadataInjectionRefused(#[error(not(source))] crate::api::message::Refused),
+
+    #[display(fmt = "Request was not authorized, the API token is missing or invalid")]
+    Unauthorized,
+
     #[display(fmt = "Unexpected response: {:?}", _0)]
     UnexpectedResponse(#[error(not(source))] reqwest::StatusCode),
 
@@ -8,9 +8,13 @@
 //! for communicating with UpdateHub:
 //!
 //! - [abort_download](Client::abort_download)
+//! - [cancel_update](Client::cancel_update)
+//! - [debug_channels](Client::debug_channels)
 //! - [info](Client::info)
 //! - [local_install](Client::local_install)
+//! - [local_install_objects](Client::local_install_objects)
 //! - [log](Client::log)
+//! - [log_tail](Client::log_tail)
 //! - [probe](Client::probe)
 //! - [remote_install](Client::remote_install)
 
@@ -23,3 +23,39 @@ pub(crate) mod duration {
         Ok(Duration::milliseconds(ms(s).map_err(de::Error::custom)?))
     }
 }
+
+pub(crate) mod duration_map {
+    use chrono::Duration;
+    use serde::{de, ser::SerializeMap, Deserialize, Deserializer, Serializer};
+    use std::collections::BTreeMap;
+
+    pub(crate) fn serialize<S>(
+        v: &BTreeMap<String, Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(v.len()))?;
+        for (key, value) in v {
+            map.serialize_entry(key, &format!("{}s", value.num_seconds()))?;
+        }
+        map.end()
+    }
+
+    pub(crate) fn deserialize<'de, D>(
+        deserializer: D,
+    ) -> Result<BTreeMap<String, Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use ms_converter::ms;
+
+        BTreeMap::<String, String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(key, value)| {
+                Ok((key, Duration::milliseconds(ms(value).map_err(de::Error::custom)?)))
+            })
+            .collect()
+    }
+}
@@ -78,6 +78,15 @@ async fn info() {
     assert!(dbg!(response).is_ok());
 }
 
+#[tokio::test]
+async fn version() {
+    let mock = MockServer::new();
+    let (addr, _guard) = &mock.start();
+    let client = sdk::Client::new(addr);
+    let response = client.version().await;
+    assert!(dbg!(response).is_ok());
+}
+
 #[tokio::test]
 async fn probe_default() {
     let mock = MockServer::new();
@@ -104,6 +113,20 @@ async fn probe_custom() {
     }
 }
 
+#[tokio::test]
+async fn probe_with_metadata() {
+    let mock = MockServer::new();
+    let (addr, _guard) = &mock.start();
+    let client = sdk::Client::new(addr);
+    let response = client.probe_with_metadata("{}", None).await;
+    match dbg!(response) {
+        Ok(_) => {}
+        Err(sdk::Error::AgentIsBusy(_)) => {}
+        Err(sdk::Error::MetadataInjectionRefused(_)) => {}
+        Err(e) => panic!("Unexpected Error response: {}", e),
+    }
+}
+
 #[tokio::test]
 async fn local_install() {
     let mock = MockServer::new();
@@ -145,6 +168,119 @@ async fn abort_download() {
     }
 }
 
+#[tokio::test]
+async fn cancel_update() {
+    let mock = MockServer::new();
+    let (addr, _guard) = &mock.start();
+    let client = sdk::Client::new(addr);
+    let response = client.cancel_update().await;
+    match dbg!(response) {
+        Ok(_) => {}
+        Err(sdk::Error::CancelUpdateRefused(_)) => {}
+        Err(e) => panic!("Unexpected Error response: {}", e),
+    }
+}
+
+#[tokio::test]
+async fn pause_download() {
+    let mock = MockServer::new();
+    let (addr, _guard) = &mock.start();
+    let client = sdk::Client::new(addr);
+    let response = client.pause_download().await;
+    match dbg!(response) {
+        Ok(_) => {}
+        Err(sdk::Error::PauseDownloadRefused(_)) => {}
+        Err(e) => panic!("Unexpected Error response: {}", e),
+    }
+}
+
+#[tokio::test]
+async fn resume_download() {
+    let mock = MockServer::new();
+    let (addr, _guard) = &mock.start();
+    let client = sdk::Client::new(addr);
+    let response = client.resume_download().await;
+    match dbg!(response) {
+        Ok(_) => {}
+        Err(sdk::Error::ResumeDownloadRefused(_)) => {}
+        Err(e) => panic!("Unexpected Error response: {}", e),
+    }
+}
+
+#[tokio::test]
+async fn confirm_install() {
+    let mock = MockServer::new();
+    let (addr, _guard) = &mock.start();
+    let client = sdk::Client::new(addr);
+    let response = client.confirm_install().await;
+    match dbg!(response) {
+        Ok(_) => {}
+        Err(sdk::Error::ConfirmInstallRefused(_)) => {}
+        Err(e) => panic!("Unexpected Error response: {}", e),
+    }
+}
+
+#[tokio::test]
+async fn debug_continue() {
+    let mock = MockServer::new();
+    let (addr, _guard) = &mock.start();
+    let client = sdk::Client::new(addr);
+    let response = client.debug_continue().await;
+    match dbg!(response) {
+        Ok(_) => {}
+        Err(sdk::Error::DebugContinueRefused(_)) => {}
+        Err(e) => panic!("Unexpected Error response: {}", e),
+    }
+}
+
+#[tokio::test]
+async fn installation_set() {
+    let mock = MockServer::new();
+    let (addr, _guard) = &mock.start();
+    let client = sdk::Client::new(addr);
+    let response = client.installation_set().await;
+    assert!(dbg!(response).is_ok());
+}
+
+#[tokio::test]
+async fn set_installation_set() {
+    let mock = MockServer::new();
+    let (addr, _guard) = &mock.start();
+    let client = sdk::Client::new(addr);
+    let response = client.set_installation_set(sdk::api::installation_set::Request::Validate).await;
+    match dbg!(response) {
+        Ok(_) => {}
+        Err(sdk::Error::AgentIsBusy(_)) => {}
+        Err(e) => panic!("Unexpected Error response: {}", e),
+    }
+}
+
+#[tokio::test]
+async fn rollback() {
+    let mock = MockServer::new();
+    let (addr, _guard) = &mock.start();
+    let client = sdk::Client::new(addr);
+    let response = client.rollback().await;
+    match dbg!(response) {
+        Ok(_) => {}
+        Err(sdk::Error::AgentIsBusy(_)) => {}
+        Err(e) => panic!("Unexpected Error response: {}", e),
+    }
+}
+
+#[tokio::test]
+async fn selftest() {
+    let mock = MockServer::new();
+    let (addr, _guard) = &mock.start();
+    let client = sdk::Client::new(addr);
+    let target = sdk::api::selftest::Target {
+        target_type: "device".to_owned(),
+        target: "/dev/mmcblk0p2".to_owned(),
+    };
+    let response = client.selftest(vec![target]).await;
+    assert!(dbg!(response).is_ok());
+}
+
 #[tokio::test]
 async fn log() {
     let mock = MockServer::new();
@@ -153,3 +289,30 @@ async fn log() {
     let response = client.log().await;
     assert!(dbg!(response).is_ok());
 }
+
+#[tokio::test]
+async fn history() {
+    let mock = MockServer::new();
+    let (addr, _guard) = &mock.start();
+    let client = sdk::Client::new(addr);
+    let response = client.history().await;
+    assert!(dbg!(response).is_ok());
+}
+
+#[tokio::test]
+async fn debug_channels() {
+    let mock = MockServer::new();
+    let (addr, _guard) = &mock.start();
+    let client = sdk::Client::new(addr);
+    let response = client.debug_channels().await;
+    assert!(dbg!(response).is_ok());
+}
+
+#[tokio::test]
+async fn log_tail() {
+    let mock = MockServer::new();
+    let (addr, _guard) = &mock.start();
+    let client = sdk::Client::new(addr);
+    let response = client.log_tail(10).await;
+    assert!(dbg!(response).is_ok());
+}
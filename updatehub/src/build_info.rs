@@ -17,3 +17,21 @@
 pub fn version() -> &'static str {
     env!("VERSION")
 }
+
+/// Returns the git commit the agent was built from, or `unknown` when
+/// built outside of a git checkout.
+pub(crate) fn git_commit() -> &'static str {
+    env!("GIT_COMMIT")
+}
+
+/// Returns the time the agent was built at. It honors `SOURCE_DATE_EPOCH`
+/// so reproducible builds get a fixed time.
+pub(crate) fn build_time() -> chrono::DateTime<chrono::Utc> {
+    use chrono::TimeZone;
+
+    env!("BUILD_TIMESTAMP")
+        .parse()
+        .ok()
+        .and_then(|secs| chrono::Utc.timestamp_opt(secs, 0).single())
+        .unwrap_or_else(|| chrono::Utc.timestamp_opt(0, 0).unwrap())
+}
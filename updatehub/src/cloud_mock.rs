@@ -13,11 +13,20 @@ std::thread_local! {
     static OBJECT_DATA: RefCell<Option<Vec<u8>>> = RefCell::new(Option::None);
 }
 
+std::thread_local! {
+    static UNREACHABLE_URLS: RefCell<Vec<String>> = RefCell::new(Vec::new());
+}
+
+std::thread_local! {
+    static FAILING_DOWNLOADS: RefCell<usize> = RefCell::new(0);
+}
+
 pub(crate) enum FakeResponse {
     NoUpdate,
     HasUpdate,
     ExtraPoll,
     InvalidUri,
+    ServerError,
 }
 
 pub(crate) struct Client<'a> {
@@ -32,11 +41,56 @@ pub(crate) fn set_download_data(data: Vec<u8>) {
     OBJECT_DATA.with(|conf| conf.borrow_mut().replace(data));
 }
 
+pub(crate) fn set_unreachable_urls(urls: Vec<String>) {
+    UNREACHABLE_URLS.with(|conf| conf.replace(urls));
+}
+
+/// Makes the next `count` downloads fail as if the server was
+/// temporarily unavailable.
+pub(crate) fn set_failing_downloads(count: usize) {
+    FAILING_DOWNLOADS.with(|conf| conf.replace(count));
+}
+
 impl<'a> Client<'a> {
-    pub(crate) fn new(_server: &'a str) -> Self {
+    pub(crate) fn new(
+        _server: &'a str,
+        _identity: Option<&cloud::ClientIdentity>,
+        _proxy: Option<&cloud::Proxy>,
+    ) -> Self {
         Self { _phantom: PhantomData }
     }
 
+    pub(crate) fn with_headers(self, _headers: &cloud::Headers) -> Self {
+        self
+    }
+
+    pub(crate) fn with_host_aliases(self, _host_aliases: &cloud::HostAliases) -> Self {
+        self
+    }
+
+    pub(crate) fn with_rate_limiter(self, _rate_limiter: Option<cloud::RateLimiter>) -> Self {
+        self
+    }
+
+    pub(crate) fn with_report_compression(self, _compress_reports: bool) -> Self {
+        self
+    }
+
+    pub(crate) fn with_probe_timeout(self, _probe_timeout: Option<std::time::Duration>) -> Self {
+        self
+    }
+
+    pub(crate) fn with_download_timeout(
+        self,
+        _download_timeout: Option<std::time::Duration>,
+    ) -> Self {
+        self
+    }
+
+    pub(crate) fn with_strict_content_length(self, _strict_content_length: bool) -> Self {
+        self
+    }
+
     pub(crate) async fn probe(
         &self,
         _num_retries: usize,
@@ -53,31 +107,89 @@ impl<'a> Client<'a> {
                 let uri_error = url::Url::parse("http://foo:--").unwrap_err();
                 Err(Error::UrlParse(uri_error))
             }
+            FakeResponse::ServerError => {
+                Err(Error::InvalidStatusResponse(reqwest::StatusCode::SERVICE_UNAVAILABLE))
+            }
         })
     }
 
-    pub(crate) async fn download_object(
+    pub(crate) fn object_url(&self, product_uid: &str, package_uid: &str, object: &str) -> String {
+        format!(
+            "http://localhost/products/{}/packages/{}/objects/{}",
+            product_uid, package_uid, object
+        )
+    }
+
+    pub(crate) async fn download_object_from(
         &self,
-        _product_uid: &str,
-        _package_uid: &str,
+        url: &str,
         download_dir: &Path,
         object: &str,
+        _size: u64,
+        progress: Option<cloud::ProgressHandler<'_>>,
     ) -> Result<()> {
+        if UNREACHABLE_URLS.with(|urls| urls.borrow().iter().any(|u| u == url))
+            || FAILING_DOWNLOADS.with(|count| {
+                let mut count = count.borrow_mut();
+                let failing = *count > 0;
+                *count = count.saturating_sub(1);
+                failing
+            })
+        {
+            return Err(Error::InvalidStatusResponse(reqwest::StatusCode::SERVICE_UNAVAILABLE));
+        }
+
         if let Some(data) = OBJECT_DATA.with(|conf| conf.borrow_mut().take()) {
-            tokio::fs::write(download_dir.join(object), data).await?
+            let len = data.len() as u64;
+            tokio::fs::write(download_dir.join(object), data).await?;
+            if let Some(progress) = progress {
+                progress(len);
+            }
         }
 
         Ok(())
     }
 
-    pub(crate) async fn report(
+    pub(crate) async fn stream_object_from<W>(
+        &self,
+        _url: &str,
+        _size: u64,
+        handle: &mut W,
+        _progress: Option<cloud::ProgressHandler<'_>>,
+    ) -> Result<()>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        use tokio::io::AsyncWriteExt;
+
+        if FAILING_DOWNLOADS.with(|count| {
+            let mut count = count.borrow_mut();
+            let failing = *count > 0;
+            *count = count.saturating_sub(1);
+            failing
+        }) {
+            return Err(Error::InvalidStatusResponse(reqwest::StatusCode::SERVICE_UNAVAILABLE));
+        }
+
+        if let Some(data) = OBJECT_DATA.with(|conf| conf.borrow_mut().take()) {
+            handle.write_all(&data).await?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn send_report(
+        &self,
+        _firmware: api::FirmwareMetadata<'_>,
+        _report: &api::Report,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    pub(crate) async fn report_batch(
         &self,
-        _state: &str,
         _firmware: api::FirmwareMetadata<'_>,
-        _package_uid: &str,
-        _previous_state: Option<&str>,
-        _error_message: Option<String>,
-        _current_log: Option<String>,
+        _reports: &[api::Report],
     ) -> Result<()> {
         Ok(())
     }
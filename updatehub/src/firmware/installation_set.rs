@@ -13,6 +13,24 @@ const VALIDATE_SCRIPT: &str = "updatehub-active-validated";
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 pub struct Set(pub InstallationSet);
 
+impl Set {
+    /// Gets the other installation set.
+    pub fn complement(self) -> Set {
+        match self {
+            Set(InstallationSet::A) => Set(InstallationSet::B),
+            Set(InstallationSet::B) => Set(InstallationSet::A),
+        }
+    }
+
+    /// Gets the position of the set on per set lists.
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Set(InstallationSet::A) => 0,
+            Set(InstallationSet::B) => 1,
+        }
+    }
+}
+
 impl FromStr for Set {
     type Err = super::Error;
 
@@ -25,6 +43,18 @@ impl FromStr for Set {
     }
 }
 
+impl TryFrom<u8> for Set {
+    type Error = super::Error;
+
+    fn try_from(set: u8) -> super::Result<Self> {
+        match set {
+            0 => Ok(Set(InstallationSet::A)),
+            1 => Ok(Set(InstallationSet::B)),
+            _ => Err(super::Error::InvalidInstallSet),
+        }
+    }
+}
+
 impl fmt::Display for Set {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -43,9 +73,22 @@ pub fn active() -> super::Result<Set> {
 }
 
 pub fn inactive() -> super::Result<Set> {
-    match active()? {
-        Set(InstallationSet::A) => Ok(Set(InstallationSet::B)),
-        Set(InstallationSet::B) => Ok(Set(InstallationSet::A)),
+    Ok(active()?.complement())
+}
+
+/// Gets the set an update is installed into. Updates go to the inactive
+/// set unless a set is pinned, which must not be the active one.
+pub fn target(pinned: Option<Set>) -> super::Result<Set> {
+    target_from(active()?, pinned)
+}
+
+/// Gets the set an update is installed into while `active` is the active
+/// set, without running the firmware scripts.
+pub(crate) fn target_from(active: Set, pinned: Option<Set>) -> super::Result<Set> {
+    let inactive = active.complement();
+    match pinned {
+        Some(set) if set != inactive => Err(super::Error::PinnedInstallSetIsActive(set)),
+        _ => Ok(inactive),
     }
 }
 
@@ -102,4 +145,10 @@ fn works() {
     assert_eq!(active().unwrap(), Set(InstallationSet::B));
     assert_eq!(inactive().unwrap(), Set(InstallationSet::A));
     assert!(swap_active().is_ok());
+
+    // A pinned installation set is only accepted when it is not the
+    // active one.
+    assert_eq!(target(None).unwrap(), Set(InstallationSet::A));
+    assert_eq!(target(Some(Set(InstallationSet::A))).unwrap(), Set(InstallationSet::A));
+    assert!(target(Some(Set(InstallationSet::B))).is_err());
 }
@@ -9,9 +9,11 @@ pub mod installation_set;
 pub mod tests;
 
 use self::hook::{run_hook, run_hooks_from_dir};
+use chrono::Duration;
 use derive_more::{Deref, DerefMut, Display, Error, From};
 pub use sdk::api::info::firmware as api;
-use slog_scope::{error, info};
+use sdk::api::info::settings::MissingCallbackPolicy;
+use slog_scope::{error, info, warn};
 use std::{io, path::Path};
 
 const PRODUCT_UID_HOOK: &str = "product-uid";
@@ -23,6 +25,8 @@ const DEVICE_ATTRIBUTES_DIR: &str = "device-attributes.d";
 const STATE_CHANGE_CALLBACK: &str = "state-change-callback";
 const VALIDATE_CALLBACK: &str = "validate-callback";
 const ROLLBACK_CALLBACK: &str = "rollback-callback";
+const VERSION_COMPARE_CALLBACK: &str = "version-compare-callback";
+const PRE_INSTALL_CALLBACK: &str = "pre-install-callback";
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -40,6 +44,26 @@ pub enum Error {
     #[display(fmt = "invalid installation set, the only know ones are 0 or 1")]
     InvalidInstallSet,
 
+    #[display(fmt = "pinned installation set {} is the active one", _0)]
+    PinnedInstallSetIsActive(#[error(not(source))] installation_set::Set),
+
+    #[display(fmt = "validate callback is missing")]
+    MissingValidateCallback,
+
+    #[display(fmt = "version compare callback is missing")]
+    MissingVersionCompareCallback,
+
+    #[display(fmt = "callback {:?} is not executable", _0)]
+    #[from(ignore)]
+    CallbackNotExecutable(#[error(not(source))] std::path::PathBuf),
+
+    #[display(fmt = "invalid public key: {}", _0)]
+    InvalidPublicKey(openssl::error::ErrorStack),
+
+    #[display(fmt = "no public key found in {:?}", _0)]
+    #[from(ignore)]
+    NoPublicKeys(#[error(not(source))] std::path::PathBuf),
+
     Walkdir(walkdir::Error),
 
     Io(std::io::Error),
@@ -89,6 +113,20 @@ impl Metadata {
         Ok(metadata)
     }
 
+    /// Uses the public key at `path` for validating the update package
+    /// signature, ensuring it can be loaded beforehand. When `path` is a
+    /// directory, every key in it is used, so keys can be rotated.
+    pub fn load_public_key(&mut self, path: &Path) -> Result<()> {
+        for key in public_keys(path)? {
+            openssl::pkey::PKey::public_key_from_pem(&std::fs::read(&key)?).map_err(|e| {
+                error!("failed to load public key from {:?}", key);
+                Error::InvalidPublicKey(e)
+            })?;
+        }
+        self.pub_key = Some(path.to_owned());
+        Ok(())
+    }
+
     pub(crate) fn as_cloud_metadata(&self) -> cloud::api::FirmwareMetadata<'_> {
         cloud::api::FirmwareMetadata {
             product_uid: &self.0.product_uid,
@@ -100,7 +138,25 @@ impl Metadata {
     }
 }
 
-pub(crate) fn state_change_callback(path: &Path, state: &str) -> Result<Transition> {
+/// Lists the public keys at `path`, which is either a key or a directory
+/// of keys, sorted by their name.
+pub(crate) fn public_keys(path: &Path) -> Result<Vec<std::path::PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_owned()]);
+    }
+
+    let mut keys = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .filter(|path| path.as_ref().map_or(true, |p| p.is_file()))
+        .collect::<io::Result<Vec<_>>>()?;
+    if keys.is_empty() {
+        return Err(Error::NoPublicKeys(path.to_owned()));
+    }
+    keys.sort();
+    Ok(keys)
+}
+
+pub(crate) async fn state_change_callback(path: &Path, state: &str) -> Result<Transition> {
     let callback = path.join(STATE_CHANGE_CALLBACK);
     if !callback.exists() {
         return Ok(Transition::Continue);
@@ -108,29 +164,71 @@ pub(crate) fn state_change_callback(path: &Path, state: &str) -> Result<Transiti
 
     info!("running state change callback for '{}' state", state);
 
-    let output = run_command_for_state(
-        &format!("{} callback", state),
-        &format!("{} {}", &callback.to_string_lossy(), &state),
-    )?;
+    let output =
+        run_callback_for_state(&format!("{} callback", state), &callback, &[state]).await?;
 
+    requested_transition(&output, STATE_CHANGE_CALLBACK, state)
+}
+
+/// Runs the pre-install callback, once the update is ready to be
+/// installed, with the package UID and the filenames of the objects about
+/// to be installed as arguments.
+pub(crate) async fn pre_install_callback(
+    path: &Path,
+    package_uid: &str,
+    objects: &[&str],
+) -> Result<Transition> {
+    let callback = path.join(PRE_INSTALL_CALLBACK);
+    if !callback.exists() {
+        return Ok(Transition::Continue);
+    }
+
+    info!("running pre-install callback for package {}", package_uid);
+
+    let args = std::iter::once(package_uid).chain(objects.iter().copied()).collect::<Vec<_>>();
+    let output = run_callback_for_state("pre-install callback", &callback, &args).await?;
+
+    requested_transition(&output, PRE_INSTALL_CALLBACK, "install")
+}
+
+/// Parses the transition requested by a callback, which prints `cancel`
+/// to cancel it or nothing to continue.
+fn requested_transition(
+    output: &easy_process::Output,
+    callback: &str,
+    state: &str,
+) -> Result<Transition> {
     match output.stdout.trim() {
         "cancel" => Ok(Transition::Cancel),
         "" => Ok(Transition::Continue),
         _ => Err(io::Error::new(
             io::ErrorKind::InvalidInput,
-            format!(
-                "invalid output format from 'state-change-callback' hook for state '{}'",
-                &state
-            ),
+            format!("invalid output format from '{}' hook for state '{}'", callback, state),
         )
         .into()),
     }
 }
 
-pub(crate) fn validate_callback(path: &Path) -> Result<Transition> {
+pub(crate) fn validate_callback(
+    path: &Path,
+    on_missing: MissingCallbackPolicy,
+) -> Result<Transition> {
     let callback = path.join(VALIDATE_CALLBACK);
     if !callback.exists() {
-        return Ok(Transition::Continue);
+        return match on_missing {
+            MissingCallbackPolicy::Validate => {
+                warn!("validate callback is missing, validating the installation");
+                Ok(Transition::Continue)
+            }
+            MissingCallbackPolicy::Rollback => {
+                warn!("validate callback is missing, rolling back the installation");
+                Ok(Transition::Cancel)
+            }
+            MissingCallbackPolicy::Error => {
+                error!("validate callback is missing, leaving the installation unconfirmed");
+                Err(Error::MissingValidateCallback)
+            }
+        };
     }
 
     info!("running validate callback");
@@ -163,8 +261,127 @@ pub(crate) fn rollback_callback(path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Compares the `target` version against the `current` one using the
+/// version compare callback.
+pub(crate) fn version_compare_callback(
+    path: &Path,
+    current: &str,
+    target: &str,
+) -> Result<std::cmp::Ordering> {
+    use std::cmp::Ordering;
+
+    let callback = path.join(VERSION_COMPARE_CALLBACK);
+    if !callback.exists() {
+        error!("version compare callback is missing");
+        return Err(Error::MissingVersionCompareCallback);
+    }
+
+    let output = run_command_for_state(
+        "version compare callback",
+        &format!("{} {} {}", &callback.to_string_lossy(), current, target),
+    )?;
+
+    match output.stdout.trim() {
+        "newer" => Ok(Ordering::Greater),
+        "equal" => Ok(Ordering::Equal),
+        "older" => Ok(Ordering::Less),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid output format from 'version-compare-callback' hook",
+        )
+        .into()),
+    }
+}
+
+/// Checks the firmware callbacks can be run, without running them.
+/// Missing callbacks are skipped, unless the validate one is required by
+/// the `on_missing` policy.
+pub(crate) fn check_callbacks(
+    path: &Path,
+    on_missing: MissingCallbackPolicy,
+) -> Vec<(&'static str, Result<()>)> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let is_executable = |m: &std::fs::Metadata| m.is_file() && m.permissions().mode() & 0o111 != 0;
+    [STATE_CHANGE_CALLBACK, VALIDATE_CALLBACK, ROLLBACK_CALLBACK]
+        .into_iter()
+        .map(|name| {
+            let callback = path.join(name);
+            let is_required =
+                name == VALIDATE_CALLBACK && on_missing == MissingCallbackPolicy::Error;
+            let res = match callback.metadata() {
+                Ok(metadata) if is_executable(&metadata) => Ok(()),
+                Ok(_) => Err(Error::CallbackNotExecutable(callback)),
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+                Err(_) if is_required => Err(Error::MissingValidateCallback),
+                Err(_) => Ok(()),
+            };
+            (name, res)
+        })
+        .collect()
+}
+
+/// Runs the health check command up to `attempts` times, waiting
+/// `interval` between them, until it succeeds.
+pub(crate) async fn health_check(command: &str, attempts: usize, interval: Duration) -> Transition {
+    for attempt in 1..=attempts {
+        info!("running health check (attempt {} of {})", attempt, attempts);
+        if run_command_for_state("health check", command).is_ok() {
+            return Transition::Continue;
+        }
+
+        if attempt < attempts {
+            tokio::time::sleep(interval.to_std().unwrap_or_default()).await;
+        }
+    }
+
+    warn!("health check has failed after {} attempts", attempts);
+    Transition::Cancel
+}
+
 fn run_command_for_state(name: &str, cmd: &str) -> Result<easy_process::Output> {
-    match easy_process::run(cmd) {
+    check_output_for_state(name, easy_process::run(cmd))
+}
+
+/// Runs the callback as a child process which is killed once the returned
+/// future is dropped, so a callback which hangs is stopped along with the
+/// state running it when the state times out.
+async fn run_callback_for_state(
+    name: &str,
+    callback: &Path,
+    args: &[&str],
+) -> Result<easy_process::Output> {
+    check_output_for_state(name, run_killable(callback, args).await)
+}
+
+/// Runs the program as a child process which is killed once the returned
+/// future is dropped, failing as `easy_process::run` does when it doesn't
+/// exit with success.
+pub(crate) async fn run_killable<S: AsRef<std::ffi::OsStr>>(
+    program: impl AsRef<std::ffi::OsStr>,
+    args: &[S],
+) -> easy_process::Result<easy_process::Output> {
+    let o = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    let output = easy_process::Output {
+        stdout: String::from_utf8_lossy(&o.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&o.stderr).to_string(),
+    };
+    match o.status.success() {
+        true => Ok(output),
+        false => Err(easy_process::Error::Failure(o.status, output)),
+    }
+}
+
+fn check_output_for_state(
+    name: &str,
+    res: easy_process::Result<easy_process::Output>,
+) -> Result<easy_process::Output> {
+    match res {
         Ok(output) => {
             info!("{} has exit with success", name);
             for err in output.stderr.lines() {
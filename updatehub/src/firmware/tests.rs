@@ -51,6 +51,10 @@ pub(crate) fn validate_hook(path: &Path) -> PathBuf {
     path.join(VALIDATE_CALLBACK)
 }
 
+pub(crate) fn pre_install_hook(path: &Path) -> PathBuf {
+    path.join(PRE_INSTALL_CALLBACK)
+}
+
 pub(crate) fn device_identity_dir(path: &Path) -> PathBuf {
     path.join(DEVICE_IDENTITY_DIR).join("identity")
 }
@@ -218,43 +222,140 @@ fn create_state_change_callback_hook(content: &str) -> tempfile::TempDir {
     tmpdir
 }
 
-#[test]
-fn state_callback_cancel() {
+#[tokio::test]
+async fn state_callback_cancel() {
     let script = "#!/bin/sh\necho cancel";
     let tmpdir = create_state_change_callback_hook(script);
     assert_eq!(
-        state_change_callback(tmpdir.path(), CALLBACK_STATE_NAME).unwrap(),
+        state_change_callback(tmpdir.path(), CALLBACK_STATE_NAME).await.unwrap(),
         Transition::Cancel,
         "Unexpected result using content {:?}",
         script,
     );
 }
 
-#[test]
-fn state_callback_continue_transition() {
+#[tokio::test]
+async fn state_callback_continue_transition() {
     let script = "#!/bin/sh\necho ";
     let tmpdir = create_state_change_callback_hook(script);
     assert_eq!(
-        state_change_callback(tmpdir.path(), CALLBACK_STATE_NAME).unwrap(),
+        state_change_callback(tmpdir.path(), CALLBACK_STATE_NAME).await.unwrap(),
         Transition::Continue,
         "Unexpected result using content {:?}",
         script,
     );
 }
 
-#[test]
-fn state_callback_non_existing_hook() {
+#[tokio::test]
+async fn state_callback_non_existing_hook() {
     assert_eq!(
-        state_change_callback(Path::new("/NaN"), CALLBACK_STATE_NAME).unwrap(),
+        state_change_callback(Path::new("/NaN"), CALLBACK_STATE_NAME).await.unwrap(),
         Transition::Continue,
         "Unexpected result for non-existing hook",
     );
 }
 
-#[test]
-fn state_callback_is_error() {
+#[tokio::test]
+async fn state_callback_is_error() {
     for script in &["#!/bin/sh\necho 123", "#!/bin/sh\necho 123\ncancel"] {
         let tmpdir = create_state_change_callback_hook(script);
-        assert!(state_change_callback(tmpdir.path(), CALLBACK_STATE_NAME).is_err());
+        assert!(state_change_callback(tmpdir.path(), CALLBACK_STATE_NAME).await.is_err());
     }
 }
+
+#[tokio::test]
+async fn pre_install_callback_transition() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let args = tmpdir.path().join("args");
+    assert_eq!(
+        pre_install_callback(tmpdir.path(), "package", &["rootfs", "kernel"]).await.unwrap(),
+        Transition::Continue
+    );
+
+    create_hook(
+        pre_install_hook(tmpdir.path()),
+        &format!("#!/bin/sh\necho \"$@\" > {}\necho cancel", args.display()),
+    );
+    assert_eq!(
+        pre_install_callback(tmpdir.path(), "package", &["rootfs", "kernel"]).await.unwrap(),
+        Transition::Cancel
+    );
+    assert_eq!(std::fs::read_to_string(&args).unwrap(), "package rootfs kernel\n");
+
+    create_hook(pre_install_hook(tmpdir.path()), "#!/bin/sh\nexit 1");
+    assert!(matches!(
+        pre_install_callback(tmpdir.path(), "package", &["rootfs"]).await,
+        Err(Error::Process(_))
+    ));
+}
+
+#[test]
+fn validate_callback_missing() {
+    assert_eq!(
+        validate_callback(Path::new("/NaN"), MissingCallbackPolicy::Validate).unwrap(),
+        Transition::Continue
+    );
+    assert_eq!(
+        validate_callback(Path::new("/NaN"), MissingCallbackPolicy::Rollback).unwrap(),
+        Transition::Cancel
+    );
+    assert!(matches!(
+        validate_callback(Path::new("/NaN"), MissingCallbackPolicy::Error),
+        Err(Error::MissingValidateCallback)
+    ));
+}
+
+#[test]
+fn version_compare_callback_output() {
+    use std::cmp::Ordering;
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let callback = tmpdir.path().join(VERSION_COMPARE_CALLBACK);
+    assert!(matches!(
+        version_compare_callback(tmpdir.path(), "1.1", "1.0"),
+        Err(Error::MissingVersionCompareCallback)
+    ));
+
+    create_hook(callback.clone(), "#!/bin/sh\n[ \"$1\" = 1.1 ] && [ \"$2\" = 1.0 ] && echo older");
+    assert_eq!(version_compare_callback(tmpdir.path(), "1.1", "1.0").unwrap(), Ordering::Less);
+
+    create_hook(callback, "#!/bin/sh\necho 1");
+    assert!(version_compare_callback(tmpdir.path(), "1.1", "1.0").is_err());
+}
+
+#[test]
+fn check_callbacks_executable() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmpdir = create_state_change_callback_hook("#!/bin/sh\necho ");
+    std::fs::write(tmpdir.path().join(ROLLBACK_CALLBACK), "#!/bin/sh\n").unwrap();
+    std::fs::set_permissions(
+        tmpdir.path().join(ROLLBACK_CALLBACK),
+        std::fs::Permissions::from_mode(0o644),
+    )
+    .unwrap();
+
+    let checks = check_callbacks(tmpdir.path(), MissingCallbackPolicy::Rollback);
+    assert!(matches!(checks[0], (STATE_CHANGE_CALLBACK, Ok(()))));
+    assert!(matches!(checks[1], (VALIDATE_CALLBACK, Ok(()))));
+    assert!(matches!(checks[2], (ROLLBACK_CALLBACK, Err(Error::CallbackNotExecutable(_)))));
+
+    let checks = check_callbacks(tmpdir.path(), MissingCallbackPolicy::Error);
+    assert!(matches!(checks[1], (VALIDATE_CALLBACK, Err(Error::MissingValidateCallback))));
+}
+
+#[test]
+fn public_keys_from_dir() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    assert!(matches!(public_keys(tmpdir.path()), Err(Error::NoPublicKeys(_))));
+
+    let key = tmpdir.path().join("new.pem");
+    std::fs::write(&key, "").unwrap();
+    std::fs::write(tmpdir.path().join("old.pem"), "").unwrap();
+    std::fs::create_dir(tmpdir.path().join("dir")).unwrap();
+    assert_eq!(
+        public_keys(tmpdir.path()).unwrap(),
+        vec![key.clone(), tmpdir.path().join("old.pem")]
+    );
+    assert_eq!(public_keys(&key).unwrap(), vec![key]);
+}
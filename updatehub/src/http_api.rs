@@ -3,24 +3,58 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::states::machine;
+use futures_util::{future::LocalBoxFuture, StreamExt};
 use sdk::api;
-use slog_scope::debug;
+use slog_scope::{debug, warn};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use warp::Filter;
 
 type Result<T> = std::result::Result<T, warp::Rejection>;
 
+/// Changes once the agent API is requested to shut down.
+type Shutdown = tokio::sync::watch::Receiver<()>;
+
+/// Time the state machine has to answer a health check.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Prefix of listen sockets which are Unix domain sockets.
+#[cfg(unix)]
+const UNIX_SOCKET_PREFIX: &str = "unix:";
+
 pub(crate) struct Api(machine::Addr);
 
 impl Api {
     pub(crate) fn server(
         addr: machine::Addr,
+        max_connections: Option<usize>,
+        api_token: Option<String>,
+        shutdown: Shutdown,
     ) -> warp::Server<warp::filters::BoxedFilter<(impl warp::Reply,)>> {
+        warp::serve(Api::routes(addr, max_connections, api_token, shutdown))
+    }
+
+    fn routes(
+        addr: machine::Addr,
+        max_connections: Option<usize>,
+        api_token: Option<String>,
+        shutdown: Shutdown,
+    ) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
         let state = warp::any().map(move || addr.clone());
+        let connections = max_connections.map(|max| Arc::new(Semaphore::new(max)));
+        let connection_permit =
+            warp::any().map(move || connections.clone()).and_then(Api::acquire_connection);
 
         let info = warp::get().and(warp::path("info")).and(state.clone()).and_then(Api::info);
-        let log = warp::get().and(warp::path("log")).and_then(Api::log);
+        let healthz =
+            warp::get().and(warp::path("healthz")).and(state.clone()).and_then(Api::healthz);
+        let log = warp::get().and(warp::path("log")).and(warp::query()).and_then(Api::log);
+        let version = warp::get().and(warp::path("version")).map(Api::version);
         let probe = warp::post()
             .and(warp::path("probe"))
+            .and(warp::path::end())
             .and(
                 warp::body::json()
                     .map(Some)
@@ -28,6 +62,11 @@ impl Api {
             )
             .and(state.clone())
             .and_then(Api::probe);
+        let probe_metadata = warp::post()
+            .and(warp::path!("probe" / "metadata"))
+            .and(warp::body::json())
+            .and(state.clone())
+            .and_then(Api::probe_metadata);
         let local_install = warp::post()
             .and(warp::path("local_install"))
             .and(warp::body::json())
@@ -40,13 +79,143 @@ impl Api {
             .and_then(Api::remote_install);
         let download_abort = warp::post()
             .and(warp::path!("update" / "download" / "abort"))
-            .and(state)
+            .and(warp::query())
+            .and(state.clone())
             .and_then(Api::download_abort);
+        let update_cancel = warp::post()
+            .and(warp::path!("update" / "cancel"))
+            .and(warp::query())
+            .and(state.clone())
+            .and_then(Api::update_cancel);
+        let download_pause = warp::post()
+            .and(warp::path!("update" / "download" / "pause"))
+            .and(state.clone())
+            .and_then(Api::download_pause);
+        let download_resume = warp::post()
+            .and(warp::path!("update" / "download" / "resume"))
+            .and(state.clone())
+            .and_then(Api::download_resume);
+        let install_confirm = warp::post()
+            .and(warp::path!("update" / "install" / "confirm"))
+            .and(state.clone())
+            .and_then(Api::install_confirm);
+        let debug_continue = warp::post()
+            .and(warp::path!("debug" / "continue"))
+            .and(state.clone())
+            .and_then(Api::debug_continue);
+        let debug_channels = warp::get()
+            .and(warp::path!("debug" / "channels"))
+            .and(state.clone())
+            .and_then(Api::debug_channels);
+        let installation_set = warp::get()
+            .and(warp::path("installation-set"))
+            .and(state.clone())
+            .and_then(Api::installation_set);
+        let set_installation_set = warp::put()
+            .and(warp::path("installation-set"))
+            .and(Api::authorized(api_token.clone()))
+            .and(warp::body::json())
+            .and(state.clone())
+            .and_then(Api::set_installation_set);
+        let rollback = warp::post()
+            .and(warp::path("rollback"))
+            .and(Api::authorized(api_token))
+            .and(state.clone())
+            .and_then(Api::rollback);
+        let polling =
+            warp::get().and(warp::path("polling")).and(state.clone()).and_then(Api::polling);
+        let set_polling = warp::post()
+            .and(warp::path("polling"))
+            .and(warp::body::json())
+            .and(state.clone())
+            .and_then(Api::set_polling);
+        let history =
+            warp::get().and(warp::path("history")).and(state.clone()).and_then(Api::history);
+        let selftest = warp::post()
+            .and(warp::path("selftest"))
+            .and(warp::body::json().or_else(|_| async {
+                Ok::<_, std::convert::Infallible>((api::selftest::Request::default(),))
+            }))
+            .and(state.clone())
+            .and_then(Api::selftest);
+        let events = warp::get()
+            .and(warp::path("events"))
+            .and(state)
+            .and(warp::any().map(move || shutdown.clone()))
+            .map(Api::events);
 
-        let main_filter = warp::any()
-            .and(info.or(log).or(probe).or(local_install).or(remote_install).or(download_abort))
-            .boxed();
-        warp::serve(main_filter)
+        warp::any()
+            .and(connection_permit)
+            .and(
+                info.or(healthz)
+                    .or(log)
+                    .or(version)
+                    .or(probe_metadata)
+                    .or(probe)
+                    .or(local_install)
+                    .or(remote_install)
+                    .or(download_abort)
+                    .or(update_cancel)
+                    .or(download_pause)
+                    .or(download_resume)
+                    .or(install_confirm)
+                    .or(debug_continue)
+                    .or(debug_channels)
+                    .or(installation_set)
+                    .or(set_installation_set)
+                    .or(rollback)
+                    .or(polling)
+                    .or(set_polling)
+                    .or(history)
+                    .or(selftest)
+                    .or(events),
+            )
+            .map(Api::hold_connection)
+            .recover(Api::refused_request)
+            .boxed()
+    }
+
+    /// Binds the agent API to the listen socket, returning the future
+    /// serving it until `signal` completes.
+    pub(crate) fn bind(
+        addr: machine::Addr,
+        listen_socket: &str,
+        socket_mode: u32,
+        max_connections: Option<usize>,
+        api_token: Option<String>,
+        signal: impl Future<Output = ()> + Send + 'static,
+    ) -> crate::Result<LocalBoxFuture<'static, ()>> {
+        // The event streams never end on their own, so they are ended
+        // along with the server for the graceful shutdown to complete
+        let (shutdown, shutdown_receiver) = tokio::sync::watch::channel(());
+        let signal = async move {
+            signal.await;
+            let _ = shutdown.send(());
+        };
+        #[cfg(unix)]
+        if let Some(path) = listen_socket.strip_prefix(UNIX_SOCKET_PREFIX) {
+            let path = PathBuf::from(path);
+            let (listener, socket) = bind_unix_socket(&path, socket_mode)?;
+            let incoming = futures_util::stream::poll_fn(move |cx| {
+                listener.poll_accept(cx).map(|res| Some(res.map(|(stream, _)| stream)))
+            });
+            let server = Api::server(addr, max_connections, api_token, shutdown_receiver)
+                .serve_incoming_with_graceful_shutdown(incoming, signal);
+            return Ok(Box::pin(async move {
+                // The socket is removed even when the server is dropped
+                // before completing
+                let _socket = socket;
+                server.await;
+            }));
+        }
+
+        // FIXME: handle failiure to parse the listen socket
+        let (_, server) = Api::server(addr, max_connections, api_token, shutdown_receiver)
+            .bind_with_graceful_shutdown(
+                listen_socket.replace("localhost", "127.0.0.1").parse::<std::net::SocketAddr>()?,
+                signal,
+            );
+        Ok(Box::pin(server))
     }
 
     async fn info(addr: machine::Addr) -> Result<warp::reply::Json> {
@@ -55,8 +224,141 @@ impl Api {
         Ok(warp::reply::json(&res))
     }
 
-    async fn log() -> Result<warp::reply::Json> {
-        Ok(warp::reply::json(&crate::logger::buffer()))
+    async fn healthz(addr: machine::Addr) -> Result<warp::reply::WithStatus<&'static str>> {
+        debug!("receiving healthz request");
+        let status = match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, addr.request_info()).await {
+            Ok(Ok(_)) => return Ok(warp::reply::with_status("ok", warp::http::StatusCode::OK)),
+            Ok(Err(e)) => format!("failed: {}", e),
+            Err(_) => format!("no answer in {} seconds", HEALTH_CHECK_TIMEOUT.as_secs()),
+        };
+
+        warn!("state machine is unresponsive, health check has {}", status);
+        Ok(warp::reply::with_status("unavailable", warp::http::StatusCode::SERVICE_UNAVAILABLE))
+    }
+
+    /// Takes one of the available API connections, refusing the request
+    /// when all of them are in use.
+    async fn acquire_connection(
+        connections: Option<Arc<Semaphore>>,
+    ) -> Result<Option<OwnedSemaphorePermit>> {
+        match connections {
+            Some(connections) => connections
+                .try_acquire_owned()
+                .map(Some)
+                .map_err(|_| warp::reject::custom(TooManyConnections)),
+            None => Ok(None),
+        }
+    }
+
+    /// Holds the connection permit until the whole body of the reply has
+    /// been sent, so streamed replies, as the event streams, take a
+    /// connection for as long as they are served.
+    fn hold_connection(
+        permit: Option<OwnedSemaphorePermit>,
+        reply: impl warp::Reply,
+    ) -> warp::reply::Response {
+        let response = warp::Reply::into_response(reply);
+        match permit {
+            Some(permit) => {
+                let (parts, body) = response.into_parts();
+                let body = body.map(move |chunk| {
+                    let _ = &permit;
+                    chunk
+                });
+                warp::reply::Response::from_parts(parts, warp::hyper::Body::wrap_stream(body))
+            }
+            None => response,
+        }
+    }
+
+    /// Filters the requests which carry the API token as their bearer
+    /// token. No request is authorized when there's no token set.
+    fn authorized(
+        api_token: Option<String>,
+    ) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+        let api_token: Option<Arc<str>> = api_token.map(Into::into);
+        warp::header::optional::<String>("authorization")
+            .and(warp::any().map(move || api_token.clone()))
+            .and_then(Api::authorize)
+            .untuple_one()
+    }
+
+    async fn authorize(authorization: Option<String>, api_token: Option<Arc<str>>) -> Result<()> {
+        let api_token = api_token.ok_or_else(|| warp::reject::custom(Unauthorized::NoToken))?;
+        match authorization.as_deref().and_then(|a| a.strip_prefix("Bearer ")) {
+            Some(token)
+                if token.len() == api_token.len()
+                    && openssl::memcmp::eq(token.as_bytes(), api_token.as_bytes()) =>
+            {
+                Ok(())
+            }
+            _ => Err(warp::reject::custom(Unauthorized::InvalidToken)),
+        }
+    }
+
+    async fn refused_request(
+        rejection: warp::Rejection,
+    ) -> Result<warp::reply::WithStatus<&'static str>> {
+        if rejection.find::<TooManyConnections>().is_some() {
+            warn!("refusing API request as the maximum number of connections has been reached");
+            return Ok(warp::reply::with_status(
+                "too many connections",
+                warp::http::StatusCode::SERVICE_UNAVAILABLE,
+            ));
+        }
+
+        match rejection.find::<Unauthorized>() {
+            Some(Unauthorized::NoToken) => {
+                warn!("refusing API request as no API token is set");
+                Ok(warp::reply::with_status("no api token set", warp::http::StatusCode::FORBIDDEN))
+            }
+            Some(Unauthorized::InvalidToken) => {
+                warn!("refusing API request with a missing or invalid API token");
+                Ok(warp::reply::with_status("unauthorized", warp::http::StatusCode::UNAUTHORIZED))
+            }
+            None => Err(rejection),
+        }
+    }
+
+    async fn log(query: api::log::Query) -> Result<warp::reply::Json> {
+        let buffer = crate::logger::buffer();
+        let buffer = buffer.lock().unwrap();
+        Ok(match query.tail {
+            Some(lines) => warp::reply::json(&buffer.tail(lines)),
+            None => warp::reply::json(&*buffer),
+        })
+    }
+
+    fn version() -> warp::reply::Json {
+        warp::reply::json(&api::version::Response {
+            version: crate::version().to_owned(),
+            git_commit: crate::build_info::git_commit().to_owned(),
+            build_time: crate::build_info::build_time(),
+        })
+    }
+
+    fn events(addr: machine::Addr, mut shutdown: Shutdown) -> impl warp::Reply {
+        use tokio::sync::broadcast::error::RecvError;
+
+        debug!("receiving events request");
+        let events =
+            futures_util::stream::unfold(addr.subscribe_events(), |mut receiver| async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(event) => {
+                            return Some((warp::sse::Event::default().json_data(&event), receiver));
+                        }
+                        // Events missed by a slow client are skipped.
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            })
+            .take_until(async move {
+                let _ = shutdown.changed().await;
+            });
+
+        warp::sse::reply(warp::sse::keep_alive().stream(events))
     }
 
     async fn probe(
@@ -68,12 +370,20 @@ impl Api {
         Ok(addr.request_probe(server_address).await?)
     }
 
+    async fn probe_metadata(
+        req: api::probe::MetadataRequest,
+        addr: machine::Addr,
+    ) -> Result<machine::InjectMetadataResponse> {
+        debug!("receiving probe with metadata request");
+        Ok(addr.request_inject_metadata(req).await?)
+    }
+
     async fn local_install(
         req: api::local_install::Request,
         addr: machine::Addr,
     ) -> Result<machine::StateResponse> {
         debug!("receiving local_install request");
-        Ok(addr.request_local_install(req.file).await?)
+        Ok(addr.request_local_install(req.file, req.sha256sum, req.objects).await?)
     }
 
     async fn remote_install(
@@ -84,58 +394,389 @@ impl Api {
         Ok(addr.request_remote_install(req.url).await?)
     }
 
-    async fn download_abort(addr: machine::Addr) -> Result<machine::AbortDownloadResponse> {
+    async fn download_abort(
+        query: api::abort_download::Query,
+        addr: machine::Addr,
+    ) -> Result<machine::AbortDownloadResponse> {
         debug!("receiving abort download request");
-        Ok(addr.request_abort_download().await?)
+        Ok(addr.request_abort_download(query.purge).await?)
+    }
+
+    async fn update_cancel(
+        query: api::abort_download::Query,
+        addr: machine::Addr,
+    ) -> Result<UpdateCancelResponse> {
+        debug!("receiving cancel update request");
+        Ok(UpdateCancelResponse(addr.request_cancel_update(query.purge).await?))
+    }
+
+    async fn download_pause(addr: machine::Addr) -> Result<DownloadPauseResponse> {
+        debug!("receiving pause download request");
+        Ok(DownloadPauseResponse(addr.request_pause_download().await?))
+    }
+
+    async fn download_resume(addr: machine::Addr) -> Result<DownloadResumeResponse> {
+        debug!("receiving resume download request");
+        Ok(DownloadResumeResponse(addr.request_resume_download().await?))
+    }
+
+    async fn install_confirm(addr: machine::Addr) -> Result<InstallConfirmResponse> {
+        debug!("receiving confirm install request");
+        Ok(InstallConfirmResponse(addr.request_confirm_install().await?))
+    }
+
+    async fn debug_continue(addr: machine::Addr) -> Result<DebugContinueResponse> {
+        debug!("receiving debug continue request");
+        Ok(DebugContinueResponse(addr.request_debug_continue().await?))
+    }
+
+    async fn debug_channels(addr: machine::Addr) -> Result<warp::reply::Json> {
+        debug!("receiving debug channels request");
+        match addr.debug_channels() {
+            Some(channels) => Ok(warp::reply::json(&channels)),
+            None => Err(warp::reject::not_found()),
+        }
+    }
+
+    async fn installation_set(addr: machine::Addr) -> Result<warp::reply::Json> {
+        debug!("receiving installation set request");
+        let res = addr.request_installation_set().await?;
+        Ok(warp::reply::json(&res))
+    }
+
+    async fn set_installation_set(
+        req: api::installation_set::Request,
+        addr: machine::Addr,
+    ) -> Result<machine::InstallationSetResponse> {
+        debug!("receiving installation set change request");
+        Ok(addr.request_set_installation_set(req).await?)
+    }
+
+    async fn rollback(addr: machine::Addr) -> Result<machine::RollbackResponse> {
+        debug!("receiving rollback request");
+        Ok(addr.request_rollback().await?)
+    }
+
+    async fn polling(addr: machine::Addr) -> Result<warp::reply::Json> {
+        debug!("receiving polling request");
+        let res = addr.request_polling().await?;
+        Ok(warp::reply::json(&res))
+    }
+
+    async fn history(addr: machine::Addr) -> Result<warp::reply::Json> {
+        debug!("receiving history request");
+        let res = addr.request_history().await?;
+        Ok(warp::reply::json(&res))
+    }
+
+    async fn selftest(
+        req: api::selftest::Request,
+        addr: machine::Addr,
+    ) -> Result<warp::reply::Json> {
+        debug!("receiving selftest request");
+        let res = addr.request_selftest(req.targets).await?;
+        Ok(warp::reply::json(&res))
+    }
+
+    async fn set_polling(
+        req: api::polling::Request,
+        addr: machine::Addr,
+    ) -> Result<machine::PollingResponse> {
+        debug!("receiving polling change request");
+        Ok(addr.request_set_polling(req.interval).await?)
     }
 }
 
 impl warp::reject::Reject for crate::states::TransitionError {}
 
+/// Rejection of requests exceeding the maximum number of API connections.
+#[derive(Debug)]
+struct TooManyConnections;
+
+impl warp::reject::Reject for TooManyConnections {}
+
+/// Rejection of requests which require the API token.
+#[derive(Debug)]
+enum Unauthorized {
+    NoToken,
+    InvalidToken,
+}
+
+impl warp::reject::Reject for Unauthorized {}
+
 impl warp::reply::Reply for machine::AbortDownloadResponse {
     fn into_response(self) -> warp::reply::Response {
-        match self {
-            machine::AbortDownloadResponse::RequestAccepted => warp::reply::Response::new(
-                serde_json::to_vec(&api::abort_download::Response {
-                    message: "request accepted, download aborted".to_owned(),
-                })
-                .unwrap()
-                .into(),
+        discard_reply(
+            self,
+            "request accepted, download aborted",
+            "there is no download to be aborted",
+        )
+    }
+}
+
+struct UpdateCancelResponse(machine::AbortDownloadResponse);
+
+impl warp::reply::Reply for UpdateCancelResponse {
+    fn into_response(self) -> warp::reply::Response {
+        discard_reply(
+            self.0,
+            "request accepted, pending update canceled",
+            "there is no pending update to be canceled",
+        )
+    }
+}
+
+struct DownloadPauseResponse(machine::DownloadControlResponse);
+struct DownloadResumeResponse(machine::DownloadControlResponse);
+struct InstallConfirmResponse(machine::DownloadControlResponse);
+struct DebugContinueResponse(machine::DownloadControlResponse);
+
+impl warp::reply::Reply for DownloadPauseResponse {
+    fn into_response(self) -> warp::reply::Response {
+        download_control_reply(
+            self.0,
+            "request accepted, download paused",
+            "there is no download to be paused",
+        )
+    }
+}
+
+impl warp::reply::Reply for DownloadResumeResponse {
+    fn into_response(self) -> warp::reply::Response {
+        download_control_reply(
+            self.0,
+            "request accepted, download resumed",
+            "there is no paused download to be resumed",
+        )
+    }
+}
+
+impl warp::reply::Reply for InstallConfirmResponse {
+    fn into_response(self) -> warp::reply::Response {
+        download_control_reply(
+            self.0,
+            "request accepted, installing update",
+            "there is no install awaiting confirmation",
+        )
+    }
+}
+
+impl warp::reply::Reply for DebugContinueResponse {
+    fn into_response(self) -> warp::reply::Response {
+        download_control_reply(
+            self.0,
+            "request accepted, continuing from breakpoint",
+            "there is no breakpoint to continue from",
+        )
+    }
+}
+
+/// Unix domain socket file, removed once it's dropped.
+#[cfg(unix)]
+struct SocketFile(PathBuf);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            warn!("failed to remove the listen socket {:?}: {}", self.0, e);
+        }
+    }
+}
+
+/// Binds a Unix domain socket at `path`, with the permissions set by
+/// `mode`. A socket file left behind by a previous run is replaced.
+///
+/// The socket is bound on a private directory and only linked to `path`
+/// once its permissions are set, so it's never reachable with looser
+/// ones.
+#[cfg(unix)]
+fn bind_unix_socket(
+    path: &Path,
+    mode: u32,
+) -> std::io::Result<(tokio::net::UnixListener, SocketFile)> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if std::fs::symlink_metadata(path).map_or(false, |m| m.file_type().is_socket()) {
+        debug!("removing stale listen socket {:?}", path);
+        std::fs::remove_file(path)?;
+    }
+
+    let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let private = tempfile::Builder::new().prefix(".updatehub-socket").tempdir_in(parent)?;
+    let bound = private.path().join("socket");
+    let listener = tokio::net::UnixListener::bind(&bound)?;
+    std::fs::set_permissions(&bound, std::fs::Permissions::from_mode(mode))?;
+    // Unlike renaming, linking never replaces an existing file
+    std::fs::hard_link(&bound, path)?;
+    Ok((listener, SocketFile(path.to_owned())))
+}
+
+fn discard_reply(
+    response: machine::AbortDownloadResponse,
+    message: &str,
+    error: &str,
+) -> warp::reply::Response {
+    use warp::reply::Reply;
+
+    match response {
+        machine::AbortDownloadResponse::RequestAccepted(purged) => warp::reply::Response::new(
+            serde_json::to_vec(&api::abort_download::Response {
+                message: message.to_owned(),
+                purged,
+            })
+            .unwrap()
+            .into(),
+        ),
+        machine::AbortDownloadResponse::InvalidState => warp::reply::with_status(
+            warp::reply::Response::new(
+                serde_json::to_vec(&api::abort_download::Refused { error: error.to_owned() })
+                    .unwrap()
+                    .into(),
             ),
-            machine::AbortDownloadResponse::InvalidState => warp::reply::with_status(
-                warp::reply::Response::new(
-                    serde_json::to_vec(&api::abort_download::Refused {
-                        error: "there is no download to be aborted".to_owned(),
-                    })
+            warp::http::StatusCode::NOT_ACCEPTABLE,
+        )
+        .into_response(),
+    }
+}
+
+fn download_control_reply(
+    response: machine::DownloadControlResponse,
+    message: &str,
+    error: &str,
+) -> warp::reply::Response {
+    use warp::reply::Reply;
+
+    match response {
+        machine::DownloadControlResponse::RequestAccepted => warp::reply::Response::new(
+            serde_json::to_vec(&api::message::Response {
+                message: message.to_owned(),
+                purged: None,
+            })
+            .unwrap()
+            .into(),
+        ),
+        machine::DownloadControlResponse::InvalidState => warp::reply::with_status(
+            warp::reply::Response::new(
+                serde_json::to_vec(&api::message::Refused { error: error.to_owned() })
                     .unwrap()
                     .into(),
-                ),
-                warp::http::StatusCode::NOT_ACCEPTABLE,
-            )
-            .into_response(),
-        }
+            ),
+            warp::http::StatusCode::NOT_ACCEPTABLE,
+        )
+        .into_response(),
     }
 }
 
+/// The probe replies with `200 OK` when it has been done, `202 Accepted`
+/// when it has to be retried later and `409 Conflict` when the agent is
+/// busy, so the outcome can be told apart without parsing the body.
 impl warp::reply::Reply for machine::ProbeResponse {
     fn into_response(self) -> warp::reply::Response {
+        let (body, status) = match self {
+            machine::ProbeResponse::Available => {
+                (serde_json::to_vec(&api::probe::Response::Updating), warp::http::StatusCode::OK)
+            }
+            machine::ProbeResponse::Unavailable => {
+                (serde_json::to_vec(&api::probe::Response::NoUpdate), warp::http::StatusCode::OK)
+            }
+            machine::ProbeResponse::Delayed(d) => (
+                serde_json::to_vec(&api::probe::Response::TryAgain(d)),
+                warp::http::StatusCode::ACCEPTED,
+            ),
+            machine::ProbeResponse::Busy(current_state) => {
+                (serde_json::to_vec(&current_state), warp::http::StatusCode::CONFLICT)
+            }
+        };
+
+        warp::reply::with_status(warp::reply::Response::new(body.unwrap().into()), status)
+            .into_response()
+    }
+}
+
+impl warp::reply::Reply for machine::InjectMetadataResponse {
+    fn into_response(self) -> warp::reply::Response {
+        let refused = |error: String, status| {
+            warp::reply::with_status(
+                warp::reply::Response::new(
+                    serde_json::to_vec(&api::message::Refused { error }).unwrap().into(),
+                ),
+                status,
+            )
+            .into_response()
+        };
+
         match self {
-            machine::ProbeResponse::Available => warp::reply::Response::new(
+            machine::InjectMetadataResponse::RequestAccepted => warp::reply::Response::new(
                 serde_json::to_vec(&api::probe::Response::Updating).unwrap().into(),
             ),
-            machine::ProbeResponse::Unavailable => warp::reply::Response::new(
-                serde_json::to_vec(&api::probe::Response::NoUpdate).unwrap().into(),
-            ),
-            machine::ProbeResponse::Delayed(d) => warp::reply::Response::new(
-                serde_json::to_vec(&api::probe::Response::TryAgain(d)).unwrap().into(),
+            machine::InjectMetadataResponse::InvalidState(current_state) => {
+                warp::reply::with_status(
+                    warp::reply::Response::new(serde_json::to_vec(&current_state).unwrap().into()),
+                    warp::http::StatusCode::NOT_ACCEPTABLE,
+                )
+                .into_response()
+            }
+            machine::InjectMetadataResponse::Disabled => refused(
+                "update metadata injection is disabled".to_owned(),
+                warp::http::StatusCode::FORBIDDEN,
             ),
-            machine::ProbeResponse::Busy(current_state) => {
-                warp::reply::Response::new(serde_json::to_vec(&current_state).unwrap().into())
+            machine::InjectMetadataResponse::InvalidMetadata(error) => {
+                refused(error, warp::http::StatusCode::BAD_REQUEST)
             }
         }
     }
 }
 
+impl warp::reply::Reply for machine::InstallationSetResponse {
+    fn into_response(self) -> warp::reply::Response {
+        match self {
+            machine::InstallationSetResponse::RequestAccepted(installation_set) => {
+                warp::reply::Response::new(serde_json::to_vec(&installation_set).unwrap().into())
+            }
+            machine::InstallationSetResponse::InvalidState(current_state) => {
+                warp::reply::with_status(
+                    warp::reply::Response::new(serde_json::to_vec(&current_state).unwrap().into()),
+                    warp::http::StatusCode::NOT_ACCEPTABLE,
+                )
+                .into_response()
+            }
+        }
+    }
+}
+
+impl warp::reply::Reply for machine::PollingResponse {
+    fn into_response(self) -> warp::reply::Response {
+        match self {
+            machine::PollingResponse::RequestAccepted(polling) => {
+                warp::reply::Response::new(serde_json::to_vec(&polling).unwrap().into())
+            }
+            machine::PollingResponse::InvalidInterval(error) => warp::reply::with_status(
+                warp::reply::Response::new(
+                    serde_json::to_vec(&api::message::Refused { error }).unwrap().into(),
+                ),
+                warp::http::StatusCode::BAD_REQUEST,
+            )
+            .into_response(),
+        }
+    }
+}
+
+impl warp::reply::Reply for machine::RollbackResponse {
+    fn into_response(self) -> warp::reply::Response {
+        match self {
+            machine::RollbackResponse::RequestAccepted(rollback) => {
+                warp::reply::Response::new(serde_json::to_vec(&rollback).unwrap().into())
+            }
+            machine::RollbackResponse::InvalidState(current_state) => warp::reply::with_status(
+                warp::reply::Response::new(serde_json::to_vec(&current_state).unwrap().into()),
+                warp::http::StatusCode::NOT_ACCEPTABLE,
+            )
+            .into_response(),
+        }
+    }
+}
+
 impl warp::reply::Reply for machine::StateResponse {
     fn into_response(self) -> warp::reply::Response {
         match self {
@@ -150,3 +791,125 @@ impl warp::reply::Reply for machine::StateResponse {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use warp::{http::StatusCode, reply::Reply};
+
+    async fn reply_of(response: machine::ProbeResponse) -> (StatusCode, String) {
+        let response = response.into_response();
+        let status = response.status();
+        let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn probe_response_status() {
+        assert_eq!(
+            reply_of(machine::ProbeResponse::Available).await,
+            (StatusCode::OK, r#""updating""#.to_owned())
+        );
+        assert_eq!(
+            reply_of(machine::ProbeResponse::Unavailable).await,
+            (StatusCode::OK, r#""no_update""#.to_owned())
+        );
+        assert_eq!(
+            reply_of(machine::ProbeResponse::Delayed(3600)).await,
+            (StatusCode::ACCEPTED, r#"{"try_again":3600}"#.to_owned())
+        );
+        assert_eq!(
+            reply_of(machine::ProbeResponse::Busy("download".to_owned())).await,
+            (StatusCode::CONFLICT, r#""download""#.to_owned())
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_socket_permissions() {
+        use std::os::unix::{fs::PermissionsExt, net::UnixListener};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("updatehub.sock");
+        // Left behind by a previous run
+        drop(UnixListener::bind(&path).unwrap());
+
+        let socket = bind_unix_socket(&path, 0o600).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        std::os::unix::net::UnixStream::connect(&path).unwrap();
+        // The private directory used for binding is gone
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_socket_keeps_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("updatehub.sock");
+        std::fs::write(&path, b"not a socket").unwrap();
+
+        assert!(bind_unix_socket(&path, 0o660).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"not a socket");
+    }
+
+    #[tokio::test]
+    async fn log_tail() {
+        let (_, shutdown) = tokio::sync::watch::channel(());
+        let routes = Api::routes(machine::Addr::detached(), Some(1), None, shutdown);
+        let log = slog::Logger::root(slog::Drain::fuse(crate::logger::buffer()), slog::o!());
+        for n in 0..3 {
+            crate::logger::start_memory_logging();
+            slog::info!(log, "log line {}", n);
+        }
+
+        // The connection is released once the reply is sent, so the
+        // limit doesn't refuse the following requests
+        for _ in 0..2 {
+            let response = warp::test::request().path("/log?tail=2").reply(&routes).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let lines: Vec<serde_json::Value> = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(lines.len(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn rollback_requires_api_token() {
+        let (_, shutdown) = tokio::sync::watch::channel(());
+        let routes =
+            Api::routes(machine::Addr::detached(), None, Some("secret".to_owned()), shutdown);
+
+        let response = warp::test::request().method("POST").path("/rollback").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/rollback")
+            .header("authorization", "Bearer other")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn api_token_authorization() {
+        let status = |api_token: Option<&str>, authorization: Option<&str>| {
+            let filter = Api::authorized(api_token.map(str::to_owned))
+                .map(warp::reply)
+                .recover(Api::refused_request);
+            let mut request = warp::test::request().method("PUT").path("/installation-set");
+            if let Some(authorization) = authorization {
+                request = request.header("authorization", authorization);
+            }
+            async move { request.reply(&filter).await.status() }
+        };
+
+        assert_eq!(status(Some("secret"), Some("Bearer secret")).await, StatusCode::OK);
+        assert_eq!(status(Some("secret"), Some("Bearer other")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("secret"), Some("secret")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("secret"), None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(None, Some("Bearer secret")).await, StatusCode::FORBIDDEN);
+    }
+}
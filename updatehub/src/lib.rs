@@ -11,6 +11,8 @@ mod object;
 mod runtime_settings;
 mod settings;
 mod states;
+#[cfg(feature = "systemd")]
+mod systemd;
 mod update_package;
 mod utils;
 
@@ -38,6 +40,7 @@ pub enum Error {
     Io(std::io::Error),
     Process(easy_process::Error),
     Sdk(sdk::Error),
+    Cloud(cloud::Error),
     Serde(serde_json::Error),
     AddrParseError(std::net::AddrParseError),
 }
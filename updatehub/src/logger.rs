@@ -4,20 +4,54 @@
 
 use crate::mem_drain::MemDrain;
 use lazy_static::lazy_static;
-use slog::{o, Drain, Logger};
-use std::sync::{Arc, Mutex};
+use sdk::api::info::settings::LogFormat;
+use slog::{o, Drain, Logger, OwnedKVList, Record};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
 
 lazy_static! {
     static ref BUFFER: Arc<Mutex<MemDrain>> = Arc::new(Mutex::new(MemDrain::default()));
 }
 
+static JSON_FORMAT: AtomicBool = AtomicBool::new(false);
+
+/// Forwards the records to the drain of the currently selected log
+/// format, so it can be changed once the settings are loaded.
+struct FormatSwitch<P, J> {
+    plain: P,
+    json: J,
+}
+
+impl<P, J> Drain for FormatSwitch<P, J>
+where
+    P: Drain<Ok = (), Err = slog::Never>,
+    J: Drain<Ok = (), Err = slog::Never>,
+{
+    type Err = slog::Never;
+    type Ok = ();
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        if JSON_FORMAT.load(Ordering::Relaxed) {
+            self.json.log(record, values)
+        } else {
+            self.plain.log(record, values)
+        }
+    }
+}
+
 pub fn init(level: slog::Level) -> slog_scope::GlobalLoggerGuard {
     let buffer_drain = buffer().filter_level(level).fuse();
-    let terminal_drain = Mutex::new(
-        slog_term::FullFormat::new(slog_term::TermDecorator::new().force_plain().build())
-            .build()
-            .filter_level(level),
-    )
+    let terminal_drain = FormatSwitch {
+        plain: Mutex::new(
+            slog_term::FullFormat::new(slog_term::TermDecorator::new().force_plain().build())
+                .build(),
+        )
+        .fuse(),
+        json: Mutex::new(slog_json::Json::new(std::io::stderr()).add_default_keys().build()).fuse(),
+    }
+    .filter_level(level)
     .fuse();
     let terminal_drain = slog_async::Async::new(terminal_drain).build().fuse();
 
@@ -26,6 +60,14 @@ pub fn init(level: slog::Level) -> slog_scope::GlobalLoggerGuard {
     slog_scope::set_global_logger(log)
 }
 
+pub fn set_format(format: LogFormat) {
+    JSON_FORMAT.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+pub fn set_buffer_size(size: usize) {
+    BUFFER.lock().unwrap().set_capacity(size)
+}
+
 pub fn buffer() -> Arc<Mutex<MemDrain>> {
     BUFFER.clone()
 }
@@ -39,5 +81,10 @@ pub fn stop_memory_logging() {
 }
 
 pub fn get_memory_log() -> String {
-    BUFFER.lock().unwrap().to_string()
+    let buffer = BUFFER.lock().unwrap();
+    if JSON_FORMAT.load(Ordering::Relaxed) {
+        buffer.to_json_lines()
+    } else {
+        buffer.to_string()
+    }
 }
@@ -43,6 +43,7 @@ enum ClientCommands {
     Probe(Probe),
     AbortDownload(AbortDownload),
     InstallPackage(InstallPackage),
+    SelfTest(SelfTest),
 }
 
 #[derive(FromArgs)]
@@ -71,7 +72,11 @@ struct Probe {
 #[derive(FromArgs)]
 /// Ask UpdateHub Agent to abort any currently running download
 #[argh(subcommand, name = "abort-download")]
-struct AbortDownload {}
+struct AbortDownload {
+    /// remove the files already downloaded, even partially
+    #[argh(switch)]
+    purge: bool,
+}
 
 #[derive(FromArgs)]
 /// Install a package from a direct URL or a local path
@@ -80,6 +85,25 @@ struct InstallPackage {
     /// the URL or path to the update package
     #[argh(positional)]
     arg: String,
+
+    /// expected sha256sum of the local package, verified before installing
+    #[argh(option)]
+    sha256sum: Option<String>,
+
+    /// filename of the local package object to be installed, skipping the
+    /// others; may be repeated
+    #[argh(option)]
+    object: Vec<String>,
+}
+
+#[derive(FromArgs)]
+/// Check the device is ready to install updates, without changing its state
+#[argh(subcommand, name = "self-test")]
+struct SelfTest {
+    /// target to be checked, as <target-type>:<target> (e.g.
+    /// device:/dev/mmcblk0p2); may be repeated
+    #[argh(option, from_str_fn(selftest_target))]
+    target: Vec<sdk::api::selftest::Target>,
 }
 
 #[derive(FromArgs)]
@@ -100,6 +124,16 @@ fn verbosity_level(value: &str) -> Result<slog::Level, String> {
     slog::Level::from_str(value).map_err(|_| format!("failed to parse verbosity level: {}", value))
 }
 
+fn selftest_target(value: &str) -> Result<sdk::api::selftest::Target, String> {
+    let (target_type, target) = value
+        .split_once(':')
+        .ok_or_else(|| format!("target must be formatted as <target-type>:<target>: {}", value))?;
+    Ok(sdk::api::selftest::Target {
+        target_type: target_type.to_owned(),
+        target: target.to_owned(),
+    })
+}
+
 async fn daemon_main(cmd: DaemonOptions) -> updatehub::Result<()> {
     let _guard = updatehub::logger::init(cmd.verbosity);
     info!("starting UpdateHub Agent {}", updatehub::version());
@@ -167,7 +201,7 @@ async fn client_main(client_options: ClientOptions) -> updatehub::Result<()> {
                 }
             }
         }
-        ClientCommands::AbortDownload(_) => {
+        ClientCommands::AbortDownload(AbortDownload { purge: false }) => {
             let response = client.abort_download().await?;
 
             if client_options.json_output {
@@ -176,7 +210,16 @@ async fn client_main(client_options: ClientOptions) -> updatehub::Result<()> {
                 println!("{:#?}", response);
             }
         }
-        ClientCommands::InstallPackage(InstallPackage { arg }) => {
+        ClientCommands::AbortDownload(AbortDownload { purge: true }) => {
+            let response = client.abort_download_and_purge().await?;
+
+            if client_options.json_output {
+                println!("{}", serde_json::to_string(&response)?);
+            } else {
+                println!("{:#?}", response);
+            }
+        }
+        ClientCommands::InstallPackage(InstallPackage { arg, sha256sum, object }) => {
             let is_remote_install = arg.starts_with("http://") || arg.starts_with("https://");
 
             let response = if is_remote_install {
@@ -189,7 +232,16 @@ async fn client_main(client_options: ClientOptions) -> updatehub::Result<()> {
                     std::env::current_dir().unwrap().join(arg)
                 };
 
-                client.local_install(&file).await?
+                match (sha256sum, object.as_slice()) {
+                    (sha256sum, [_, ..]) => {
+                        let objects = object.iter().map(String::as_str).collect::<Vec<_>>();
+                        client.local_install_objects(&file, sha256sum.as_deref(), &objects).await?
+                    }
+                    (Some(sha256sum), []) => {
+                        client.local_install_with_checksum(&file, &sha256sum).await?
+                    }
+                    (None, []) => client.local_install(&file).await?,
+                }
             };
 
             if client_options.json_output {
@@ -199,6 +251,23 @@ async fn client_main(client_options: ClientOptions) -> updatehub::Result<()> {
                 println!("Run 'updatehub client log --watch' to follow the log's progress");
             }
         }
+        ClientCommands::SelfTest(SelfTest { target }) => {
+            let response = client.selftest(target).await?;
+
+            if client_options.json_output {
+                println!("{}", serde_json::to_string(&response)?);
+            } else {
+                for check in &response.checks {
+                    match &check.error {
+                        None => println!("[ OK ] {}", check.name),
+                        Some(e) => println!("[FAIL] {}: {}", check.name, e),
+                    }
+                }
+                if !response.passed {
+                    println!("Self-test has failed");
+                }
+            }
+        }
     }
 
     Ok(())
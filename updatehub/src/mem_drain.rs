@@ -5,18 +5,30 @@
 use serde::Serialize;
 use slog::{Drain, Key, OwnedKVList, Record, KV};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     fmt::{self, Write},
     io,
     sync::RwLock,
 };
 
+const DEFAULT_CAPACITY: usize = 256 * 1024;
+
 #[derive(Debug, Default)]
 pub struct MemDrain {
-    records: RwLock<Vec<LogRecord>>,
+    records: RwLock<Records>,
     logging: bool,
 }
 
+/// Log records kept up to `capacity` bytes, the oldest ones are dropped
+/// when it is full.
+#[derive(Debug)]
+struct Records {
+    entries: VecDeque<LogRecord>,
+    size: usize,
+    capacity: usize,
+    dropped: usize,
+}
+
 #[derive(Debug, Serialize)]
 struct LogRecord {
     level: String,
@@ -25,6 +37,76 @@ struct LogRecord {
     data: HashMap<String, String>,
 }
 
+impl LogRecord {
+    fn size(&self) -> usize {
+        self.level.len()
+            + self.message.len()
+            + self.time.len()
+            + self.data.iter().map(|(k, v)| k.len() + v.len()).sum::<usize>()
+    }
+}
+
+impl Default for Records {
+    fn default() -> Self {
+        Records { entries: VecDeque::new(), size: 0, capacity: DEFAULT_CAPACITY, dropped: 0 }
+    }
+}
+
+impl Records {
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.size = 0;
+        self.dropped = 0;
+    }
+
+    fn push(&mut self, record: LogRecord) {
+        self.size += record.size();
+        self.entries.push_back(record);
+        self.shrink();
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.shrink();
+    }
+
+    fn shrink(&mut self) {
+        while self.size > self.capacity {
+            match self.entries.pop_front() {
+                Some(record) => {
+                    self.size -= record.size();
+                    self.dropped += 1;
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Marker noting how many of the oldest records have been dropped,
+    /// if any.
+    fn dropped_marker(&self) -> Option<LogRecord> {
+        if self.dropped == 0 {
+            return None;
+        }
+
+        Some(LogRecord {
+            level: "warn".to_string(),
+            message: format!("{} older log entries have been dropped", self.dropped),
+            time: self.entries.front().map(|r| r.time.clone()).unwrap_or_default(),
+            data: HashMap::default(),
+        })
+    }
+}
+
+impl Serialize for Records {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_seq(self.dropped_marker().iter().chain(self.entries.iter()))
+    }
+}
+
 impl MemDrain {
     pub fn start_logging(&mut self) {
         self.records.write().unwrap().clear();
@@ -34,6 +116,51 @@ impl MemDrain {
     pub fn stop_logging(&mut self) {
         self.logging = false;
     }
+
+    /// Sets the maximum size, in bytes, of the stored records.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.records.write().unwrap().set_capacity(capacity);
+    }
+
+    /// Formats the records as one JSON object per line.
+    pub fn to_json_lines(&self) -> String {
+        let records = self.records.read().unwrap();
+
+        let mut ret = String::new();
+        for record in records.dropped_marker().iter().chain(records.entries.iter()) {
+            writeln!(&mut ret, "{}", serde_json::to_string(record).unwrap()).unwrap();
+        }
+        ret
+    }
+
+    /// Only the `count` newest records, serialized as the whole drain
+    /// is. The marker of dropped records is left out.
+    pub fn tail(&self, count: usize) -> Tail<'_> {
+        Tail { records: &self.records, count }
+    }
+}
+
+/// Newest records of a [`MemDrain`].
+pub struct Tail<'a> {
+    records: &'a RwLock<Records>,
+    count: usize,
+}
+
+impl Serialize for Tail<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+
+        let records = self.records.read().unwrap();
+        let skip = records.entries.len().saturating_sub(self.count);
+        let entries: Vec<_> = records.entries.iter().skip(skip).collect();
+
+        let mut state = serializer.serialize_struct("Log", 1)?;
+        state.serialize_field("entries", &entries)?;
+        state.end()
+    }
 }
 
 impl Serialize for MemDrain {
@@ -54,8 +181,7 @@ impl ToString for MemDrain {
         let records = self.records.read().unwrap();
 
         let mut ret = String::new();
-        let records = records.iter();
-        for record in records {
+        for record in records.dropped_marker().iter().chain(records.entries.iter()) {
             let mut msg = record.message.clone();
             for (k, v) in &record.data {
                 msg = msg.replace(k, v);
@@ -171,6 +297,72 @@ mod tests {
         assert!(result.contains(macro_value));
     }
 
+    #[test]
+    fn drain_capacity() {
+        let drain = Arc::new(Mutex::new(MemDrain::default()));
+        let r_vec = drain.clone();
+        drain.lock().unwrap().start_logging();
+        drain.lock().unwrap().set_capacity(200);
+        let log = Logger::root(drain.fuse(), o!());
+        for i in 0..20 {
+            slog_info!(log, "message {}", i);
+        }
+        let result = r_vec.lock().unwrap().to_string();
+        println!("{}", result);
+
+        let lines: Vec<_> = result.lines().collect();
+        assert!(lines[0].ends_with("older log entries have been dropped"));
+        assert!(lines.last().unwrap().ends_with("message 19"));
+        assert!(!result.contains("message 0\n"));
+
+        let dropped: usize = lines[0].split(' ').nth(4).unwrap().parse().unwrap();
+        assert_eq!(dropped + lines.len() - 1, 20);
+    }
+
+    #[test]
+    fn drain_json_lines() {
+        let drain = Arc::new(Mutex::new(MemDrain::default()));
+        let r_vec = drain.clone();
+        drain.lock().unwrap().start_logging();
+        let log = Logger::root(drain.fuse(), o!());
+        slog_info!(log, "{}", "info 1");
+        slog_error!(log, "{}", "error n"; "field1" => "value1");
+        let result = r_vec.lock().unwrap().to_json_lines();
+
+        let records: Vec<serde_json::Value> =
+            result.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["level"], "info");
+        assert_eq!(records[0]["message"], "info 1");
+        assert_eq!(records[1]["level"], "error");
+        assert_eq!(records[1]["data"]["field1"], "value1");
+        assert!(records[1]["time"].is_string());
+    }
+
+    #[test]
+    fn drain_tail() {
+        let drain = Arc::new(Mutex::new(MemDrain::default()));
+        let r_vec = drain.clone();
+        drain.lock().unwrap().start_logging();
+        drain.lock().unwrap().set_capacity(200);
+        let log = Logger::root(drain.fuse(), o!());
+        for i in 0..20 {
+            slog_info!(log, "message {}", i);
+        }
+
+        let drain = r_vec.lock().unwrap();
+        let result = serde_json::to_value(drain.tail(3)).unwrap();
+        let messages: Vec<_> =
+            result["entries"].as_array().unwrap().iter().map(|e| e["message"].clone()).collect();
+        assert_eq!(messages, ["message 17", "message 18", "message 19"]);
+
+        let result = serde_json::to_value(drain.tail(100)).unwrap();
+        let entries = result["entries"].as_array().unwrap();
+        assert_eq!(entries.last().unwrap()["message"], "message 19");
+        assert!(entries.len() < 20);
+        assert!(!entries[0]["message"].as_str().unwrap().contains("dropped"));
+    }
+
     #[test]
     fn drain_serialized() {
         let expected = r#"{
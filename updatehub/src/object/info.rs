@@ -4,10 +4,11 @@
 
 use super::Result;
 use crate::utils;
-use openssl::sha::Sha256;
 use pkg_schema::{
+    definitions::HashAlgorithm,
     objects::{
-        Copy, Flash, Imxkobs, Mender, Raw, RawDelta, Tarball, Test, Ubifs, UbootEnv, Zephyr,
+        Command, Copy, Flash, Imxkobs, Mender, Patch, Raw, RawDelta, Tarball, Test, Ubifs,
+        UbootEnv, Zephyr,
     },
     Object,
 };
@@ -29,16 +30,19 @@ impl_remote_object_info!(RawDelta);
 impl_compressed_object_info!(Copy);
 impl_compressed_object_info!(Raw);
 impl_compressed_object_info!(Ubifs);
+impl_object_info!(Command);
 impl_object_info!(Flash);
 impl_object_info!(Imxkobs);
 impl_object_info!(Mender);
+impl_object_info!(Patch);
 impl_object_info!(Tarball);
 impl_object_info!(Test);
 impl_object_info!(UbootEnv);
 impl_object_info!(Zephyr);
 
 impl_object_for_object_types!(
-    RawDelta, Copy, Flash, Imxkobs, Mender, Tarball, Ubifs, Raw, Test, UbootEnv, Zephyr
+    RawDelta, Command, Copy, Flash, Imxkobs, Mender, Patch, Tarball, Ubifs, Raw, Test, UbootEnv,
+    Zephyr
 );
 
 pub(crate) trait Info {
@@ -55,7 +59,7 @@ pub(crate) trait Info {
 
         let mut buf = [0; 1024];
         let mut reader = BufReader::new(File::open(object)?);
-        let mut hasher = Sha256::new();
+        let mut hasher = utils::Hasher::new(self.hash_algorithm());
         loop {
             let len = reader.read(&mut buf)?;
             hasher.update(&buf[..len]);
@@ -65,7 +69,7 @@ pub(crate) trait Info {
             }
         }
 
-        if utils::hex_encode(&hasher.finish()) != self.sha256sum() {
+        if hasher.finish() != self.sha256sum() {
             return Ok(Status::Corrupted);
         }
 
@@ -80,5 +84,9 @@ pub(crate) trait Info {
     fn filename(&self) -> &str;
     fn len(&self) -> u64;
     fn sha256sum(&self) -> &str;
+    fn hash_algorithm(&self) -> HashAlgorithm;
+    /// Mirrors the object is downloaded from, tried in order, instead of
+    /// the server.
+    fn urls(&self) -> &[String];
     fn required_install_size(&self) -> u64;
 }
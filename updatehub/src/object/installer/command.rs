@@ -0,0 +1,203 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{Context, Error, Result};
+use crate::{
+    object::{Info, Installer},
+    utils::{self, log::LogContent},
+};
+use pkg_schema::objects;
+use slog_scope::{error, info};
+
+/// Placeholder replaced by the path of the verified object in the command
+/// line.
+const FILE_PLACEHOLDER: &str = "{file}";
+
+/// Exit codes of `timeout` when the command was terminated or killed.
+const TIMEOUT_EXIT_CODES: [i32; 2] = [124, 137];
+
+/// Quotes the argument so the command line parser takes it as a single
+/// word, verbatim. The command is not run through a shell, so only the
+/// parser's escapes need to be handled.
+pub(super) fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+#[async_trait::async_trait(?Send)]
+impl Installer for objects::Command {
+    async fn check_requirements(&self, _: &Context) -> Result<()> {
+        info!("'command' handle checking requirements");
+        if self.command.trim().is_empty() {
+            error!("'command' object has an empty command line");
+            return Err(Error::EmptyCommand);
+        }
+        if self.timeout.is_some() {
+            utils::fs::is_executable_in_path("timeout").log_error_msg("timeout not in PATH")?;
+        }
+
+        Ok(())
+    }
+
+    async fn install(&self, context: &Context) -> Result<()> {
+        info!("'command' handler Install {} ({})", self.filename, self.sha256sum);
+
+        let source = context.download_dir.join(self.sha256sum());
+        let source = source
+            .to_str()
+            .ok_or(Error::InvalidPath)
+            .log_error_msg("invalid path from download_dir for command")?;
+        let mut cmd = self.command.replace(FILE_PLACEHOLDER, &quote(source));
+        if let Some(timeout) = self.timeout {
+            cmd = format!("timeout -s KILL {} {}", timeout, cmd);
+        }
+
+        info!("running install command: {}", cmd);
+        // The command line is parsed as `easy_process::run` does, but it's
+        // run as a child process killed along with the install state when
+        // the state times out
+        let mut words = cmdline_words_parser::parse_posix(cmd.as_mut_str());
+        let program = words.next().ok_or(Error::EmptyCommand)?.to_owned();
+        let args = words.map(str::to_owned).collect::<Vec<_>>();
+        match crate::firmware::run_killable(&program, &args).await {
+            Ok(output) => {
+                for line in output.stdout.lines() {
+                    info!("command (stdout): {}", line);
+                }
+                for line in output.stderr.lines() {
+                    info!("command (stderr): {}", line);
+                }
+                Ok(())
+            }
+            Err(easy_process::Error::Failure(status, output)) => {
+                for line in output.stdout.lines() {
+                    error!("command (stdout): {}", line);
+                }
+                for line in output.stderr.lines() {
+                    error!("command (stderr): {}", line);
+                }
+                match self.timeout {
+                    Some(timeout)
+                        if status.code().map_or(false, |c| TIMEOUT_EXIT_CODES.contains(&c)) =>
+                    {
+                        error!("install command has timed out after {} seconds", timeout);
+                        Err(Error::CommandTimeout(timeout))
+                    }
+                    _ => {
+                        error!("install command has failed with status: {}", status);
+                        Err(easy_process::Error::Failure(status, output).into())
+                    }
+                }
+            }
+            Err(e) => {
+                error!("install command has failed to run: {}", e);
+                Err(e.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::{fs, path::Path};
+
+    fn fake_command_obj(command: &str, timeout: Option<u64>) -> objects::Command {
+        objects::Command {
+            filename: "install.sh".to_string(),
+            size: 0,
+            sha256sum: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+                .to_string(),
+            hash_algorithm: pkg_schema::definitions::HashAlgorithm::default(),
+            urls: Vec::new(),
+            command: command.to_string(),
+            timeout,
+            install_order: None,
+        }
+    }
+
+    fn setup(download_dir: &Path, script: &str, obj: &objects::Command) -> Context {
+        fs::create_dir_all(download_dir).unwrap();
+        fs::write(download_dir.join(&obj.sha256sum), script).unwrap();
+        Context { download_dir: download_dir.to_owned(), ..Context::default() }
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote(r#"/tmp/a b/it's $(rm -rf /) \n"#), r#"'/tmp/a b/it\'s $(rm -rf /) \\n'"#);
+    }
+
+    #[tokio::test]
+    async fn install_passes_file_verbatim() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let download_dir = tmpdir.path().join("it's a \"dir\" $(touch pwned); \\n");
+        let output = tmpdir.path().join("output");
+        let obj = fake_command_obj(&format!("sh {{file}} {}", output.display()), None);
+        let context = setup(&download_dir, "printf '%s' \"$0\" > \"$1\"", &obj);
+
+        obj.check_requirements(&context).await.unwrap();
+        obj.install(&context).await.unwrap();
+
+        assert_eq!(
+            fs::read_to_string(output).unwrap(),
+            download_dir.join(&obj.sha256sum).to_str().unwrap()
+        );
+        assert!(!tmpdir.path().join("pwned").exists());
+    }
+
+    #[tokio::test]
+    async fn install_fails_on_non_zero_exit() {
+        let download_dir = tempfile::tempdir().unwrap();
+        let obj = fake_command_obj("sh {file}", None);
+        let context = setup(download_dir.path(), "echo failing >&2; exit 3", &obj);
+
+        match obj.install(&context).await {
+            Err(Error::Process(easy_process::Error::Failure(status, output))) => {
+                assert_eq!(status.code(), Some(3));
+                assert_eq!(output.stderr, "failing\n");
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn install_times_out() {
+        let download_dir = tempfile::tempdir().unwrap();
+        let obj = fake_command_obj("sh {file}", Some(1));
+        let context = setup(download_dir.path(), "sleep 30", &obj);
+
+        obj.check_requirements(&context).await.unwrap();
+        match obj.install(&context).await {
+            Err(Error::CommandTimeout(1)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn install_killed_with_state() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let download_dir = tmpdir.path().join("download");
+        let output = tmpdir.path().join("output");
+        let obj = fake_command_obj(&format!("sh {{file}} {}", output.display()), None);
+        let context = setup(&download_dir, "sleep 1; touch \"$1\"", &obj);
+
+        // Dropping the install, as a state timing out does, kills the
+        // command before it's done
+        let res =
+            tokio::time::timeout(std::time::Duration::from_millis(100), obj.install(&context))
+                .await;
+        assert!(res.is_err());
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+        assert!(!output.exists());
+    }
+
+    #[tokio::test]
+    async fn empty_command() {
+        let obj = fake_command_obj("  ", None);
+        assert!(matches!(
+            obj.check_requirements(&Context::default()).await,
+            Err(Error::EmptyCommand)
+        ));
+    }
+}
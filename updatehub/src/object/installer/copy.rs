@@ -8,7 +8,7 @@ use crate::{
     utils::{self, definitions::TargetTypeExt, log::LogContent},
 };
 use pkg_schema::{definitions, objects};
-use slog_scope::info;
+use slog_scope::{error, info, warn};
 use std::os::unix::fs::PermissionsExt;
 use tokio::{
     fs,
@@ -20,7 +20,9 @@ impl Installer for objects::Copy {
     async fn check_requirements(&self, _: &Context) -> Result<()> {
         info!("'copy' handle checking requirements");
 
-        if let definitions::TargetType::Device(_) =
+        if let definitions::TargetType::Device(_)
+        | definitions::TargetType::File(_)
+        | definitions::TargetType::GptLabel(_) =
             self.target_type.valid().log_error_msg("device failed vaidation")?
         {
             utils::fs::ensure_disk_space(
@@ -41,7 +43,7 @@ impl Installer for objects::Copy {
         let filesystem = self.filesystem;
         let mount_options = &self.mount_options;
         let format_options = &self.target_format.format_options;
-        let chunk_size = definitions::ChunkSize::default().0;
+        let chunk_size = context.write_chunk_size();
         let sha256sum = self.sha256sum();
         let target_path = self.target_path.strip_prefix("/").unwrap_or(&self.target_path);
         let source = context.download_dir.join(sha256sum);
@@ -50,11 +52,9 @@ impl Installer for objects::Copy {
             let mount_guard = utils::fs::mount(&device, filesystem, mount_options)?;
             let file_path = mount_guard.mount_point().join(target_path);
             let should_skip_install = file_path.exists()
-                && super::should_skip_install(
-                    &self.install_if_different,
-                    &self.sha256sum,
-                    async move { Ok(fs::File::open(file_path).await?) },
-                )
+                && super::should_skip_install(&self.install_if_different, self, async move {
+                    Ok(fs::File::open(file_path).await?)
+                })
                 .await?;
             if should_skip_install {
                 return Ok(());
@@ -68,6 +68,15 @@ impl Installer for objects::Copy {
 
         let mount_guard = utils::fs::mount(&device, filesystem, mount_options)?;
         let dest = mount_guard.mount_point().join(target_path);
+        // Unless written in place, the content is written into a staged
+        // file renamed over the target once complete, so the target is
+        // never left partially written
+        let staged = if self.write_in_place {
+            None
+        } else {
+            Some(utils::fs::stage_file(&dest).log_error_msg("failed to stage target file")?)
+        };
+        let written = staged.as_ref().map_or(dest.as_path(), |f| f.path()).to_owned();
         let mut input = utils::io::timed_buf_reader(
             chunk_size,
             fs::File::open(source).await.log_error_msg("failed to open source object")?,
@@ -79,40 +88,79 @@ impl Installer for objects::Copy {
                 .write(true)
                 .create(true)
                 .truncate(true)
-                .open(&dest)
+                .open(&written)
                 .await
                 .log_error_msg("failed to open target file")?,
         );
 
         // File's access mode is changed here as we might not have write permission over
         // it. It will be restored or overwritten later on by the target_mode parameter
-        let metadata = dest.metadata().log_error_msg("failed to get target metadata")?;
+        let metadata = written.metadata().log_error_msg("failed to get target metadata")?;
         let orig_mode = metadata.permissions().mode();
         metadata.permissions().set_mode(0o100_666);
 
-        if self.compressed {
-            compress_tools::tokio_support::uncompress_data(&mut input, &mut output)
-                .await
-                .log_error_msg("failed to uncompress data")?;
+        // Compressed objects are only verified when the checksum of their
+        // uncompressed content is given
+        let mut hashing = utils::io::HashingWriter::new(&mut output, self.hash_algorithm());
+        let expected = if self.compressed {
+            utils::io::uncompress_data(
+                self.compression,
+                context.decompress_threads,
+                context.decompress_memory_limit,
+                &mut input,
+                &mut hashing,
+            )
+            .await
+            .log_error_msg("failed to uncompress data")?;
+            self.uncompressed_sha256sum.as_ref()
         } else {
-            io::copy(&mut input, &mut output)
+            io::copy_buf(&mut input, &mut hashing)
                 .await
                 .log_error_msg("failed to copy from object to target")?;
+            Some(&self.sha256sum)
+        };
+        let checksum = hashing.finish();
+        if let Some(expected) = expected {
+            if checksum != *expected {
+                error!("written {:?} has checksum {} instead of {}", dest, checksum, expected);
+                return Err(Error::WrittenChecksumMismatch {
+                    expected: expected.clone(),
+                    found: checksum,
+                });
+            }
         }
         output.flush().await.log_error_msg("failed to flush disk write")?;
+        output.get_ref().get_ref().sync_all().await.log_error_msg("failed to sync disk write")?;
         metadata.permissions().set_mode(orig_mode);
 
         if let Some(mode) = self.target_permissions.target_mode {
-            utils::fs::chmod(&dest, mode).log_error_msg("failed to update permission")?;
+            utils::fs::chmod(&written, mode).log_error_msg("failed to update permission")?;
         }
 
         utils::fs::chown(
-            &dest,
+            &written,
             &self.target_permissions.target_uid,
             &self.target_permissions.target_gid,
         )
         .log_error_msg("failed to update ownership")?;
 
+        for (name, value) in &self.target_permissions.target_xattrs {
+            match utils::fs::set_xattr(&written, name, value.as_bytes()) {
+                Ok(()) => {}
+                Err(e) if !self.target_permissions.strict_xattrs => {
+                    warn!("failed to set {} extended attribute of {:?}: {}", name, dest, e)
+                }
+                Err(e) => {
+                    error!("failed to set {} extended attribute of {:?}: {}", name, dest, e);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        if let Some(staged) = staged {
+            staged.persist(&dest).map_err(|e| e.error).log_error_msg("failed to rename target")?;
+        }
+
         Ok(())
     }
 }
@@ -164,7 +212,6 @@ mod tests {
 
         // Generate the source file
         let download_dir = tempfile::tempdir()?;
-        let mut source = tempfile::NamedTempFile::new_in(download_dir.path())?;
         let original_data = iter::repeat(DEFAULT_BYTE).take(FILE_SIZE).collect::<Vec<_>>();
         let data = if compressed {
             let mut e = GzEncoder::new(Vec::new(), Compression::default());
@@ -173,7 +220,8 @@ mod tests {
         } else {
             original_data.clone()
         };
-        source.write_all(&data)?;
+        let sha256sum = utils::sha256sum(&data);
+        std::fs::write(download_dir.path().join(&sha256sum), &data)?;
 
         // When needed, create a file inside the mounted device
         if let Some(perm) = original_permissions {
@@ -197,23 +245,31 @@ mod tests {
             filename: "".to_string(),
             filesystem: definitions::Filesystem::Ext4,
             size: FILE_SIZE as u64,
-            sha256sum: source.path().to_string_lossy().to_string(),
+            sha256sum,
             target_type: definitions::TargetType::Device(device.clone()),
             target_path: PathBuf::from("original_file"),
             install_if_different: None,
             target_permissions: definitions::TargetPermissions::default(),
             compressed: false,
+            compression: None,
+            uncompressed_sha256sum: None,
             required_uncompressed_size: 0,
             target_format: definitions::TargetFormat::default(),
             mount_options: String::default(),
+            write_in_place: false,
+            install_order: None,
+            hash_algorithm: definitions::HashAlgorithm::default(),
+            urls: Vec::new(),
         };
 
         // Change copy object to be used on current test
         f(&mut obj);
 
         // Peform Install
-        obj.check_requirements(&Context::default()).await?;
-        obj.install(&Context::default()).await?;
+        let context =
+            Context { download_dir: download_dir.path().to_owned(), ..Context::default() };
+        obj.check_requirements(&context).await?;
+        obj.install(&context).await?;
 
         // Validade File
         {
@@ -252,6 +308,16 @@ mod tests {
                 let gid = gid.as_u32();
                 assert_eq!(gid, metadata.gid());
             };
+
+            // No staged file is left behind
+            let entries = std::fs::read_dir(dest.parent().unwrap())?
+                .map(|e| e.map(|e| e.file_name()))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            assert!(
+                entries.iter().all(|name| !name.to_string_lossy().starts_with(".updatehub-")),
+                "{:?}",
+                entries
+            );
         }
 
         loopdev.detach()?;
@@ -265,6 +331,12 @@ mod tests {
         exec_test_with_copy(|obj| obj.compressed = true, None, true).await.unwrap();
     }
 
+    #[tokio::test]
+    #[ignore]
+    async fn copy_in_place() {
+        exec_test_with_copy(|obj| obj.write_in_place = true, None, false).await.unwrap();
+    }
+
     #[tokio::test]
     #[ignore]
     async fn copy_over_formated_partion() {
@@ -282,6 +354,7 @@ mod tests {
                 target_mode: Some(0o666),
                 target_gid: Some(definitions::target_permissions::Gid::Number(1000)),
                 target_uid: Some(definitions::target_permissions::Uid::Number(1000)),
+                ..definitions::TargetPermissions::default()
             }),
             false,
         )
@@ -316,6 +389,7 @@ mod tests {
                 target_mode: Some(0o666),
                 target_gid: Some(definitions::target_permissions::Gid::Number(1000)),
                 target_uid: Some(definitions::target_permissions::Uid::Number(1000)),
+                ..definitions::TargetPermissions::default()
             }),
             false,
         )
@@ -332,10 +406,48 @@ mod tests {
                 target_mode: Some(0o666),
                 target_gid: Some(definitions::target_permissions::Gid::Number(1000)),
                 target_uid: Some(definitions::target_permissions::Uid::Number(1000)),
+                ..definitions::TargetPermissions::default()
             }),
             false,
         )
         .await
         .unwrap();
     }
+
+    #[tokio::test]
+    #[ignore]
+    async fn copy_set_xattrs() {
+        exec_test_with_copy(
+            |obj| {
+                obj.target_permissions.target_xattrs =
+                    [("user.updatehub".to_string(), "value".to_string())].into();
+                obj.target_permissions.strict_xattrs = true;
+            },
+            None,
+            false,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn copy_unsupported_xattr() {
+        let unsupported = |obj: &mut objects::Copy| {
+            obj.target_permissions.target_xattrs =
+                [("unsupported.updatehub".to_string(), "value".to_string())].into();
+        };
+
+        exec_test_with_copy(unsupported, None, false).await.unwrap();
+        let res = exec_test_with_copy(
+            |obj| {
+                unsupported(obj);
+                obj.target_permissions.strict_xattrs = true;
+            },
+            None,
+            false,
+        )
+        .await;
+        assert!(matches!(res, Err(Error::Utils(_))), "Unexpected result: {:?}", res);
+    }
 }
@@ -38,7 +38,20 @@ impl Installer for objects::Flash {
         let target = self.target.get_target()?;
         let source = context.download_dir.join(self.sha256sum());
 
-        if super::should_skip_install(&self.install_if_different, &self.sha256sum, async {
+        // NAND pages read back through the MTD name may include padding and
+        // bad block remapping, so the checksum comparison is not reliable
+        let install_if_different = match (&self.target, &self.install_if_different) {
+            (
+                definitions::TargetType::MTDName(_),
+                Some(definitions::InstallIfDifferent::CheckSum),
+            ) => {
+                info!("checksum readback is not supported on MTD name targets, the object will be written");
+                None
+            }
+            (_, rule) => rule.clone(),
+        };
+
+        if super::should_skip_install(&install_if_different, self, async {
             tokio::fs::File::open(&target).await.map_err(Error::from)
         })
         .await?
@@ -78,6 +91,9 @@ mod tests {
             target: definitions::TargetType::MTDName(target.to_string()),
 
             install_if_different: None,
+            install_order: None,
+            hash_algorithm: definitions::HashAlgorithm::default(),
+            urls: Vec::new(),
         }
     }
 
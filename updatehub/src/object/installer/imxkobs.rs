@@ -24,7 +24,7 @@ impl Installer for objects::Imxkobs {
         info!("'imxkobs' handler Install {} ({})", self.filename, self.sha256sum);
 
         let should_skip_install =
-            super::should_skip_install(&self.install_if_different, &self.sha256sum, async {
+            super::should_skip_install(&self.install_if_different, self, async {
                 let path = self
                     .chip_0_device_path
                     .as_ref()
@@ -90,6 +90,9 @@ mod tests {
             search_exponent: 2,
             chip_0_device_path: Some(PathBuf::from("/dev/sda1")),
             chip_1_device_path: Some(PathBuf::from("/dev/sda2")),
+            install_order: None,
+            hash_algorithm: pkg_schema::definitions::HashAlgorithm::default(),
+            urls: Vec::new(),
         }
     }
 
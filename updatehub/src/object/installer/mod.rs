@@ -2,10 +2,12 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+mod command;
 mod copy;
 mod flash;
 mod imxkobs;
 mod mender;
+mod patch;
 mod raw;
 mod raw_delta;
 mod tarball;
@@ -14,7 +16,9 @@ mod ubifs;
 mod uboot_env;
 mod zephyr;
 
-use super::{Error, Result};
+pub(crate) use self::ubifs::ubi_device;
+
+use super::{Error, Info, Result};
 use crate::utils;
 use find_binary_version::{self as fbv, BinaryKind};
 use pkg_schema::{definitions, Object};
@@ -27,6 +31,64 @@ pub(crate) struct Context {
     pub(crate) download_dir: PathBuf,
     pub(crate) offline_update: bool,
     pub(crate) base_url: String,
+    pub(crate) download_client: DownloadClient,
+    pub(crate) write_chunk_size: Option<usize>,
+    pub(crate) stream_install: bool,
+    pub(crate) decompress_threads: usize,
+    pub(crate) decompress_memory_limit: Option<u64>,
+}
+
+/// Configuration the objects installed straight from the server are
+/// downloaded with, matching the one used by the download state.
+#[derive(Clone, Default)]
+pub(crate) struct DownloadClient {
+    pub(crate) server: String,
+    pub(crate) identity: Option<cloud::ClientIdentity>,
+    pub(crate) proxy: Option<cloud::Proxy>,
+    pub(crate) headers: cloud::Headers,
+    pub(crate) host_aliases: cloud::HostAliases,
+    pub(crate) rate_limiter: Option<cloud::RateLimiter>,
+    pub(crate) timeout: Option<std::time::Duration>,
+    pub(crate) retries: usize,
+    pub(crate) retry_backoff: std::time::Duration,
+    pub(crate) strict_content_length: bool,
+}
+
+impl DownloadClient {
+    pub(crate) fn client(&self) -> crate::CloudClient<'_> {
+        crate::CloudClient::new(&self.server, self.identity.as_ref(), self.proxy.as_ref())
+            .with_headers(&self.headers)
+            .with_host_aliases(&self.host_aliases)
+            .with_rate_limiter(self.rate_limiter.clone())
+            .with_download_timeout(self.timeout)
+            .with_strict_content_length(self.strict_content_length)
+    }
+}
+
+impl std::fmt::Debug for DownloadClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The client identity and the proxy hold credentials, so only
+        // their presence is shown
+        f.debug_struct("DownloadClient")
+            .field("server", &self.server)
+            .field("identity", &self.identity.is_some())
+            .field("proxy", &self.proxy.is_some())
+            .field("host_aliases", &self.host_aliases)
+            .field("rate_limiter", &self.rate_limiter)
+            .field("timeout", &self.timeout)
+            .field("retries", &self.retries)
+            .field("retry_backoff", &self.retry_backoff)
+            .field("strict_content_length", &self.strict_content_length)
+            .finish()
+    }
+}
+
+impl Context {
+    /// Gets the size of the buffers used to write the objects into their
+    /// targets.
+    pub(crate) fn write_chunk_size(&self) -> usize {
+        self.write_chunk_size.unwrap_or_else(|| definitions::ChunkSize::default().0)
+    }
 }
 
 #[async_trait::async_trait(?Send)]
@@ -53,13 +115,25 @@ impl Installer for Object {
 async fn check_if_different<R: AsyncRead + AsyncSeek + Unpin>(
     handle: &mut R,
     rule: &definitions::InstallIfDifferent,
-    sha256sum: &str,
+    object: &impl Info,
 ) -> Result<bool> {
     match rule {
         definitions::InstallIfDifferent::CheckSum => {
-            let mut buffer = Vec::default();
-            handle.read_to_end(&mut buffer).await?;
-            if utils::sha256sum(&buffer) == sha256sum {
+            // Only the region the object would be written to is read back,
+            // as the target is usually larger than the object itself
+            let mut reader = (&mut *handle).take(object.len());
+            let mut hasher = utils::Hasher::new(object.hash_algorithm());
+            let mut buffer = vec![0; definitions::ChunkSize::default().0];
+            let mut read = 0;
+            loop {
+                let n = reader.read(&mut buffer).await?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..n]);
+                read += n as u64;
+            }
+            if read == object.len() && hasher.finish() == object.sha256sum() {
                 return Ok(true);
             }
         }
@@ -93,7 +167,7 @@ async fn check_if_different<R: AsyncRead + AsyncSeek + Unpin>(
 
 async fn should_skip_install<F, R>(
     rule: &Option<definitions::InstallIfDifferent>,
-    sha256sum: &str,
+    object: &impl Info,
     handler: F,
 ) -> Result<bool>
 where
@@ -107,11 +181,13 @@ where
         }
         Some(ref rule) => {
             let mut h = handler.await?;
-            match check_if_different(&mut h, rule, sha256sum).await {
+            match check_if_different(&mut h, rule, object).await {
                 Ok(true) => {
                     info!(
-                        "installation of {} has been skipped (install if different): {}",
-                        sha256sum, rule,
+                        "installation of {} ({}) has been skipped as target already matches (install if different): {}",
+                        object.filename(),
+                        object.sha256sum(),
+                        rule,
                     );
                     Ok(true)
                 }
@@ -182,6 +258,32 @@ mod tests {
         Ok((mocks, calls))
     }
 
+    #[test]
+    fn write_chunk_size() {
+        assert_eq!(Context::default().write_chunk_size(), definitions::ChunkSize::default().0);
+        assert_eq!(
+            Context { write_chunk_size: Some(4096), ..Context::default() }.write_chunk_size(),
+            4096
+        );
+    }
+
+    fn fake_test_obj(
+        sha256sum: &str,
+        hash_algorithm: definitions::HashAlgorithm,
+        size: u64,
+    ) -> pkg_schema::objects::Test {
+        pkg_schema::objects::Test {
+            filename: "image".to_string(),
+            sha256sum: sha256sum.to_string(),
+            hash_algorithm,
+            urls: Vec::new(),
+            target: String::default(),
+            size,
+            force_check_requirements_fail: false,
+            install_order: None,
+        }
+    }
+
     #[tokio::test]
     async fn unmatched_checksum() {
         let f = tempfile::NamedTempFile::new().unwrap();
@@ -190,7 +292,7 @@ mod tests {
             !check_if_different(
                 &mut h,
                 &definitions::InstallIfDifferent::CheckSum,
-                "some_sha256sum"
+                &fake_test_obj("some_sha256sum", definitions::HashAlgorithm::Sha256, 14),
             )
             .await
             .unwrap(),
@@ -207,11 +309,72 @@ mod tests {
             check_if_different(
                 &mut h,
                 &definitions::InstallIfDifferent::CheckSum,
-                "7dc201ce54a835790d78835363a0bce4db704dd23c0c05e399d2a7d1f8fcef19",
+                &fake_test_obj(
+                    "7dc201ce54a835790d78835363a0bce4db704dd23c0c05e399d2a7d1f8fcef19",
+                    definitions::HashAlgorithm::Sha256,
+                    14,
+                ),
             )
             .await
             .unwrap(),
             "Empty fille should not be validated to the checksum"
         );
     }
+
+    #[tokio::test]
+    async fn checksum_with_blake3() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        tokio::fs::write(f.path(), b"some_sha256sum").await.unwrap();
+        let blake3sum = utils::digest(definitions::HashAlgorithm::Blake3, b"some_sha256sum");
+        let mut h = tokio::fs::File::open(f.path()).await.unwrap();
+        assert!(
+            check_if_different(
+                &mut h,
+                &definitions::InstallIfDifferent::CheckSum,
+                &fake_test_obj(&blake3sum, definitions::HashAlgorithm::Blake3, 14),
+            )
+            .await
+            .unwrap(),
+            "File should be validated to the blake3 checksum"
+        );
+
+        let mut h = tokio::fs::File::open(f.path()).await.unwrap();
+        assert!(
+            !check_if_different(
+                &mut h,
+                &definitions::InstallIfDifferent::CheckSum,
+                &fake_test_obj(&blake3sum, definitions::HashAlgorithm::Sha256, 14),
+            )
+            .await
+            .unwrap(),
+            "File should not be validated with a mismatching algorithm"
+        );
+    }
+
+    #[tokio::test]
+    async fn checksum_reads_back_object_size_only() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        tokio::fs::write(f.path(), b"some_sha256sum and some trailing data").await.unwrap();
+        let obj = fake_test_obj(
+            "7dc201ce54a835790d78835363a0bce4db704dd23c0c05e399d2a7d1f8fcef19",
+            definitions::HashAlgorithm::Sha256,
+            14,
+        );
+        let mut h = tokio::fs::File::open(f.path()).await.unwrap();
+        assert!(
+            check_if_different(&mut h, &definitions::InstallIfDifferent::CheckSum, &obj)
+                .await
+                .unwrap(),
+            "Only the object size should be read back from the target"
+        );
+
+        tokio::fs::write(f.path(), b"some_sha").await.unwrap();
+        let mut h = tokio::fs::File::open(f.path()).await.unwrap();
+        assert!(
+            !check_if_different(&mut h, &definitions::InstallIfDifferent::CheckSum, &obj)
+                .await
+                .unwrap(),
+            "Target smaller than the object should not be validated to the checksum"
+        );
+    }
 }
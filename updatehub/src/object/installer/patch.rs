@@ -0,0 +1,224 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{Context, Error, Result};
+use crate::{
+    object::{Info, Installer},
+    utils::{self, definitions::TargetTypeExt, log::LogContent},
+};
+use pkg_schema::{definitions, objects};
+use slog_scope::{error, info};
+use std::path::{Path, PathBuf};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWrite, AsyncWriteExt},
+};
+
+impl objects::Patch {
+    fn target_path(&self) -> Result<PathBuf> {
+        match self.target {
+            definitions::TargetType::Device(_)
+            | definitions::TargetType::File(_)
+            | definitions::TargetType::GptLabel(_) => Ok(self.target.get_target()?),
+            _ => Err(Error::InvalidTargetType(self.target.clone())),
+        }
+    }
+
+    /// Checks the start of the target holds the content the patch applies
+    /// to, copying it into `dest` when given.
+    async fn check_base(&self, target: &Path, dest: Option<&Path>) -> Result<()> {
+        let source = fs::File::open(target).await?;
+        let dest: Box<dyn AsyncWrite + Unpin> = match dest {
+            Some(dest) => Box::new(fs::File::create(dest).await?),
+            None => Box::new(tokio::io::sink()),
+        };
+        let mut dest = utils::io::HashingWriter::new(dest, self.hash_algorithm());
+        tokio::io::copy(&mut source.take(self.base_size), &mut dest).await?;
+        dest.flush().await?;
+
+        let checksum = dest.finish();
+        if checksum != self.base_sha256sum {
+            error!(
+                "target {:?} doesn't hold the base of patch {}, a full update is required",
+                target, self.filename
+            );
+            return Err(Error::PatchBaseMismatch {
+                expected: self.base_sha256sum.clone(),
+                found: checksum,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Installer for objects::Patch {
+    async fn check_requirements(&self, _: &Context) -> Result<()> {
+        info!("'patch' handle checking requirements");
+        utils::fs::is_executable_in_path("bspatch").log_error_msg("bspatch not in PATH")?;
+
+        self.target.valid().log_error_msg("target failed validation")?;
+        let target = self.target_path()?;
+        // Fail before downloading the patch if it can't be applied
+        self.check_base(&target, None).await
+    }
+
+    async fn install(&self, context: &Context) -> Result<()> {
+        info!("'patch' handler Install {} ({})", self.filename, self.sha256sum);
+
+        let target = self.target_path()?;
+        let patch = context.download_dir.join(self.sha256sum());
+        // The base and patched content are staged on the download dir, as
+        // the target is overwritten by the patched content
+        utils::fs::ensure_disk_space(&context.download_dir, self.base_size + self.patched_size)
+            .log_error_msg("not enough disk space to apply the patch")?;
+        let base = tempfile::NamedTempFile::new_in(&context.download_dir)?;
+        let patched = tempfile::NamedTempFile::new_in(&context.download_dir)?;
+
+        self.check_base(&target, Some(base.path())).await?;
+
+        let output = tokio::process::Command::new("bspatch")
+            .arg(base.path())
+            .arg(patched.path())
+            .arg(&patch)
+            .output()
+            .await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            error!("bspatch has failed with {}: {}", output.status, stderr.trim());
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("bspatch has failed with {}", output.status),
+            )
+            .into());
+        }
+
+        let mut hasher = utils::io::HashingWriter::new(tokio::io::sink(), self.hash_algorithm());
+        let len = tokio::io::copy(&mut fs::File::open(patched.path()).await?, &mut hasher).await?;
+        let checksum = hasher.finish();
+        if len != self.patched_size || checksum != self.patched_sha256sum {
+            error!("patch {} has produced unexpected content", self.filename);
+            return Err(Error::PatchResultMismatch {
+                expected: self.patched_sha256sum.clone(),
+                found: checksum,
+            });
+        }
+
+        // Devices keep their size, while files are replaced by the patched
+        // content
+        let mut dest = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(matches!(self.target, definitions::TargetType::File(_)))
+            .open(&target)
+            .await
+            .log_error_msg("failed to open target")?;
+        tokio::io::copy(&mut fs::File::open(patched.path()).await?, &mut dest).await?;
+        dest.sync_all().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::{env, fs, os::unix::fs::PermissionsExt};
+
+    const BASE: &[u8] = b"base content";
+    const PATCH: &[u8] = b", patched";
+
+    /// Creates a `bspatch` which appends the patch to the base content, so
+    /// the handling around it can be tested.
+    fn fake_bspatch() -> tempfile::TempDir {
+        let bin_dir = tempfile::tempdir().unwrap();
+        let bspatch = bin_dir.path().join("bspatch");
+        fs::write(&bspatch, "#!/bin/sh\ncat \"$1\" \"$3\" > \"$2\"\n").unwrap();
+        fs::set_permissions(&bspatch, fs::Permissions::from_mode(0o755)).unwrap();
+        env::set_var(
+            "PATH",
+            format!(
+                "{}{}",
+                bin_dir.path().display(),
+                env::var("PATH").map(|s| format!(":{}", s)).unwrap_or_default()
+            ),
+        );
+        bin_dir
+    }
+
+    fn fake_patch_obj(target: &Path) -> objects::Patch {
+        let patched = [BASE, PATCH].concat();
+        objects::Patch {
+            filename: "image.bspatch".to_string(),
+            size: PATCH.len() as u64,
+            sha256sum: utils::sha256sum(PATCH),
+            hash_algorithm: definitions::HashAlgorithm::default(),
+            urls: Vec::new(),
+            target: definitions::TargetType::File(target.to_owned()),
+            base_sha256sum: utils::sha256sum(BASE),
+            base_size: BASE.len() as u64,
+            patched_sha256sum: utils::sha256sum(&patched),
+            patched_size: patched.len() as u64,
+            install_order: None,
+        }
+    }
+
+    fn setup(dir: &Path, obj: &objects::Patch) -> Context {
+        let download_dir = dir.join("download");
+        fs::create_dir_all(&download_dir).unwrap();
+        fs::write(download_dir.join(&obj.sha256sum), PATCH).unwrap();
+        Context { download_dir, ..Context::default() }
+    }
+
+    #[tokio::test]
+    async fn install_patch() {
+        let _bin_dir = fake_bspatch();
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("image");
+        fs::write(&target, BASE).unwrap();
+        let obj = fake_patch_obj(&target);
+        let context = setup(dir.path(), &obj);
+
+        obj.check_requirements(&context).await.unwrap();
+        obj.install(&context).await.unwrap();
+
+        assert_eq!(fs::read(&target).unwrap(), [BASE, PATCH].concat());
+        // Only the patch is left on the download dir
+        assert_eq!(fs::read_dir(&context.download_dir).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn base_mismatch() {
+        let _bin_dir = fake_bspatch();
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("image");
+        fs::write(&target, b"other content").unwrap();
+        let obj = fake_patch_obj(&target);
+        let context = setup(dir.path(), &obj);
+
+        let err = obj.check_requirements(&context).await.unwrap_err();
+        assert!(matches!(err, Error::PatchBaseMismatch { .. }), "{:?}", err);
+        assert!(err.to_string().contains("a full update is required"), "{}", err);
+        assert!(matches!(obj.install(&context).await, Err(Error::PatchBaseMismatch { .. })));
+        assert_eq!(fs::read(&target).unwrap(), b"other content");
+    }
+
+    #[tokio::test]
+    async fn result_mismatch() {
+        let _bin_dir = fake_bspatch();
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("image");
+        fs::write(&target, BASE).unwrap();
+        let obj = objects::Patch {
+            patched_sha256sum: utils::sha256sum(b"expected content"),
+            ..fake_patch_obj(&target)
+        };
+        let context = setup(dir.path(), &obj);
+
+        assert!(matches!(obj.install(&context).await, Err(Error::PatchResultMismatch { .. })));
+        assert_eq!(fs::read(&target).unwrap(), BASE);
+    }
+}
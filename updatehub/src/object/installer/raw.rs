@@ -11,69 +11,399 @@ use crate::{
     utils::{self, definitions::TargetTypeExt, log::LogContent},
 };
 use pkg_schema::{definitions, objects};
-use slog_scope::info;
-use std::io::SeekFrom;
+use slog_scope::{error, info, warn};
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
 use tokio::{
     fs,
-    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt},
+    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
 };
 use tokio_take_seek::AsyncTakeSeekExt;
 
+impl objects::Raw {
+    fn targets(&self) -> impl Iterator<Item = &definitions::TargetType> {
+        std::iter::once(&self.target_type).chain(self.additional_targets.iter())
+    }
+
+    /// Checks if the object can be written into its targets as it is
+    /// downloaded, which requires the object to be written as is and
+    /// whole, so its checksum can be verified along the way. Sparse images
+    /// are only detected once downloaded, so objects with an expanded
+    /// checksum are never streamed.
+    pub(crate) fn is_streamable(&self) -> bool {
+        self.is_whole() && self.expanded_sha256sum.is_none()
+    }
+
+    /// Checks if the object is written as is and whole.
+    fn is_whole(&self) -> bool {
+        !self.compressed && self.skip.0 == 0 && matches!(self.count, definitions::Count::All)
+    }
+
+    /// Expands the sparse image into its targets, verifying the expanded
+    /// content when its checksum is given. File targets are extended to
+    /// the expanded size, as trailing don't care chunks are not written.
+    async fn expand_into<R, W>(
+        &self,
+        input: &mut R,
+        target: &mut W,
+        devices: &[PathBuf],
+    ) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + AsyncSeek + Unpin,
+    {
+        info!("expanding sparse image {} into its targets", self.filename);
+        let algorithm = self.expanded_sha256sum.as_ref().map(|_| self.hash_algorithm());
+        let expanded = utils::sparse::expand(input, target, algorithm)
+            .await
+            .log_error_msg("failed to expand sparse image")?;
+
+        if let (Some(expected), Some(found)) = (&self.expanded_sha256sum, &expanded.digest) {
+            if expected != found {
+                error!(
+                    "expanded image {} has checksum {} instead of {}, targets are left dirty",
+                    self.filename, found, expected
+                );
+                return Err(Error::DirtyTargets(self.filename.clone()));
+            }
+        }
+
+        let len = self.target_offset() + expanded.len;
+        for device in devices {
+            let file = std::fs::OpenOptions::new().write(true).open(device)?;
+            let metadata = file.metadata()?;
+            if metadata.is_file() && metadata.len() < len {
+                file.set_len(len)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Opens the targets for writing, positioned at the object's offset.
+    async fn open_targets(
+        &self,
+        devices: &[PathBuf],
+    ) -> Result<impl AsyncWrite + AsyncSeek + Unpin> {
+        let mut targets = Vec::with_capacity(devices.len());
+        for device in devices {
+            let mut target = utils::io::timed_buf_writer(
+                self.chunk_size.0,
+                fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(self.truncate.0)
+                    .open(device)
+                    .await
+                    .log_error_msg("failed to open target file")?,
+            );
+            target
+                .seek(SeekFrom::Start(self.target_offset()))
+                .await
+                .log_error_msg("failed to seek target file")?;
+            targets.push(target);
+        }
+        Ok(utils::io::FanOut::new(targets))
+    }
+
+    /// Downloads the object straight into its targets, verifying its
+    /// checksum once the whole object is written. Failed downloads are
+    /// retried from the start, as configured for the regular downloads.
+    async fn stream_into(&self, context: &Context, devices: &[PathBuf]) -> Result<()> {
+        let url = format!("{}/{}", context.base_url, self.sha256sum);
+        info!("streaming {} ({}) into its targets", self.filename, self.sha256sum);
+
+        let download = &context.download_client;
+        let api = download.client();
+        let mut attempt = 0;
+        let checksum = loop {
+            let mut target = utils::io::HashingWriter::new(
+                self.open_targets(devices).await?,
+                self.hash_algorithm(),
+            );
+            match api.stream_object_from(&url, self.size, &mut target, None).await {
+                Err(e) if attempt < download.retries && e.is_retryable() => {
+                    attempt += 1;
+                    let delay = utils::retry_delay(download.retry_backoff, attempt);
+                    info!(
+                        "retrying stream of {} in {:?} (attempt {} of {}), error: {}",
+                        self.filename, delay, attempt, download.retries, e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                res => {
+                    res.log_error_msg("failed to stream object into target")?;
+                    target.flush().await.log_error_msg("failed to flush target")?;
+                    break target.finish();
+                }
+            }
+        };
+
+        if checksum != self.sha256sum {
+            error!(
+                "streamed object {} has checksum {} instead of {}, targets are left dirty",
+                self.filename, checksum, self.sha256sum
+            );
+            return Err(Error::DirtyTargets(self.filename.clone()));
+        }
+
+        Ok(())
+    }
+
+    /// Gets the offset, in bytes, the object is written at within its
+    /// targets.
+    fn target_offset(&self) -> u64 {
+        self.seek * self.chunk_size.0 as u64 + self.target_offset.unwrap_or_default()
+    }
+
+    /// Checks the object fits in the region it's written to, when an
+    /// offset or a region size is given. The region must also fit within
+    /// the device.
+    fn check_target_region(&self, device: &Path) -> Result<()> {
+        use std::io::Seek;
+
+        if self.target_offset.is_none() && self.target_size.is_none() {
+            return Ok(());
+        }
+
+        let required = self.required_install_size();
+        let region = self.target_size.unwrap_or(required);
+        if required > region {
+            error!("object of {} bytes doesn't fit in the target region", required);
+            return Err(Error::TargetRegionTooSmall { available: region, required });
+        }
+
+        // Block devices report no length on their metadata, so their size
+        // is found by seeking to their end
+        let device_size = std::fs::File::open(device)?.seek(SeekFrom::End(0))?;
+        let available = device_size.saturating_sub(self.target_offset());
+        if region > available {
+            error!(
+                "target region of {} bytes at offset {} doesn't fit in {:?}",
+                region,
+                self.target_offset(),
+                device
+            );
+            return Err(Error::TargetRegionTooSmall { available, required: region });
+        }
+
+        Ok(())
+    }
+
+    /// Gets the filesystem the post-install actions are run on, if any.
+    /// They are skipped for images holding no filesystem.
+    fn post_install_filesystem(&self) -> Option<definitions::Filesystem> {
+        if self.post_install.is_empty() {
+            return None;
+        }
+        if self.filesystem.is_none() {
+            warn!("skipping post-install actions of '{}' as it holds no filesystem", self.filename);
+        }
+        self.filesystem
+    }
+
+    /// Gets the post-install actions in the order they are run. Resizing
+    /// a damaged filesystem can corrupt it further, so it's always
+    /// checked first.
+    fn post_install_actions(&self) -> Vec<definitions::PostInstallAction> {
+        let mut actions = self.post_install.clone();
+        if actions.contains(&definitions::PostInstallAction::Resize) {
+            actions.push(definitions::PostInstallAction::Fsck);
+        }
+        actions.sort();
+        actions.dedup();
+        actions
+    }
+
+    /// Runs the post-install actions on every written target.
+    fn run_post_install(&self, devices: &[PathBuf]) -> Result<()> {
+        let fs = match self.post_install_filesystem() {
+            Some(fs) => fs,
+            None => return Ok(()),
+        };
+        let actions = self.post_install_actions();
+
+        for device in devices {
+            for action in &actions {
+                info!("running {} on {:?}", action, device);
+                utils::fs::post_install(device, fs, *action)
+                    .log_error_msg("post-install action has failed")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads back the region written into every target, checking it holds
+    /// `len` bytes of the expected content, so silent write errors are
+    /// caught. Targets which can't be read back, as FIFOs, are skipped.
+    fn verify_targets(&self, devices: &[PathBuf], len: u64, expected: &str) -> Result<()> {
+        use std::{
+            io::{Read, Seek},
+            os::unix::{fs::FileTypeExt, io::AsRawFd},
+        };
+
+        for device in devices {
+            let file_type = std::fs::metadata(device)?.file_type();
+            if file_type.is_fifo() || file_type.is_socket() {
+                warn!("skipping read back of {:?} as it can't be read back", device);
+                continue;
+            }
+
+            let mut file = std::fs::File::open(device)?;
+            // The written content is dropped from the page cache, so it
+            // is read back from the target itself
+            file.sync_all()?;
+            let _ = nix::fcntl::posix_fadvise(
+                file.as_raw_fd(),
+                0,
+                0,
+                nix::fcntl::PosixFadviseAdvice::POSIX_FADV_DONTNEED,
+            );
+            if let Err(e) = file.seek(SeekFrom::Start(self.target_offset())) {
+                warn!("skipping read back of {:?} as it can't be seeked: {}", device, e);
+                continue;
+            }
+
+            let mut reader = file.take(len);
+            let mut hasher = utils::Hasher::new(self.hash_algorithm());
+            let mut buf = vec![0; self.chunk_size.0];
+            let mut read = 0;
+            loop {
+                let n = reader.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+                read += n as u64;
+            }
+
+            let checksum = hasher.finish();
+            if read != len || checksum != expected {
+                error!(
+                    "read back {} bytes of {:?} with checksum {} instead of {}",
+                    read, device, checksum, expected
+                );
+                return Err(Error::ReadbackMismatch {
+                    target: device.clone(),
+                    expected: expected.to_owned(),
+                    found: checksum,
+                });
+            }
+            info!("read back of {:?} matches the written content", device);
+        }
+
+        Ok(())
+    }
+
+    fn check_target(&self, target: &definitions::TargetType) -> Result<()> {
+        match target.valid().log_error_msg("device failed vaidation")? {
+            definitions::TargetType::Device(dev) => {
+                utils::fs::ensure_disk_space(dev, self.required_install_size())
+                    .log_error_msg("not enough disk space")?;
+                self.check_target_region(dev)
+            }
+            definitions::TargetType::GptLabel(_) => {
+                let dev = target.get_target()?;
+                utils::fs::ensure_disk_space(&dev, self.required_install_size())
+                    .log_error_msg("not enough disk space")?;
+                self.check_target_region(&dev)
+            }
+            // The file may not exist yet, so we check the space of the
+            // filesystem it is going to be written to
+            definitions::TargetType::File(file) => {
+                utils::fs::ensure_disk_space(
+                    file.parent().unwrap_or(file),
+                    self.required_install_size(),
+                )
+                .log_error_msg("not enough disk space")?;
+                Ok(())
+            }
+            _ => Err(Error::InvalidTargetType(target.clone())),
+        }
+    }
+}
+
 #[async_trait::async_trait(?Send)]
 impl Installer for objects::Raw {
     async fn check_requirements(&self, _: &Context) -> Result<()> {
         info!("'raw' handle checking requirements");
 
-        if let definitions::TargetType::Device(dev) =
-            self.target_type.valid().log_error_msg("device failed vaidation")?
-        {
-            utils::fs::ensure_disk_space(dev, self.required_install_size())
-                .log_error_msg("not enough disk space")?;
-            return Ok(());
+        for target in self.targets() {
+            self.check_target(target)?;
+        }
+        if let Some(fs) = self.post_install_filesystem() {
+            for action in self.post_install_actions() {
+                utils::fs::check_post_install(action, fs)
+                    .log_error_msg("post-install action can't be run")?;
+            }
         }
 
-        Err(Error::InvalidTargetType(self.target_type.clone()))
+        Ok(())
     }
 
     async fn install(&self, context: &Context) -> Result<()> {
         info!("'raw' handler Install {} ({})", self.filename, self.sha256sum);
 
-        let device = match self.target_type {
-            definitions::TargetType::Device(ref p) => p,
-            _ => unreachable!("device should be secured by check_requirements"),
-        };
         let source = context.download_dir.join(self.sha256sum());
         let chunk_size = self.chunk_size.0;
-        let seek = self.seek * chunk_size as u64;
+        let seek = self.target_offset();
         let skip = self.skip.0 * chunk_size as u64;
-        let truncate = self.truncate.0;
         let count = self.count.clone();
 
-        let should_skip_install =
-            super::should_skip_install(&self.install_if_different, &self.sha256sum, async {
-                trait AsyncReadSeek: AsyncRead + AsyncSeek + Unpin {}
-                impl<R: AsyncRead + AsyncSeek + Unpin> AsyncReadSeek for R {}
-
-                let h = fs::OpenOptions::new().read(true).open(device).await?;
-                let mut h = utils::io::timed_buf_reader(chunk_size, h);
-                h.seek(SeekFrom::Start(seek)).await?;
-                let h: Box<dyn AsyncReadSeek> = match &count {
-                    definitions::Count::All => Box::new(h),
-                    definitions::Count::Limited(n) => {
-                        Box::new(h.take_with_seek((*n as usize * chunk_size) as u64))
-                    }
-                };
-                Ok(h)
-            })
-            .await?;
-        if should_skip_install {
+        let mut devices = Vec::new();
+        for target in self.targets() {
+            let device = match target {
+                definitions::TargetType::Device(p) | definitions::TargetType::File(p) => p.clone(),
+                definitions::TargetType::GptLabel(_) => {
+                    target.get_target().log_error_msg("failed to get target device")?
+                }
+                _ => unreachable!("device should be secured by check_requirements"),
+            };
+
+            let should_skip_install = device.exists()
+                && super::should_skip_install(&self.install_if_different, self, async {
+                    trait AsyncReadSeek: AsyncRead + AsyncSeek + Unpin {}
+                    impl<R: AsyncRead + AsyncSeek + Unpin> AsyncReadSeek for R {}
+
+                    let h = fs::OpenOptions::new().read(true).open(&device).await?;
+                    let mut h = utils::io::timed_buf_reader(chunk_size, h);
+                    h.seek(SeekFrom::Start(seek)).await?;
+                    let h: Box<dyn AsyncReadSeek> = match &count {
+                        definitions::Count::All => Box::new(h),
+                        definitions::Count::Limited(n) => {
+                            Box::new(h.take_with_seek((*n as usize * chunk_size) as u64))
+                        }
+                    };
+                    Ok(h)
+                })
+                .await?;
+            if !should_skip_install {
+                devices.push(device);
+            }
+        }
+        if devices.is_empty() {
             return Ok(());
         }
 
+        if context.stream_install && self.is_streamable() && !source.exists() {
+            self.stream_into(context, &devices).await?;
+            if self.verify_after_write {
+                self.verify_targets(&devices, self.size, &self.sha256sum)?;
+            }
+            return self.run_post_install(&devices);
+        }
+
+        // The same stream is written into every target, so the object is
+        // only downloaded and uncompressed once.
+        let mut target = self.open_targets(&devices).await?;
+
         let mut input: Box<dyn AsyncRead + Unpin> = {
             let mut input = utils::io::timed_buf_reader(
                 chunk_size,
-                fs::File::open(source).await.log_error_msg("failed to open source file")?,
+                fs::File::open(&source).await.log_error_msg("failed to open source file")?,
             );
             input.seek(SeekFrom::Start(skip)).await.log_error_msg("failed to seek source file")?;
             match count {
@@ -83,31 +413,61 @@ impl Installer for objects::Raw {
                 }
             }
         };
-        let mut target = {
-            let mut target = utils::io::timed_buf_writer(
-                chunk_size,
-                fs::OpenOptions::new()
-                    .write(true)
-                    .truncate(truncate)
-                    .open(device)
-                    .await
-                    .log_error_msg("failed to open target file")?,
-            );
-            target.seek(SeekFrom::Start(seek)).await.log_error_msg("failed to seek target file")?;
-            target
-        };
 
-        if self.compressed {
-            compress_tools::tokio_support::uncompress_data(&mut input, &mut target)
-                .await
-                .log_error_msg("failed to uncompress data")?;
+        // Holds the length and digest of the content written
+        let written = if self.is_whole() && utils::sparse::is_sparse_image(&source).await? {
+            self.expand_into(&mut input, &mut target, &devices).await?;
+            None
         } else {
-            tokio::io::copy(&mut input, &mut target)
+            let mut target = utils::io::HashingWriter::new(&mut target, self.hash_algorithm());
+            if self.compressed {
+                utils::io::uncompress_data(
+                    self.compression,
+                    context.decompress_threads,
+                    context.decompress_memory_limit,
+                    &mut input,
+                    &mut target,
+                )
                 .await
-                .log_error_msg("failed copy from source into target")?;
+                .log_error_msg("failed to uncompress data")?;
+            } else {
+                tokio::io::copy(&mut input, &mut target)
+                    .await
+                    .log_error_msg("failed copy from source into target")?;
+            }
+            let (len, checksum) = (target.written(), target.finish());
+            if let Some(expected) = self.uncompressed_sha256sum.as_ref().filter(|_| self.compressed)
+            {
+                if *expected != checksum {
+                    error!(
+                        "uncompressed {} has checksum {} instead of {}, targets are left dirty",
+                        self.filename, checksum, expected
+                    );
+                    return Err(Error::DirtyTargets(self.filename.clone()));
+                }
+            }
+            Some((len, checksum))
+        };
+        target.flush().await?;
+        // The targets are closed before their filesystem is handled
+        drop(target);
+
+        if self.verify_after_write {
+            match written {
+                // Objects written as is must hold the object itself
+                Some(_) if self.is_whole() => {
+                    self.verify_targets(&devices, self.size, &self.sha256sum)?
+                }
+                Some((len, checksum)) => self.verify_targets(&devices, len, &checksum)?,
+                None => warn!(
+                    "skipping read back of sparse image '{}' as its don't care chunks are \
+                     not written",
+                    self.filename
+                ),
+            }
         }
 
-        Ok(())
+        self.run_post_install(&devices)
     }
 }
 
@@ -159,15 +519,27 @@ mod tests {
                 size,
                 sha256sum: source.path().to_string_lossy().to_string(),
                 target_type: definitions::TargetType::Device(dest.path().into()),
+                additional_targets: Vec::new(),
 
                 install_if_different: None,
                 compressed,
+                compression: None,
+                uncompressed_sha256sum: None,
                 required_uncompressed_size: 0,
                 chunk_size: definitions::ChunkSize(chunk_size),
                 skip: definitions::Skip(skip),
                 seek,
+                target_offset: None,
+                target_size: None,
                 count,
                 truncate: definitions::Truncate(truncate),
+                filesystem: None,
+                post_install: Vec::new(),
+                expanded_sha256sum: None,
+                verify_after_write: false,
+                install_order: None,
+                hash_algorithm: definitions::HashAlgorithm::default(),
+                urls: Vec::new(),
             },
             download_dir,
             source,
@@ -227,6 +599,48 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn raw_stream_install() {
+        let (mut obj, download_dir, _source_guard, target_guard, original_data) =
+            fake_raw_object(2048, 8, 0, 0, definitions::Count::All, false, false).unwrap();
+        obj.sha256sum = utils::sha256sum(&original_data);
+
+        // The first attempt fails, so the object is streamed again
+        crate::cloud_mock::set_failing_downloads(1);
+        crate::cloud_mock::set_download_data(original_data.clone());
+        let context = Context {
+            download_dir: download_dir.path().to_owned(),
+            download_client: crate::object::installer::DownloadClient {
+                retries: 1,
+                ..Default::default()
+            },
+            stream_install: true,
+            ..Context::default()
+        };
+        obj.install(&context).await.unwrap();
+
+        assert_eq!(std::fs::read(target_guard.path()).unwrap(), original_data);
+    }
+
+    #[tokio::test]
+    async fn raw_stream_install_checksum_mismatch() {
+        let (mut obj, download_dir, _source_guard, _target_guard, original_data) =
+            fake_raw_object(2048, 8, 0, 0, definitions::Count::All, false, false).unwrap();
+        obj.sha256sum = utils::sha256sum(&original_data);
+
+        crate::cloud_mock::set_download_data(original_data.iter().rev().copied().collect());
+        let context = Context {
+            download_dir: download_dir.path().to_owned(),
+            stream_install: true,
+            ..Context::default()
+        };
+
+        match obj.install(&context).await {
+            Err(Error::DirtyTargets(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
     #[tokio::test]
     async fn raw_full_copy_compressed() {
         let size = 2048;
@@ -250,6 +664,38 @@ mod tests {
             .unwrap();
     }
 
+    #[tokio::test]
+    async fn raw_full_copy_zstd_compressed() {
+        let size = 2048;
+        let chunk_size = 8;
+        let count = definitions::Count::All;
+        let seek = 0;
+        let skip = 0;
+        let truncate = false;
+
+        let (mut obj, download_dir, source_guard, target_guard, original_data) =
+            fake_raw_object(size, chunk_size, skip, seek, count.clone(), truncate, false).unwrap();
+        std::fs::write(source_guard.path(), zstd::encode_all(&original_data[..], 0).unwrap())
+            .unwrap();
+        obj.compressed = true;
+        obj.compression = Some(definitions::Compression::Zstd);
+        obj.uncompressed_sha256sum = Some(utils::sha256sum(&original_data));
+        let context =
+            Context { download_dir: download_dir.path().to_owned(), ..Context::default() };
+        obj.check_requirements(&context).await.unwrap();
+        obj.install(&context).await.unwrap();
+
+        validate_file(original_data, target_guard.path(), chunk_size, skip, seek, count)
+            .await
+            .unwrap();
+
+        obj.uncompressed_sha256sum = Some(utils::sha256sum(b"other content"));
+        match obj.install(&context).await {
+            Err(Error::DirtyTargets(filename)) => assert_eq!(filename, obj.filename),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
     #[tokio::test]
     async fn raw_full_copy() {
         let size = 2048;
@@ -344,4 +790,238 @@ mod tests {
             .unwrap();
         check_unwritten_blocks(target_guard.path(), 1024, 1024).await.unwrap();
     }
+
+    #[tokio::test]
+    async fn raw_copy_to_new_file() {
+        let size = 2048;
+        let chunk_size = 8;
+        let count = definitions::Count::All;
+        let seek = 0;
+        let skip = 0;
+        let truncate = false;
+        let compressed = false;
+
+        let (mut obj, download_dir, _source_guard, _target_guard, original_data) =
+            fake_raw_object(size, chunk_size, skip, seek, count.clone(), truncate, compressed)
+                .unwrap();
+        let target = download_dir.path().join("image.img");
+        obj.target_type = definitions::TargetType::File(target.clone());
+        let context =
+            Context { download_dir: download_dir.path().to_owned(), ..Context::default() };
+        obj.check_requirements(&context).await.unwrap();
+        obj.install(&context).await.unwrap();
+
+        validate_file(original_data, &target, chunk_size, skip, seek, count).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn raw_file_target_must_be_regular_file() {
+        let (mut obj, download_dir, ..) =
+            fake_raw_object(2048, 8, 0, 0, definitions::Count::All, false, false).unwrap();
+        obj.target_type = definitions::TargetType::File(download_dir.path().to_owned());
+        let context =
+            Context { download_dir: download_dir.path().to_owned(), ..Context::default() };
+
+        assert!(obj.check_requirements(&context).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn raw_copy_to_additional_targets() {
+        let size = 2048;
+        let chunk_size = 8;
+        let count = definitions::Count::All;
+        let seek = 0;
+        let skip = 0;
+        let truncate = false;
+        let compressed = true;
+
+        let (mut obj, download_dir, _source_guard, target_guard, original_data) =
+            fake_raw_object(size, chunk_size, skip, seek, count.clone(), truncate, compressed)
+                .unwrap();
+        let additional_target = download_dir.path().join("image.img");
+        obj.additional_targets = vec![definitions::TargetType::File(additional_target.clone())];
+        let context =
+            Context { download_dir: download_dir.path().to_owned(), ..Context::default() };
+        obj.check_requirements(&context).await.unwrap();
+        obj.install(&context).await.unwrap();
+
+        validate_file(
+            original_data.clone(),
+            target_guard.path(),
+            chunk_size,
+            skip,
+            seek,
+            count.clone(),
+        )
+        .await
+        .unwrap();
+        validate_file(original_data, &additional_target, chunk_size, skip, seek, count)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn raw_target_offset() {
+        let size = 2048;
+        let (mut obj, download_dir, _source_guard, target_guard, original_data) =
+            fake_raw_object(size, 8, 0, 0, definitions::Count::All, false, false).unwrap();
+        std::fs::write(target_guard.path(), vec![DEFAULT_BYTE; 4096]).unwrap();
+        obj.target_offset = Some(512);
+        let context =
+            Context { download_dir: download_dir.path().to_owned(), ..Context::default() };
+        obj.check_requirements(&context).await.unwrap();
+        obj.install(&context).await.unwrap();
+
+        let written = std::fs::read(target_guard.path()).unwrap();
+        assert_eq!(written.len(), 4096);
+        assert_eq!(&written[..512], &[DEFAULT_BYTE; 512][..]);
+        assert_eq!(&written[512..512 + size as usize], &original_data[..]);
+        check_unwritten_blocks(target_guard.path(), 512 + size, 4096 - 512 - size).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn raw_post_install() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (mut obj, download_dir, _source_guard, target_guard, _) =
+            fake_raw_object(2048, 8, 0, 0, definitions::Count::All, false, false).unwrap();
+        let context =
+            Context { download_dir: download_dir.path().to_owned(), ..Context::default() };
+        let calls = download_dir.path().join("calls");
+
+        // Images holding no filesystem are left as written
+        obj.post_install = vec![definitions::PostInstallAction::Resize];
+        obj.check_requirements(&context).await.unwrap();
+        obj.install(&context).await.unwrap();
+        assert!(!calls.exists());
+
+        let bin_dir = tempdir().unwrap();
+        for tool in ["e2fsck", "resize2fs"] {
+            let path = bin_dir.path().join(tool);
+            std::fs::write(
+                &path,
+                format!("#!/bin/sh\necho {} \"$@\" >> {}\n", tool, calls.display()),
+            )
+            .unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        std::env::set_var(
+            "PATH",
+            format!(
+                "{}{}",
+                bin_dir.path().display(),
+                std::env::var("PATH").map(|s| format!(":{}", s)).unwrap_or_default()
+            ),
+        );
+
+        // The filesystem is always checked before it is resized
+        obj.filesystem = Some(definitions::Filesystem::Ext4);
+        obj.check_requirements(&context).await.unwrap();
+        obj.install(&context).await.unwrap();
+        let target = target_guard.path().display();
+        assert_eq!(
+            std::fs::read_to_string(&calls).unwrap(),
+            format!("e2fsck -f -y {}\nresize2fs {}\n", target, target)
+        );
+
+        // Errors corrected by fsck don't fail the install, but the ones
+        // left on the filesystem do
+        std::fs::remove_file(&calls).unwrap();
+        let fsck = bin_dir.path().join("e2fsck");
+        obj.post_install = vec![definitions::PostInstallAction::Fsck];
+        std::fs::write(&fsck, "#!/bin/sh\nexit 1\n").unwrap();
+        obj.install(&context).await.unwrap();
+        std::fs::write(&fsck, "#!/bin/sh\nexit 4\n").unwrap();
+        assert!(obj.install(&context).await.is_err());
+        assert!(!calls.exists());
+
+        obj.filesystem = Some(definitions::Filesystem::Jffs2);
+        match obj.check_requirements(&context).await {
+            Err(Error::Utils(utils::Error::UnsupportedPostInstall(..))) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn raw_target_region_too_small() {
+        let (mut obj, download_dir, _source_guard, target_guard, _) =
+            fake_raw_object(2048, 8, 0, 0, definitions::Count::All, false, false).unwrap();
+        std::fs::write(target_guard.path(), vec![DEFAULT_BYTE; 4096]).unwrap();
+        let context =
+            Context { download_dir: download_dir.path().to_owned(), ..Context::default() };
+
+        obj.target_offset = Some(3072);
+        match obj.check_requirements(&context).await {
+            Err(Error::TargetRegionTooSmall { available: 1024, required: 2048 }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        obj.target_offset = Some(512);
+        obj.target_size = Some(1024);
+        match obj.check_requirements(&context).await {
+            Err(Error::TargetRegionTooSmall { available: 1024, required: 2048 }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        obj.target_size = Some(3584);
+        obj.check_requirements(&context).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn raw_sparse_install() {
+        let (image, expanded) = utils::sparse::tests::crafted_image();
+        let (mut obj, download_dir, source_guard, target_guard, _) =
+            fake_raw_object(expanded.len() as u64, 8, 0, 0, definitions::Count::All, false, false)
+                .unwrap();
+        std::fs::write(source_guard.path(), &image).unwrap();
+        obj.expanded_sha256sum = Some(utils::sha256sum(&expanded));
+        let context =
+            Context { download_dir: download_dir.path().to_owned(), ..Context::default() };
+        assert!(!obj.is_streamable());
+
+        obj.install(&context).await.unwrap();
+
+        // The trailing don't care chunk is seeked over
+        let data_len = 3 * utils::sparse::tests::BLOCK_SIZE as usize;
+        let written = std::fs::read(target_guard.path()).unwrap();
+        assert_eq!(written[..data_len], expanded[..data_len]);
+        assert_eq!(written[data_len..], vec![DEFAULT_BYTE; expanded.len() - data_len]);
+
+        obj.expanded_sha256sum = Some(utils::sha256sum(b"other content"));
+        match obj.install(&context).await {
+            Err(Error::DirtyTargets(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn raw_verify_after_write() {
+        let (mut obj, download_dir, _source_guard, target_guard, original_data) =
+            fake_raw_object(2048, 8, 0, 0, definitions::Count::All, false, false).unwrap();
+        obj.sha256sum = utils::sha256sum(&original_data);
+        std::fs::write(download_dir.path().join(&obj.sha256sum), &original_data).unwrap();
+        obj.verify_after_write = true;
+        let context =
+            Context { download_dir: download_dir.path().to_owned(), ..Context::default() };
+        obj.install(&context).await.unwrap();
+
+        // Trailing bytes of the target are not read back
+        let target = target_guard.path().to_owned();
+        std::fs::OpenOptions::new().append(true).open(&target).unwrap().write_all(b"x").unwrap();
+        obj.verify_targets(&[target.clone()], 2048, &obj.sha256sum).unwrap();
+
+        std::fs::write(&target, vec![DEFAULT_BYTE; 2048]).unwrap();
+        match obj.verify_targets(&[target], 2048, &obj.sha256sum) {
+            Err(Error::ReadbackMismatch { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // Compressed objects are checked against the uncompressed content
+        let (mut obj, download_dir, _source_guard, _target_guard, _) =
+            fake_raw_object(2048, 8, 0, 0, definitions::Count::All, false, true).unwrap();
+        obj.verify_after_write = true;
+        let context =
+            Context { download_dir: download_dir.path().to_owned(), ..Context::default() };
+        obj.install(&context).await.unwrap();
+    }
 }
@@ -16,18 +16,28 @@ impl Installer for objects::RawDelta {
     async fn check_requirements(&self, context: &Context) -> Result<()> {
         info!("'raw-delta' handle checking requirements");
 
-        if let definitions::TargetType::Device(dev) =
-            self.target.valid().log_error_msg("device failed validation")?
-        {
-            let seed = get_seed_path(self, context);
-            let required_size = delta::get_required_size(&seed, dev)
-                .await
-                .log_error_msg("failed to fetch delta required install size")?;
-            utils::fs::ensure_disk_space(dev, required_size)
-                .log_error_msg("not enough disk space")?;
-            return Ok(());
-        }
-        Err(Error::InvalidTargetType(self.target.clone()))
+        let (dev, space_path) =
+            match self.target.valid().log_error_msg("device failed validation")? {
+                definitions::TargetType::Device(dev) => (dev.clone(), dev.clone()),
+                definitions::TargetType::GptLabel(_) => {
+                    let dev = self.target.get_target()?;
+                    (dev.clone(), dev)
+                }
+                // The file may not exist yet, so we check the space of the
+                // filesystem it is going to be written to
+                definitions::TargetType::File(file) => {
+                    (file.clone(), file.parent().unwrap_or(file).to_path_buf())
+                }
+                _ => return Err(Error::InvalidTargetType(self.target.clone())),
+            };
+
+        let seed = get_seed_path(self, context);
+        let required_size = delta::get_required_size(&seed, &dev)
+            .await
+            .log_error_msg("failed to fetch delta required install size")?;
+        utils::fs::ensure_disk_space(&space_path, required_size)
+            .log_error_msg("not enough disk space")?;
+        Ok(())
     }
 
     async fn install(&self, context: &Context) -> Result<()> {
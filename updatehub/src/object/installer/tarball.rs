@@ -18,7 +18,9 @@ impl Installer for objects::Tarball {
         match self.target {
             definitions::TargetType::Device(_)
             | definitions::TargetType::UBIVolume(_)
-            | definitions::TargetType::MTDName(_) => {
+            | definitions::TargetType::MTDName(_)
+            | definitions::TargetType::File(_)
+            | definitions::TargetType::GptLabel(_) => {
                 utils::fs::ensure_disk_space(
                     &self.target.get_target().log_error_msg("failed to get target device")?,
                     self.required_install_size(),
@@ -110,6 +112,9 @@ mod tests {
             required_uncompressed_size: CONTENT_SIZE as u64,
             target_format: definitions::TargetFormat::default(),
             mount_options: String::default(),
+            install_order: None,
+            hash_algorithm: definitions::HashAlgorithm::default(),
+            urls: Vec::new(),
         };
         f(&mut obj);
         let context = Context { download_dir: PathBuf::from("fixtures"), ..Context::default() };
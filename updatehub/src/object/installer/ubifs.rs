@@ -8,7 +8,58 @@ use crate::{
     utils::{self, definitions::TargetTypeExt, log::LogContent},
 };
 use pkg_schema::{definitions, objects};
-use slog_scope::info;
+use slog_scope::{error, info};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_UBI_DEVICE: &str = "/dev/ubi0";
+
+/// Gets the UBI device the volume is created on.
+pub(crate) fn ubi_device(obj: &objects::Ubifs) -> PathBuf {
+    obj.ubi_device.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_UBI_DEVICE))
+}
+
+fn volume_size_arg(size: Option<u64>) -> String {
+    size.map_or_else(|| "-m".to_owned(), |size| format!("-s {}", size))
+}
+
+/// Creates the volume when it's missing, or resizes it when it doesn't
+/// have the requested size.
+fn prepare_volume(obj: &objects::Ubifs, volume: &str) -> Result<()> {
+    match utils::mtd::ubi_volume(volume)? {
+        None if obj.create_volume => {
+            let device = ubi_device(obj);
+            info!("creating UBI volume {} on {}", volume, device.display());
+            easy_process::run(&format!(
+                "ubimkvol {} -N {} {}",
+                device.display(),
+                volume,
+                volume_size_arg(obj.volume_size)
+            ))
+            .log_error_msg("ubimkvol failed to run")?;
+        }
+        Some(ubi) if obj.volume_size.map_or(false, |size| !ubi.has_size(size)) => {
+            info!("resizing UBI volume {} on {}", volume, ubi.device.display());
+            easy_process::run(&format!(
+                "ubirsvol {} -N {} {}",
+                ubi.device.display(),
+                volume,
+                volume_size_arg(obj.volume_size)
+            ))
+            .log_error_msg("ubirsvol failed to run")?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn ensure_device_exists(device: &Path) -> Result<()> {
+    if !device.exists() {
+        error!("UBI device {} not found", device.display());
+        return Err(utils::Error::DeviceDoesNotExist(device.to_owned()).into());
+    }
+    Ok(())
+}
 
 #[async_trait::async_trait(?Send)]
 impl Installer for objects::Ubifs {
@@ -19,20 +70,51 @@ impl Installer for objects::Ubifs {
             .log_error_msg("ubiupdatevol not on PATH")?;
         utils::fs::is_executable_in_path("ubinfo").log_error_msg("ubinfo not on PATH")?;
 
-        if let definitions::TargetType::UBIVolume(_) =
-            self.target.valid().log_error_msg("device failed validation")?
-        {
-            utils::fs::ensure_disk_space(&self.target.get_target()?, self.required_install_size())
-                .log_error_msg("not enough disk space")?;
-            return Ok(());
+        let volume = match &self.target {
+            definitions::TargetType::UBIVolume(volume) => volume,
+            _ => return Err(Error::InvalidTargetType(self.target.clone())),
+        };
+
+        if let Some(volume_size) = self.volume_size {
+            if volume_size < self.required_install_size() {
+                return Err(Error::VolumeTooSmall {
+                    volume_size,
+                    required: self.required_install_size(),
+                })
+                .log_error_msg("UBI volume can't hold the object");
+            }
         }
 
-        Err(Error::InvalidTargetType(self.target.clone()))
+        match utils::mtd::ubi_volume(volume)? {
+            None if self.create_volume => {
+                utils::fs::is_executable_in_path("ubimkvol")
+                    .log_error_msg("ubimkvol not on PATH")?;
+                ensure_device_exists(&ubi_device(self))
+            }
+            Some(ubi) if self.volume_size.map_or(false, |size| !ubi.has_size(size)) => {
+                utils::fs::is_executable_in_path("ubirsvol")
+                    .log_error_msg("ubirsvol not on PATH")?;
+                ensure_device_exists(&ubi.device)
+            }
+            _ => {
+                self.target.valid().log_error_msg("device failed validation")?;
+                utils::fs::ensure_disk_space(
+                    &self.target.get_target()?,
+                    self.required_install_size(),
+                )
+                .log_error_msg("not enough disk space")?;
+                Ok(())
+            }
+        }
     }
 
     async fn install(&self, context: &Context) -> Result<()> {
         info!("'ubifs' handler Install {} ({})", self.filename, self.sha256sum);
 
+        if let definitions::TargetType::UBIVolume(volume) = &self.target {
+            prepare_volume(self, volume)?;
+        }
+
         let target = self.target.get_target().log_error_msg("failed to get target device")?;
         let source = context.download_dir.join(self.sha256sum());
 
@@ -42,8 +124,13 @@ impl Installer for objects::Ubifs {
                 |stdin| {
                     let mut file =
                         std::fs::File::open(source).log_error_msg("failed open object")?;
-                    compress_tools::uncompress_data(&mut file, stdin)
-                        .log_error_msg("failed object to stdin of ubiupdatevol")?;
+                    utils::io::uncompress_data_sync(
+                        self.compression,
+                        context.decompress_memory_limit,
+                        &mut file,
+                        stdin,
+                    )
+                    .log_error_msg("failed object to stdin of ubiupdatevol")?;
                     Result::Ok(())
                 },
             )
@@ -75,7 +162,14 @@ mod tests {
             target: definitions::TargetType::UBIVolume(name.to_string()),
 
             compressed: false,
+            compression: None,
             required_uncompressed_size: 2048,
+            install_order: None,
+            hash_algorithm: definitions::HashAlgorithm::default(),
+            urls: Vec::new(),
+            volume_size: None,
+            create_volume: false,
+            ubi_device: None,
         }
     }
 
@@ -115,4 +209,67 @@ mod tests {
         let expected = format!("ubiupdatevol {} {}\n", target.display(), source.display());
         assert_eq!(std::fs::read_to_string(calls).unwrap(), expected);
     }
+
+    #[tokio::test]
+    async fn check_requirements_to_create_volume() {
+        let ubi_device = tempfile::NamedTempFile::new().unwrap();
+        let ubifs_obj = objects::Ubifs {
+            create_volume: true,
+            ubi_device: Some(ubi_device.path().to_owned()),
+            ..fake_ubifs_obj("missing-volume")
+        };
+
+        env::set_var("PATH", "");
+        let (_handle, _) = create_echo_bins(&["ubiupdatevol", "ubinfo"]).unwrap();
+        assert!(ubifs_obj.check_requirements(&Context::default()).await.is_err());
+
+        env::set_var("PATH", "");
+        let (_handle, _) = create_echo_bins(&["ubiupdatevol", "ubinfo", "ubimkvol"]).unwrap();
+        ubifs_obj.check_requirements(&Context::default()).await.unwrap();
+
+        let ubifs_obj = objects::Ubifs {
+            ubi_device: Some(ubi_device.path().with_extension("missing")),
+            ..ubifs_obj
+        };
+        assert!(ubifs_obj.check_requirements(&Context::default()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn check_requirements_with_small_volume() {
+        let ubifs_obj =
+            objects::Ubifs { volume_size: Some(512), ..fake_ubifs_obj("missing-volume") };
+
+        env::set_var("PATH", "");
+        let (_handle, _) = create_echo_bins(&["ubiupdatevol", "ubinfo"]).unwrap();
+        match ubifs_obj.check_requirements(&Context::default()).await {
+            Err(Error::VolumeTooSmall { volume_size: 512, required: 1024 }) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn install_resizing_volume() {
+        let _mtd_lock = SERIALIZE.lock();
+        let _ubi = FakeUbi::new(&["home"], MtdKind::Nor).unwrap();
+        let ubifs_obj = objects::Ubifs { volume_size: Some(4 << 20), ..fake_ubifs_obj("home") };
+        let download_dir = tempfile::tempdir().unwrap();
+        let target = ubifs_obj.target.get_target().unwrap();
+        let source = download_dir.path().join(&ubifs_obj.sha256sum);
+        let context =
+            Context { download_dir: download_dir.path().to_owned(), ..Context::default() };
+
+        let (_handle, calls) = create_echo_bins(&["ubiupdatevol", "ubirsvol"]).unwrap();
+
+        ubifs_obj.check_requirements(&context).await.unwrap();
+        ubifs_obj.install(&context).await.unwrap();
+
+        let expected = format!(
+            "ubirsvol /dev/ubi0 -N home -s {}\nubiupdatevol {} {}\n",
+            4 << 20,
+            target.display(),
+            source.display()
+        );
+        assert_eq!(std::fs::read_to_string(calls).unwrap(), expected);
+    }
 }
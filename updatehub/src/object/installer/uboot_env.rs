@@ -2,16 +2,60 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use super::{Context, Error, Result};
+use super::{command::quote, Context, Error, Result};
 use crate::{
     firmware,
     object::{Info, Installer},
     utils::{self, log::LogContent},
 };
-use pkg_schema::{definitions, objects};
-use slog_scope::info;
+use pkg_schema::objects;
+use slog_scope::{error, info};
+use std::{io::Write, path::Path};
 use tokio::{fs, io};
 
+impl objects::UbootEnv {
+    /// Sets the variables on the environment, checking each of them holds
+    /// the expected value afterwards. The variables are all set by a
+    /// single `fw_setenv` call, so the environment is written only once.
+    fn set_variables(&self, dir: &Path) -> Result<()> {
+        let mut script = tempfile::NamedTempFile::new_in(dir)?;
+        for (name, value) in &self.variables {
+            writeln!(script, "{} {}", name, value)?;
+        }
+        script.flush()?;
+
+        run(&format!("fw_setenv --script {}", quote(&script.path().to_string_lossy())))?;
+
+        for (name, expected) in &self.variables {
+            let output = run(&format!("fw_printenv -n {}", quote(name)))?;
+            let found = output.stdout.strip_suffix('\n').unwrap_or(&output.stdout);
+            if found != expected {
+                error!("U-Boot environment variable '{}' has not been set", name);
+                return Err(Error::UbootEnvMismatch {
+                    name: name.clone(),
+                    expected: expected.clone(),
+                    found: found.to_string(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs the command, logging its output when it fails.
+fn run(cmd: &str) -> Result<easy_process::Output> {
+    easy_process::run(cmd).map_err(|e| {
+        if let easy_process::Error::Failure(status, output) = &e {
+            for line in output.stdout.lines().chain(output.stderr.lines()) {
+                error!("{}: {}", cmd, line);
+            }
+            error!("'{}' has failed with status: {}", cmd, status);
+        }
+        e.into()
+    })
+}
+
 #[async_trait::async_trait(?Send)]
 impl Installer for objects::UbootEnv {
     async fn check_requirements(&self, _: &Context) -> Result<()> {
@@ -27,13 +71,31 @@ impl Installer for objects::UbootEnv {
             return Err(Error::FwSetEnvNoScriptOption);
         }
 
+        if !self.variables.is_empty() {
+            utils::fs::is_executable_in_path("fw_printenv")
+                .log_error_msg("fw_printenv not found in PATH")?;
+        }
+        for (name, value) in &self.variables {
+            if name.is_empty()
+                || name.contains(|c: char| c.is_whitespace() || c == '=')
+                || value.contains('\n')
+                // An empty value deletes the variable and the leading
+                // whitespace is dropped, so neither would hold the value
+                || value.starts_with(char::is_whitespace)
+                || value.is_empty()
+            {
+                error!("'uboot-env' object can't set variable '{}'", name);
+                return Err(Error::InvalidUbootEnvVariable(name.clone()));
+            }
+        }
+
         Ok(())
     }
 
     async fn install(&self, context: &Context) -> Result<()> {
         info!("'uboot-env' handler Install {} ({})", self.filename, self.sha256sum);
 
-        let chunk_size = definitions::ChunkSize::default().0;
+        let chunk_size = context.write_chunk_size();
 
         let source = context.download_dir.join(self.sha256sum());
         let mut input = utils::io::timed_buf_reader(
@@ -54,7 +116,7 @@ impl Installer for objects::UbootEnv {
                 .log_error_msg("failed to open target file")?,
         );
 
-        io::copy(&mut input, &mut output)
+        io::copy_buf(&mut input, &mut output)
             .await
             .log_error_msg("failed to copy from object to target")?;
 
@@ -67,6 +129,10 @@ impl Installer for objects::UbootEnv {
         firmware::installation_set::set_active(active_install_set)
             .log_error_msg("failed to set installation active set")?;
 
+        if !self.variables.is_empty() {
+            self.set_variables(&context.download_dir)?;
+        }
+
         Ok(())
     }
 }
@@ -77,12 +143,17 @@ mod tests {
     use crate::firmware::installation_set::Set;
     use pretty_assertions::assert_eq;
     use sdk::api::info::runtime_settings::InstallationSet;
+    use std::collections::BTreeMap;
 
     fn fake_uboot_env_obj() -> objects::UbootEnv {
         objects::UbootEnv {
             filename: "updatehub.defenv".to_string(),
             size: 1024,
             sha256sum: "e3b0c44298fc1c149afb".to_string(),
+            install_order: None,
+            hash_algorithm: pkg_schema::definitions::HashAlgorithm::default(),
+            urls: Vec::new(),
+            variables: BTreeMap::new(),
         }
     }
 
@@ -137,4 +208,80 @@ esac
         let expected = format!("updatehub-active-set {}\n", Set(InstallationSet::A));
         assert_eq!(std::fs::read_to_string(&setup.binaries.data).unwrap(), expected);
     }
+
+    #[tokio::test]
+    async fn check_requirements_with_invalid_variable() {
+        let setup = crate::tests::TestEnvironment::build()
+            .add_echo_binary("fw_setenv")
+            .add_echo_binary("fw_printenv")
+            .finish();
+        std::fs::write(setup.binaries.stored_path.join("fw_setenv"), "#!/bin/sh\necho --script\n")
+            .unwrap();
+
+        for (name, value) in [
+            ("boot part", "2"),
+            ("boot=part", "2"),
+            ("bootcmd", "a\nb"),
+            ("boot_part", ""),
+            ("boot_part", " 2"),
+        ] {
+            let uboot_env_obj = objects::UbootEnv {
+                variables: BTreeMap::from([(name.to_string(), value.to_string())]),
+                ..fake_uboot_env_obj()
+            };
+            assert!(matches!(
+                uboot_env_obj.check_requirements(&Context::default()).await,
+                Err(Error::InvalidUbootEnvVariable(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn install_variables() {
+        let setup = crate::tests::TestEnvironment::build()
+            .add_echo_binary("updatehub-active-set")
+            .add_echo_binary("fw_setenv")
+            .add_echo_binary("fw_printenv")
+            .finish();
+        let env = setup.binaries.stored_path.join("env");
+        std::fs::write(
+            setup.binaries.stored_path.join("fw_setenv"),
+            format!("#!/bin/sh\ncat \"$2\" >> {}\n", env.display()),
+        )
+        .unwrap();
+        std::fs::write(
+            setup.binaries.stored_path.join("fw_printenv"),
+            format!("#!/bin/sh\nsed -n \"s/^$2 //p\" {} | tail -n 1\n", env.display()),
+        )
+        .unwrap();
+
+        let uboot_env_obj = objects::UbootEnv {
+            variables: BTreeMap::from([
+                ("boot_part".to_string(), "2".to_string()),
+                ("upgrade_available".to_string(), "1".to_string()),
+            ]),
+            ..fake_uboot_env_obj()
+        };
+        let download_dir = setup.settings.data.update.download_dir.clone();
+        std::fs::write(download_dir.join(&uboot_env_obj.sha256sum), "abc").unwrap();
+        let context = Context { download_dir, ..Context::default() };
+
+        uboot_env_obj.install(&context).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&env).unwrap(), "boot_part 2\nupgrade_available 1\n");
+
+        // A variable not holding the expected value fails the installation
+        let uboot_env_obj = objects::UbootEnv {
+            variables: BTreeMap::from([("boot_part".to_string(), "3".to_string())]),
+            ..uboot_env_obj
+        };
+        std::fs::write(setup.binaries.stored_path.join("fw_setenv"), "#!/bin/sh\n").unwrap();
+        match uboot_env_obj.install(&context).await {
+            Err(Error::UbootEnvMismatch { name, expected, found }) => {
+                assert_eq!(name, "boot_part");
+                assert_eq!(expected, "3");
+                assert_eq!(found, "2");
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
 }
@@ -35,6 +35,18 @@ macro_rules! impl_object_for_object_types {
                 }
             }
 
+            fn hash_algorithm(&self) -> pkg_schema::definitions::HashAlgorithm {
+                match *self {
+                    $( Object::$objtype(ref o) => o.hash_algorithm(), )*
+                }
+            }
+
+            fn urls(&self) -> &[String] {
+                match *self {
+                    $( Object::$objtype(ref o) => o.urls(), )*
+                }
+            }
+
             fn required_install_size(&self) -> u64 {
                 match *self {
                     $( Object::$objtype(ref o) => o.required_install_size(), )*
@@ -69,6 +81,14 @@ macro_rules! impl_object_info {
                 &self.sha256sum
             }
 
+            fn hash_algorithm(&self) -> pkg_schema::definitions::HashAlgorithm {
+                self.hash_algorithm
+            }
+
+            fn urls(&self) -> &[String] {
+                &self.urls
+            }
+
             fn required_install_size(&self) -> u64 {
                 self.size
             }
@@ -95,6 +115,14 @@ macro_rules! impl_compressed_object_info {
                 &self.sha256sum
             }
 
+            fn hash_algorithm(&self) -> pkg_schema::definitions::HashAlgorithm {
+                self.hash_algorithm
+            }
+
+            fn urls(&self) -> &[String] {
+                &self.urls
+            }
+
             fn required_install_size(&self) -> u64 {
                 if self.compressed { self.required_uncompressed_size } else { self.size }
             }
@@ -121,6 +149,14 @@ macro_rules! impl_remote_object_info {
                 &self.sha256sum
             }
 
+            fn hash_algorithm(&self) -> pkg_schema::definitions::HashAlgorithm {
+                self.hash_algorithm
+            }
+
+            fn urls(&self) -> &[String] {
+                &self.urls
+            }
+
             fn required_install_size(&self) -> u64 {
                 self.size
             }
@@ -135,10 +171,12 @@ macro_rules! impl_remote_object_info {
 macro_rules! for_any_object {
     ($mode:ident, $alias:ident, $code:block) => {
         match $mode {
+            Object::Command($alias) => $code,
             Object::Copy($alias) => $code,
             Object::Flash($alias) => $code,
             Object::Imxkobs($alias) => $code,
             Object::Mender($alias) => $code,
+            Object::Patch($alias) => $code,
             Object::Raw($alias) => $code,
             Object::RawDelta($alias) => $code,
             Object::Tarball($alias) => $code,
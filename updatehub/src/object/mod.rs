@@ -9,7 +9,17 @@ pub(crate) mod info;
 pub(crate) mod installer;
 
 pub(crate) use self::{info::Info, installer::Installer};
+use crate::{
+    firmware::installation_set::Set,
+    utils::{self, definitions::TargetTypeExt},
+};
 use derive_more::{Display, Error, From};
+use pkg_schema::{definitions::TargetType, Object};
+use slog_scope::{debug, error};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -21,13 +31,450 @@ pub enum Error {
     FwSetEnvNoScriptOption,
     #[display(fmt = "unsupported object model")]
     Unsupported,
+    #[display(fmt = "command line is empty")]
+    EmptyCommand,
 
     Utils(crate::utils::Error),
     Firmware(crate::firmware::Error),
 
     #[display(fmt = "invalid target type {:?}", _0)]
     InvalidTargetType(#[error(not(source))] pkg_schema::definitions::TargetType),
+    #[display(fmt = "invalid targets: {}", _0)]
+    InvalidTargets(#[error(not(source))] String),
     Io(std::io::Error),
     Process(easy_process::Error),
     Uncompress(compress_tools::Error),
+    Cloud(cloud::Error),
+
+    #[display(
+        fmt = "volume size of {} bytes can't hold the object of {} bytes",
+        volume_size,
+        required
+    )]
+    VolumeTooSmall {
+        volume_size: u64,
+        required: u64,
+    },
+
+    #[display(
+        fmt = "target region of {} bytes can't hold the object of {} bytes",
+        available,
+        required
+    )]
+    #[from(ignore)]
+    TargetRegionTooSmall {
+        available: u64,
+        required: u64,
+    },
+
+    #[display(
+        fmt = "target content has checksum {} instead of the patch base {}, a full update is required",
+        found,
+        expected
+    )]
+    #[from(ignore)]
+    PatchBaseMismatch {
+        expected: String,
+        found: String,
+    },
+
+    #[display(fmt = "written content has checksum {} instead of {}", found, expected)]
+    #[from(ignore)]
+    WrittenChecksumMismatch {
+        expected: String,
+        found: String,
+    },
+
+    #[display(fmt = "{:?} read back with checksum {} instead of {}", target, found, expected)]
+    #[from(ignore)]
+    ReadbackMismatch {
+        target: PathBuf,
+        expected: String,
+        found: String,
+    },
+
+    #[display(fmt = "patched content has checksum {} instead of {}", found, expected)]
+    #[from(ignore)]
+    PatchResultMismatch {
+        expected: String,
+        found: String,
+    },
+
+    #[display(fmt = "no installation set devices named '{}'", _0)]
+    #[from(ignore)]
+    UnknownSetDevice(#[error(not(source))] String),
+
+    #[display(fmt = "target {:?} is a device of the active installation set", _0)]
+    #[from(ignore)]
+    ActiveSetTarget(#[error(not(source))] PathBuf),
+
+    #[display(fmt = "command has not finished after {} seconds", _0)]
+    CommandTimeout(#[error(not(source))] u64),
+
+    #[display(
+        fmt = "streamed object '{}' failed checksum verification, its targets are dirty",
+        _0
+    )]
+    #[from(ignore)]
+    DirtyTargets(#[error(not(source))] String),
+
+    #[display(fmt = "invalid U-Boot environment variable '{}'", _0)]
+    #[from(ignore)]
+    InvalidUbootEnvVariable(#[error(not(source))] String),
+
+    #[display(
+        fmt = "U-Boot environment variable '{}' holds '{}' instead of '{}'",
+        name,
+        found,
+        expected
+    )]
+    #[from(ignore)]
+    UbootEnvMismatch {
+        name: String,
+        expected: String,
+        found: String,
+    },
+}
+
+/// Gets the targets the object is installed into, for the objects that
+/// have them.
+pub(crate) fn target_types(object: &Object) -> Vec<&TargetType> {
+    match object {
+        Object::Copy(o) => vec![&o.target_type],
+        Object::Flash(o) => vec![&o.target],
+        Object::Raw(o) => {
+            std::iter::once(&o.target_type).chain(o.additional_targets.iter()).collect()
+        }
+        Object::Patch(o) => vec![&o.target],
+        Object::RawDelta(o) => vec![&o.target],
+        Object::Tarball(o) => vec![&o.target],
+        Object::Ubifs(o) => vec![&o.target],
+        Object::Command(_)
+        | Object::Imxkobs(_)
+        | Object::Mender(_)
+        | Object::Test(_)
+        | Object::UbootEnv(_)
+        | Object::Zephyr(_) => Vec::new(),
+    }
+}
+
+fn target_types_mut(object: &mut Object) -> Vec<&mut TargetType> {
+    match object {
+        Object::Copy(o) => vec![&mut o.target_type],
+        Object::Flash(o) => vec![&mut o.target],
+        Object::Raw(o) => {
+            std::iter::once(&mut o.target_type).chain(o.additional_targets.iter_mut()).collect()
+        }
+        Object::Patch(o) => vec![&mut o.target],
+        Object::RawDelta(o) => vec![&mut o.target],
+        Object::Tarball(o) => vec![&mut o.target],
+        Object::Ubifs(o) => vec![&mut o.target],
+        Object::Command(_)
+        | Object::Imxkobs(_)
+        | Object::Mender(_)
+        | Object::Test(_)
+        | Object::UbootEnv(_)
+        | Object::Zephyr(_) => Vec::new(),
+    }
+}
+
+/// Checks if the object can be installed into the kind of target.
+pub(crate) fn accepts_target(object: &Object, target: &TargetType) -> bool {
+    matches!(
+        (object, target),
+        (Object::Tarball(_), _)
+            | (Object::Ubifs(_), TargetType::UBIVolume(_))
+            | (Object::Flash(_), TargetType::Device(_) | TargetType::MTDName(_))
+            | (
+                Object::Copy(_) | Object::Patch(_) | Object::Raw(_) | Object::RawDelta(_),
+                TargetType::Device(_) | TargetType::File(_) | TargetType::GptLabel(_)
+            )
+    )
+}
+
+/// Gets the install priority of the object, if the package defines one.
+pub(crate) fn install_order(object: &Object) -> Option<i32> {
+    for_any_object!(object, o, { o.install_order })
+}
+
+/// Checks if the object is written into its targets as it is downloaded
+/// when stream install is enabled, so it doesn't need to be staged on the
+/// download dir.
+pub(crate) fn is_streamable(object: &Object) -> bool {
+    match object {
+        Object::Raw(o) => o.is_streamable(),
+        _ => false,
+    }
+}
+
+/// Validates the target of every object, reporting all the failing ones
+/// at once.
+pub(crate) fn validate_targets<'a>(objects: impl IntoIterator<Item = &'a Object>) -> Result<()> {
+    let failures = objects
+        .into_iter()
+        .flat_map(|o| {
+            target_types(o).into_iter().filter_map(move |target| {
+                let err = valid_target(o, target).err()?;
+                error!("target {:?} of '{}' failed validation: {}", target, o.filename(), err);
+                Some(format!("{:?} ({})", target, err))
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    Err(Error::InvalidTargets(failures.join(", ")))
+}
+
+/// Checks the target can be installed into. Volumes created on install
+/// don't exist yet, so the UBI device they are created on is checked
+/// instead.
+fn valid_target(object: &Object, target: &TargetType) -> utils::Result<()> {
+    if let (Object::Ubifs(o), TargetType::UBIVolume(volume)) = (object, target) {
+        if o.create_volume && !utils::mtd::ubi_volume_exists(volume)? {
+            let device = installer::ubi_device(o);
+            if !device.exists() {
+                return Err(utils::Error::DeviceDoesNotExist(device));
+            }
+            return Ok(());
+        }
+    }
+
+    target.valid().map(drop)
+}
+
+/// Resolves the set-relative device targets, written as `{name}`, into
+/// the named device of the installation set being updated.
+pub(crate) fn resolve_set_targets<'a>(
+    objects: impl IntoIterator<Item = &'a mut Object>,
+    devices: &BTreeMap<String, [PathBuf; 2]>,
+    set: Set,
+) -> Result<()> {
+    for target in objects.into_iter().flat_map(target_types_mut) {
+        if let TargetType::Device(device) = target {
+            let name = match set_device_name(device) {
+                Some(name) => name,
+                None => continue,
+            };
+            let resolved = devices
+                .get(name)
+                .map(|d| d[set.index()].clone())
+                .ok_or_else(|| Error::UnknownSetDevice(name.to_owned()))?;
+            debug!("target {:?} resolved to {:?} on installation set {}", device, resolved, set);
+            *device = resolved;
+        }
+    }
+
+    Ok(())
+}
+
+fn set_device_name(device: &Path) -> Option<&str> {
+    device.to_str()?.strip_prefix('{')?.strip_suffix('}')
+}
+
+/// Refuses objects targeting the devices of the active installation set,
+/// as writing into them would overwrite the running system.
+pub(crate) fn check_active_set_targets<'a>(
+    objects: impl IntoIterator<Item = &'a Object>,
+    devices: &BTreeMap<String, [PathBuf; 2]>,
+    active: Set,
+) -> Result<()> {
+    let canonical = |p: &Path| p.canonicalize().unwrap_or_else(|_| p.to_owned());
+    let active_devices =
+        devices.values().map(|d| canonical(&d[active.index()])).collect::<Vec<_>>();
+    if active_devices.is_empty() {
+        return Ok(());
+    }
+
+    for object in objects {
+        for target in target_types(object) {
+            // Targets which can't be found are left for the target
+            // validation to report
+            let device = match target.get_target() {
+                Ok(device) => canonical(&device),
+                Err(_) => continue,
+            };
+            if active_devices.contains(&device) {
+                error!(
+                    "'{}' targets {:?} of the active installation set",
+                    object.filename(),
+                    device
+                );
+                return Err(Error::ActiveSetTarget(device));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use info::Status;
+    use pkg_schema::definitions::HashAlgorithm;
+    use serde_json::json;
+
+    fn raw_object(target_type: &str, target: &str) -> Object {
+        serde_json::from_value(json!({
+            "mode": "raw",
+            "filename": "image",
+            "size": 1024,
+            "sha256sum": "cfe2be1c64b0387500853de0f48303e3de7b1c6f1508dc719eeafa0d41c36722",
+            "target-type": target_type,
+            "target": target,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn valid_targets() {
+        let dir = tempfile::tempdir().unwrap();
+        let device = tempfile::NamedTempFile::new_in(dir.path()).unwrap();
+        let objects = vec![
+            raw_object("device", device.path().to_str().unwrap()),
+            raw_object("file", dir.path().join("image.img").to_str().unwrap()),
+        ];
+
+        validate_targets(&objects).unwrap();
+    }
+
+    #[test]
+    fn additional_targets_are_validated() {
+        let dir = tempfile::tempdir().unwrap();
+        let device = tempfile::NamedTempFile::new_in(dir.path()).unwrap();
+        let mut object = raw_object("device", device.path().to_str().unwrap());
+        if let Object::Raw(ref mut raw) = object {
+            raw.additional_targets =
+                vec![TargetType::Device(std::path::PathBuf::from("/dev/missing-device"))];
+        }
+
+        match validate_targets(&[object]) {
+            Err(Error::InvalidTargets(msg)) => {
+                assert!(msg.contains("/dev/missing-device"), "{}", msg)
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    fn set_devices(dir: &Path) -> BTreeMap<String, [PathBuf; 2]> {
+        let mut devices = BTreeMap::new();
+        devices.insert("rootfs".to_owned(), [dir.join("rootfs-a"), dir.join("rootfs-b")]);
+        devices
+    }
+
+    #[test]
+    fn set_targets_are_resolved() {
+        use sdk::api::info::runtime_settings::InstallationSet;
+
+        let dir = tempfile::tempdir().unwrap();
+        let devices = set_devices(dir.path());
+
+        for (set, device) in [(InstallationSet::A, "rootfs-a"), (InstallationSet::B, "rootfs-b")] {
+            let mut objects = vec![raw_object("device", "{rootfs}")];
+            resolve_set_targets(&mut objects, &devices, Set(set)).unwrap();
+            assert_eq!(target_types(&objects[0]), [&TargetType::Device(dir.path().join(device))]);
+        }
+
+        let mut objects = vec![raw_object("device", "{bootfs}")];
+        match resolve_set_targets(&mut objects, &devices, Set(InstallationSet::A)) {
+            Err(Error::UnknownSetDevice(name)) => assert_eq!(name, "bootfs"),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn active_set_targets_are_refused() {
+        use sdk::api::info::runtime_settings::InstallationSet;
+
+        let dir = tempfile::tempdir().unwrap();
+        let devices = set_devices(dir.path());
+        for device in ["rootfs-a", "rootfs-b"] {
+            std::fs::write(dir.path().join(device), b"").unwrap();
+        }
+
+        for (active, active_device, inactive_device) in [
+            (InstallationSet::A, "rootfs-a", "rootfs-b"),
+            (InstallationSet::B, "rootfs-b", "rootfs-a"),
+        ] {
+            let objects =
+                vec![raw_object("device", dir.path().join(inactive_device).to_str().unwrap())];
+            check_active_set_targets(&objects, &devices, Set(active)).unwrap();
+
+            // The device is matched even when reached through another path
+            let mut object =
+                raw_object("device", dir.path().join(inactive_device).to_str().unwrap());
+            if let Object::Raw(ref mut raw) = object {
+                raw.additional_targets =
+                    vec![TargetType::Device(dir.path().join(".").join(active_device))];
+            }
+            match check_active_set_targets(&[object], &devices, Set(active)) {
+                Err(Error::ActiveSetTarget(device)) => {
+                    assert_eq!(device, dir.path().join(active_device).canonicalize().unwrap())
+                }
+                res => panic!("Unexpected result: {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn status_uses_hash_algorithm() {
+        let download_dir = tempfile::tempdir().unwrap();
+        let content = b"some object content";
+        let blake3sum = crate::utils::digest(HashAlgorithm::Blake3, content);
+        std::fs::write(download_dir.path().join(&blake3sum), content).unwrap();
+
+        let mut object: Object = serde_json::from_value(json!({
+            "mode": "test",
+            "filename": "image",
+            "size": content.len(),
+            "sha256sum": blake3sum,
+            "hash-algorithm": "blake3",
+            "target": "/dev/null",
+            "force-check-requirements-fail": false,
+            "force-install-fail": false,
+        }))
+        .unwrap();
+        assert_eq!(object.status(download_dir.path()).unwrap(), Status::Ready);
+
+        if let Object::Test(ref mut test) = object {
+            test.hash_algorithm = HashAlgorithm::Sha256;
+        }
+        assert_eq!(object.status(download_dir.path()).unwrap(), Status::Corrupted);
+    }
+
+    #[test]
+    fn unknown_hash_algorithm() {
+        let res = serde_json::from_value::<Object>(json!({
+            "mode": "raw",
+            "filename": "image",
+            "size": 1024,
+            "sha256sum": "cfe2be1c64b0387500853de0f48303e3de7b1c6f1508dc719eeafa0d41c36722",
+            "hash-algorithm": "md5",
+            "target-type": "device",
+            "target": "/dev/null",
+        }));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn invalid_targets_are_all_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let objects = vec![
+            raw_object("device", "/dev/missing-device"),
+            raw_object("file", dir.path().to_str().unwrap()),
+            raw_object("file", dir.path().join("image.img").to_str().unwrap()),
+        ];
+
+        match validate_targets(&objects) {
+            Err(Error::InvalidTargets(msg)) => {
+                assert!(msg.contains("/dev/missing-device"), "{}", msg);
+                assert!(msg.contains("not a regular file"), "{}", msg);
+                assert!(!msg.contains("image.img"), "{}", msg);
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
 }
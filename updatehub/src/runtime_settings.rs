@@ -6,10 +6,10 @@ use crate::firmware::{
     self,
     installation_set::{self, Set},
 };
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use derive_more::{Deref, DerefMut, Display, Error, From};
-use sdk::api::info::runtime_settings as api;
-use slog_scope::{debug, warn};
+use sdk::api::info::{runtime_settings as api, settings};
+use slog_scope::{debug, info, warn};
 use std::{fs, io, path::Path};
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -28,6 +28,26 @@ pub enum Error {
     V1Parsing(serde_json::Error, serde_ini::de::Error),
 }
 
+/// Outcome of saving the runtime settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Saved {
+    /// Written to the runtime settings file.
+    Persisted,
+    /// Kept in memory only, as persistency is disabled, so it is lost
+    /// once the agent stops.
+    NotPersisted,
+}
+
+impl Saved {
+    /// Logs when the `change` is kept in memory only, as it's lost once
+    /// the agent stops.
+    pub(crate) fn log_if_not_persisted(self, change: &str) {
+        if self == Saved::NotPersisted {
+            debug!("{} is not persisted and is lost once the agent stops", change);
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deref, DerefMut, PartialEq, Eq)]
 pub struct RuntimeSettings {
     #[deref]
@@ -47,10 +67,20 @@ impl Default for RuntimeSettings {
                     retries: 0,
                     now: false,
                     server_address: api::ServerAddress::Default,
+                    interval: Duration::zero(),
+                    last_probe: None,
+                    working_server_address: None,
                 },
                 update: api::RuntimeUpdate {
                     upgrade_to_installation: None,
                     applied_package_uid: None,
+                    boot_count: 0,
+                    last_update_result: None,
+                    last_update_time: None,
+                    pending_update: None,
+                    history: Vec::new(),
+                    rollback_attempts: 0,
+                    rollback_exhausted: false,
                 },
                 path: std::path::PathBuf::new(),
                 persistent: false,
@@ -90,6 +120,35 @@ impl RuntimeSettings {
         Ok(this)
     }
 
+    /// Loads the runtime settings from where the storage settings place
+    /// them. On read-only storage, they are persisted on the overlay when
+    /// one is configured, which is seeded from the runtime settings file
+    /// on first use. Otherwise they are kept in memory only.
+    pub(crate) fn load_from_storage(storage: &settings::Storage) -> Result<Self> {
+        if !storage.read_only {
+            let mut this = Self::load(&storage.runtime_settings)?;
+            this.enable_persistency();
+            return Ok(this);
+        }
+
+        match &storage.runtime_settings_overlay {
+            Some(overlay) => {
+                info!("storage is read-only, persisting runtime settings on {:?}", overlay);
+                let mut this =
+                    Self::load(if overlay.exists() { overlay } else { &storage.runtime_settings })?;
+                this.path = overlay.clone();
+                this.enable_persistency();
+                Ok(this)
+            }
+            None => {
+                warn!(
+                    "storage is read-only, runtime settings are not persisted and are lost once the agent stops"
+                );
+                Self::load(&storage.runtime_settings)
+            }
+        }
+    }
+
     fn parse(content: &str) -> Result<Self> {
         let runtime_settings = serde_json::from_str(content).map(|s| RuntimeSettings {
             inner: s,
@@ -106,10 +165,10 @@ impl RuntimeSettings {
         runtime_settings.map_err(Error::from)
     }
 
-    fn save(&self) -> Result<()> {
+    pub(crate) fn save(&self) -> Result<Saved> {
         if !self.persistent {
             debug!("skipping runtime settings save, using non-persistent");
-            return Ok(());
+            return Ok(Saved::NotPersisted);
         }
 
         let parent = self.path.parent().ok_or(Error::InvalidDestination)?;
@@ -121,21 +180,31 @@ impl RuntimeSettings {
         fs::write(&self.path, self.serialize()?)?;
         debug!("saved runtime settings to {:?}", &self.path);
 
-        Ok(())
+        Ok(Saved::Persisted)
+    }
+
+    /// Outcome of a change which needs no saving, as it keeps the
+    /// settings as they are.
+    fn unchanged(&self) -> Saved {
+        if self.persistent {
+            Saved::Persisted
+        } else {
+            Saved::NotPersisted
+        }
     }
 
     fn serialize(&self) -> Result<String> {
         Ok(serde_json::to_string(&self.inner)?)
     }
 
-    pub(crate) fn get_inactive_installation_set(&self) -> Result<Set> {
+    pub(crate) fn get_inactive_installation_set(&self, pinned: Option<Set>) -> Result<Set> {
         // If the `upgrade_to_installation` is defined, the current inactive
         // installation_set has already been swapped.
         if let Some(s) = self.update.upgrade_to_installation {
             return Ok(Set(s));
         }
 
-        Ok(installation_set::inactive()?)
+        Ok(installation_set::target(pinned)?)
     }
 
     pub(crate) fn enable_persistency(&mut self) {
@@ -146,7 +215,13 @@ impl RuntimeSettings {
         self.polling.now
     }
 
-    pub(crate) fn disable_force_poll(&mut self) -> Result<()> {
+    pub(crate) fn force_poll(&mut self) -> Result<Saved> {
+        debug!("forcing poll");
+        self.polling.now = true;
+        self.save()
+    }
+
+    pub(crate) fn disable_force_poll(&mut self) -> Result<Saved> {
         debug!("disabling foce poll");
         self.polling.now = false;
         self.save()
@@ -156,19 +231,28 @@ impl RuntimeSettings {
         self.polling.retries
     }
 
-    pub(crate) fn inc_retries(&mut self) {
+    /// Counts a failed probe, persisting it so the poll backoff is kept
+    /// across restarts.
+    pub(crate) fn inc_retries(&mut self) -> Result<Saved> {
         self.polling.retries += 1;
+        self.save()
     }
 
-    pub(crate) fn clear_retries(&mut self) {
+    pub(crate) fn clear_retries(&mut self) -> Result<Saved> {
+        if self.polling.retries == 0 {
+            return Ok(self.unchanged());
+        }
+
+        debug!("clearing probe retries");
         self.polling.retries = 0;
+        self.save()
     }
 
     pub(crate) fn last_polling(&self) -> DateTime<Utc> {
         self.polling.last
     }
 
-    pub(crate) fn set_last_polling(&mut self, last_polling: DateTime<Utc>) -> Result<()> {
+    pub(crate) fn set_last_polling(&mut self, last_polling: DateTime<Utc>) -> Result<Saved> {
         debug!("updating last polling time");
         self.polling.last = last_polling;
         self.save()
@@ -178,18 +262,120 @@ impl RuntimeSettings {
         self.update.applied_package_uid.clone()
     }
 
-    pub(crate) fn set_applied_package_uid(&mut self, applied_package_uid: &str) -> Result<()> {
+    pub(crate) fn set_applied_package_uid(&mut self, applied_package_uid: &str) -> Result<Saved> {
         debug!("marking package {} as installed", applied_package_uid);
         self.update.applied_package_uid = Some(applied_package_uid.to_string());
         self.save()
     }
 
-    pub(crate) fn set_upgrading_to(&mut self, new_install_set: Set) -> Result<()> {
+    pub(crate) fn set_upgrading_to(&mut self, new_install_set: Set) -> Result<Saved> {
         debug!("setting upgrading to {}", new_install_set);
         self.update.upgrade_to_installation = Some(new_install_set.0);
         self.save()
     }
 
+    pub(crate) fn inc_boot_count(&mut self) -> Result<Saved> {
+        self.update.boot_count += 1;
+        debug!("updating boot count to {}", self.update.boot_count);
+        self.save()
+    }
+
+    pub(crate) fn rollback_attempts(&self) -> u32 {
+        self.update.rollback_attempts
+    }
+
+    pub(crate) fn inc_rollback_attempts(&mut self) -> Result<Saved> {
+        self.update.rollback_attempts += 1;
+        debug!("updating rollback attempts to {}", self.update.rollback_attempts);
+        self.save()
+    }
+
+    pub(crate) fn is_rollback_exhausted(&self) -> bool {
+        self.update.rollback_exhausted
+    }
+
+    pub(crate) fn set_rollback_exhausted(&mut self) -> Result<Saved> {
+        self.update.rollback_exhausted = true;
+        self.save()
+    }
+
+    /// Records the outcome of the last update attempt, also appending it
+    /// to the history which keeps up to `history_size` attempts. They are
+    /// kept across installations so they can be queried after the device
+    /// reboots.
+    pub(crate) fn set_last_update_result(
+        &mut self,
+        result: api::UpdateResult,
+        error_code: Option<&str>,
+        history_size: usize,
+    ) -> Result<Saved> {
+        debug!("recording {:?} as last update result of {}", result.status, result.package_uid);
+        let now = Utc::now();
+        self.update.history.push(api::UpdateAttempt {
+            timestamp: now,
+            package_uid: result.package_uid.clone(),
+            status: result.status,
+            error_code: error_code.map(str::to_owned),
+        });
+        let excess = self.update.history.len().saturating_sub(history_size);
+        self.update.history.drain(..excess);
+
+        self.update.last_update_result = Some(result);
+        self.update.last_update_time = Some(now);
+        self.save()
+    }
+
+    pub(crate) fn pending_update(&self) -> Option<&api::PendingUpdate> {
+        self.update.pending_update.as_ref()
+    }
+
+    /// Keeps the update package selected by a probe so it can be resumed
+    /// if the agent restarts before installing it.
+    pub(crate) fn set_pending_update(
+        &mut self,
+        pending_update: api::PendingUpdate,
+    ) -> Result<Saved> {
+        debug!("storing pending update package");
+        self.update.pending_update = Some(pending_update);
+        self.save()
+    }
+
+    pub(crate) fn clear_pending_update(&mut self) -> Result<Saved> {
+        if self.update.pending_update.take().is_none() {
+            return Ok(self.unchanged());
+        }
+
+        debug!("clearing pending update package");
+        self.save()
+    }
+
+    pub(crate) fn last_probe(&self) -> Option<&api::ProbeSummary> {
+        self.polling.last_probe.as_ref()
+    }
+
+    pub(crate) fn set_last_probe(
+        &mut self,
+        update_available: bool,
+        extra_poll: Option<i64>,
+    ) -> Result<Saved> {
+        self.polling.last_probe =
+            Some(api::ProbeSummary { timestamp: Utc::now(), update_available, extra_poll });
+        self.save()
+    }
+
+    /// Gets the polling interval set through the agent API, if any.
+    pub(crate) fn polling_interval(&self) -> Option<Duration> {
+        Some(self.polling.interval).filter(|i| !i.is_zero())
+    }
+
+    /// Overrides the configured polling interval, or restores it when
+    /// `None` is given.
+    pub(crate) fn set_polling_interval(&mut self, interval: Option<Duration>) -> Result<Saved> {
+        debug!("setting polling interval override to {:?}", interval);
+        self.polling.interval = interval.unwrap_or_else(Duration::zero);
+        self.save()
+    }
+
     pub(crate) fn custom_server_address(&self) -> Option<&str> {
         match &self.polling.server_address {
             api::ServerAddress::Custom(s) => Some(s),
@@ -199,6 +385,22 @@ impl RuntimeSettings {
 
     pub(crate) fn set_custom_server_address(&mut self, server_address: &str) {
         self.polling.server_address = api::ServerAddress::Custom(server_address.to_owned());
+        // Failures of the previous server must not delay probing the new one
+        self.polling.retries = 0;
+    }
+
+    /// Gets the last configured server which answered a probe.
+    pub(crate) fn working_server_address(&self) -> Option<&str> {
+        self.polling.working_server_address.as_deref()
+    }
+
+    pub(crate) fn set_working_server_address(&mut self, server_address: &str) -> Result<Saved> {
+        if self.working_server_address() == Some(server_address) {
+            return Ok(self.unchanged());
+        }
+        debug!("setting working server address to {}", server_address);
+        self.polling.working_server_address = Some(server_address.to_owned());
+        self.save()
     }
 
     /// Reset settings that are only need through a single installation
@@ -208,10 +410,13 @@ impl RuntimeSettings {
         self.polling.server_address = api::ServerAddress::Default;
     }
 
-    pub(crate) fn reset_installation_settings(&mut self) -> Result<()> {
+    pub(crate) fn reset_installation_settings(&mut self) -> Result<Saved> {
         debug!("reseting installation settings");
         self.update.upgrade_to_installation = None;
         self.update.applied_package_uid = None;
+        self.update.boot_count = 0;
+        self.update.rollback_attempts = 0;
+        self.update.rollback_exhausted = false;
 
         // Ensure we do a probe as soon as possible so full update
         // cycle can be finished.
@@ -273,6 +478,9 @@ fn v1_parse(content: &str, json_err: serde_json::Error) -> Result<api::RuntimeSe
             retries: old_runtime_settings.polling.retries,
             now: old_runtime_settings.polling.probe_asap,
             server_address: api::ServerAddress::Default,
+            interval: Duration::zero(),
+            last_probe: None,
+            working_server_address: None,
         },
         update: api::RuntimeUpdate {
             upgrade_to_installation: match old_runtime_settings.update.upgrade_to_installation {
@@ -281,6 +489,13 @@ fn v1_parse(content: &str, json_err: serde_json::Error) -> Result<api::RuntimeSe
                 _ => None,
             },
             applied_package_uid: None,
+            boot_count: 0,
+            last_update_result: None,
+            last_update_time: None,
+            pending_update: None,
+            history: Vec::new(),
+            rollback_attempts: 0,
+            rollback_exhausted: false,
         },
         path: std::path::PathBuf::new(),
         persistent: false,
@@ -302,10 +517,20 @@ mod tests {
                     retries: 0,
                     now: false,
                     server_address: api::ServerAddress::Default,
+                    interval: Duration::zero(),
+                    last_probe: None,
+                    working_server_address: None,
                 },
                 update: api::RuntimeUpdate {
                     upgrade_to_installation: None,
                     applied_package_uid: None,
+                    boot_count: 0,
+                    last_update_result: None,
+                    last_update_time: None,
+                    pending_update: None,
+                    history: Vec::new(),
+                    rollback_attempts: 0,
+                    rollback_exhausted: false,
                 },
                 path: std::path::PathBuf::new(),
                 persistent: false,
@@ -335,6 +560,172 @@ mod tests {
         assert_eq!(settings.update, new_settings.update);
     }
 
+    #[test]
+    fn boot_count() {
+        use std::fs;
+        use tempfile::NamedTempFile;
+
+        let tempfile = NamedTempFile::new().unwrap();
+        let settings_file = tempfile.path();
+        fs::remove_file(settings_file).unwrap();
+
+        let mut settings = RuntimeSettings::load(settings_file).unwrap();
+        settings.enable_persistency();
+        settings.inc_boot_count().unwrap();
+        settings.inc_boot_count().unwrap();
+        assert_eq!(RuntimeSettings::load(settings_file).unwrap().update.boot_count, 2);
+
+        settings.reset_installation_settings().unwrap();
+        assert_eq!(RuntimeSettings::load(settings_file).unwrap().update.boot_count, 0);
+    }
+
+    #[test]
+    fn last_update_result() {
+        use std::fs;
+        use tempfile::NamedTempFile;
+
+        let tempfile = NamedTempFile::new().unwrap();
+        let settings_file = tempfile.path();
+        fs::remove_file(settings_file).unwrap();
+
+        let mut settings = RuntimeSettings::load(settings_file).unwrap();
+        settings.enable_persistency();
+        let result = api::UpdateResult {
+            status: api::UpdateStatus::Failure,
+            package_uid: "package-uid".to_owned(),
+            error: Some("failed to install".to_owned()),
+        };
+        settings.set_last_update_result(result.clone(), Some("install_failed"), 10).unwrap();
+
+        // The result must survive the reset done after an installation.
+        settings.reset_installation_settings().unwrap();
+        let new_settings = RuntimeSettings::load(settings_file).unwrap();
+        assert_eq!(new_settings.update.last_update_result, Some(result));
+        assert!(new_settings.update.last_update_time.is_some());
+    }
+
+    #[test]
+    fn update_history() {
+        use std::fs;
+        use tempfile::NamedTempFile;
+
+        let tempfile = NamedTempFile::new().unwrap();
+        let settings_file = tempfile.path();
+        fs::remove_file(settings_file).unwrap();
+
+        let mut settings = RuntimeSettings::load(settings_file).unwrap();
+        settings.enable_persistency();
+        let result = |package_uid: &str, status| api::UpdateResult {
+            status,
+            package_uid: package_uid.to_owned(),
+            error: None,
+        };
+        settings
+            .set_last_update_result(result("first", api::UpdateStatus::Success), None, 2)
+            .unwrap();
+        settings
+            .set_last_update_result(
+                result("second", api::UpdateStatus::Failure),
+                Some("no_space"),
+                2,
+            )
+            .unwrap();
+        settings
+            .set_last_update_result(result("third", api::UpdateStatus::Success), None, 2)
+            .unwrap();
+
+        // Only the newest attempts are kept, oldest first
+        let history = RuntimeSettings::load(settings_file).unwrap().update.history;
+        let attempts = history
+            .iter()
+            .map(|a| (a.package_uid.as_str(), a.status, a.error_code.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            attempts,
+            [
+                ("second", api::UpdateStatus::Failure, Some("no_space")),
+                ("third", api::UpdateStatus::Success, None)
+            ]
+        );
+        assert!(history[0].timestamp <= history[1].timestamp);
+    }
+
+    #[test]
+    fn polling_interval() {
+        use std::fs;
+        use tempfile::NamedTempFile;
+
+        let tempfile = NamedTempFile::new().unwrap();
+        let settings_file = tempfile.path();
+        fs::remove_file(settings_file).unwrap();
+
+        let mut settings = RuntimeSettings::load(settings_file).unwrap();
+        settings.enable_persistency();
+        assert_eq!(settings.polling_interval(), None);
+
+        settings.set_polling_interval(Some(Duration::hours(6))).unwrap();
+        assert_eq!(
+            RuntimeSettings::load(settings_file).unwrap().polling_interval(),
+            Some(Duration::hours(6))
+        );
+
+        settings.set_polling_interval(None).unwrap();
+        assert_eq!(RuntimeSettings::load(settings_file).unwrap().polling_interval(), None);
+    }
+
+    #[test]
+    fn retries_are_persisted() {
+        use std::fs;
+        use tempfile::NamedTempFile;
+
+        let tempfile = NamedTempFile::new().unwrap();
+        let settings_file = tempfile.path();
+        fs::remove_file(settings_file).unwrap();
+
+        let mut settings = RuntimeSettings::load(settings_file).unwrap();
+        settings.enable_persistency();
+        settings.inc_retries().unwrap();
+        settings.inc_retries().unwrap();
+        assert_eq!(RuntimeSettings::load(settings_file).unwrap().retries(), 2);
+
+        settings.set_custom_server_address("http://localhost");
+        assert_eq!(settings.retries(), 0);
+
+        settings.inc_retries().unwrap();
+        settings.clear_retries().unwrap();
+        assert_eq!(RuntimeSettings::load(settings_file).unwrap().retries(), 0);
+    }
+
+    #[test]
+    fn read_only_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = crate::settings::Settings::default().storage.clone();
+        storage.read_only = true;
+        storage.runtime_settings = dir.path().join("runtime_settings.conf");
+
+        let mut settings = RuntimeSettings::load(&storage.runtime_settings).unwrap();
+        settings.enable_persistency();
+        settings.inc_boot_count().unwrap();
+
+        let mut settings = RuntimeSettings::load_from_storage(&storage).unwrap();
+        assert!(!settings.persistent);
+        assert_eq!(settings.inc_boot_count().unwrap(), Saved::NotPersisted);
+        assert_eq!(settings.clear_retries().unwrap(), Saved::NotPersisted);
+        assert_eq!(RuntimeSettings::load(&storage.runtime_settings).unwrap().update.boot_count, 1);
+
+        // The overlay is seeded from the runtime settings file
+        let overlay = dir.path().join("overlay.conf");
+        storage.runtime_settings_overlay = Some(overlay.clone());
+        let mut settings = RuntimeSettings::load_from_storage(&storage).unwrap();
+        assert!(settings.persistent);
+        assert_eq!(settings.inc_boot_count().unwrap(), Saved::Persisted);
+        assert_eq!(RuntimeSettings::load(&overlay).unwrap().update.boot_count, 2);
+        assert_eq!(RuntimeSettings::load(&storage.runtime_settings).unwrap().update.boot_count, 1);
+
+        let settings = RuntimeSettings::load_from_storage(&storage).unwrap();
+        assert_eq!(settings.update.boot_count, 2);
+    }
+
     #[test]
     fn load_bad_formated_file() {
         use std::fs;
@@ -386,10 +777,20 @@ UpgradeToInstallation=1
                     retries: 0,
                     now: false,
                     server_address: api::ServerAddress::Default,
+                    interval: Duration::zero(),
+                    last_probe: None,
+                    working_server_address: None,
                 },
                 update: api::RuntimeUpdate {
                     upgrade_to_installation: Some(api::InstallationSet::B),
                     applied_package_uid: None,
+                    boot_count: 0,
+                    last_update_result: None,
+                    last_update_time: None,
+                    pending_update: None,
+                    history: Vec::new(),
+                    rollback_attempts: 0,
+                    rollback_exhausted: false,
                 },
                 path: std::path::PathBuf::new(),
                 persistent: false,
@@ -2,11 +2,12 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::firmware::installation_set::Set;
 use chrono::Duration;
 use derive_more::{Deref, DerefMut, Display, Error, From};
 use sdk::api::info::settings as api;
 use slog_scope::{debug, error};
-use std::{fs, io, path::Path};
+use std::{collections::BTreeMap, fs, io, path::Path};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -20,6 +21,33 @@ pub enum Error {
     TooSmallPollingInterval,
     #[display(fmt = "invalid setting for server address, it must use the protocol prefix")]
     ServerAddressWithoutProtocol,
+    #[display(fmt = "invalid setting for webhooks, they must use the protocol prefix")]
+    WebhookWithoutProtocol,
+    #[display(fmt = "invalid setting for max concurrent downloads, it cannot be zero")]
+    ZeroMaxConcurrentDownloads,
+    #[display(fmt = "invalid setting for download rate limit, it cannot be zero")]
+    ZeroDownloadRateLimit,
+    #[display(fmt = "invalid setting for client certificate, it must be set along with its key")]
+    IncompleteClientIdentity,
+    #[display(fmt = "invalid setting for health check attempts, it cannot be zero")]
+    ZeroHealthCheckAttempts,
+    #[display(fmt = "invalid setting for target installation set, it must be 0 or 1")]
+    InvalidTargetInstallationSet,
+    #[display(
+        fmt = "invalid setting for write chunk size, it must be a power of two between 512 bytes and 64 MiB"
+    )]
+    InvalidWriteChunkSize,
+    #[display(fmt = "invalid setting for decompress threads, it cannot be zero")]
+    ZeroDecompressThreads,
+    #[display(fmt = "invalid setting for decompress memory limit, it cannot be zero")]
+    ZeroDecompressMemoryLimit,
+    #[display(fmt = "invalid setting for authorization, it cannot be set along with its file")]
+    ConflictingAuthorization,
+    #[display(fmt = "invalid setting for request headers: {}", _0)]
+    InvalidHeaders(cloud::Error),
+    #[display(fmt = "invalid setting for host aliases: {}", _0)]
+    #[from(ignore)]
+    InvalidHostAliases(cloud::Error),
 
     #[cfg(feature = "v1-parsing")]
     #[display(fmt = "parsing error: toml: {}, ini: {}", _0, _1)]
@@ -32,10 +60,24 @@ pub struct Settings(pub api::Settings);
 impl Default for Settings {
     fn default() -> Self {
         Settings(api::Settings {
-            polling: api::Polling { interval: Duration::days(1), enabled: true },
+            polling: api::Polling {
+                interval: Duration::days(1),
+                enabled: true,
+                update_window: Vec::new(),
+                jitter: Duration::zero(),
+                max_backoff: Duration::hours(1),
+                probe_on_startup: false,
+            },
             storage: api::Storage {
                 read_only: false,
                 runtime_settings: "/var/lib/updatehub/runtime_settings.conf".into(),
+                dry_run: false,
+                write_chunk_size: None,
+                stream_install: false,
+                keep_downloads: false,
+                decompress_threads: 1,
+                runtime_settings_overlay: None,
+                decompress_memory_limit: None,
             },
             update: api::Update {
                 download_dir: "/tmp/updatehub".into(),
@@ -45,12 +87,72 @@ impl Default for Settings {
                 .iter()
                 .map(|i| (*i).to_string())
                 .collect(),
+                download_space_margin: 0,
+                shutdown_timeout: Duration::seconds(30),
+                revalidate_on_resume: false,
+                state_timeouts: BTreeMap::new(),
+                allow_metadata_injection: false,
+                require_install_confirmation: false,
+                install_confirmation_timeout: Duration::zero(),
+                install_confirmation_timeout_action: api::ConfirmationTimeoutAction::Abort,
+                debug_breakpoints: Vec::new(),
+                history_size: 10,
+                error_retries: 0,
+                error_retry_delay: Duration::seconds(10),
             },
             network: api::Network {
                 server_address: "https://api.updatehub.io".to_string(),
                 listen_socket: "localhost:8080".to_string(),
+                max_concurrent_downloads: 1,
+                download_rate_limit: None,
+                client_certificate: None,
+                client_key: None,
+                download_retries: 0,
+                download_retry_backoff: Duration::seconds(1),
+                log_format: api::LogFormat::Plain,
+                proxy: None,
+                no_proxy: None,
+                report_batch_size: 1,
+                probe_timeout: Duration::zero(),
+                download_timeout: Duration::zero(),
+                user_agent: None,
+                extra_headers: BTreeMap::new(),
+                max_package_size: None,
+                fallback_servers: Vec::new(),
+                connectivity_check: false,
+                connectivity_check_target: None,
+                listen_socket_mode: 0o660,
+                max_api_connections: None,
+                enable_debug_api: false,
+                download_rate_schedule: Vec::new(),
+                compress_reports: false,
+                report_state_timings: false,
+                authorization: None,
+                authorization_file: None,
+                webhooks: Vec::new(),
+                webhook_secret: None,
+                host_aliases: BTreeMap::new(),
+                api_token: None,
+                content_length_mismatch: api::ContentLengthMismatchPolicy::Fail,
+            },
+            firmware: api::Firmware {
+                metadata: "/usr/share/updatehub".into(),
+                public_key_path: None,
+                log_buffer_size: 256 * 1024,
+                health_check: None,
+                health_check_attempts: 3,
+                health_check_interval: Duration::seconds(10),
+                target_installation_set: None,
+                reboot_command: "reboot".to_owned(),
+                reboot_delay: Duration::zero(),
+                validate_on_missing_callback: api::MissingCallbackPolicy::Rollback,
+                installation_set_devices: BTreeMap::new(),
+                version_scheme: api::VersionScheme::None,
+                allow_downgrade: false,
+                minimum_uptime_before_install: Duration::zero(),
+                error_log_path: None,
+                rollback_attempts: 3,
             },
-            firmware: api::Firmware { metadata: "/usr/share/updatehub".into() },
         })
     }
 }
@@ -80,15 +182,168 @@ impl Settings {
             return Err(Error::TooSmallPollingInterval);
         }
 
-        if !&settings.network.server_address.starts_with("http://")
-            && !&settings.network.server_address.starts_with("https://")
+        if std::iter::once(&settings.network.server_address)
+            .chain(&settings.network.fallback_servers)
+            .any(|s| !s.starts_with("http://") && !s.starts_with("https://"))
         {
             error!("invalid setting for server address, it must use the protocol prefix");
             return Err(Error::ServerAddressWithoutProtocol);
         }
 
+        if settings
+            .network
+            .webhooks
+            .iter()
+            .any(|s| !s.starts_with("http://") && !s.starts_with("https://"))
+        {
+            error!("invalid setting for webhooks, they must use the protocol prefix");
+            return Err(Error::WebhookWithoutProtocol);
+        }
+
+        if settings.network.max_concurrent_downloads == 0 {
+            error!("invalid setting for max concurrent downloads, it cannot be zero");
+            return Err(Error::ZeroMaxConcurrentDownloads);
+        }
+
+        if settings.network.download_rate_limit == Some(0)
+            || settings.network.download_rate_schedule.iter().any(|s| s.rate_limit == Some(0))
+        {
+            error!("invalid setting for download rate limit, it cannot be zero");
+            return Err(Error::ZeroDownloadRateLimit);
+        }
+
+        if settings.network.client_certificate.is_some() != settings.network.client_key.is_some() {
+            error!("invalid setting for client certificate, it must be set along with its key");
+            return Err(Error::IncompleteClientIdentity);
+        }
+
+        if settings.firmware.health_check_attempts == 0 {
+            error!("invalid setting for health check attempts, it cannot be zero");
+            return Err(Error::ZeroHealthCheckAttempts);
+        }
+
+        if settings.firmware.target_installation_set.map_or(false, |set| set > 1) {
+            error!("invalid setting for target installation set, it must be 0 or 1");
+            return Err(Error::InvalidTargetInstallationSet);
+        }
+
+        if settings
+            .storage
+            .write_chunk_size
+            .map_or(false, |size| !size.is_power_of_two() || !(512..=64 << 20).contains(&size))
+        {
+            error!(
+                "invalid setting for write chunk size, it must be a power of two between 512 bytes and 64 MiB"
+            );
+            return Err(Error::InvalidWriteChunkSize);
+        }
+
+        if settings.storage.decompress_threads == 0 {
+            error!("invalid setting for decompress threads, it cannot be zero");
+            return Err(Error::ZeroDecompressThreads);
+        }
+
+        if settings.storage.decompress_memory_limit == Some(0) {
+            error!("invalid setting for decompress memory limit, it cannot be zero");
+            return Err(Error::ZeroDecompressMemoryLimit);
+        }
+
+        if settings.network.authorization.is_some() && settings.network.authorization_file.is_some()
+        {
+            error!("invalid setting for authorization, it cannot be set along with its file");
+            return Err(Error::ConflictingAuthorization);
+        }
+
+        if let Err(e) = settings.headers() {
+            error!("invalid setting for request headers: {}", e);
+            return Err(Error::InvalidHeaders(e));
+        }
+
+        if let Err(e) = settings.host_aliases() {
+            error!("invalid setting for host aliases: {}", e);
+            return Err(Error::InvalidHostAliases(e));
+        }
+
         Ok(settings)
     }
+
+    /// Gets the headers sent on every request to the server.
+    pub(crate) fn headers(&self) -> cloud::Result<cloud::Headers> {
+        let headers = cloud::Headers::new(
+            self.network.user_agent.as_deref(),
+            self.network.extra_headers.iter().map(|(name, value)| (name.as_str(), value.as_str())),
+        )?;
+        match &self.network.authorization {
+            Some(authorization) => headers.with_authorization(authorization),
+            None => Ok(headers),
+        }
+    }
+
+    /// Gets the addresses the hosts are connected to instead of resolving
+    /// their names.
+    pub(crate) fn host_aliases(&self) -> cloud::Result<cloud::HostAliases> {
+        cloud::HostAliases::new(
+            self.network.host_aliases.iter().map(|(host, ip)| (host.as_str(), ip.as_str())),
+        )
+    }
+
+    /// Gets the time limit of probes, if any.
+    pub(crate) fn probe_timeout(&self) -> Option<std::time::Duration> {
+        self.network.probe_timeout.to_std().ok().filter(|t| !t.is_zero())
+    }
+
+    /// Gets the time limit object downloads may go without receiving
+    /// data, if any.
+    pub(crate) fn download_timeout(&self) -> Option<std::time::Duration> {
+        self.network.download_timeout.to_std().ok().filter(|t| !t.is_zero())
+    }
+
+    /// Whether downloads fail when their announced Content Length
+    /// disagrees with the object size.
+    pub(crate) fn strict_content_length(&self) -> bool {
+        self.network.content_length_mismatch == api::ContentLengthMismatchPolicy::Fail
+    }
+
+    /// Gets the download rate limit applied at `time`, if any. The first
+    /// scheduled range holding it wins, falling back to the unscheduled
+    /// limit.
+    pub(crate) fn download_rate_limit_at(&self, time: chrono::NaiveTime) -> Option<u64> {
+        self.network
+            .download_rate_schedule
+            .iter()
+            .find(|s| s.window.contains(time))
+            .map_or(self.network.download_rate_limit, |s| s.rate_limit)
+    }
+
+    /// Gets the time limit for handling the state, if any. States which
+    /// wait to be awoken are never limited.
+    pub(crate) fn state_timeout(&self, state: &str) -> Option<std::time::Duration> {
+        let default = match state {
+            "park" | "poll" | "paused_download" | "awaiting_approval" => return None,
+            "entry_point" | "probe" | "error" => Duration::minutes(5),
+            "validation" | "reboot" => Duration::minutes(10),
+            "prepare_local_install" => Duration::hours(1),
+            _ => Duration::zero(),
+        };
+        self.update
+            .state_timeouts
+            .get(state)
+            .unwrap_or(&default)
+            .to_std()
+            .ok()
+            .filter(|t| !t.is_zero())
+    }
+
+    /// Checks if the agent must pause before handling the state. The
+    /// breakpoints are only honored when the debug API is enabled.
+    pub(crate) fn is_breakpoint(&self, state: &str) -> bool {
+        self.network.enable_debug_api && self.update.debug_breakpoints.iter().any(|b| b == state)
+    }
+
+    /// Gets the installation set updates are pinned to, if any.
+    pub(crate) fn target_installation_set(&self) -> Option<Set> {
+        self.firmware.target_installation_set.and_then(|set| Set::try_from(set).ok())
+    }
 }
 
 #[cfg(feature = "v1-parsing")]
@@ -197,22 +452,93 @@ fn v1_parse(content: &str, toml_err: toml::de::Error) -> Result<api::Settings> {
         .map_err(|ini_err| Error::V1Parsing(Box::new(toml_err), ini_err))?;
 
     Ok(api::Settings {
-        firmware: api::Firmware { metadata: old_settings.firmware.metadata_path },
+        firmware: api::Firmware {
+            metadata: old_settings.firmware.metadata_path,
+            public_key_path: None,
+            log_buffer_size: 256 * 1024,
+            health_check: None,
+            health_check_attempts: 3,
+            health_check_interval: Duration::seconds(10),
+            target_installation_set: None,
+            reboot_command: "reboot".to_owned(),
+            reboot_delay: Duration::zero(),
+            validate_on_missing_callback: api::MissingCallbackPolicy::Rollback,
+            installation_set_devices: BTreeMap::new(),
+            version_scheme: api::VersionScheme::None,
+            allow_downgrade: false,
+            minimum_uptime_before_install: Duration::zero(),
+            error_log_path: None,
+            rollback_attempts: 3,
+        },
         network: api::Network {
             server_address: old_settings.network.server_address,
             listen_socket: old_settings.network.listen_socket,
+            max_concurrent_downloads: 1,
+            download_rate_limit: None,
+            client_certificate: None,
+            client_key: None,
+            download_retries: 0,
+            download_retry_backoff: Duration::seconds(1),
+            log_format: api::LogFormat::Plain,
+            proxy: None,
+            no_proxy: None,
+            report_batch_size: 1,
+            probe_timeout: Duration::zero(),
+            download_timeout: Duration::zero(),
+            user_agent: None,
+            extra_headers: BTreeMap::new(),
+            max_package_size: None,
+            fallback_servers: Vec::new(),
+            connectivity_check: false,
+            connectivity_check_target: None,
+            listen_socket_mode: 0o660,
+            max_api_connections: None,
+            enable_debug_api: false,
+            download_rate_schedule: Vec::new(),
+            compress_reports: false,
+            report_state_timings: false,
+            authorization: None,
+            authorization_file: None,
+            webhooks: Vec::new(),
+            webhook_secret: None,
+            host_aliases: BTreeMap::new(),
+            api_token: None,
+            content_length_mismatch: api::ContentLengthMismatchPolicy::Fail,
         },
         polling: api::Polling {
             interval: old_settings.polling.interval,
             enabled: old_settings.polling.enabled,
+            update_window: Vec::new(),
+            jitter: Duration::zero(),
+            max_backoff: Duration::hours(1),
+            probe_on_startup: false,
         },
         storage: api::Storage {
             read_only: old_settings.storage.read_only,
             runtime_settings: old_settings.storage.runtime_settings_path.into(),
+            dry_run: false,
+            write_chunk_size: None,
+            stream_install: false,
+            keep_downloads: false,
+            decompress_threads: 1,
+            runtime_settings_overlay: None,
+            decompress_memory_limit: None,
         },
         update: api::Update {
             download_dir: old_settings.update.download_dir,
             supported_install_modes: old_settings.update.supported_install_modes,
+            download_space_margin: 0,
+            shutdown_timeout: Duration::seconds(30),
+            revalidate_on_resume: false,
+            state_timeouts: BTreeMap::new(),
+            allow_metadata_injection: false,
+            require_install_confirmation: false,
+            install_confirmation_timeout: Duration::zero(),
+            install_confirmation_timeout_action: api::ConfirmationTimeoutAction::Abort,
+            debug_breakpoints: Vec::new(),
+            history_size: 10,
+            error_retries: 0,
+            error_retry_delay: Duration::seconds(10),
         },
     })
 }
@@ -251,10 +577,24 @@ supported_install_modes=["copy", "tarball"]
 metadata="/usr/share/updatehub"
 "#;
         let expected = Settings(api::Settings {
-            polling: api::Polling { interval: Duration::minutes(1), enabled: true },
+            polling: api::Polling {
+                interval: Duration::minutes(1),
+                enabled: true,
+                update_window: Vec::new(),
+                jitter: Duration::zero(),
+                max_backoff: Duration::hours(1),
+                probe_on_startup: false,
+            },
             storage: api::Storage {
                 read_only: false,
                 runtime_settings: "/data/updatehub/state.data".into(),
+                dry_run: false,
+                write_chunk_size: None,
+                stream_install: false,
+                keep_downloads: false,
+                decompress_threads: 1,
+                runtime_settings_overlay: None,
+                decompress_memory_limit: None,
             },
             update: api::Update {
                 download_dir: "/tmp/updatehub".into(),
@@ -262,12 +602,72 @@ metadata="/usr/share/updatehub"
                     .iter()
                     .map(|i| (*i).to_string())
                     .collect(),
+                download_space_margin: 0,
+                shutdown_timeout: Duration::seconds(30),
+                revalidate_on_resume: false,
+                state_timeouts: BTreeMap::new(),
+                allow_metadata_injection: false,
+                require_install_confirmation: false,
+                install_confirmation_timeout: Duration::zero(),
+                install_confirmation_timeout_action: api::ConfirmationTimeoutAction::Abort,
+                debug_breakpoints: Vec::new(),
+                history_size: 10,
+                error_retries: 0,
+                error_retry_delay: Duration::seconds(10),
             },
             network: api::Network {
                 server_address: "https://api.updatehub.io".to_string(),
                 listen_socket: "localhost:8080".to_string(),
+                max_concurrent_downloads: 1,
+                download_rate_limit: None,
+                client_certificate: None,
+                client_key: None,
+                download_retries: 0,
+                download_retry_backoff: Duration::seconds(1),
+                log_format: api::LogFormat::Plain,
+                proxy: None,
+                no_proxy: None,
+                report_batch_size: 1,
+                probe_timeout: Duration::zero(),
+                download_timeout: Duration::zero(),
+                user_agent: None,
+                extra_headers: BTreeMap::new(),
+                max_package_size: None,
+                fallback_servers: Vec::new(),
+                connectivity_check: false,
+                connectivity_check_target: None,
+                listen_socket_mode: 0o660,
+                max_api_connections: None,
+                enable_debug_api: false,
+                download_rate_schedule: Vec::new(),
+                compress_reports: false,
+                report_state_timings: false,
+                authorization: None,
+                authorization_file: None,
+                webhooks: Vec::new(),
+                webhook_secret: None,
+                host_aliases: BTreeMap::new(),
+                api_token: None,
+                content_length_mismatch: api::ContentLengthMismatchPolicy::Fail,
+            },
+            firmware: api::Firmware {
+                metadata: "/usr/share/updatehub".into(),
+                public_key_path: None,
+                log_buffer_size: 256 * 1024,
+                health_check: None,
+                health_check_attempts: 3,
+                health_check_interval: Duration::seconds(10),
+                target_installation_set: None,
+                reboot_command: "reboot".to_owned(),
+                reboot_delay: Duration::zero(),
+                validate_on_missing_callback: api::MissingCallbackPolicy::Rollback,
+                installation_set_devices: BTreeMap::new(),
+                version_scheme: api::VersionScheme::None,
+                allow_downgrade: false,
+                minimum_uptime_before_install: Duration::zero(),
+                error_log_path: None,
+                rollback_attempts: 3,
             },
-            firmware: api::Firmware { metadata: "/usr/share/updatehub".into() },
         });
         assert_eq!(Settings::parse(sample).unwrap(), expected);
     }
@@ -323,16 +723,498 @@ metadata="/usr/share/updatehub"
         assert!(Settings::parse(sample).is_err());
     }
 
+    #[test]
+    fn invalid_max_concurrent_downloads() {
+        let sample = r#"
+[network]
+server_address="https://api.updatehub.io"
+listen_socket="localhost:8080"
+max_concurrent_downloads=0
+
+[storage]
+read_only = false
+runtime_settings="/data/updatehub/state.data"
+
+[polling]
+enabled=true
+interval="60s"
+
+[update]
+download_dir="/tmp/updatehub"
+supported_install_modes=["copy", "tarball"]
+
+[firmware]
+metadata="/usr/share/updatehub"
+"#;
+
+        assert!(Settings::parse(sample).is_err());
+    }
+
+    #[test]
+    fn invalid_download_rate_limit() {
+        let sample = r#"
+[network]
+server_address="https://api.updatehub.io"
+listen_socket="localhost:8080"
+download_rate_limit=0
+
+[storage]
+read_only = false
+runtime_settings="/data/updatehub/state.data"
+
+[polling]
+enabled=true
+interval="60s"
+
+[update]
+download_dir="/tmp/updatehub"
+supported_install_modes=["copy", "tarball"]
+
+[firmware]
+metadata="/usr/share/updatehub"
+"#;
+
+        assert!(Settings::parse(sample).is_err());
+    }
+
+    #[test]
+    fn incomplete_client_identity() {
+        let sample = r#"
+[network]
+server_address="https://api.updatehub.io"
+listen_socket="localhost:8080"
+client_certificate="/etc/updatehub/client.crt"
+
+[storage]
+read_only = false
+runtime_settings="/data/updatehub/state.data"
+
+[polling]
+enabled=true
+interval="60s"
+
+[update]
+download_dir="/tmp/updatehub"
+supported_install_modes=["copy", "tarball"]
+
+[firmware]
+metadata="/usr/share/updatehub"
+"#;
+
+        assert!(Settings::parse(sample).is_err());
+    }
+
+    #[test]
+    fn request_timeouts() {
+        let sample = r#"
+[network]
+server_address="https://api.updatehub.io"
+listen_socket="localhost:8080"
+probe_timeout="5s"
+download_timeout="2m"
+
+[storage]
+read_only = false
+runtime_settings="/data/updatehub/state.data"
+
+[polling]
+enabled=true
+interval="60s"
+
+[update]
+download_dir="/tmp/updatehub"
+supported_install_modes=["copy", "tarball"]
+
+[firmware]
+metadata="/usr/share/updatehub"
+"#;
+
+        let settings = Settings::parse(sample).unwrap();
+        assert_eq!(settings.probe_timeout(), Some(std::time::Duration::from_secs(5)));
+        assert_eq!(settings.download_timeout(), Some(std::time::Duration::from_secs(120)));
+
+        let settings = Settings::parse(
+            &sample.replace("probe_timeout=\"5s\"\n", "").replace("download_timeout=\"2m\"\n", ""),
+        )
+        .unwrap();
+        assert_eq!(settings.probe_timeout(), None);
+        assert_eq!(settings.download_timeout(), None);
+    }
+
+    #[test]
+    fn state_timeouts() {
+        let sample = r#"
+[network]
+server_address="https://api.updatehub.io"
+listen_socket="localhost:8080"
+
+[storage]
+read_only = false
+runtime_settings="/data/updatehub/state.data"
+
+[polling]
+enabled=true
+interval="60s"
+
+[update]
+download_dir="/tmp/updatehub"
+supported_install_modes=["copy", "tarball"]
+
+[update.state_timeouts]
+install="30m"
+validation="0s"
+park="1m"
+
+[firmware]
+metadata="/usr/share/updatehub"
+"#;
+
+        let settings = Settings::parse(sample).unwrap();
+        assert_eq!(settings.state_timeout("install"), Some(std::time::Duration::from_secs(1800)));
+        assert_eq!(settings.state_timeout("probe"), Some(std::time::Duration::from_secs(300)));
+        assert_eq!(settings.state_timeout("validation"), None);
+        assert_eq!(settings.state_timeout("download"), None);
+        assert_eq!(settings.state_timeout("park"), None);
+    }
+
+    #[test]
+    fn debug_breakpoints() {
+        let sample = r#"
+[network]
+server_address="https://api.updatehub.io"
+listen_socket="localhost:8080"
+
+[storage]
+read_only = false
+runtime_settings="/data/updatehub/state.data"
+
+[polling]
+enabled=true
+interval="60s"
+
+[update]
+download_dir="/tmp/updatehub"
+supported_install_modes=["copy", "tarball"]
+debug_breakpoints=["install", "reboot"]
+
+[firmware]
+metadata="/usr/share/updatehub"
+"#;
+
+        let settings = Settings::parse(sample).unwrap();
+        assert!(!settings.is_breakpoint("install"));
+
+        let settings = Settings::parse(
+            &sample.replace("listen_socket=", "enable_debug_api=true\nlisten_socket="),
+        )
+        .unwrap();
+        assert!(settings.is_breakpoint("install"));
+        assert!(settings.is_breakpoint("reboot"));
+        assert!(!settings.is_breakpoint("download"));
+    }
+
+    #[test]
+    fn request_headers() {
+        let sample = r#"
+[network]
+server_address="https://api.updatehub.io"
+listen_socket="localhost:8080"
+user_agent="fleet-agent/1.0"
+
+[network.extra_headers]
+X-Fleet="edge"
+
+[storage]
+read_only = false
+runtime_settings="/data/updatehub/state.data"
+
+[polling]
+enabled=true
+interval="60s"
+
+[update]
+download_dir="/tmp/updatehub"
+supported_install_modes=["copy", "tarball"]
+
+[firmware]
+metadata="/usr/share/updatehub"
+"#;
+
+        let settings = Settings::parse(sample).unwrap();
+        assert_eq!(settings.network.user_agent.as_deref(), Some("fleet-agent/1.0"));
+        assert_eq!(
+            settings.network.extra_headers,
+            BTreeMap::from([("X-Fleet".to_owned(), "edge".to_owned())])
+        );
+
+        for invalid in ["\"X Fleet\"=\"edge\"", "X-Fleet=\"line\\nbreak\""] {
+            match Settings::parse(&sample.replace("X-Fleet=\"edge\"", invalid)) {
+                Err(Error::InvalidHeaders(_)) => {}
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn authorization() {
+        let sample = r#"
+[network]
+server_address="https://api.updatehub.io"
+listen_socket="localhost:8080"
+authorization="Bearer secret"
+
+[storage]
+read_only = false
+runtime_settings="/data/updatehub/state.data"
+
+[polling]
+enabled=true
+interval="60s"
+
+[update]
+download_dir="/tmp/updatehub"
+supported_install_modes=["copy", "tarball"]
+
+[firmware]
+metadata="/usr/share/updatehub"
+"#;
+
+        let settings = Settings::parse(sample).unwrap();
+        assert_eq!(settings.network.authorization.as_deref(), Some("Bearer secret"));
+        // The credentials aren't served along with the settings
+        assert!(!serde_json::to_string(&settings.0).unwrap().contains("secret"));
+
+        match Settings::parse(&sample.replace(
+            "authorization=\"Bearer secret\"",
+            "authorization=\"Bearer secret\"\nauthorization_file=\"/run/token\"",
+        )) {
+            Err(Error::ConflictingAuthorization) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match Settings::parse(&sample.replace("Bearer secret", "Bearer \\nsecret")) {
+            Err(Error::InvalidHeaders(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn update_window() {
+        let sample = r#"
+[network]
+server_address="https://api.updatehub.io"
+listen_socket="localhost:8080"
+
+[storage]
+read_only = false
+runtime_settings="/data/updatehub/state.data"
+
+[polling]
+enabled=true
+interval="60s"
+update_window=["02:00-04:00", "23:30-00:30"]
+
+[update]
+download_dir="/tmp/updatehub"
+supported_install_modes=["copy", "tarball"]
+
+[firmware]
+metadata="/usr/share/updatehub"
+"#;
+
+        let settings = Settings::parse(sample).unwrap();
+        let windows: Vec<_> =
+            settings.polling.update_window.iter().map(|w| w.to_string()).collect();
+        assert_eq!(windows, vec!["02:00-04:00", "23:30-00:30"]);
+
+        assert!(Settings::parse(&sample.replace("02:00-04:00", "02:00")).is_err());
+        assert!(Settings::parse(&sample.replace("02:00-04:00", "25:00-04:00")).is_err());
+        assert!(Settings::parse(&sample.replace("02:00-04:00", "02:00-02:00")).is_err());
+    }
+
+    #[test]
+    fn download_rate_schedule() {
+        let sample = r#"
+[network]
+server_address="https://api.updatehub.io"
+listen_socket="localhost:8080"
+download_rate_limit=4096
+download_rate_schedule=[
+    { window="08:00-18:00", rate_limit=1024 },
+    { window="12:00-13:00" },
+    { window="22:00-02:00" },
+]
+
+[storage]
+read_only = false
+runtime_settings="/data/updatehub/state.data"
+
+[polling]
+enabled=true
+interval="60s"
+
+[update]
+download_dir="/tmp/updatehub"
+supported_install_modes=["copy", "tarball"]
+
+[firmware]
+metadata="/usr/share/updatehub"
+"#;
+
+        let settings = Settings::parse(sample).unwrap();
+        let at = |t: &str| {
+            settings.download_rate_limit_at(chrono::NaiveTime::parse_from_str(t, "%H:%M").unwrap())
+        };
+        // The first matching range wins, even over later unlimited ones
+        assert_eq!(at("12:30"), Some(1024));
+        assert_eq!(at("23:00"), None);
+        assert_eq!(at("01:00"), None);
+        assert_eq!(at("20:00"), Some(4096));
+
+        match Settings::parse(&sample.replace("rate_limit=1024", "rate_limit=0")) {
+            Err(Error::ZeroDownloadRateLimit) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert!(Settings::parse(&sample.replace("08:00-18:00", "08:00")).is_err());
+    }
+
+    #[test]
+    fn target_installation_set() {
+        let sample = r#"
+[network]
+server_address="https://api.updatehub.io"
+listen_socket="localhost:8080"
+
+[storage]
+read_only = false
+runtime_settings="/data/updatehub/state.data"
+
+[polling]
+enabled=true
+interval="60s"
+
+[update]
+download_dir="/tmp/updatehub"
+supported_install_modes=["copy", "tarball"]
+
+[firmware]
+metadata="/usr/share/updatehub"
+target_installation_set=1
+"#;
+
+        let settings = Settings::parse(sample).unwrap();
+        assert_eq!(
+            settings.target_installation_set(),
+            Some(Set(sdk::api::info::runtime_settings::InstallationSet::B))
+        );
+
+        match Settings::parse(
+            &sample.replace("target_installation_set=1", "target_installation_set=2"),
+        ) {
+            Err(Error::InvalidTargetInstallationSet) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn write_chunk_size() {
+        let sample = r#"
+[network]
+server_address="https://api.updatehub.io"
+listen_socket="localhost:8080"
+
+[storage]
+read_only = false
+runtime_settings="/data/updatehub/state.data"
+write_chunk_size=4096
+
+[polling]
+enabled=true
+interval="60s"
+
+[update]
+download_dir="/tmp/updatehub"
+supported_install_modes=["copy", "tarball"]
+
+[firmware]
+metadata="/usr/share/updatehub"
+"#;
+
+        let settings = Settings::parse(sample).unwrap();
+        assert_eq!(settings.storage.write_chunk_size, Some(4096));
+
+        for invalid in ["write_chunk_size=4000", "write_chunk_size=256", "write_chunk_size=0"] {
+            match Settings::parse(&sample.replace("write_chunk_size=4096", invalid)) {
+                Err(Error::InvalidWriteChunkSize) => {}
+                res => panic!("Unexpected result for {}: {:?}", invalid, res),
+            }
+        }
+    }
+
+    #[test]
+    fn decompress_threads() {
+        let sample = r#"
+[network]
+server_address="https://api.updatehub.io"
+listen_socket="localhost:8080"
+
+[storage]
+read_only = false
+runtime_settings="/data/updatehub/state.data"
+decompress_threads=4
+
+[polling]
+enabled=true
+interval="60s"
+
+[update]
+download_dir="/tmp/updatehub"
+supported_install_modes=["copy", "tarball"]
+
+[firmware]
+metadata="/usr/share/updatehub"
+"#;
+
+        let settings = Settings::parse(sample).unwrap();
+        assert_eq!(settings.storage.decompress_threads, 4);
+
+        match Settings::parse(&sample.replace("decompress_threads=4", "decompress_threads=0")) {
+            Err(Error::ZeroDecompressThreads) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let sample = sample.replace("decompress_threads=4", "decompress_memory_limit=16777216");
+        let settings = Settings::parse(&sample).unwrap();
+        assert_eq!(settings.storage.decompress_memory_limit, Some(16 << 20));
+        match Settings::parse(&sample.replace("=16777216", "=0")) {
+            Err(Error::ZeroDecompressMemoryLimit) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
     #[test]
     fn default() {
         let mut settings = Settings::default();
         settings.network.server_address = "https://api.updatehub.io".to_string();
 
         let expected = Settings(api::Settings {
-            polling: api::Polling { interval: Duration::days(1), enabled: true },
+            polling: api::Polling {
+                interval: Duration::days(1),
+                enabled: true,
+                update_window: Vec::new(),
+                jitter: Duration::zero(),
+                max_backoff: Duration::hours(1),
+                probe_on_startup: false,
+            },
             storage: api::Storage {
                 read_only: false,
                 runtime_settings: "/var/lib/updatehub/runtime_settings.conf".into(),
+                dry_run: false,
+                write_chunk_size: None,
+                stream_install: false,
+                keep_downloads: false,
+                decompress_threads: 1,
+                runtime_settings_overlay: None,
+                decompress_memory_limit: None,
             },
             update: api::Update {
                 download_dir: "/tmp/updatehub".into(),
@@ -342,12 +1224,72 @@ metadata="/usr/share/updatehub"
                 .iter()
                 .map(|i| i.to_string())
                 .collect(),
+                download_space_margin: 0,
+                shutdown_timeout: Duration::seconds(30),
+                revalidate_on_resume: false,
+                state_timeouts: BTreeMap::new(),
+                allow_metadata_injection: false,
+                require_install_confirmation: false,
+                install_confirmation_timeout: Duration::zero(),
+                install_confirmation_timeout_action: api::ConfirmationTimeoutAction::Abort,
+                debug_breakpoints: Vec::new(),
+                history_size: 10,
+                error_retries: 0,
+                error_retry_delay: Duration::seconds(10),
             },
             network: api::Network {
                 server_address: "https://api.updatehub.io".to_string(),
                 listen_socket: "localhost:8080".to_string(),
+                max_concurrent_downloads: 1,
+                download_rate_limit: None,
+                client_certificate: None,
+                client_key: None,
+                download_retries: 0,
+                download_retry_backoff: Duration::seconds(1),
+                log_format: api::LogFormat::Plain,
+                proxy: None,
+                no_proxy: None,
+                report_batch_size: 1,
+                probe_timeout: Duration::zero(),
+                download_timeout: Duration::zero(),
+                user_agent: None,
+                extra_headers: BTreeMap::new(),
+                max_package_size: None,
+                fallback_servers: Vec::new(),
+                connectivity_check: false,
+                connectivity_check_target: None,
+                listen_socket_mode: 0o660,
+                max_api_connections: None,
+                enable_debug_api: false,
+                download_rate_schedule: Vec::new(),
+                compress_reports: false,
+                report_state_timings: false,
+                authorization: None,
+                authorization_file: None,
+                webhooks: Vec::new(),
+                webhook_secret: None,
+                host_aliases: BTreeMap::new(),
+                api_token: None,
+                content_length_mismatch: api::ContentLengthMismatchPolicy::Fail,
+            },
+            firmware: api::Firmware {
+                metadata: "/usr/share/updatehub".into(),
+                public_key_path: None,
+                log_buffer_size: 256 * 1024,
+                health_check: None,
+                health_check_attempts: 3,
+                health_check_interval: Duration::seconds(10),
+                target_installation_set: None,
+                reboot_command: "reboot".to_owned(),
+                reboot_delay: Duration::zero(),
+                validate_on_missing_callback: api::MissingCallbackPolicy::Rollback,
+                installation_set_devices: BTreeMap::new(),
+                version_scheme: api::VersionScheme::None,
+                allow_downgrade: false,
+                minimum_uptime_before_install: Duration::zero(),
+                error_log_path: None,
+                rollback_attempts: 3,
             },
-            firmware: api::Firmware { metadata: "/usr/share/updatehub".into() },
         });
 
         assert_eq!(Some(settings), Some(expected));
@@ -374,20 +1316,94 @@ ListenSocket=localhost:8313
 ";
 
         let expected = Settings(api::Settings {
-            polling: api::Polling { interval: Duration::minutes(1), enabled: false },
+            polling: api::Polling {
+                interval: Duration::minutes(1),
+                enabled: false,
+                update_window: Vec::new(),
+                jitter: Duration::zero(),
+                max_backoff: Duration::hours(1),
+                probe_on_startup: false,
+            },
             storage: api::Storage {
                 read_only: false,
                 runtime_settings: "/run/updatehub/state".into(),
+                dry_run: false,
+                write_chunk_size: None,
+                stream_install: false,
+                keep_downloads: false,
+                decompress_threads: 1,
+                runtime_settings_overlay: None,
+                decompress_memory_limit: None,
             },
             update: api::Update {
                 download_dir: "/tmp/download".into(),
                 supported_install_modes: ["mode1", "mode2"].iter().map(|i| i.to_string()).collect(),
+                download_space_margin: 0,
+                shutdown_timeout: Duration::seconds(30),
+                revalidate_on_resume: false,
+                state_timeouts: BTreeMap::new(),
+                allow_metadata_injection: false,
+                require_install_confirmation: false,
+                install_confirmation_timeout: Duration::zero(),
+                install_confirmation_timeout_action: api::ConfirmationTimeoutAction::Abort,
+                debug_breakpoints: Vec::new(),
+                history_size: 10,
+                error_retries: 0,
+                error_retry_delay: Duration::seconds(10),
             },
             network: api::Network {
                 server_address: "http://localhost".to_string(),
                 listen_socket: "localhost:8313".to_string(),
+                max_concurrent_downloads: 1,
+                download_rate_limit: None,
+                client_certificate: None,
+                client_key: None,
+                download_retries: 0,
+                download_retry_backoff: Duration::seconds(1),
+                log_format: api::LogFormat::Plain,
+                proxy: None,
+                no_proxy: None,
+                report_batch_size: 1,
+                probe_timeout: Duration::zero(),
+                download_timeout: Duration::zero(),
+                user_agent: None,
+                extra_headers: BTreeMap::new(),
+                max_package_size: None,
+                fallback_servers: Vec::new(),
+                connectivity_check: false,
+                connectivity_check_target: None,
+                listen_socket_mode: 0o660,
+                max_api_connections: None,
+                enable_debug_api: false,
+                download_rate_schedule: Vec::new(),
+                compress_reports: false,
+                report_state_timings: false,
+                authorization: None,
+                authorization_file: None,
+                webhooks: Vec::new(),
+                webhook_secret: None,
+                host_aliases: BTreeMap::new(),
+                api_token: None,
+                content_length_mismatch: api::ContentLengthMismatchPolicy::Fail,
+            },
+            firmware: api::Firmware {
+                metadata: "/usr/share/updatehub".into(),
+                public_key_path: None,
+                log_buffer_size: 256 * 1024,
+                health_check: None,
+                health_check_attempts: 3,
+                health_check_interval: Duration::seconds(10),
+                target_installation_set: None,
+                reboot_command: "reboot".to_owned(),
+                reboot_delay: Duration::zero(),
+                validate_on_missing_callback: api::MissingCallbackPolicy::Rollback,
+                installation_set_devices: BTreeMap::new(),
+                version_scheme: api::VersionScheme::None,
+                allow_downgrade: false,
+                minimum_uptime_before_install: Duration::zero(),
+                error_log_path: None,
+                rollback_attempts: 3,
             },
-            firmware: api::Firmware { metadata: "/usr/share/updatehub".into() },
         });
 
         assert_eq!(Settings::parse(sample).unwrap(), expected);
@@ -0,0 +1,177 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{
+    machine::{self, Context},
+    Install, Result, State, StateChangeImpl, TransitionError,
+};
+use crate::{
+    object,
+    update_package::{UpdatePackage, UpdatePackageExt},
+};
+use chrono::{DateTime, Utc};
+use sdk::api::info::settings::ConfirmationTimeoutAction;
+use slog_scope::{info, warn};
+
+/// Holds a downloaded, and verified, update until its install is
+/// confirmed by the user or the confirmation times out.
+#[derive(Debug)]
+pub(super) struct AwaitingApproval {
+    pub(super) update_package: UpdatePackage,
+    pub(super) object_context: object::installer::Context,
+    /// Time the confirmation times out at. It is set when the state is
+    /// first handled, as it is handled again after each user request.
+    pub(super) deadline: Option<DateTime<Utc>>,
+}
+
+impl AwaitingApproval {
+    pub(super) fn new(
+        update_package: UpdatePackage,
+        object_context: object::installer::Context,
+    ) -> Self {
+        AwaitingApproval { update_package, object_context, deadline: None }
+    }
+
+    pub(super) fn confirm(&self) -> Install {
+        Install {
+            update_package: self.update_package.clone(),
+            object_context: self.object_context.clone(),
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl StateChangeImpl for AwaitingApproval {
+    fn name(&self) -> &'static str {
+        "awaiting_approval"
+    }
+
+    fn is_handling_download(&self) -> bool {
+        true
+    }
+
+    fn purge_download(&self, context: &Context) -> std::io::Result<usize> {
+        self.update_package.remove_staged_objects(&context.settings.update.download_dir)
+    }
+
+    async fn handle(mut self, context: &mut Context) -> Result<(State, machine::StepTransition)> {
+        let timeout = context.settings.update.install_confirmation_timeout;
+        if timeout.is_zero() {
+            info!("update downloaded, waiting for the install to be confirmed");
+            return Ok((State::AwaitingApproval(self), machine::StepTransition::Never));
+        }
+
+        let now = Utc::now();
+        let deadline = *self.deadline.get_or_insert_with(|| {
+            info!(
+                "update downloaded, waiting up to {} seconds for the install to be confirmed",
+                timeout.num_seconds()
+            );
+            now + timeout
+        });
+        if now < deadline {
+            return Ok((
+                State::AwaitingApproval(self),
+                machine::StepTransition::Delayed(deadline - now),
+            ));
+        }
+
+        match context.settings.update.install_confirmation_timeout_action {
+            ConfirmationTimeoutAction::Install => {
+                warn!("install has not been confirmed in time, installing the update");
+                Ok((State::Install(self.confirm()), machine::StepTransition::Immediate))
+            }
+            ConfirmationTimeoutAction::Abort => {
+                warn!("install has not been confirmed in time, aborting the update");
+                match self.purge_download(context) {
+                    Ok(purged) => info!("purged {} staged files of the aborted update", purged),
+                    Err(e) => warn!("failed to purge the staged files: {}", e),
+                }
+
+                // The abort is reported right away, as a failure, and the
+                // failed update recorded as it leaves the state
+                let e = TransitionError::InstallNotConfirmed(timeout.num_seconds());
+                context
+                    .report(
+                        cloud::api::Report {
+                            state: "error".to_owned(),
+                            package_uid: self.update_package.package_uid(),
+                            previous_state: Some(self.name().to_owned()),
+                            error_code: Some(e.code().to_owned()),
+                            error_message: Some(e.to_string()),
+                            current_log: Some(crate::logger::get_memory_log()),
+                            state_timings: context.state_timings(),
+                        },
+                        true,
+                    )
+                    .await;
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        firmware::installation_set::Set, object::Info, update_package::tests::get_update_package,
+    };
+    use sdk::api::info::runtime_settings::{InstallationSet, UpdateStatus};
+
+    #[tokio::test]
+    async fn waits_for_confirmation() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let state = AwaitingApproval::new(get_update_package(), Default::default());
+
+        let (machine, trans) =
+            State::AwaitingApproval(state).move_to_next_state(&mut context).await.unwrap();
+        assert_state!(machine, AwaitingApproval);
+        assert!(matches!(trans, machine::StepTransition::Never));
+    }
+
+    #[tokio::test]
+    async fn timeout_action() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context.settings.update.install_confirmation_timeout = chrono::Duration::minutes(5);
+
+        let state = AwaitingApproval::new(get_update_package(), Default::default());
+        let (machine, trans) =
+            State::AwaitingApproval(state).move_to_next_state(&mut context).await.unwrap();
+        assert!(matches!(trans, machine::StepTransition::Delayed(_)));
+
+        let expired = |machine: State| match machine {
+            State::AwaitingApproval(mut s) => {
+                s.deadline = Some(Utc::now() - chrono::Duration::seconds(1));
+                State::AwaitingApproval(s)
+            }
+            s => panic!("unexpected state: {:?}", s),
+        };
+
+        let download_dir = context.settings.update.download_dir.clone();
+        std::fs::create_dir_all(&download_dir).unwrap();
+        for object in get_update_package().objects(Set(InstallationSet::A)) {
+            std::fs::write(download_dir.join(object.sha256sum()), b"staged").unwrap();
+        }
+
+        let res = expired(machine).move_to_next_state(&mut context).await;
+        assert!(matches!(res, Err(TransitionError::InstallNotConfirmed(300))));
+        for object in get_update_package().objects(Set(InstallationSet::A)) {
+            assert!(!download_dir.join(object.sha256sum()).exists());
+        }
+        let last_result = context.runtime_settings.update.last_update_result.as_ref().unwrap();
+        assert_eq!(last_result.status, UpdateStatus::Failure);
+        assert_eq!(last_result.package_uid, get_update_package().package_uid());
+
+        context.settings.update.install_confirmation_timeout_action =
+            ConfirmationTimeoutAction::Install;
+        let state = AwaitingApproval::new(get_update_package(), Default::default());
+        let (machine, _) =
+            State::AwaitingApproval(state).move_to_next_state(&mut context).await.unwrap();
+        let (machine, _) = expired(machine).move_to_next_state(&mut context).await.unwrap();
+        assert_state!(machine, Install);
+    }
+}
@@ -10,6 +10,9 @@ use crate::utils::log::LogContent;
 use async_lock::Mutex;
 use slog_scope::info;
 
+/// Name of the file the package is fetched into, on the download dir.
+const FETCHED_PACKAGE: &str = "fetched_pkg";
+
 #[derive(Debug)]
 pub(super) struct DirectDownload {
     pub(super) url: String,
@@ -27,24 +30,60 @@ impl StateChangeImpl for DirectDownload {
         true
     }
 
+    fn purge_download(&self, context: &Context) -> std::io::Result<usize> {
+        let update_file = context.settings.update.download_dir.join(FETCHED_PACKAGE);
+        if !update_file.exists() {
+            return Ok(0);
+        }
+
+        std::fs::remove_file(update_file)?;
+        Ok(1)
+    }
+
     async fn handle(self, context: &mut Context) -> Result<(State, machine::StepTransition)> {
         info!("fetching update package directly from url: {:?}", self.url);
+        context.local_update = true;
         use std::ops::DerefMut;
         let communication_receiver = &context.communication.receiver.clone();
         let context = Mutex::new(context);
 
         let download_future = async {
             let download_dir = context.lock().await.settings.update.download_dir.clone();
+            let rate_limiter = context
+                .lock()
+                .await
+                .settings
+                .download_rate_limit_at(chrono::Local::now().time())
+                .and_then(std::num::NonZeroU64::new)
+                .map(cloud::RateLimiter::new);
             tokio::fs::create_dir_all(&download_dir)
                 .await
                 .log_error_msg("unable to create download dir")?;
-            let update_file = download_dir.join("fetched_pkg");
+            let update_file = download_dir.join(FETCHED_PACKAGE);
             let mut file = tokio::fs::File::create(&update_file)
                 .await
                 .log_error_msg("unable to open file for fatching package")?;
-            cloud::get(&self.url, &mut file).await.log_error_msg("failed to fetch package")?;
+            let progress = context.lock().await.download_progress.clone();
+            progress.start(std::iter::once((self.url.clone(), None, 0)));
+            progress.start_object();
+            let client_identity = context.lock().await.client_identity.clone();
+            let proxy = context.lock().await.proxy.clone();
+            let host_aliases = context.lock().await.host_aliases();
+            let download_timeout = context.lock().await.settings.download_timeout();
+            cloud::Client::new(&self.url, client_identity.as_ref(), proxy.as_ref())
+                .with_host_aliases(&host_aliases)
+                .with_rate_limiter(rate_limiter)
+                .with_download_timeout(download_timeout)
+                .get(&self.url, &mut file, Some(&|read| progress.add(0, read)))
+                .await
+                .log_error_msg("failed to fetch package")?;
+            progress.complete_object(0);
 
-            Ok(State::PrepareLocalInstall(PrepareLocalInstall { update_file }))
+            Ok(State::PrepareLocalInstall(PrepareLocalInstall {
+                update_file,
+                sha256sum: None,
+                objects: Vec::new(),
+            }))
         };
 
         let message_handle_future = async {
@@ -63,12 +102,12 @@ impl StateChangeImpl for DirectDownload {
         futures_util::pin_mut!(download_future);
         futures_util::pin_mut!(message_handle_future);
 
-        Ok((
-            futures_util::future::select(download_future, message_handle_future)
-                .await
-                .factor_first()
-                .0?,
-            machine::StepTransition::Immediate,
-        ))
+        let res = futures_util::future::select(download_future, message_handle_future)
+            .await
+            .factor_first()
+            .0;
+        context.lock().await.download_progress.clear();
+
+        Ok((res?, machine::StepTransition::Immediate))
     }
 }
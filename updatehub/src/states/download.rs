@@ -4,16 +4,18 @@
 
 use super::{
     machine::{self, CommunicationState, Context},
-    CallbackReporter, ProgressReporter, Result, State, StateChangeImpl, Validation,
+    CallbackReporter, PausedDownload, ProgressReporter, Result, State, StateChangeImpl, Validation,
 };
 use crate::{
     firmware::installation_set,
     object::{self, Info},
     update_package::{UpdatePackage, UpdatePackageExt},
-    utils::log::LogContent,
+    utils::{self, log::LogContent},
 };
 use async_lock::Mutex;
-use slog_scope::{debug, error, trace};
+use futures_util::{stream, TryStreamExt};
+use slog_scope::{debug, error, info, trace, warn};
+use std::path::Path;
 
 #[derive(Debug)]
 pub(super) struct Download {
@@ -34,6 +36,8 @@ impl Download {
             .clear_unrelated_files(&download_dir, installation_set, &context.lock().await.settings)
             .log_error_msg("failed to cleanup files unrelated to current update")?;
 
+        let stream_install = context.lock().await.settings.storage.stream_install;
+
         // Get missing or incomplete objects for download
         let pending_download = {
             let mut objects: Vec<_> = update_package
@@ -47,6 +51,10 @@ impl Download {
                         );
                         return None;
                     }
+                    if stream_install && object::is_streamable(o) {
+                        trace!("skip download for {} as it is streamed on install", o.filename());
+                        return None;
+                    }
 
                     match (o.filename(), o.sha256sum(), o.status(&download_dir)) {
                         (filename, sha256sum, Err(err)) => {
@@ -55,13 +63,13 @@ impl Download {
                                 filename, sha256sum, err
                             );
 
-                            Some((filename, sha256sum))
+                            Some((filename, sha256sum, o.len(), o.urls()))
                         }
 
                         (filename, sha256sum, Ok(object::info::Status::Missing))
                         | (filename, sha256sum, Ok(object::info::Status::Incomplete))
                         | (filename, sha256sum, Ok(object::info::Status::Corrupted)) => {
-                            Some((filename, sha256sum))
+                            Some((filename, sha256sum, o.len(), o.urls()))
                         }
 
                         (_, _, Ok(object::info::Status::Ready)) => None,
@@ -77,26 +85,139 @@ impl Download {
 
         trace!("the following objects are missing: {:?}", pending_download);
 
-        // Download the missing or incomplete objects
+        let margin = context.lock().await.settings.update.download_space_margin;
+        let required = required_download_space(&pending_download, &download_dir) + margin;
+        std::fs::create_dir_all(&download_dir).log_error_msg("unable to create download dir")?;
+        match crate::utils::fs::ensure_disk_space(&download_dir, required) {
+            Err(crate::utils::Error::NotEnoughSpace { available, required }) => {
+                error!(
+                    "not enough space on download dir: {} bytes required, {} available",
+                    required, available
+                );
+                return Err(super::TransitionError::NotEnoughDownloadSpace { available, required });
+            }
+            res => res.map_err(crate::object::Error::from)?,
+        }
+
+        // Download the missing or incomplete objects, up to
+        // `max_concurrent_downloads` at a time. The first failure drops
+        // all the downloads still in flight.
         let url = context.lock().await.server_address().to_owned();
         let product_uid = context.lock().await.firmware.product_uid.clone();
-        let api = crate::CloudClient::new(&url);
-        for (name, sha256sum) in pending_download {
-            debug!("starting download of: {} ({})", name, sha256sum);
-            api.download_object(
-                &product_uid,
-                &update_package.package_uid(),
-                &download_dir,
-                sha256sum,
+        let max_concurrent_downloads =
+            context.lock().await.settings.network.max_concurrent_downloads;
+        let rate_limiter = context
+            .lock()
+            .await
+            .settings
+            .download_rate_limit_at(chrono::Local::now().time())
+            .and_then(std::num::NonZeroU64::new)
+            .map(cloud::RateLimiter::new);
+        let progress = context.lock().await.download_progress.clone();
+        let client_identity = context.lock().await.client_identity.clone();
+        let proxy = context.lock().await.proxy.clone();
+        let host_aliases = context.lock().await.host_aliases();
+        let headers = context.lock().await.request_headers()?;
+        let retries = context.lock().await.settings.network.download_retries;
+        let backoff = context
+            .lock()
+            .await
+            .settings
+            .network
+            .download_retry_backoff
+            .to_std()
+            .unwrap_or_default();
+        let download_timeout = context.lock().await.settings.download_timeout();
+        let strict_content_length = context.lock().await.settings.strict_content_length();
+        progress.start(pending_download.iter().map(|(name, sha256sum, size, _)| {
+            let downloaded = download_dir.join(sha256sum).metadata().map_or(0, |m| m.len());
+            (name.to_string(), Some(*size), downloaded.min(*size))
+        }));
+
+        let package_uid = update_package.package_uid();
+        let api = crate::CloudClient::new(&url, client_identity.as_ref(), proxy.as_ref())
+            .with_headers(&headers)
+            .with_host_aliases(&host_aliases)
+            .with_rate_limiter(rate_limiter)
+            .with_download_timeout(download_timeout)
+            .with_strict_content_length(strict_content_length);
+        let (api, product_uid, package_uid, download_dir, progress) =
+            (&api, &product_uid, &package_uid, &download_dir, &progress);
+        stream::iter(pending_download.into_iter().enumerate().map(Result::Ok))
+            .try_for_each_concurrent(
+                max_concurrent_downloads,
+                |(index, (name, sha256sum, size, mirrors))| async move {
+                    debug!("starting download of: {} ({})", name, sha256sum);
+                    progress.start_object();
+                    // The server is only used when the object has no mirrors
+                    let urls = match mirrors {
+                        [] => vec![api.object_url(product_uid, package_uid, sha256sum)],
+                        mirrors => mirrors.to_vec(),
+                    };
+                    let mut res = Ok(());
+                    for (n, url) in urls.iter().enumerate() {
+                        let mut attempt = 0;
+                        res = loop {
+                            let res = api
+                                .download_object_from(
+                                    url,
+                                    download_dir,
+                                    sha256sum,
+                                    size,
+                                    Some(&|read| progress.add(index, read)),
+                                )
+                                .await;
+                            match res {
+                                Err(e) if attempt < retries && e.is_retryable() => {
+                                    attempt += 1;
+                                    let delay = utils::retry_delay(backoff, attempt);
+                                    info!(
+                                        "retrying download of {} in {:?} (attempt {} of {}), error: {}",
+                                        name, delay, attempt, retries, e
+                                    );
+                                    tokio::time::sleep(delay).await;
+                                }
+                                res => break res,
+                            }
+                        };
+
+                        match &res {
+                            Ok(()) if !mirrors.is_empty() => {
+                                info!("downloaded {} from mirror {}", name, url);
+                                break;
+                            }
+                            Ok(()) => break,
+                            Err(e) if n + 1 < urls.len() => {
+                                warn!(
+                                    "failed to download {} from mirror {}, trying the next one: {}",
+                                    name, url, e
+                                );
+                            }
+                            Err(_) => {}
+                        }
+                    }
+                    res.log_error_msg("failed to download object from update package")?;
+                    progress.complete_object(index);
+                    Ok(())
+                },
             )
             .await
-            .log_error_msg("failed to download object from update package")?;
-        }
-
-        Ok(())
     }
 }
 
+/// Space needed on the download dir for the pending objects. Objects
+/// installed straight from the server are not pending and, as partially
+/// downloaded objects are either resumed or replaced, only the missing
+/// bytes are accounted for.
+fn required_download_space(pending: &[(&str, &str, u64, &[String])], download_dir: &Path) -> u64 {
+    pending
+        .iter()
+        .map(|(_, sha256sum, size, _)| {
+            size.saturating_sub(download_dir.join(sha256sum).metadata().map_or(0, |m| m.len()))
+        })
+        .sum()
+}
+
 impl CallbackReporter for Download {}
 
 impl ProgressReporter for Download {
@@ -111,9 +232,28 @@ impl ProgressReporter for Download {
     fn report_leave_state_name(&self) -> &'static str {
         "downloaded"
     }
+
+    fn leave_webhook_event(&self) -> Option<machine::WebhookEvent> {
+        Some(machine::WebhookEvent::DownloadCompleted)
+    }
 }
 
-impl CommunicationState for Download {}
+#[async_trait::async_trait]
+impl CommunicationState for Download {
+    async fn handle_pause_download(
+        &self,
+        _: &Context,
+    ) -> Result<(machine::DownloadControlResponse, Option<State>)> {
+        info!("pausing download as requested by the user");
+        Ok((
+            machine::DownloadControlResponse::RequestAccepted,
+            Some(State::PausedDownload(PausedDownload {
+                update_package: self.update_package.clone(),
+                sign: self.sign.clone(),
+            })),
+        ))
+    }
+}
 
 #[async_trait::async_trait(?Send)]
 impl StateChangeImpl for Download {
@@ -125,6 +265,10 @@ impl StateChangeImpl for Download {
         true
     }
 
+    fn purge_download(&self, context: &Context) -> std::io::Result<usize> {
+        self.update_package.remove_staged_objects(&context.settings.update.download_dir)
+    }
+
     async fn handle(mut self, context: &mut Context) -> Result<(State, machine::StepTransition)> {
         use std::ops::DerefMut;
         let communication_receiver = &context.communication.receiver.clone();
@@ -157,12 +301,13 @@ impl StateChangeImpl for Download {
         futures_util::pin_mut!(download_future);
         futures_util::pin_mut!(message_handle_future);
 
-        if let Some(new_state) =
-            futures_util::future::select(download_future, message_handle_future)
-                .await
-                .factor_first()
-                .0?
-        {
+        let res = futures_util::future::select(download_future, message_handle_future)
+            .await
+            .factor_first()
+            .0;
+        context.lock().await.download_progress.clear();
+
+        if let Some(new_state) = res? {
             return Ok((new_state, machine::StepTransition::Immediate));
         }
 
@@ -229,6 +374,85 @@ mod test {
         assert_eq!(&utils::sha256sum(object_content.as_bytes()), &shasum, "Checksum mismatch");
     }
 
+    #[test]
+    fn retry_delay_backoff() {
+        let backoff = std::time::Duration::from_secs(1);
+        for (attempt, base) in [(1, 1), (2, 2), (3, 4), (4, 8)] {
+            let delay = utils::retry_delay(backoff, attempt);
+            assert!(delay >= std::time::Duration::from_secs(base), "{:?}", delay);
+            assert!(delay <= std::time::Duration::from_millis(base * 1500), "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn required_download_space_skips_downloaded_bytes() {
+        let download_dir = tempfile::tempdir().unwrap();
+        fs::write(download_dir.path().join("partial"), [0; 10]).unwrap();
+        fs::write(download_dir.path().join("oversized"), [0; 50]).unwrap();
+
+        let pending: [(_, _, _, &[String]); 3] = [
+            ("missing", "missing", 100, &[]),
+            ("partial", "partial", 30, &[]),
+            ("oversized", "oversized", 20, &[]),
+        ];
+        assert_eq!(required_download_space(&pending, download_dir.path()), 120);
+    }
+
+    #[tokio::test]
+    async fn download_from_mirrors() {
+        use crate::update_package::tests::{get_update_json, OBJECT, SHA256SUM};
+
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context.settings.network.download_retries = 1;
+        context.settings.network.download_retry_backoff = chrono::Duration::zero();
+        let mut metadata = get_update_json(SHA256SUM);
+        for objects in metadata["objects"].as_array_mut().unwrap() {
+            objects[0]["urls"] = serde_json::json!([
+                "https://mirror-a.example.com/object",
+                "https://mirror-b.example.com/object"
+            ]);
+        }
+        let update_package = UpdatePackage::parse(metadata.to_string().as_bytes()).unwrap();
+
+        // The first mirror is down, so the object comes from the second one
+        cloud_mock::set_unreachable_urls(vec!["https://mirror-a.example.com/object".to_owned()]);
+        cloud_mock::set_download_data(OBJECT.to_vec());
+        let res = Download::start_download(update_package, &Mutex::new(&mut context)).await;
+        cloud_mock::set_unreachable_urls(Vec::new());
+        res.unwrap();
+
+        assert_eq!(fs::read(context.settings.update.download_dir.join(SHA256SUM)).unwrap(), OBJECT);
+    }
+
+    #[tokio::test]
+    async fn retry_with_backoff() {
+        use crate::update_package::tests::{get_update_package, OBJECT, SHA256SUM};
+
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context.settings.network.download_retries = 2;
+        context.settings.network.download_retry_backoff = chrono::Duration::milliseconds(50);
+
+        // The first attempt fails, and the second one waits for the backoff
+        cloud_mock::set_failing_downloads(1);
+        cloud_mock::set_download_data(OBJECT.to_vec());
+        let start = std::time::Instant::now();
+        let res = Download::start_download(get_update_package(), &Mutex::new(&mut context)).await;
+        cloud_mock::set_failing_downloads(0);
+        res.unwrap();
+
+        assert!(start.elapsed() >= std::time::Duration::from_millis(50), "{:?}", start.elapsed());
+        assert_eq!(fs::read(context.settings.update.download_dir.join(SHA256SUM)).unwrap(), OBJECT);
+
+        // Once the retries are exhausted the download fails
+        fs::remove_file(context.settings.update.download_dir.join(SHA256SUM)).unwrap();
+        cloud_mock::set_failing_downloads(3);
+        let res = Download::start_download(get_update_package(), &Mutex::new(&mut context)).await;
+        cloud_mock::set_failing_downloads(0);
+        assert!(res.is_err());
+    }
+
     #[tokio::test]
     #[ignore]
     async fn download_small_object() {
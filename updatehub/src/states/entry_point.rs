@@ -4,10 +4,10 @@
 
 use super::{
     machine::{self, Context},
-    Park, Poll, Probe, Result, State, StateChangeImpl,
+    Park, Poll, Probe, Result, State, StateChangeImpl, TransitionError,
 };
 use crate::utils::log::LogContent;
-use slog_scope::{debug, info};
+use slog_scope::{debug, error, info};
 
 #[derive(Debug)]
 pub(super) struct EntryPoint {}
@@ -28,6 +28,40 @@ impl StateChangeImpl for EntryPoint {
     }
 
     async fn handle(self, context: &mut Context) -> Result<(State, machine::StepTransition)> {
+        // Any update reaching here has either finished or been given up
+        // on, so it must not be resumed on the next start
+        context
+            .runtime_settings
+            .clear_pending_update()
+            .log_error_msg("failed to clear pending update")?;
+        // Immediate retries move straight to probe, so the retries are over
+        context.error_retries = 0;
+        context.local_update = false;
+
+        if context.runtime_settings.is_rollback_exhausted() {
+            let e = TransitionError::RollbackExhausted {
+                attempts: context.runtime_settings.rollback_attempts(),
+            };
+            error!("{}, parking until the device is recovered", e);
+            if let Some(package_uid) = context.runtime_settings.applied_package_uid() {
+                context
+                    .report(
+                        cloud::api::Report {
+                            state: "error".to_owned(),
+                            package_uid,
+                            previous_state: None,
+                            error_code: Some(e.code().to_owned()),
+                            error_message: Some(e.to_string()),
+                            current_log: Some(crate::logger::get_memory_log()),
+                            state_timings: Vec::new(),
+                        },
+                        true,
+                    )
+                    .await;
+            }
+            return Ok((State::Park(Park {}), machine::StepTransition::Immediate));
+        }
+
         if context.runtime_settings.is_polling_forced() {
             info!("triggering Probe to finish update");
             context
@@ -91,4 +125,33 @@ mod tests {
             _ => panic!("Unexpected StepTransition: {:?}", trans),
         }
     }
+
+    #[tokio::test]
+    async fn clears_pending_update() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context
+            .runtime_settings
+            .set_pending_update(sdk::api::info::runtime_settings::PendingUpdate {
+                package: "{}".to_owned(),
+                signature: None,
+            })
+            .unwrap();
+
+        State::EntryPoint(EntryPoint {}).move_to_next_state(&mut context).await.unwrap();
+
+        assert_eq!(context.runtime_settings.pending_update(), None);
+    }
+
+    #[tokio::test]
+    async fn rollback_exhausted() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context.runtime_settings.set_rollback_exhausted().unwrap();
+
+        let machine =
+            State::EntryPoint(EntryPoint {}).move_to_next_state(&mut context).await.unwrap().0;
+
+        assert_state!(machine, Park);
+    }
 }
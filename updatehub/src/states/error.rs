@@ -4,10 +4,17 @@
 
 use super::{
     machine::{self, Context},
-    CallbackReporter, EntryPoint, Result, State, StateChangeImpl, TransitionError,
+    CallbackReporter, EntryPoint, Probe, Result, State, StateChangeImpl, TransitionError,
 };
 
-use slog_scope::{error, info};
+use slog_scope::{error, info, warn};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Number of error logs kept, including the latest one.
+const ERROR_LOGS_KEPT: usize = 5;
 
 #[derive(Debug)]
 pub(super) struct Error {
@@ -22,15 +29,209 @@ impl StateChangeImpl for Error {
         "error"
     }
 
-    async fn handle(self, _: &mut Context) -> Result<(State, machine::StepTransition)> {
+    async fn handle(self, context: &mut Context) -> Result<(State, machine::StepTransition)> {
         error!("error state reached: {}", self.error);
+        if let Some(path) = &context.settings.firmware.error_log_path {
+            // Saving the log is best effort, so it never changes the
+            // outcome of the error
+            if let Err(e) = save_error_log(path, &self.error) {
+                warn!("failed to save error log to {:?}: {}", path, e);
+            }
+        }
+
+        // Retries go through a new probe, which would fetch the server's
+        // update instead of the one requested through the install API
+        let max_retries = context.settings.update.error_retries;
+        if self.error.is_retryable() && !context.local_update && context.error_retries < max_retries
+        {
+            context.error_retries += 1;
+            let delay = retry_delay(context.settings.update.error_retry_delay);
+            warn!(
+                "retrying after '{}' error in {} seconds (attempt {} of {})",
+                self.error.code(),
+                delay.num_seconds(),
+                context.error_retries,
+                max_retries
+            );
+            return Ok((State::Probe(Probe {}), machine::StepTransition::Delayed(delay)));
+        }
+
+        if context.error_retries > 0 {
+            info!("giving up after {} retries", context.error_retries);
+        }
         info!("returning to machine's entry point");
         Ok((State::EntryPoint(EntryPoint {}), machine::StepTransition::Immediate))
     }
 }
 
+/// Saves the in-memory log to `path`, along with the error, shifting
+/// the logs of previous errors to numbered suffixes.
+fn save_error_log(path: &Path, error: &TransitionError) -> io::Result<()> {
+    let rotated = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    for n in (1..ERROR_LOGS_KEPT).rev() {
+        let from = if n == 1 { path.to_owned() } else { rotated(n - 1) };
+        match fs::rename(&from, rotated(n)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+
+    fs::write(
+        path,
+        format!(
+            "{} error '{}': {}\n{}",
+            chrono::Utc::now().to_rfc3339(),
+            error.code(),
+            error,
+            crate::logger::get_memory_log()
+        ),
+    )
+}
+
+/// Adds up to 50% of jitter to the retry delay, so devices failing
+/// together don't retry in lockstep.
+fn retry_delay(delay: chrono::Duration) -> chrono::Duration {
+    delay + super::poll::random_jitter(delay / 2)
+}
+
 impl From<TransitionError> for State {
     fn from(error: TransitionError) -> State {
         State::Error(Error { error })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network_error() -> TransitionError {
+        TransitionError::Client(cloud::Error::Io(std::io::ErrorKind::ConnectionRefused.into()))
+    }
+
+    #[tokio::test]
+    async fn retry_transient_error() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context.settings.update.error_retries = 2;
+        context.settings.update.error_retry_delay = chrono::Duration::seconds(10);
+
+        for attempt in 1..=2 {
+            let (machine, trans) =
+                State::from(network_error()).move_to_next_state(&mut context).await.unwrap();
+            assert_state!(machine, Probe);
+            match trans {
+                machine::StepTransition::Delayed(t) => {
+                    assert!(t >= chrono::Duration::seconds(10), "{:?}", t);
+                    assert!(t <= chrono::Duration::seconds(15), "{:?}", t);
+                }
+                _ => panic!("Unexpected StepTransition: {:?}", trans),
+            }
+            assert_eq!(context.error_retries, attempt);
+        }
+
+        // Retries are exhausted, so it waits for the next poll
+        let machine =
+            State::from(network_error()).move_to_next_state(&mut context).await.unwrap().0;
+        assert_state!(machine, EntryPoint);
+
+        State::EntryPoint(EntryPoint {}).move_to_next_state(&mut context).await.unwrap();
+        assert_eq!(context.error_retries, 0);
+    }
+
+    #[tokio::test]
+    async fn local_install_error_is_not_retried() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context.settings.update.error_retries = 2;
+
+        // The package is gone, so the local install fails
+        let res = State::PrepareLocalInstall(super::super::PrepareLocalInstall {
+            update_file: setup.settings.data.update.download_dir.join("missing.uhupkg"),
+            sha256sum: None,
+            objects: Vec::new(),
+        })
+        .move_to_next_state(&mut context)
+        .await;
+        assert!(res.is_err());
+
+        // A retry would probe the server instead of installing the package
+        let machine =
+            State::from(network_error()).move_to_next_state(&mut context).await.unwrap().0;
+        assert_state!(machine, EntryPoint);
+        assert_eq!(context.error_retries, 0);
+
+        // Updates from the server are retried again
+        State::EntryPoint(EntryPoint {}).move_to_next_state(&mut context).await.unwrap();
+        let machine =
+            State::from(network_error()).move_to_next_state(&mut context).await.unwrap().0;
+        assert_state!(machine, Probe);
+    }
+
+    #[tokio::test]
+    async fn save_rotated_error_logs() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("error.log");
+        context.settings.firmware.error_log_path = Some(path.clone());
+
+        for _ in 0..ERROR_LOGS_KEPT + 1 {
+            let machine =
+                State::from(network_error()).move_to_next_state(&mut context).await.unwrap().0;
+            assert_state!(machine, EntryPoint);
+        }
+
+        let log = fs::read_to_string(&path).unwrap();
+        assert!(log.contains("error 'network_error'"), "{}", log);
+        let mut files: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            ["error.log", "error.log.1", "error.log.2", "error.log.3", "error.log.4"]
+        );
+
+        // A failure to save the log doesn't change the transition
+        context.settings.firmware.error_log_path = Some(dir.path().join("missing/error.log"));
+        let machine =
+            State::from(network_error()).move_to_next_state(&mut context).await.unwrap().0;
+        assert_state!(machine, EntryPoint);
+    }
+
+    #[tokio::test]
+    async fn non_retryable_error() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context.settings.update.error_retries = 2;
+
+        let error =
+            TransitionError::PackageChecksumMismatch { expected: "a".into(), found: "b".into() };
+        let machine = State::from(error).move_to_next_state(&mut context).await.unwrap().0;
+
+        assert_state!(machine, EntryPoint);
+        assert_eq!(context.error_retries, 0);
+    }
+
+    #[tokio::test]
+    async fn stuck_callback_times_out() {
+        let setup = crate::tests::TestEnvironment::build()
+            .state_change_callback("#!/bin/sh\nsleep 30".to_owned())
+            .finish();
+        let mut context = setup.gen_context();
+        context.settings.update.state_timeouts.insert("error".into(), chrono::Duration::seconds(1));
+
+        // The error state isn't entered again, which would run the stuck
+        // callback once more
+        let start = std::time::Instant::now();
+        let machine =
+            State::from(network_error()).handle_with_timeout(&mut context).await.unwrap().0;
+        assert_state!(machine, EntryPoint);
+        assert!(start.elapsed() < std::time::Duration::from_secs(10), "{:?}", start.elapsed());
+    }
+}
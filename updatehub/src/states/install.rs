@@ -3,16 +3,22 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::{
-    machine::{self, Context},
-    CallbackReporter, ProgressReporter, Reboot, Result, State, StateChangeImpl,
+    machine::{self, CommunicationState, Context},
+    CallbackReporter, EntryPoint, ProgressReporter, Reboot, Result, State, StateChangeImpl,
 };
 use crate::{
-    firmware::installation_set,
+    firmware::{self, installation_set, Transition},
     object::{self, Info, Installer},
     update_package::{UpdatePackage, UpdatePackageExt},
-    utils::log::LogContent,
+    utils::{definitions::TargetTypeExt, log::LogContent},
 };
-use slog_scope::info;
+use chrono::{DateTime, Duration, Local, NaiveDateTime, TimeZone};
+use sdk::api::info::{
+    install_progress::InstallStatus,
+    runtime_settings::{UpdateResult, UpdateStatus},
+    settings::UpdateWindow,
+};
+use slog_scope::{info, warn};
 
 #[derive(Debug)]
 pub(super) struct Install {
@@ -22,6 +28,163 @@ pub(super) struct Install {
 
 impl CallbackReporter for Install {}
 
+impl CommunicationState for Install {}
+
+impl Install {
+    /// Postpones the installation when outside of the configured update
+    /// windows. The update is probed again once the next window opens.
+    pub(super) fn postpone_to_update_window(
+        &self,
+        context: &mut Context,
+    ) -> Result<Option<(State, machine::StepTransition)>> {
+        let now = Local::now();
+        let install_time = match next_install_time(&context.settings.polling.update_window, now) {
+            Some(t) if t > now => t,
+            _ => return Ok(None),
+        };
+
+        info!("outside of update window, postponing install until {}", install_time);
+        context
+            .runtime_settings
+            .force_poll()
+            .log_error_msg("failed to force poll")?
+            .log_if_not_persisted("forced poll");
+
+        Ok(Some((
+            State::EntryPoint(EntryPoint {}),
+            machine::StepTransition::Delayed(install_time.signed_duration_since(now)),
+        )))
+    }
+
+    /// Postpones the installation until the system has been up for the
+    /// configured minimum uptime. The update is probed again once it's
+    /// reached.
+    pub(super) fn postpone_to_minimum_uptime(
+        &self,
+        context: &mut Context,
+    ) -> Result<Option<(State, machine::StepTransition)>> {
+        let minimum = context.settings.firmware.minimum_uptime_before_install;
+        if minimum <= Duration::zero() {
+            return Ok(None);
+        }
+        let uptime = system_uptime().log_error_msg("failed to read system uptime")?;
+        if uptime >= minimum {
+            return Ok(None);
+        }
+
+        let remaining = minimum - uptime;
+        info!(
+            "system is up for {} seconds, postponing install for {} seconds",
+            uptime.num_seconds(),
+            remaining.num_seconds()
+        );
+        context
+            .runtime_settings
+            .force_poll()
+            .log_error_msg("failed to force poll")?
+            .log_if_not_persisted("forced poll");
+
+        Ok(Some((State::EntryPoint(EntryPoint {}), machine::StepTransition::Delayed(remaining))))
+    }
+
+    /// Runs the pre-install callback, going back to the entry point when
+    /// it cancels the install. The downloaded objects are kept so the
+    /// update can be installed later.
+    pub(super) async fn run_pre_install_callback(
+        &self,
+        context: &Context,
+    ) -> Result<Option<(State, machine::StepTransition)>> {
+        let installation_set = context
+            .runtime_settings
+            .get_inactive_installation_set(context.settings.target_installation_set())
+            .log_error_msg("unable to get inactive installation set")?;
+        let objects: Vec<_> =
+            self.update_package.objects(installation_set).iter().map(|o| o.filename()).collect();
+
+        match firmware::pre_install_callback(
+            &context.settings.firmware.metadata,
+            &self.update_package.package_uid(),
+            &objects,
+        )
+        .await
+        .log_error_msg("pre-install callback has failed")?
+        {
+            Transition::Continue => Ok(None),
+            Transition::Cancel => {
+                info!("canceling install due to pre-install callback request");
+                Ok(Some((State::EntryPoint(EntryPoint {}), machine::StepTransition::Immediate)))
+            }
+        }
+    }
+}
+
+/// Reads the time the system has been up for.
+fn system_uptime() -> std::io::Result<Duration> {
+    let content = std::fs::read_to_string("/proc/uptime")?;
+    content
+        .split_whitespace()
+        .next()
+        .and_then(|s| s.parse::<f64>().ok())
+        .map(|secs| Duration::milliseconds((secs * 1000.0) as i64))
+        .ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed /proc/uptime")
+        })
+}
+
+/// Returns the earliest time, from `now` on, an update is allowed to be
+/// installed or `None` if no update window is configured.
+pub(super) fn next_install_time(
+    windows: &[UpdateWindow],
+    now: DateTime<Local>,
+) -> Option<DateTime<Local>> {
+    next_window_opening(windows, now.naive_local()).map(|t| {
+        // Daylight saving time may skip the window start so we take
+        // the first existing time after it.
+        Local
+            .from_local_datetime(&t)
+            .earliest()
+            .or_else(|| Local.from_local_datetime(&(t + Duration::hours(1))).earliest())
+            .unwrap_or(now)
+    })
+}
+
+fn next_window_opening(windows: &[UpdateWindow], now: NaiveDateTime) -> Option<NaiveDateTime> {
+    windows
+        .iter()
+        .map(|w| {
+            if w.contains(now.time()) {
+                return now;
+            }
+
+            let start = now.date().and_time(w.start);
+            if start > now {
+                start
+            } else {
+                start + Duration::days(1)
+            }
+        })
+        .min()
+}
+
+/// Sorts the objects in the order they must be installed.
+///
+/// When the package sets an `install-order` for any of its objects, they are
+/// installed in ascending priority, with objects without one taken as
+/// priority 0. Objects sharing the same priority keep their order in the
+/// metadata.
+///
+/// Otherwise, objects are sorted in reverse order so the smaller objects are
+/// installed later. This postpones objects like U-Boot updates and U-Boot
+/// environment changes towards the end of the update.
+fn sort_for_install(objs: &mut [pkg_schema::Object]) {
+    if objs.iter().any(|o| object::install_order(o).is_some()) {
+        objs.sort_by_key(|o| object::install_order(o).unwrap_or_default());
+        return;
+    }
+
+    objs.sort_by(|a, b| a.len().partial_cmp(&b.len()).unwrap().reverse());
+}
+
 impl ProgressReporter for Install {
     fn package_uid(&self) -> String {
         self.update_package.package_uid()
@@ -34,6 +197,14 @@ impl ProgressReporter for Install {
     fn report_leave_state_name(&self) -> &'static str {
         "installed"
     }
+
+    fn leave_webhook_event(&self) -> Option<machine::WebhookEvent> {
+        Some(machine::WebhookEvent::InstallCompleted)
+    }
+
+    fn report_state_timings_on_leave(&self) -> bool {
+        true
+    }
 }
 
 #[async_trait::async_trait(?Send)]
@@ -42,27 +213,100 @@ impl StateChangeImpl for Install {
         "install"
     }
 
+    fn is_handling_download(&self) -> bool {
+        self.object_context.stream_install
+    }
+
     async fn handle(mut self, context: &mut Context) -> Result<(State, machine::StepTransition)> {
         let package_uid = self.update_package.package_uid();
         info!("installing update: {} ({})", self.update_package.version(), &package_uid);
 
         let installation_set = context
             .runtime_settings
-            .get_inactive_installation_set()
+            .get_inactive_installation_set(context.settings.target_installation_set())
             .log_error_msg("unable to get inactive installation set")?;
-        info!("using installation set as target {}", installation_set);
+        match context.settings.target_installation_set() {
+            Some(_) => info!("using pinned installation set as target {}", installation_set),
+            None => info!("using installation set as target {}", installation_set),
+        }
 
-        let obj_context = self.object_context;
-        let objs = self.update_package.objects_mut(installation_set);
+        let obj_context = self.object_context.clone();
+        sort_for_install(self.update_package.objects_mut(installation_set));
+        let objs = self.update_package.objects(installation_set);
 
-        // Objects are sorted in reverse order so the smaller objects are installed
-        // later. This postpones objects like U-Boot updates and U-Boot environment
-        // changes towards the end of the update.
-        objs.sort_by(|a, b| a.len().partial_cmp(&b.len()).unwrap().reverse());
+        if context.settings.storage.dry_run {
+            for obj in objs.iter() {
+                let targets = object::target_types(obj);
+                if targets.is_empty() {
+                    info!("dry-run: would install '{}' ({})", obj.filename(), obj.mode());
+                }
+                for target in targets {
+                    let device = target
+                        .get_target()
+                        .map_err(object::Error::from)
+                        .log_error_msg("failed to resolve object target")?;
+                    info!(
+                        "dry-run: would install '{}' ({}) into {:?}",
+                        obj.filename(),
+                        obj.mode(),
+                        device
+                    );
+                }
+            }
 
-        // Run the install routine for every object.
-        for obj in objs.iter_mut() {
-            obj.install(&obj_context).await?;
+            info!("dry-run: update has not been installed");
+            return Ok((
+                State::Reboot(Reboot {
+                    update_package: Some(self.update_package),
+                    delay_elapsed: false,
+                }),
+                machine::StepTransition::Immediate,
+            ));
+        }
+
+        // Run the install routine for every object while handling the user
+        // requests, so a stream install can be aborted.
+        let progress = context.install_progress.clone();
+        progress.start(objs.iter().map(|o| (o.filename().to_owned(), o.mode())));
+        let aborted = {
+            let communication_receiver = &context.communication.receiver.clone();
+            let install_future = async {
+                for (index, obj) in objs.iter().enumerate() {
+                    info!("installing object {}/{}: {}", index + 1, objs.len(), obj.filename());
+                    progress.set_status(index, InstallStatus::Installing);
+                    if let Err(e) = obj.install(&obj_context).await {
+                        progress.set_status(index, InstallStatus::Failed);
+                        return Err(e.into());
+                    }
+                    progress.set_status(index, InstallStatus::Done);
+                }
+                Result::Ok(None)
+            };
+
+            let message_handle_future = async {
+                while let Ok((msg, responder)) = communication_receiver.recv().await {
+                    if let Some(new_state) =
+                        self.handle_communication(msg, responder, &mut *context).await
+                    {
+                        return Ok(Some(new_state));
+                    }
+                }
+                futures_util::future::pending().await
+            };
+
+            futures_util::pin_mut!(install_future);
+            futures_util::pin_mut!(message_handle_future);
+
+            futures_util::future::select(install_future, message_handle_future)
+                .await
+                .factor_first()
+                .0
+        };
+        progress.clear();
+        let aborted = aborted?;
+        if let Some(new_state) = aborted {
+            warn!("install has been aborted, its targets may be left dirty");
+            return Ok((new_state, machine::StepTransition::Immediate));
         }
 
         // Avoid installing same package twice.
@@ -70,6 +314,10 @@ impl StateChangeImpl for Install {
             .runtime_settings
             .set_applied_package_uid(&package_uid)
             .log_error_msg("failed to set applied package uid to runtime settings")?;
+        context
+            .runtime_settings
+            .clear_pending_update()
+            .log_error_msg("failed to clear pending update from runtime settings")?;
 
         // Set upgrading to the new installation set.
         context
@@ -81,10 +329,31 @@ impl StateChangeImpl for Install {
         info!("swapping active installation set");
         installation_set::swap_active()
             .log_error_msg("unable to update active installation set")?;
+        context.refresh_installation_set();
 
         info!("update installed successfully");
+        if !context.settings.storage.keep_downloads {
+            match self
+                .update_package
+                .reclaim_installed_objects(&context.settings.update.download_dir)
+            {
+                Ok(reclaimed) => info!("removed installed objects, reclaiming {} bytes", reclaimed),
+                Err(e) => warn!("failed to remove installed objects: {}", e),
+            }
+        }
+        match context.runtime_settings.set_last_update_result(
+            UpdateResult { status: UpdateStatus::Success, package_uid, error: None },
+            None,
+            context.settings.update.history_size,
+        ) {
+            Ok(saved) => saved.log_if_not_persisted("update result"),
+            Err(e) => warn!("failed to record update result: {}", e),
+        }
         Ok((
-            State::Reboot(Reboot { update_package: self.update_package }),
+            State::Reboot(Reboot {
+                update_package: Some(self.update_package),
+                delay_elapsed: false,
+            }),
             machine::StepTransition::Immediate,
         ))
     }
@@ -93,9 +362,69 @@ impl StateChangeImpl for Install {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::update_package::tests::get_update_package;
+    use crate::update_package::tests::{create_fake_object, get_update_package, OBJECT, SHA256SUM};
+    use chrono::NaiveDate;
     use pretty_assertions::assert_eq;
 
+    fn at(hour: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2020, 1, 1).unwrap().and_hms_opt(hour, min, 0).unwrap()
+    }
+
+    #[test]
+    fn update_window_opening() {
+        let windows = [
+            UpdateWindow::try_from("02:00-04:00".to_string()).unwrap(),
+            UpdateWindow::try_from("23:00-00:30".to_string()).unwrap(),
+        ];
+
+        assert_eq!(next_window_opening(&[], at(12, 0)), None);
+        assert_eq!(next_window_opening(&windows, at(3, 0)), Some(at(3, 0)));
+        assert_eq!(next_window_opening(&windows, at(0, 15)), Some(at(0, 15)));
+        assert_eq!(next_window_opening(&windows, at(23, 30)), Some(at(23, 30)));
+        assert_eq!(next_window_opening(&windows, at(1, 0)), Some(at(2, 0)));
+        assert_eq!(next_window_opening(&windows, at(12, 0)), Some(at(23, 0)));
+        assert_eq!(next_window_opening(&windows, at(4, 0)), Some(at(23, 0)));
+        assert_eq!(
+            next_window_opening(&windows[..1], at(12, 0)),
+            Some(at(2, 0) + Duration::days(1))
+        );
+    }
+
+    #[test]
+    fn install_order() {
+        let object = |filename: &str, size: u64, order: Option<i32>| {
+            let mut value = serde_json::json!({
+                "mode": "test",
+                "filename": filename,
+                "sha256sum": "",
+                "target": "",
+                "size": size,
+                "force-check-requirements-fail": false,
+            });
+            if let Some(order) = order {
+                value["install-order"] = order.into();
+            }
+            serde_json::from_value::<pkg_schema::Object>(value).unwrap()
+        };
+        let filenames = |objs: &[pkg_schema::Object]| {
+            objs.iter().map(|o| o.filename().to_owned()).collect::<Vec<_>>()
+        };
+
+        let mut objs = vec![object("small", 1, None), object("big", 10, None)];
+        sort_for_install(&mut objs);
+        assert_eq!(filenames(&objs), ["big", "small"]);
+
+        let mut objs = vec![
+            object("bootloader", 1, Some(10)),
+            object("rootfs", 10, None),
+            object("config", 2, Some(-1)),
+            object("kernel", 5, None),
+            object("env", 1, Some(10)),
+        ];
+        sort_for_install(&mut objs);
+        assert_eq!(filenames(&objs), ["config", "rootfs", "kernel", "bootloader", "env"]);
+    }
+
     #[tokio::test]
     async fn has_package_uid_if_succeed() {
         let setup = crate::tests::TestEnvironment::build().finish();
@@ -108,11 +437,145 @@ mod test {
         let machine = State::Install(state).move_to_next_state(&mut context).await.unwrap().0;
 
         match machine {
-            State::Reboot(_) => assert_eq!(
-                context.runtime_settings.applied_package_uid(),
-                Some(get_update_package().package_uid())
-            ),
+            State::Reboot(_) => {
+                assert_eq!(
+                    context.runtime_settings.applied_package_uid(),
+                    Some(get_update_package().package_uid())
+                );
+                assert_eq!(
+                    context.runtime_settings.update.last_update_result.as_ref().map(|r| r.status),
+                    Some(UpdateStatus::Success)
+                );
+                assert_eq!(
+                    context.runtime_settings.update.history.last().map(|a| a.status),
+                    Some(UpdateStatus::Success)
+                );
+                assert_eq!(context.install_progress.get(), None);
+            }
             s => panic!("Invalid success: {:?}", s),
         }
     }
+
+    #[tokio::test]
+    async fn pre_install_callback_cancel() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let args = setup.firmware.stored_path.join("pre-install-args");
+        crate::firmware::tests::create_hook(
+            crate::firmware::tests::pre_install_hook(&context.settings.firmware.metadata),
+            &format!("#!/bin/sh\necho \"$@\" > {}\necho cancel", args.display()),
+        );
+        let state = Install {
+            update_package: get_update_package(),
+            object_context: object::installer::Context::default(),
+        };
+
+        let machine = State::Install(state).move_to_next_state(&mut context).await.unwrap().0;
+
+        assert_state!(machine, EntryPoint);
+        assert_eq!(context.runtime_settings.applied_package_uid(), None);
+        assert_eq!(
+            std::fs::read_to_string(&args).unwrap(),
+            format!("{} testfile\n", get_update_package().package_uid())
+        );
+    }
+
+    #[tokio::test]
+    async fn dry_run() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context.settings.storage.dry_run = true;
+        let state = Install {
+            update_package: get_update_package(),
+            object_context: object::installer::Context::default(),
+        };
+
+        let machine = State::Install(state).move_to_next_state(&mut context).await.unwrap().0;
+
+        assert_state!(machine, Reboot);
+        assert_eq!(context.runtime_settings.applied_package_uid(), None);
+        assert_eq!(context.runtime_settings.update.upgrade_to_installation, None);
+    }
+
+    #[tokio::test]
+    async fn postponed_outside_update_window() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let start = Local::now() + Duration::hours(1);
+        let end = start + Duration::hours(1);
+        context.settings.polling.update_window = vec![UpdateWindow::try_from(format!(
+            "{}-{}",
+            start.format("%H:%M"),
+            end.format("%H:%M")
+        ))
+        .unwrap()];
+        let state = Install {
+            update_package: get_update_package(),
+            object_context: object::installer::Context::default(),
+        };
+
+        let (machine, trans) =
+            State::Install(state).move_to_next_state(&mut context).await.unwrap();
+
+        assert_state!(machine, EntryPoint);
+        match trans {
+            machine::StepTransition::Delayed(d) if d > Duration::zero() => {}
+            _ => panic!("Unexpected StepTransition: {:?}", trans),
+        }
+        assert!(context.runtime_settings.is_polling_forced());
+        assert_eq!(context.runtime_settings.applied_package_uid(), None);
+    }
+
+    #[tokio::test]
+    async fn postponed_to_minimum_uptime() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let uptime = system_uptime().unwrap();
+        context.settings.firmware.minimum_uptime_before_install = uptime + Duration::hours(1);
+        let state = Install {
+            update_package: get_update_package(),
+            object_context: object::installer::Context::default(),
+        };
+
+        let (machine, trans) =
+            State::Install(state).move_to_next_state(&mut context).await.unwrap();
+
+        assert_state!(machine, EntryPoint);
+        match trans {
+            machine::StepTransition::Delayed(d)
+                if d > Duration::zero() && d <= Duration::hours(1) => {}
+            _ => panic!("Unexpected StepTransition: {:?}", trans),
+        }
+        assert!(context.runtime_settings.is_polling_forced());
+        assert_eq!(context.runtime_settings.applied_package_uid(), None);
+
+        context.settings.firmware.minimum_uptime_before_install = uptime;
+        let state = Install {
+            update_package: get_update_package(),
+            object_context: object::installer::Context::default(),
+        };
+        assert!(state.postpone_to_minimum_uptime(&mut context).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn removes_installed_objects() {
+        for keep_downloads in [false, true] {
+            let setup = crate::tests::TestEnvironment::build().finish();
+            let mut context = setup.gen_context();
+            context.settings.storage.keep_downloads = keep_downloads;
+            let download_dir = context.settings.update.download_dir.clone();
+            create_fake_object(OBJECT, SHA256SUM, &context.settings);
+            std::fs::write(download_dir.join("other-package-object"), OBJECT).unwrap();
+            let state = Install {
+                update_package: get_update_package(),
+                object_context: object::installer::Context::default(),
+            };
+
+            let machine = State::Install(state).move_to_next_state(&mut context).await.unwrap().0;
+
+            assert_state!(machine, Reboot);
+            assert_eq!(download_dir.join(SHA256SUM).exists(), keep_downloads);
+            assert!(download_dir.join("other-package-object").exists());
+        }
+    }
 }
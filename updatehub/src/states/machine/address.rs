@@ -3,30 +3,69 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use slog_scope::trace;
-use std::path::PathBuf;
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::broadcast;
 
 #[derive(Clone)]
 pub(crate) struct Addr {
     pub(super) message:
         async_channel::Sender<(Message, async_channel::Sender<super::Result<Response>>)>,
+    pub(super) events: broadcast::Sender<sdk::api::events::Event>,
+    pub(super) shutdown: async_channel::Sender<()>,
+    pub(super) waker: async_channel::Sender<()>,
+    /// Timestamp, in seconds, of the last successful state transition.
+    pub(super) last_transition: Arc<AtomicI64>,
+    pub(super) debug_enabled: bool,
 }
 
 #[derive(Debug)]
 pub(crate) enum Message {
     Info,
     Probe(Option<String>),
-    AbortDownload,
-    LocalInstall(PathBuf),
+    InjectMetadata(sdk::api::probe::MetadataRequest),
+    AbortDownload(bool),
+    CancelUpdate(bool),
+    PauseDownload,
+    ResumeDownload,
+    ConfirmInstall,
+    DebugContinue,
+    LocalInstall(PathBuf, Option<String>, Vec<String>),
     RemoteInstall(String),
+    InstallationSet,
+    SetInstallationSet(sdk::api::installation_set::Request),
+    Rollback,
+    Polling,
+    SetPolling(chrono::Duration),
+    History,
+    SelfTest(Vec<sdk::api::selftest::Target>),
 }
 
 #[derive(Debug)]
 pub(crate) enum Response {
     Info(Box<sdk::api::info::Response>),
     Probe(ProbeResponse),
+    InjectMetadata(InjectMetadataResponse),
     AbortDownload(AbortDownloadResponse),
+    CancelUpdate(AbortDownloadResponse),
+    PauseDownload(DownloadControlResponse),
+    ResumeDownload(DownloadControlResponse),
+    ConfirmInstall(DownloadControlResponse),
+    DebugContinue(DownloadControlResponse),
     LocalInstall(StateResponse),
     RemoteInstall(StateResponse),
+    InstallationSet(sdk::api::installation_set::Response),
+    SetInstallationSet(InstallationSetResponse),
+    Rollback(RollbackResponse),
+    Polling(sdk::api::polling::Response),
+    SetPolling(PollingResponse),
+    History(sdk::api::history::Response),
+    SelfTest(sdk::api::selftest::Response),
 }
 
 #[derive(Debug)]
@@ -37,12 +76,46 @@ pub(crate) enum ProbeResponse {
     Busy(String),
 }
 
+#[derive(Debug)]
+pub(crate) enum InjectMetadataResponse {
+    RequestAccepted,
+    InvalidState(String),
+    Disabled,
+    /// Holds the reason the metadata has been refused.
+    InvalidMetadata(String),
+}
+
 #[derive(Debug)]
 pub(crate) enum AbortDownloadResponse {
+    /// Holds the number of purged files, if a purge has been requested.
+    RequestAccepted(Option<usize>),
+    InvalidState,
+}
+
+#[derive(Debug)]
+pub(crate) enum DownloadControlResponse {
     RequestAccepted,
     InvalidState,
 }
 
+#[derive(Debug)]
+pub(crate) enum InstallationSetResponse {
+    RequestAccepted(sdk::api::installation_set::Response),
+    InvalidState(String),
+}
+
+#[derive(Debug)]
+pub(crate) enum PollingResponse {
+    RequestAccepted(sdk::api::polling::Response),
+    InvalidInterval(String),
+}
+
+#[derive(Debug)]
+pub(crate) enum RollbackResponse {
+    RequestAccepted(sdk::api::rollback::Response),
+    InvalidState(String),
+}
+
 #[derive(Debug)]
 pub(crate) enum StateResponse {
     RequestAccepted(String),
@@ -56,6 +129,48 @@ impl<T> From<async_channel::SendError<T>> for crate::states::TransitionError {
 }
 
 impl Addr {
+    /// Address which isn't served by any state machine, so every
+    /// request sent through it fails.
+    #[cfg(test)]
+    pub(crate) fn detached() -> Self {
+        Addr {
+            message: async_channel::bounded(1).0,
+            events: broadcast::channel(1).0,
+            shutdown: async_channel::bounded(1).0,
+            waker: async_channel::bounded(1).0,
+            last_transition: Arc::new(AtomicI64::new(0)),
+            debug_enabled: false,
+        }
+    }
+
+    pub(crate) fn subscribe_events(&self) -> broadcast::Receiver<sdk::api::events::Event> {
+        self.events.subscribe()
+    }
+
+    /// Asks the state machine to stop once the running step is done.
+    /// The request is kept, so it is heeded even if the machine is
+    /// busy when it arrives.
+    pub(crate) fn request_shutdown(&self) {
+        self.shutdown.close();
+    }
+
+    /// Gets the depth of the state machine channels, without waiting on
+    /// the machine, so it can be read even if the machine is stuck. It is
+    /// `None` when the debug API is disabled.
+    pub(crate) fn debug_channels(&self) -> Option<sdk::api::debug::Response> {
+        if !self.debug_enabled {
+            return None;
+        }
+
+        let channel = |len, capacity| sdk::api::debug::Channel { len, capacity };
+        let last_transition = self.last_transition.load(Ordering::Relaxed);
+        Some(sdk::api::debug::Response {
+            communication: channel(self.message.len(), self.message.capacity()),
+            waker: channel(self.waker.len(), self.waker.capacity()),
+            seconds_since_last_transition: chrono::Utc::now().timestamp() - last_transition,
+        })
+    }
+
     pub(crate) async fn request_info(&self) -> super::Result<sdk::api::info::Response> {
         let (sndr, recv) = async_channel::bounded(1);
         self.message.send((Message::Info, sndr)).await?;
@@ -79,9 +194,26 @@ impl Addr {
         }
     }
 
-    pub(crate) async fn request_abort_download(&self) -> super::Result<AbortDownloadResponse> {
+    pub(crate) async fn request_inject_metadata(
+        &self,
+        req: sdk::api::probe::MetadataRequest,
+    ) -> super::Result<InjectMetadataResponse> {
+        trace!("Update metadata injection requested");
         let (sndr, recv) = async_channel::bounded(1);
-        self.message.send((Message::AbortDownload, sndr)).await?;
+        self.message.send((Message::InjectMetadata(req), sndr)).await?;
+        match recv.recv().await {
+            Ok(Ok(Response::InjectMetadata(resp))) => Ok(resp),
+            Ok(Err(e)) => Err(e),
+            res => unreachable!("Unexpected response: {:?}", res),
+        }
+    }
+
+    pub(crate) async fn request_abort_download(
+        &self,
+        purge: bool,
+    ) -> super::Result<AbortDownloadResponse> {
+        let (sndr, recv) = async_channel::bounded(1);
+        self.message.send((Message::AbortDownload(purge), sndr)).await?;
         match recv.recv().await {
             Ok(Ok(Response::AbortDownload(resp))) => Ok(resp),
             Ok(Err(e)) => Err(e),
@@ -89,13 +221,69 @@ impl Addr {
         }
     }
 
+    pub(crate) async fn request_cancel_update(
+        &self,
+        purge: bool,
+    ) -> super::Result<AbortDownloadResponse> {
+        trace!("Pending update cancel requested");
+        let (sndr, recv) = async_channel::bounded(1);
+        self.message.send((Message::CancelUpdate(purge), sndr)).await?;
+        match recv.recv().await {
+            Ok(Ok(Response::CancelUpdate(resp))) => Ok(resp),
+            Ok(Err(e)) => Err(e),
+            res => unreachable!("Unexpected response: {:?}", res),
+        }
+    }
+
+    pub(crate) async fn request_pause_download(&self) -> super::Result<DownloadControlResponse> {
+        let (sndr, recv) = async_channel::bounded(1);
+        self.message.send((Message::PauseDownload, sndr)).await?;
+        match recv.recv().await {
+            Ok(Ok(Response::PauseDownload(resp))) => Ok(resp),
+            Ok(Err(e)) => Err(e),
+            res => unreachable!("Unexpected response: {:?}", res),
+        }
+    }
+
+    pub(crate) async fn request_resume_download(&self) -> super::Result<DownloadControlResponse> {
+        let (sndr, recv) = async_channel::bounded(1);
+        self.message.send((Message::ResumeDownload, sndr)).await?;
+        match recv.recv().await {
+            Ok(Ok(Response::ResumeDownload(resp))) => Ok(resp),
+            Ok(Err(e)) => Err(e),
+            res => unreachable!("Unexpected response: {:?}", res),
+        }
+    }
+
+    pub(crate) async fn request_confirm_install(&self) -> super::Result<DownloadControlResponse> {
+        let (sndr, recv) = async_channel::bounded(1);
+        self.message.send((Message::ConfirmInstall, sndr)).await?;
+        match recv.recv().await {
+            Ok(Ok(Response::ConfirmInstall(resp))) => Ok(resp),
+            Ok(Err(e)) => Err(e),
+            res => unreachable!("Unexpected response: {:?}", res),
+        }
+    }
+
+    pub(crate) async fn request_debug_continue(&self) -> super::Result<DownloadControlResponse> {
+        let (sndr, recv) = async_channel::bounded(1);
+        self.message.send((Message::DebugContinue, sndr)).await?;
+        match recv.recv().await {
+            Ok(Ok(Response::DebugContinue(resp))) => Ok(resp),
+            Ok(Err(e)) => Err(e),
+            res => unreachable!("Unexpected response: {:?}", res),
+        }
+    }
+
     pub(crate) async fn request_local_install(
         &self,
         path: PathBuf,
+        sha256sum: Option<String>,
+        objects: Vec<String>,
     ) -> super::Result<StateResponse> {
         trace!("Local install requested");
         let (sndr, recv) = async_channel::bounded(1);
-        self.message.send((Message::LocalInstall(path), sndr)).await?;
+        self.message.send((Message::LocalInstall(path, sha256sum, objects), sndr)).await?;
         match recv.recv().await {
             Ok(Ok(Response::LocalInstall(resp))) => Ok(resp),
             Ok(Err(e)) => Err(e),
@@ -113,4 +301,89 @@ impl Addr {
             res => unreachable!("Unexpected response: {:?}", res),
         }
     }
+
+    pub(crate) async fn request_installation_set(
+        &self,
+    ) -> super::Result<sdk::api::installation_set::Response> {
+        let (sndr, recv) = async_channel::bounded(1);
+        self.message.send((Message::InstallationSet, sndr)).await?;
+        match recv.recv().await {
+            Ok(Ok(Response::InstallationSet(resp))) => Ok(resp),
+            Ok(Err(e)) => Err(e),
+            res => unreachable!("Unexpected response: {:?}", res),
+        }
+    }
+
+    pub(crate) async fn request_set_installation_set(
+        &self,
+        req: sdk::api::installation_set::Request,
+    ) -> super::Result<InstallationSetResponse> {
+        trace!("Installation set change requested");
+        let (sndr, recv) = async_channel::bounded(1);
+        self.message.send((Message::SetInstallationSet(req), sndr)).await?;
+        match recv.recv().await {
+            Ok(Ok(Response::SetInstallationSet(resp))) => Ok(resp),
+            Ok(Err(e)) => Err(e),
+            res => unreachable!("Unexpected response: {:?}", res),
+        }
+    }
+
+    pub(crate) async fn request_rollback(&self) -> super::Result<RollbackResponse> {
+        trace!("Rollback requested");
+        let (sndr, recv) = async_channel::bounded(1);
+        self.message.send((Message::Rollback, sndr)).await?;
+        match recv.recv().await {
+            Ok(Ok(Response::Rollback(resp))) => Ok(resp),
+            Ok(Err(e)) => Err(e),
+            res => unreachable!("Unexpected response: {:?}", res),
+        }
+    }
+
+    pub(crate) async fn request_polling(&self) -> super::Result<sdk::api::polling::Response> {
+        let (sndr, recv) = async_channel::bounded(1);
+        self.message.send((Message::Polling, sndr)).await?;
+        match recv.recv().await {
+            Ok(Ok(Response::Polling(resp))) => Ok(resp),
+            Ok(Err(e)) => Err(e),
+            res => unreachable!("Unexpected response: {:?}", res),
+        }
+    }
+
+    pub(crate) async fn request_history(&self) -> super::Result<sdk::api::history::Response> {
+        let (sndr, recv) = async_channel::bounded(1);
+        self.message.send((Message::History, sndr)).await?;
+        match recv.recv().await {
+            Ok(Ok(Response::History(resp))) => Ok(resp),
+            Ok(Err(e)) => Err(e),
+            res => unreachable!("Unexpected response: {:?}", res),
+        }
+    }
+
+    pub(crate) async fn request_set_polling(
+        &self,
+        interval: chrono::Duration,
+    ) -> super::Result<PollingResponse> {
+        trace!("Polling interval change requested");
+        let (sndr, recv) = async_channel::bounded(1);
+        self.message.send((Message::SetPolling(interval), sndr)).await?;
+        match recv.recv().await {
+            Ok(Ok(Response::SetPolling(resp))) => Ok(resp),
+            Ok(Err(e)) => Err(e),
+            res => unreachable!("Unexpected response: {:?}", res),
+        }
+    }
+
+    pub(crate) async fn request_selftest(
+        &self,
+        targets: Vec<sdk::api::selftest::Target>,
+    ) -> super::Result<sdk::api::selftest::Response> {
+        trace!("Self-test requested");
+        let (sndr, recv) = async_channel::bounded(1);
+        self.message.send((Message::SelfTest(targets), sndr)).await?;
+        match recv.recv().await {
+            Ok(Ok(Response::SelfTest(resp))) => Ok(resp),
+            Ok(Err(e)) => Err(e),
+            res => unreachable!("Unexpected response: {:?}", res),
+        }
+    }
 }
@@ -3,17 +3,41 @@
 // SPDX-License-Identifier: Apache-2.0
 
 mod address;
+mod progress;
+mod selftest;
+mod webhook;
 
 use super::{
-    DirectDownload, EntryPoint, Metadata, PrepareLocalInstall, Result, RuntimeSettings, Settings,
-    State, StateChangeImpl, Validation,
+    DirectDownload, EntryPoint, Metadata, Poll, PrepareLocalInstall, Reboot, Result,
+    RuntimeSettings, Settings, State, StateChangeImpl, TransitionError, Validation,
 };
-use slog_scope::{error, info, trace};
-use std::path::PathBuf;
+use crate::{
+    firmware::{self, installation_set},
+    runtime_settings::Saved,
+    update_package::{UpdatePackage, UpdatePackageExt},
+};
+use chrono::{DateTime, Utc};
+use slog_scope::{debug, error, info, trace, warn};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::broadcast;
 
 pub(crate) use address::{
-    AbortDownloadResponse, Addr, Message, ProbeResponse, Response, StateResponse,
+    AbortDownloadResponse, Addr, DownloadControlResponse, InjectMetadataResponse,
+    InstallationSetResponse, Message, PollingResponse, ProbeResponse, Response, RollbackResponse,
+    StateResponse,
 };
+pub(crate) use progress::{DownloadProgress, InstallProgress};
+pub(crate) use webhook::WebhookEvent;
+
+/// Bounds, in seconds, of the polling interval set through the agent API.
+const MIN_POLLING_INTERVAL: i64 = 60;
+const MAX_POLLING_INTERVAL: i64 = 30 * 24 * 60 * 60;
 
 pub(super) struct StateMachine {
     state: State,
@@ -23,6 +47,39 @@ pub(super) struct StateMachine {
 pub struct Context {
     pub(super) communication: Channel<(Message, async_channel::Sender<Result<Response>>)>,
     pub(super) waker: Channel<()>,
+    pub(super) shutdown: Channel<()>,
+    pub(super) download_progress: DownloadProgress,
+    pub(super) install_progress: InstallProgress,
+    pub(super) client_identity: Option<cloud::ClientIdentity>,
+    pub(super) headers: cloud::Headers,
+    pub(super) proxy: Option<cloud::Proxy>,
+    pub(super) reports: Vec<cloud::api::Report>,
+    pub(super) events: broadcast::Sender<sdk::api::events::Event>,
+    /// Number of packages refused for not supporting the device's hardware.
+    pub(super) rejected_packages: u64,
+    /// Number of immediate retries done since the update has first failed.
+    pub(super) error_retries: u32,
+    /// Whether the update being handled was requested through the local
+    /// or remote install API, instead of found by a probe.
+    pub(super) local_update: bool,
+    /// Webhook notifications which may still be in flight.
+    pub(super) webhook_notifications: Vec<tokio::task::JoinHandle<()>>,
+    /// Timestamp, in seconds, of the last successful state transition,
+    /// shared with the API so it can be read while the machine is busy.
+    pub(super) last_transition: Arc<AtomicI64>,
+    /// Debugging breakpoint the machine is paused at, or has just been
+    /// continued from.
+    pub(super) breakpoint: Option<Breakpoint>,
+    /// Time spent on the states handled since the update cycle has
+    /// started, recorded when `report_state_timings` is enabled.
+    pub(super) state_timings: Vec<cloud::api::StateTiming>,
+    /// State being handled, and when it was entered, while its time is
+    /// recorded.
+    pub(super) current_state: Option<(&'static str, DateTime<Utc>)>,
+    /// Installation set the device runs from, read at startup and again
+    /// whenever the agent changes it, so the API requests don't run the
+    /// firmware scripts.
+    pub(super) active_installation_set: Option<installation_set::Set>,
     pub settings: Settings,
     pub runtime_settings: RuntimeSettings,
     pub firmware: Metadata,
@@ -33,6 +90,12 @@ pub(super) struct Channel<T> {
     pub(super) receiver: async_channel::Receiver<T>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Breakpoint {
+    Paused(&'static str),
+    Continued(&'static str),
+}
+
 impl<T> Channel<T> {
     fn new(cap: usize) -> Self {
         let (sender, receiver) = async_channel::bounded(cap);
@@ -40,7 +103,95 @@ impl<T> Channel<T> {
     }
 }
 
-impl CommunicationState for State {}
+#[async_trait::async_trait]
+impl CommunicationState for State {
+    async fn handle_cancel_update(
+        &self,
+        context: &mut Context,
+        purge: bool,
+    ) -> Result<(address::AbortDownloadResponse, Option<State>)> {
+        let update_package = match self {
+            State::AwaitingApproval(s) => Some(s.update_package.clone()),
+            // A postponed install waits on a preemptive state, with the
+            // update kept pending until it's probed again
+            s if s.is_preemptive_state() => match context.runtime_settings.pending_update() {
+                Some(pending) => UpdatePackage::parse(pending.package.as_bytes()).ok(),
+                None => return Ok((address::AbortDownloadResponse::InvalidState, None)),
+            },
+            _ => return Ok((address::AbortDownloadResponse::InvalidState, None)),
+        };
+
+        info!("canceling pending update as requested by the user");
+        context.runtime_settings.clear_pending_update()?.log_if_not_persisted("canceled update");
+        // The postponed install has forced a poll, which would only
+        // select the update again
+        context.runtime_settings.disable_force_poll()?.log_if_not_persisted("forced poll");
+        let purged = if purge {
+            let download_dir = &context.settings.update.download_dir;
+            let purged = update_package.map_or(Ok(0), |p| p.remove_staged_objects(download_dir))?;
+            info!("purged {} staged files of the canceled update", purged);
+            Some(purged)
+        } else {
+            None
+        };
+
+        // The update may be waiting to be awoken, so ensure it leaves the
+        // wait for the cancel to take effect.
+        let _ = context.waker.sender.try_send(());
+        Ok((
+            address::AbortDownloadResponse::RequestAccepted(purged),
+            Some(State::EntryPoint(EntryPoint {})),
+        ))
+    }
+
+    async fn handle_pause_download(
+        &self,
+        context: &Context,
+    ) -> Result<(address::DownloadControlResponse, Option<State>)> {
+        match self {
+            State::Download(s) => s.handle_pause_download(context).await,
+            _ => Ok((address::DownloadControlResponse::InvalidState, None)),
+        }
+    }
+
+    async fn handle_resume_download(
+        &self,
+        context: &Context,
+    ) -> Result<(address::DownloadControlResponse, Option<State>)> {
+        match self {
+            State::PausedDownload(s) => {
+                info!("resuming download as requested by the user");
+                // The paused state waits to be awoken, so ensure it leaves
+                // the wait to resume the download.
+                let _ = context.waker.sender.try_send(());
+                Ok((
+                    address::DownloadControlResponse::RequestAccepted,
+                    Some(State::Download(s.resume())),
+                ))
+            }
+            _ => Ok((address::DownloadControlResponse::InvalidState, None)),
+        }
+    }
+
+    async fn handle_confirm_install(
+        &self,
+        context: &Context,
+    ) -> Result<(address::DownloadControlResponse, Option<State>)> {
+        match self {
+            State::AwaitingApproval(s) => {
+                info!("installing update as confirmed by the user");
+                // The state waits to be awoken, so ensure it leaves the
+                // wait to start the install.
+                let _ = context.waker.sender.try_send(());
+                Ok((
+                    address::DownloadControlResponse::RequestAccepted,
+                    Some(State::Install(s.confirm())),
+                ))
+            }
+            _ => Ok((address::DownloadControlResponse::InvalidState, None)),
+        }
+    }
+}
 
 #[async_trait::async_trait]
 pub(super) trait CommunicationState: StateChangeImpl {
@@ -59,9 +210,46 @@ pub(super) trait CommunicationState: StateChangeImpl {
                     address::Response::Info(Box::new(sdk::api::info::Response {
                         state,
                         version: crate::version().to_string(),
-                        config: context.settings.0.clone(),
+                        config: {
+                            let mut config = context.settings.0.clone();
+                            config.polling.interval = context.polling_interval();
+                            config
+                        },
                         firmware: context.firmware.0.clone(),
                         runtime_settings: context.runtime_settings.inner.clone(),
+                        download_progress: context.download_progress.get(),
+                        install_progress: context.install_progress.get(),
+                        active_installation_set: context.active_installation_set.map(|s| s.0),
+                        target_installation_set: context.target_installation_set().map(|s| s.0),
+                        next_install_time: super::install::next_install_time(
+                            &context.settings.polling.update_window,
+                            chrono::Local::now(),
+                        )
+                        .map(|t| t.with_timezone(&chrono::Utc)),
+                        last_update_result: context
+                            .runtime_settings
+                            .update
+                            .last_update_result
+                            .clone(),
+                        last_update_time: context.runtime_settings.update.last_update_time,
+                        rejected_packages: context.rejected_packages,
+                        breakpoint: match context.breakpoint {
+                            Some(Breakpoint::Paused(state)) => Some(state.to_owned()),
+                            _ => None,
+                        },
+                        download_rate_limit: context
+                            .settings
+                            .download_rate_limit_at(chrono::Local::now().time()),
+                        state_timings: context
+                            .state_timings()
+                            .into_iter()
+                            .map(|t| sdk::api::info::StateTiming {
+                                state: t.state,
+                                entered_at: t.entered_at,
+                                left_at: t.left_at,
+                                duration_ms: t.duration_ms,
+                            })
+                            .collect(),
                     })),
                     None,
                 ))
@@ -70,18 +258,67 @@ pub(super) trait CommunicationState: StateChangeImpl {
                 .handle_probe(context, custom_server)
                 .await
                 .map(|(res, st)| (address::Response::Probe(res), st)),
-            address::Message::AbortDownload => self
-                .handle_abort_download(context)
+            address::Message::InjectMetadata(req) => self
+                .handle_inject_metadata(context, req)
+                .await
+                .map(|(res, st)| (address::Response::InjectMetadata(res), st)),
+            address::Message::AbortDownload(purge) => self
+                .handle_abort_download(context, purge)
                 .await
                 .map(|(res, st)| (address::Response::AbortDownload(res), st)),
-            address::Message::LocalInstall(update_file) => self
-                .handle_local_install(context, update_file)
+            address::Message::CancelUpdate(purge) => self
+                .handle_cancel_update(context, purge)
+                .await
+                .map(|(res, st)| (address::Response::CancelUpdate(res), st)),
+            address::Message::PauseDownload => self
+                .handle_pause_download(context)
+                .await
+                .map(|(res, st)| (address::Response::PauseDownload(res), st)),
+            address::Message::ResumeDownload => self
+                .handle_resume_download(context)
+                .await
+                .map(|(res, st)| (address::Response::ResumeDownload(res), st)),
+            address::Message::DebugContinue => {
+                Ok((address::Response::DebugContinue(context.continue_breakpoint()), None))
+            }
+            address::Message::ConfirmInstall => self
+                .handle_confirm_install(context)
+                .await
+                .map(|(res, st)| (address::Response::ConfirmInstall(res), st)),
+            address::Message::LocalInstall(update_file, sha256sum, objects) => self
+                .handle_local_install(context, update_file, sha256sum, objects)
                 .await
                 .map(|(res, st)| (address::Response::LocalInstall(res), st)),
             address::Message::RemoteInstall(url) => self
                 .handle_remote_install(context, url)
                 .await
                 .map(|(res, st)| (address::Response::RemoteInstall(res), st)),
+            address::Message::InstallationSet => current_installation_set(context)
+                .map(|res| (address::Response::InstallationSet(res), None)),
+            address::Message::SetInstallationSet(req) => self
+                .handle_set_installation_set(context, req)
+                .await
+                .map(|(res, st)| (address::Response::SetInstallationSet(res), st)),
+            address::Message::Rollback => self
+                .handle_rollback(context)
+                .await
+                .map(|(res, st)| (address::Response::Rollback(res), st)),
+            address::Message::Polling => {
+                Ok((address::Response::Polling(current_polling(context)), None))
+            }
+            address::Message::SetPolling(interval) => self
+                .handle_set_polling(context, interval)
+                .await
+                .map(|(res, st)| (address::Response::SetPolling(res), st)),
+            address::Message::History => Ok((
+                address::Response::History(sdk::api::history::Response {
+                    entries: context.runtime_settings.update.history.clone(),
+                }),
+                None,
+            )),
+            address::Message::SelfTest(targets) => {
+                Ok((address::Response::SelfTest(selftest::run(context, &targets)), None))
+            }
         };
 
         match res {
@@ -117,11 +354,13 @@ pub(super) trait CommunicationState: StateChangeImpl {
         if let Some(server_address) = custom_server {
             context.runtime_settings.set_custom_server_address(&server_address);
         }
+        // A manual probe resets any backoff from previous failures
+        context.runtime_settings.clear_retries()?.log_if_not_persisted("probe retries");
 
-        match crate::CloudClient::new(context.server_address())
-            .probe(context.runtime_settings.retries(), context.firmware.as_cloud_metadata())
-            .await?
-        {
+        let probe = super::probe::probe_servers(context).await?;
+        super::probe::record_probe(context, &probe)?;
+
+        match probe {
             ProbeResponse::ExtraPoll(s) => {
                 info!("server responded with extra poll of {} seconds", s);
                 Ok((address::ProbeResponse::Delayed(s), None))
@@ -132,7 +371,10 @@ pub(super) trait CommunicationState: StateChangeImpl {
                 context.waker.sender.send(()).await?;
 
                 // Store timestamp of last polling
-                context.runtime_settings.set_last_polling(Utc::now())?;
+                context
+                    .runtime_settings
+                    .set_last_polling(Utc::now())?
+                    .log_if_not_persisted("last polling time");
                 Ok((address::ProbeResponse::Unavailable, Some(State::EntryPoint(EntryPoint {}))))
             }
 
@@ -141,7 +383,10 @@ pub(super) trait CommunicationState: StateChangeImpl {
                 context.waker.sender.send(()).await?;
 
                 // Store timestamp of last polling
-                context.runtime_settings.set_last_polling(Utc::now())?;
+                context
+                    .runtime_settings
+                    .set_last_polling(Utc::now())?
+                    .log_if_not_persisted("last polling time");
                 Ok((
                     address::ProbeResponse::Available,
                     Some(State::Validation(Validation { package, sign, require_download: true })),
@@ -150,13 +395,71 @@ pub(super) trait CommunicationState: StateChangeImpl {
         }
     }
 
+    async fn handle_inject_metadata(
+        &self,
+        context: &mut Context,
+        req: sdk::api::probe::MetadataRequest,
+    ) -> Result<(address::InjectMetadataResponse, Option<State>)> {
+        if !context.settings.update.allow_metadata_injection {
+            warn!("refusing injected update metadata as injection is disabled");
+            return Ok((address::InjectMetadataResponse::Disabled, None));
+        }
+        if !self.is_preemptive_state() {
+            let name = self.name().to_owned();
+            return Ok((address::InjectMetadataResponse::InvalidState(name), None));
+        }
+
+        let package = match cloud::api::UpdatePackage::parse(req.metadata.as_bytes()) {
+            Ok(package) => package,
+            Err(e) => {
+                let reason = format!("invalid update metadata: {}", e);
+                return Ok((address::InjectMetadataResponse::InvalidMetadata(reason), None));
+            }
+        };
+        let sign = match req.signature.as_deref().map(cloud::api::Signature::from_base64_str) {
+            None => None,
+            Some(Ok(sign)) => Some(sign),
+            Some(Err(e)) => {
+                let reason = format!("invalid signature: {}", e);
+                return Ok((address::InjectMetadataResponse::InvalidMetadata(reason), None));
+            }
+        };
+
+        // Starting logging a new scope of operation since we are
+        // starting to handle a user request
+        crate::logger::start_memory_logging();
+        warn!(
+            "update metadata injected by the user, bypassing the server: {} ({})",
+            package.version(),
+            package.package_uid()
+        );
+        context.waker.sender.send(()).await?;
+
+        Ok((
+            address::InjectMetadataResponse::RequestAccepted,
+            Some(State::Validation(Validation { package, sign, require_download: true })),
+        ))
+    }
+
     async fn handle_abort_download(
         &self,
-        _: &Context,
+        context: &Context,
+        purge: bool,
     ) -> Result<(address::AbortDownloadResponse, Option<State>)> {
         if self.is_handling_download() {
+            let purged = if purge {
+                let purged = self.purge_download(context)?;
+                info!("purged {} staged files of the aborted download", purged);
+                Some(purged)
+            } else {
+                None
+            };
+
+            // A paused download is waiting to be awoken, so ensure it
+            // leaves the wait for the abort to take effect.
+            let _ = context.waker.sender.try_send(());
             Ok((
-                address::AbortDownloadResponse::RequestAccepted,
+                address::AbortDownloadResponse::RequestAccepted(purged),
                 Some(State::EntryPoint(EntryPoint {})),
             ))
         } else {
@@ -164,10 +467,41 @@ pub(super) trait CommunicationState: StateChangeImpl {
         }
     }
 
+    async fn handle_cancel_update(
+        &self,
+        _: &mut Context,
+        _: bool,
+    ) -> Result<(address::AbortDownloadResponse, Option<State>)> {
+        Ok((address::AbortDownloadResponse::InvalidState, None))
+    }
+
+    async fn handle_pause_download(
+        &self,
+        _: &Context,
+    ) -> Result<(address::DownloadControlResponse, Option<State>)> {
+        Ok((address::DownloadControlResponse::InvalidState, None))
+    }
+
+    async fn handle_resume_download(
+        &self,
+        _: &Context,
+    ) -> Result<(address::DownloadControlResponse, Option<State>)> {
+        Ok((address::DownloadControlResponse::InvalidState, None))
+    }
+
+    async fn handle_confirm_install(
+        &self,
+        _: &Context,
+    ) -> Result<(address::DownloadControlResponse, Option<State>)> {
+        Ok((address::DownloadControlResponse::InvalidState, None))
+    }
+
     async fn handle_local_install(
         &self,
         context: &Context,
         update_file: PathBuf,
+        sha256sum: Option<String>,
+        objects: Vec<String>,
     ) -> Result<(address::StateResponse, Option<State>)> {
         let name = self.name().to_owned();
         if self.is_preemptive_state() {
@@ -178,7 +512,11 @@ pub(super) trait CommunicationState: StateChangeImpl {
 
             Ok((
                 address::StateResponse::RequestAccepted(name),
-                Some(State::PrepareLocalInstall(PrepareLocalInstall { update_file })),
+                Some(State::PrepareLocalInstall(PrepareLocalInstall {
+                    update_file,
+                    sha256sum,
+                    objects,
+                })),
             ))
         } else {
             Ok((address::StateResponse::InvalidState(name), None))
@@ -206,6 +544,118 @@ pub(super) trait CommunicationState: StateChangeImpl {
             Ok((address::StateResponse::InvalidState(name), None))
         }
     }
+
+    async fn handle_set_installation_set(
+        &self,
+        context: &mut Context,
+        req: sdk::api::installation_set::Request,
+    ) -> Result<(address::InstallationSetResponse, Option<State>)> {
+        use sdk::api::installation_set::Request;
+
+        if !self.is_preemptive_state() {
+            let name = self.name().to_owned();
+            return Ok((address::InstallationSetResponse::InvalidState(name), None));
+        }
+
+        match req {
+            Request::Swap => {
+                info!("swapping active installation set as requested by the user");
+                installation_set::swap_active()?;
+                context.refresh_installation_set();
+            }
+            Request::Validate => {
+                info!("validating active installation set as requested by the user");
+                installation_set::validate()?;
+                if context.runtime_settings.update.upgrade_to_installation.is_some() {
+                    context
+                        .runtime_settings
+                        .reset_installation_settings()?
+                        .log_if_not_persisted("installation settings");
+                }
+            }
+        }
+
+        Ok((
+            address::InstallationSetResponse::RequestAccepted(current_installation_set(context)?),
+            None,
+        ))
+    }
+
+    async fn handle_rollback(
+        &self,
+        context: &mut Context,
+    ) -> Result<(address::RollbackResponse, Option<State>)> {
+        if !self.is_preemptive_state() {
+            let name = self.name().to_owned();
+            return Ok((address::RollbackResponse::InvalidState(name), None));
+        }
+
+        info!("rolling back to the inactive installation set as requested by the user");
+        installation_set::swap_active()?;
+        context.refresh_installation_set();
+        firmware::rollback_callback(&context.settings.firmware.metadata)?;
+        context.waker.sender.send(()).await?;
+
+        Ok((
+            address::RollbackResponse::RequestAccepted(sdk::api::rollback::Response {
+                active: installation_set::active()?.0,
+            }),
+            Some(State::Reboot(Reboot { update_package: None, delay_elapsed: false })),
+        ))
+    }
+
+    async fn handle_set_polling(
+        &self,
+        context: &mut Context,
+        interval: chrono::Duration,
+    ) -> Result<(address::PollingResponse, Option<State>)> {
+        if !(MIN_POLLING_INTERVAL..=MAX_POLLING_INTERVAL).contains(&interval.num_seconds()) {
+            return Ok((
+                address::PollingResponse::InvalidInterval(format!(
+                    "polling interval must be between {} and {} seconds",
+                    MIN_POLLING_INTERVAL, MAX_POLLING_INTERVAL
+                )),
+                None,
+            ));
+        }
+
+        info!(
+            "setting polling interval to {} seconds as requested by the user",
+            interval.num_seconds()
+        );
+        let saved = context.runtime_settings.set_polling_interval(
+            Some(interval).filter(|i| *i != context.settings.polling.interval),
+        )?;
+        if saved == Saved::NotPersisted {
+            warn!("polling interval is not persisted, the configured one is used after a restart");
+        }
+
+        // A pending probe has its delay computed from the previous
+        // interval, so we go back to poll to compute it again.
+        let state = if self.name() == "probe" {
+            context.waker.sender.send(()).await?;
+            Some(State::Poll(Poll {}))
+        } else {
+            None
+        };
+
+        Ok((address::PollingResponse::RequestAccepted(current_polling(context)), state))
+    }
+}
+
+fn current_polling(context: &Context) -> sdk::api::polling::Response {
+    sdk::api::polling::Response {
+        interval: context.polling_interval(),
+        overridden: context.runtime_settings.polling_interval().is_some(),
+    }
+}
+
+fn current_installation_set(context: &Context) -> Result<sdk::api::installation_set::Response> {
+    Ok(sdk::api::installation_set::Response {
+        active: installation_set::active()?.0,
+        inactive: installation_set::inactive()?.0,
+        validated: context.runtime_settings.update.upgrade_to_installation.is_none(),
+    })
 }
 
 impl Context {
@@ -213,20 +663,303 @@ impl Context {
         settings: Settings,
         runtime_settings: RuntimeSettings,
         firmware: Metadata,
+        client_identity: Option<cloud::ClientIdentity>,
+        proxy: Option<cloud::Proxy>,
+        headers: cloud::Headers,
     ) -> Self {
-        Context {
+        let mut context = Context {
             communication: Channel::new(10),
             waker: Channel::new(1),
+            shutdown: Channel::new(1),
+            download_progress: DownloadProgress::default(),
+            install_progress: InstallProgress::default(),
+            client_identity,
+            headers,
+            proxy,
+            reports: Vec::new(),
+            events: broadcast::channel(16).0,
+            rejected_packages: 0,
+            error_retries: 0,
+            local_update: false,
+            webhook_notifications: Vec::new(),
+            last_transition: Arc::new(AtomicI64::new(Utc::now().timestamp())),
+            breakpoint: None,
+            state_timings: Vec::new(),
+            current_state: None,
+            active_installation_set: None,
             settings,
             runtime_settings,
             firmware,
+        };
+        context.refresh_installation_set();
+        context
+    }
+
+    /// Reads the active installation set again, once it's been changed.
+    pub(super) fn refresh_installation_set(&mut self) {
+        self.active_installation_set = installation_set::active()
+            .map_err(|e| warn!("unable to get the active installation set: {}", e))
+            .ok();
+    }
+
+    /// Gets the set an update is installed into, from the cached active
+    /// installation set.
+    fn target_installation_set(&self) -> Option<installation_set::Set> {
+        // If the `upgrade_to_installation` is defined, the current inactive
+        // installation_set has already been swapped.
+        if let Some(set) = self.runtime_settings.update.upgrade_to_installation {
+            return Some(installation_set::Set(set));
+        }
+
+        installation_set::target_from(
+            self.active_installation_set?,
+            self.settings.target_installation_set(),
+        )
+        .ok()
+    }
+
+    /// Checks if the machine must stay paused before handling the state,
+    /// as it is a breakpoint which has not been continued from yet.
+    fn pause_at_breakpoint(&mut self, state: &'static str) -> bool {
+        let breakpoint = self.breakpoint.take();
+        if !self.settings.is_breakpoint(state) || breakpoint == Some(Breakpoint::Continued(state)) {
+            return false;
+        }
+
+        if breakpoint != Some(Breakpoint::Paused(state)) {
+            info!("pausing at breakpoint before '{}' state", state);
+        }
+        self.breakpoint = Some(Breakpoint::Paused(state));
+        true
+    }
+
+    /// Continues from the breakpoint the machine is paused at, if any.
+    fn continue_breakpoint(&mut self) -> address::DownloadControlResponse {
+        match self.breakpoint {
+            Some(Breakpoint::Paused(state)) => {
+                info!("continuing from breakpoint before '{}' state", state);
+                self.breakpoint = Some(Breakpoint::Continued(state));
+                // The machine waits to be awoken while paused, so ensure
+                // it leaves the wait to handle the state.
+                let _ = self.waker.sender.try_send(());
+                address::DownloadControlResponse::RequestAccepted
+            }
+            _ => address::DownloadControlResponse::InvalidState,
         }
     }
 
+    /// Starts timing the state about to be handled. A new update cycle
+    /// starts on the entry point, discarding the previous timings.
+    fn enter_state(&mut self, state: &State) {
+        if !self.settings.network.report_state_timings {
+            return;
+        }
+
+        if let State::EntryPoint(_) = state {
+            self.state_timings.clear();
+        }
+        self.current_state = Some((state.name(), Utc::now()));
+    }
+
+    /// Records the time spent on the state which has been handled.
+    fn leave_state(&mut self) {
+        if let Some((state, entered_at)) = self.current_state.take() {
+            self.state_timings.push(state_timing(state, entered_at, Utc::now()));
+        }
+    }
+
+    /// Gets the time spent on the states of the current update cycle,
+    /// including the one being handled up to now.
+    pub(super) fn state_timings(&self) -> Vec<cloud::api::StateTiming> {
+        let now = Utc::now();
+        self.state_timings
+            .iter()
+            .cloned()
+            .chain(
+                self.current_state.map(|(state, entered_at)| state_timing(state, entered_at, now)),
+            )
+            .collect()
+    }
+
+    /// Gets the polling interval in use, which may have been overridden
+    /// through the agent API.
+    pub(super) fn polling_interval(&self) -> chrono::Duration {
+        self.runtime_settings.polling_interval().unwrap_or(self.settings.polling.interval)
+    }
+
+    /// Gets the server in use, which is the custom one requested through
+    /// the agent API or the configured one which last answered a probe.
     pub(super) fn server_address(&self) -> &str {
+        let network = &self.settings.network;
         self.runtime_settings
             .custom_server_address()
-            .unwrap_or(&self.settings.network.server_address)
+            .or_else(|| {
+                self.runtime_settings
+                    .working_server_address()
+                    .filter(|working| network.fallback_servers.iter().any(|s| s == working))
+            })
+            .unwrap_or(&network.server_address)
+    }
+
+    /// Gets the servers to be probed, in order, starting with the one in
+    /// use.
+    pub(super) fn probe_server_addresses(&self) -> Vec<String> {
+        let current = self.server_address();
+        if self.runtime_settings.custom_server_address().is_some() {
+            return vec![current.to_owned()];
+        }
+
+        let network = &self.settings.network;
+        std::iter::once(current)
+            .chain(
+                std::iter::once(network.server_address.as_str())
+                    .chain(network.fallback_servers.iter().map(String::as_str))
+                    .filter(|s| *s != current),
+            )
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// Gets the headers sent on the requests to the server, reading the
+    /// authorization from its file, if any, so rotated credentials are
+    /// picked up. It fails when the file can't be read, as requests sent
+    /// without the credentials would only be refused by the server.
+    pub(super) fn request_headers(&self) -> cloud::Result<cloud::Headers> {
+        let path = match &self.settings.network.authorization_file {
+            Some(path) => path,
+            None => return Ok(self.headers.clone()),
+        };
+
+        std::fs::read_to_string(path)
+            .map_err(cloud::Error::from)
+            .and_then(|authorization| self.headers.clone().with_authorization(authorization.trim()))
+            .map_err(|e| {
+                error!("failed to read the authorization from {:?}: {}", path, e);
+                e
+            })
+    }
+
+    /// Queues the report, sending all the queued ones once the batch is
+    /// full or when `flush` is requested.
+    pub(super) async fn report(&mut self, report: cloud::api::Report, flush: bool) {
+        self.reports.push(report);
+        if flush || self.reports.len() >= self.settings.network.report_batch_size {
+            self.flush_reports().await;
+        }
+    }
+
+    /// Gets the addresses the hosts are connected to instead of resolving
+    /// their names.
+    pub(super) fn host_aliases(&self) -> cloud::HostAliases {
+        self.settings.host_aliases().unwrap_or_else(|e| {
+            error!("ignoring invalid host aliases: {}", e);
+            cloud::HostAliases::default()
+        })
+    }
+
+    /// Notifies the configured webhooks of the update event, along with
+    /// the state the update has failed on, if it has.
+    pub(super) fn notify_webhooks(
+        &mut self,
+        event: WebhookEvent,
+        package_uid: Option<String>,
+        version: Option<String>,
+        failure: Option<(&str, &TransitionError)>,
+    ) {
+        if self.settings.network.webhooks.is_empty() {
+            return;
+        }
+
+        let connection = webhook::Connection {
+            identity: self.client_identity.clone(),
+            proxy: self.proxy.clone(),
+            host_aliases: self.host_aliases(),
+        };
+        self.webhook_notifications.retain(|handle| !handle.is_finished());
+        self.webhook_notifications.extend(webhook::notify(
+            &self.settings.network.webhooks,
+            self.settings.network.webhook_secret.as_deref(),
+            &connection,
+            webhook::Payload {
+                event,
+                timestamp: Utc::now(),
+                product_uid: self.firmware.product_uid.clone(),
+                package_uid,
+                version,
+                state: failure.map(|(state, _)| state.to_owned()),
+                error_code: failure.map(|(_, e)| e.code().to_owned()),
+                error_message: failure.map(|(_, e)| e.to_string()),
+            },
+        ));
+    }
+
+    /// Waits, for as long as a single request may take, for the webhook
+    /// notifications still in flight, so they aren't lost on a reboot
+    /// or when the agent stops.
+    pub(super) async fn flush_webhooks(&mut self) {
+        let pending = std::mem::take(&mut self.webhook_notifications);
+        if pending.is_empty() {
+            return;
+        }
+        if tokio::time::timeout(webhook::TIMEOUT, futures_util::future::join_all(pending))
+            .await
+            .is_err()
+        {
+            warn!("gave up waiting for the webhook notifications");
+        }
+    }
+
+    /// Sends the queued reports, if any.
+    pub(super) async fn flush_reports(&mut self) {
+        let reports = std::mem::take(&mut self.reports);
+        if reports.is_empty() {
+            return;
+        }
+        let headers = match self.request_headers() {
+            Ok(headers) => headers,
+            Err(e) => {
+                warn!("report failed: {}", e);
+                return;
+            }
+        };
+        let api = crate::CloudClient::new(
+            self.server_address(),
+            self.client_identity.as_ref(),
+            self.proxy.as_ref(),
+        )
+        .with_headers(&headers)
+        .with_host_aliases(&self.host_aliases())
+        .with_report_compression(self.settings.network.compress_reports);
+        let firmware = self.firmware.as_cloud_metadata();
+
+        let res = match reports.as_slice() {
+            [report] => api.send_report(firmware, report).await,
+            reports => {
+                debug!("sending {} queued reports", reports.len());
+                api.report_batch(firmware, reports).await
+            }
+        };
+
+        match res {
+            Err(cloud::Error::Unauthorized) => {
+                error!("report refused as the server doesn't accept the device authorization")
+            }
+            Err(e) => warn!("report failed: {}", e),
+            Ok(()) => {}
+        }
+    }
+}
+
+fn state_timing(
+    state: &str,
+    entered_at: DateTime<Utc>,
+    left_at: DateTime<Utc>,
+) -> cloud::api::StateTiming {
+    cloud::api::StateTiming {
+        state: state.to_owned(),
+        entered_at,
+        left_at,
+        duration_ms: (left_at - entered_at).num_milliseconds().max(0) as u64,
     }
 }
 
@@ -243,12 +976,32 @@ impl StateMachine {
         settings: Settings,
         runtime_settings: RuntimeSettings,
         firmware: Metadata,
+        client_identity: Option<cloud::ClientIdentity>,
+        proxy: Option<cloud::Proxy>,
+        headers: cloud::Headers,
     ) -> Self {
-        StateMachine { state, context: Context::new(settings, runtime_settings, firmware) }
+        StateMachine {
+            state,
+            context: Context::new(
+                settings,
+                runtime_settings,
+                firmware,
+                client_identity,
+                proxy,
+                headers,
+            ),
+        }
     }
 
     pub(super) fn address(&self) -> Addr {
-        Addr { message: self.context.communication.sender.clone() }
+        Addr {
+            message: self.context.communication.sender.clone(),
+            events: self.context.events.clone(),
+            shutdown: self.context.shutdown.sender.clone(),
+            waker: self.context.waker.sender.clone(),
+            last_transition: self.context.last_transition.clone(),
+            debug_enabled: self.context.settings.network.enable_debug_api,
+        }
     }
 
     pub(super) async fn start(mut self) {
@@ -257,18 +1010,45 @@ impl StateMachine {
             // discharges any wake message received.
             let _ = self.context.waker.receiver.try_recv();
 
+            if self.context.shutdown.receiver.is_closed() {
+                break;
+            }
+
             self.consume_pending_communication().await;
 
-            let (state, transition) = self
-                .state
-                .handle(&mut self.context)
-                .await
-                .unwrap_or_else(|e| (State::from(e), StepTransition::Immediate));
-            self.state = state;
+            let transition = if self.context.pause_at_breakpoint(self.state.name()) {
+                StepTransition::Never
+            } else {
+                let previous_state = self.state.name();
+                self.context.enter_state(&self.state);
+                let (state, transition) =
+                    match self.state.handle_with_timeout(&mut self.context).await {
+                        Ok(res) => {
+                            self.context
+                                .last_transition
+                                .store(Utc::now().timestamp(), Ordering::Relaxed);
+                            res
+                        }
+                        Err(e) => (State::from(e), StepTransition::Immediate),
+                    };
+                self.context.leave_state();
+                self.state = state;
+                self.broadcast_transition(previous_state);
+                transition
+            };
+
+            // A closed shutdown channel makes `recv` return right away, so
+            // any wait below is cut short once a shutdown is requested.
+            let shutdown = self.context.shutdown.receiver.clone();
+            let shutdown_fut = async {
+                let _ = shutdown.recv().await;
+            };
+            futures_util::pin_mut!(shutdown_fut);
 
             match transition {
                 StepTransition::Immediate => {}
                 StepTransition::Delayed(t) => {
+                    self.context.flush_reports().await;
                     trace!("delaying transition for: {} seconds", t.num_seconds());
                     let waker = self.context.waker.receiver.clone();
 
@@ -283,45 +1063,624 @@ impl StateMachine {
                     futures_util::pin_mut!(comm_fut);
 
                     let _ = futures_util::future::select(
-                        futures_util::future::select(sleep_fut, waker_fut),
-                        comm_fut,
+                        futures_util::future::select(
+                            futures_util::future::select(sleep_fut, waker_fut),
+                            comm_fut,
+                        ),
+                        shutdown_fut,
                     )
                     .await;
                 }
                 StepTransition::Never => {
+                    self.context.flush_reports().await;
                     trace!("stopping transition until awoken");
                     let waker_recv = self.context.waker.receiver.clone();
-                    let recv_fut = waker_recv.recv();
-                    let comm_fut = async {
-                        self.await_communication().await;
-                        std::result::Result::<_, async_channel::RecvError>::Ok(())
+                    let recv_fut = async {
+                        let _ = waker_recv.recv().await;
                     };
+                    let comm_fut = self.await_communication();
 
-                    // recv_fut doesn't need to be pinned as it doesn't capture any context
+                    futures_util::pin_mut!(recv_fut);
                     futures_util::pin_mut!(comm_fut);
-                    let _ = futures_util::future::select(recv_fut, comm_fut).await;
+                    let _ = futures_util::future::select(
+                        futures_util::future::select(recv_fut, comm_fut),
+                        shutdown_fut,
+                    )
+                    .await;
                 }
             }
         }
+
+        info!("stopping state machine on '{}' state", self.state.name());
+        self.context.flush_reports().await;
+        self.context.flush_webhooks().await;
+        if let Err(e) = self.context.runtime_settings.save() {
+            error!("failed to save runtime settings while stopping: {}", e);
+        }
     }
 
     async fn consume_pending_communication(&mut self) {
         while let Ok((msg, responder)) = self.context.communication.receiver.try_recv() {
+            let previous_state = self.state.name();
             if let Some(new_state) =
                 self.state.handle_communication(msg, responder, &mut self.context).await
             {
                 self.state = new_state;
+                self.broadcast_transition(previous_state);
             }
         }
     }
 
     async fn await_communication(&mut self) {
         while let Ok((msg, responder)) = self.context.communication.receiver.recv().await {
+            let previous_state = self.state.name();
             if let Some(new_state) =
                 self.state.handle_communication(msg, responder, &mut self.context).await
             {
                 self.state = new_state;
+                self.broadcast_transition(previous_state);
             }
         }
     }
+
+    fn broadcast_transition(&self, previous_state: &str) {
+        let state = self.state.name();
+        if state == previous_state {
+            return;
+        }
+
+        // Sending only fails when there are no subscribers, in which
+        // case the event is simply dropped.
+        let _ = self
+            .context
+            .events
+            .send(sdk::api::events::Event { state: state.to_owned(), timestamp: Utc::now() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{Park, Probe, Validation},
+        *,
+    };
+
+    #[tokio::test]
+    async fn broadcast_state_transitions() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let machine = StateMachine { state: State::Park(Park {}), context: setup.gen_context() };
+        let mut first = machine.address().subscribe_events();
+        let mut second = machine.address().subscribe_events();
+
+        machine.broadcast_transition("park");
+        machine.broadcast_transition("entry_point");
+
+        for receiver in [&mut first, &mut second] {
+            let event = receiver.try_recv().unwrap();
+            assert_eq!(event.state, "park");
+            assert!(receiver.try_recv().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_update_notifies_webhooks() {
+        let setup = crate::tests::TestEnvironment::build().invalid_hardware().finish();
+        let mut context = setup.gen_context();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        context.settings.network.webhooks =
+            vec![format!("http://{}/hook", listener.local_addr().unwrap())];
+        let server = webhook::tests::receive(listener);
+
+        let res = State::Validation(Validation {
+            package: crate::update_package::tests::get_update_package(),
+            sign: None,
+            require_download: true,
+        })
+        .move_to_next_state(&mut context)
+        .await;
+        assert!(res.is_err());
+
+        // The notification is waited for, as it is before rebooting
+        context.flush_webhooks().await;
+        assert!(context.webhook_notifications.is_empty());
+        let (_, body) = server.join().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["event"], "update_failed");
+        assert_eq!(body["state"], "validation");
+        assert_eq!(body["error_code"], "incompatible_hardware");
+    }
+
+    #[tokio::test]
+    async fn unreadable_authorization_file() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        context.settings.network.authorization_file = Some(path.clone());
+
+        std::fs::write(&path, "Bearer token\n").unwrap();
+        context.request_headers().unwrap();
+
+        // Requests are never sent without the credentials
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(context.request_headers(), Err(cloud::Error::Io(_))));
+    }
+
+    #[tokio::test]
+    async fn debug_channels() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let machine = StateMachine { state: State::Park(Park {}), context: setup.gen_context() };
+        assert_eq!(machine.address().debug_channels(), None);
+
+        let mut context = setup.gen_context();
+        context.settings.network.enable_debug_api = true;
+        let machine = StateMachine { state: State::Park(Park {}), context };
+        let addr = machine.address();
+        addr.waker.try_send(()).unwrap();
+
+        let channels = addr.debug_channels().unwrap();
+        assert_eq!(channels.communication, sdk::api::debug::Channel { len: 0, capacity: Some(10) });
+        assert_eq!(channels.waker, sdk::api::debug::Channel { len: 1, capacity: Some(1) });
+        assert!(channels.seconds_since_last_transition >= 0);
+    }
+
+    #[tokio::test]
+    async fn probe_server_order() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let primary = context.settings.network.server_address.clone();
+        context.settings.network.fallback_servers =
+            vec!["http://backup1".to_owned(), "http://backup2".to_owned()];
+        assert_eq!(
+            context.probe_server_addresses(),
+            vec![primary.clone(), "http://backup1".to_owned(), "http://backup2".to_owned()]
+        );
+
+        context.runtime_settings.set_working_server_address("http://backup2").unwrap();
+        assert_eq!(context.server_address(), "http://backup2");
+        assert_eq!(
+            context.probe_server_addresses(),
+            vec!["http://backup2".to_owned(), primary.clone(), "http://backup1".to_owned()]
+        );
+
+        // Servers no longer configured are ignored
+        context.settings.network.fallback_servers = vec!["http://backup1".to_owned()];
+        assert_eq!(context.server_address(), primary);
+
+        context.runtime_settings.set_custom_server_address("http://custom");
+        assert_eq!(context.probe_server_addresses(), vec!["http://custom".to_owned()]);
+    }
+
+    #[test]
+    fn state_timings() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+
+        context.enter_state(&State::EntryPoint(EntryPoint {}));
+        context.leave_state();
+        assert!(context.state_timings().is_empty(), "states are only timed when enabled");
+
+        context.settings.network.report_state_timings = true;
+        context.enter_state(&State::EntryPoint(EntryPoint {}));
+        context.leave_state();
+        context.enter_state(&State::Poll(Poll {}));
+        let timings = context.state_timings();
+        assert_eq!(
+            timings.iter().map(|t| t.state.as_str()).collect::<Vec<_>>(),
+            ["entry_point", "poll"],
+            "the state being handled is timed up to now"
+        );
+        assert!(timings.iter().all(|t| t.left_at >= t.entered_at));
+        context.leave_state();
+        assert_eq!(context.state_timings.len(), 2);
+
+        // A new update cycle starts on the entry point
+        context.enter_state(&State::EntryPoint(EntryPoint {}));
+        context.leave_state();
+        assert_eq!(context.state_timings.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn batch_reports() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context.settings.network.report_batch_size = 3;
+        let report = |state: &str| cloud::api::Report {
+            state: state.to_owned(),
+            package_uid: "package-uid".to_owned(),
+            previous_state: None,
+            error_code: None,
+            error_message: None,
+            current_log: None,
+            state_timings: Vec::new(),
+        };
+
+        context.report(report("downloading"), false).await;
+        context.report(report("downloaded"), false).await;
+        assert_eq!(context.reports.len(), 2);
+
+        context.report(report("installing"), false).await;
+        assert!(context.reports.is_empty(), "full batch should have been sent");
+
+        context.report(report("installed"), false).await;
+        context.report(report("error"), true).await;
+        assert!(context.reports.is_empty(), "flush should send the queued reports");
+    }
+
+    #[tokio::test]
+    async fn set_polling_interval() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let (sndr, recv) = async_channel::bounded(1);
+
+        let state = State::Probe(Probe {})
+            .handle_communication(
+                address::Message::SetPolling(chrono::Duration::seconds(30)),
+                sndr.clone(),
+                &mut context,
+            )
+            .await;
+        assert!(state.is_none());
+        assert!(matches!(
+            recv.recv().await,
+            Ok(Ok(address::Response::SetPolling(address::PollingResponse::InvalidInterval(_))))
+        ));
+
+        let state = State::Probe(Probe {})
+            .handle_communication(
+                address::Message::SetPolling(chrono::Duration::hours(6)),
+                sndr,
+                &mut context,
+            )
+            .await;
+        let state = state.unwrap();
+        assert_state!(state, Poll);
+        assert_eq!(context.polling_interval(), chrono::Duration::hours(6));
+        match recv.recv().await {
+            Ok(Ok(address::Response::SetPolling(address::PollingResponse::RequestAccepted(r)))) => {
+                assert_eq!(r.interval, chrono::Duration::hours(6));
+                assert!(r.overridden);
+            }
+            res => panic!("unexpected response: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn inject_metadata() {
+        use crate::update_package::tests::{get_update_json, SHA256SUM};
+        use sdk::api::probe::MetadataRequest;
+
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let request = |metadata: String| MetadataRequest { metadata, signature: None };
+        let metadata = get_update_json(SHA256SUM).to_string();
+        let (sndr, recv) = async_channel::bounded(1);
+
+        let state = State::Park(Park {})
+            .handle_communication(
+                address::Message::InjectMetadata(request(metadata.clone())),
+                sndr.clone(),
+                &mut context,
+            )
+            .await;
+        assert!(state.is_none());
+        assert!(matches!(
+            recv.recv().await,
+            Ok(Ok(address::Response::InjectMetadata(address::InjectMetadataResponse::Disabled)))
+        ));
+
+        context.settings.update.allow_metadata_injection = true;
+        let state = State::Park(Park {})
+            .handle_communication(
+                address::Message::InjectMetadata(request("{}".to_owned())),
+                sndr.clone(),
+                &mut context,
+            )
+            .await;
+        assert!(state.is_none());
+        assert!(matches!(
+            recv.recv().await,
+            Ok(Ok(address::Response::InjectMetadata(
+                address::InjectMetadataResponse::InvalidMetadata(_)
+            )))
+        ));
+
+        let state = State::Park(Park {})
+            .handle_communication(
+                address::Message::InjectMetadata(request(metadata)),
+                sndr,
+                &mut context,
+            )
+            .await;
+        let state = state.unwrap();
+        assert_state!(state, Validation);
+        assert!(matches!(
+            recv.recv().await,
+            Ok(Ok(address::Response::InjectMetadata(
+                address::InjectMetadataResponse::RequestAccepted
+            )))
+        ));
+    }
+
+    #[tokio::test]
+    async fn selftest() {
+        use sdk::api::selftest::Target;
+
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let tmpdir = tempfile::tempdir().unwrap();
+        let target = |target_type: &str, target: &std::path::Path| Target {
+            target_type: target_type.to_owned(),
+            target: target.to_string_lossy().into_owned(),
+        };
+        let targets = vec![
+            target("file", &tmpdir.path().join("rootfs.img")),
+            target("file", &tmpdir.path().join("missing").join("rootfs.img")),
+            target("unknown", tmpdir.path()),
+        ];
+        let (sndr, recv) = async_channel::bounded(1);
+
+        let state = State::Park(Park {})
+            .handle_communication(address::Message::SelfTest(targets), sndr, &mut context)
+            .await;
+
+        assert!(state.is_none());
+        assert!(!tmpdir.path().join("rootfs.img").exists());
+        match recv.recv().await {
+            Ok(Ok(address::Response::SelfTest(r))) => {
+                assert!(!r.passed);
+                let passed = r.checks.iter().map(|c| c.passed).collect::<Vec<_>>();
+                assert_eq!(passed, [true, false, false, true, true, true, true, true]);
+                assert_eq!(r.checks[5].name, "download_dir");
+            }
+            res => panic!("unexpected response: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn abort_download_purging_staged_objects() {
+        use crate::{
+            firmware::installation_set::Set,
+            object::Info,
+            update_package::{tests::get_update_package, UpdatePackageExt},
+        };
+        use sdk::api::info::runtime_settings::InstallationSet;
+
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let download_dir = context.settings.update.download_dir.clone();
+        let update_package = get_update_package();
+        std::fs::create_dir_all(&download_dir).unwrap();
+        for object in update_package.objects(Set(InstallationSet::A)) {
+            std::fs::write(download_dir.join(object.sha256sum()), b"partial").unwrap();
+        }
+        std::fs::write(download_dir.join("unrelated-file"), b"unrelated").unwrap();
+        let (sndr, recv) = async_channel::bounded(1);
+
+        let state = State::Park(Park {})
+            .handle_communication(address::Message::AbortDownload(true), sndr.clone(), &mut context)
+            .await;
+        assert!(state.is_none());
+        assert!(matches!(
+            recv.recv().await,
+            Ok(Ok(address::Response::AbortDownload(address::AbortDownloadResponse::InvalidState)))
+        ));
+
+        let state = State::PausedDownload(super::super::PausedDownload {
+            update_package: update_package.clone(),
+            sign: None,
+        })
+        .handle_communication(address::Message::AbortDownload(true), sndr, &mut context)
+        .await;
+        let state = state.unwrap();
+        assert_state!(state, EntryPoint);
+        match recv.recv().await {
+            Ok(Ok(address::Response::AbortDownload(
+                address::AbortDownloadResponse::RequestAccepted(Some(purged)),
+            ))) => assert_eq!(purged, 1),
+            res => panic!("unexpected response: {:?}", res),
+        }
+        for object in update_package.objects(Set(InstallationSet::A)) {
+            assert!(!download_dir.join(object.sha256sum()).exists());
+        }
+        assert!(download_dir.join("unrelated-file").exists());
+    }
+
+    #[tokio::test]
+    async fn cancel_pending_update() {
+        use crate::{
+            firmware::installation_set::Set,
+            object::Info,
+            update_package::{tests::get_update_package, UpdatePackageExt},
+        };
+        use sdk::api::info::runtime_settings::{InstallationSet, PendingUpdate};
+
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let download_dir = context.settings.update.download_dir.clone();
+        let update_package = get_update_package();
+        std::fs::create_dir_all(&download_dir).unwrap();
+        for object in update_package.objects(Set(InstallationSet::A)) {
+            std::fs::write(download_dir.join(object.sha256sum()), b"downloaded").unwrap();
+        }
+        let (sndr, recv) = async_channel::bounded(1);
+
+        let state = State::Park(Park {})
+            .handle_communication(address::Message::CancelUpdate(true), sndr.clone(), &mut context)
+            .await;
+        assert!(state.is_none());
+        assert!(matches!(
+            recv.recv().await,
+            Ok(Ok(address::Response::CancelUpdate(address::AbortDownloadResponse::InvalidState)))
+        ));
+
+        // A postponed install keeps the update pending while waiting
+        context
+            .runtime_settings
+            .set_pending_update(PendingUpdate {
+                package: String::from_utf8_lossy(&update_package.raw).into_owned(),
+                signature: None,
+            })
+            .unwrap();
+        context.runtime_settings.force_poll().unwrap();
+        let state = State::EntryPoint(EntryPoint {})
+            .handle_communication(address::Message::CancelUpdate(false), sndr.clone(), &mut context)
+            .await;
+        assert_state!(state.unwrap(), EntryPoint);
+        assert!(matches!(
+            recv.recv().await,
+            Ok(Ok(address::Response::CancelUpdate(
+                address::AbortDownloadResponse::RequestAccepted(None)
+            )))
+        ));
+        assert_eq!(context.runtime_settings.pending_update(), None);
+        assert!(!context.runtime_settings.is_polling_forced());
+
+        let state = State::AwaitingApproval(super::super::AwaitingApproval::new(
+            update_package.clone(),
+            Default::default(),
+        ))
+        .handle_communication(address::Message::CancelUpdate(true), sndr, &mut context)
+        .await;
+        assert_state!(state.unwrap(), EntryPoint);
+        match recv.recv().await {
+            Ok(Ok(address::Response::CancelUpdate(
+                address::AbortDownloadResponse::RequestAccepted(Some(purged)),
+            ))) => assert_eq!(purged, 1),
+            res => panic!("unexpected response: {:?}", res),
+        }
+        for object in update_package.objects(Set(InstallationSet::A)) {
+            assert!(!download_dir.join(object.sha256sum()).exists());
+        }
+    }
+
+    #[tokio::test]
+    async fn confirm_install() {
+        use crate::update_package::tests::get_update_package;
+
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let (sndr, recv) = async_channel::bounded(1);
+
+        let state = State::Park(Park {})
+            .handle_communication(address::Message::ConfirmInstall, sndr.clone(), &mut context)
+            .await;
+        assert!(state.is_none());
+        assert!(matches!(
+            recv.recv().await,
+            Ok(Ok(address::Response::ConfirmInstall(
+                address::DownloadControlResponse::InvalidState
+            )))
+        ));
+
+        let state = State::AwaitingApproval(super::super::AwaitingApproval::new(
+            get_update_package(),
+            Default::default(),
+        ))
+        .handle_communication(address::Message::ConfirmInstall, sndr, &mut context)
+        .await;
+        let state = state.unwrap();
+        assert_state!(state, Install);
+        assert!(matches!(
+            recv.recv().await,
+            Ok(Ok(address::Response::ConfirmInstall(
+                address::DownloadControlResponse::RequestAccepted
+            )))
+        ));
+    }
+
+    #[tokio::test]
+    async fn info_uses_cached_installation_set() {
+        use crate::firmware::installation_set::Set;
+        use sdk::api::info::runtime_settings::InstallationSet;
+
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        assert_eq!(context.active_installation_set, Some(Set(InstallationSet::A)));
+
+        // The firmware scripts still report A, so B can only come from
+        // the cache
+        context.active_installation_set = Some(Set(InstallationSet::B));
+        let (sndr, recv) = async_channel::bounded(1);
+        State::Park(Park {}).handle_communication(address::Message::Info, sndr, &mut context).await;
+        match recv.recv().await {
+            Ok(Ok(address::Response::Info(info))) => {
+                assert_eq!(info.active_installation_set, Some(InstallationSet::B));
+                assert_eq!(info.target_installation_set, Some(InstallationSet::A));
+            }
+            res => panic!("Unexpected response: {:?}", res),
+        }
+
+        context.refresh_installation_set();
+        assert_eq!(context.active_installation_set, Some(Set(InstallationSet::A)));
+    }
+
+    #[tokio::test]
+    async fn debug_breakpoints() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context.settings.update.debug_breakpoints = vec!["probe".to_owned()];
+        assert!(!context.pause_at_breakpoint("probe"));
+
+        context.settings.network.enable_debug_api = true;
+        assert!(!context.pause_at_breakpoint("poll"));
+        assert!(context.pause_at_breakpoint("probe"));
+        // Stays paused until continued
+        assert!(context.pause_at_breakpoint("probe"));
+
+        let (sndr, recv) = async_channel::bounded(1);
+        State::Park(Park {})
+            .handle_communication(address::Message::Info, sndr.clone(), &mut context)
+            .await;
+        match recv.recv().await {
+            Ok(Ok(address::Response::Info(info))) => {
+                assert_eq!(info.breakpoint.as_deref(), Some("probe"))
+            }
+            res => panic!("Unexpected response: {:?}", res),
+        }
+
+        State::Park(Park {})
+            .handle_communication(address::Message::DebugContinue, sndr.clone(), &mut context)
+            .await;
+        assert!(matches!(
+            recv.recv().await,
+            Ok(Ok(address::Response::DebugContinue(
+                address::DownloadControlResponse::RequestAccepted
+            )))
+        ));
+        assert!(!context.pause_at_breakpoint("probe"));
+        // The breakpoint is hit again next time the state is entered
+        assert!(context.pause_at_breakpoint("probe"));
+
+        State::Park(Park {})
+            .handle_communication(address::Message::DebugContinue, sndr.clone(), &mut context)
+            .await;
+        recv.recv().await.unwrap().unwrap();
+        State::Park(Park {})
+            .handle_communication(address::Message::DebugContinue, sndr, &mut context)
+            .await;
+        assert!(matches!(
+            recv.recv().await,
+            Ok(Ok(address::Response::DebugContinue(
+                address::DownloadControlResponse::InvalidState
+            )))
+        ));
+    }
+
+    #[tokio::test]
+    async fn stop_on_shutdown_request() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let machine = StateMachine { state: State::Park(Park {}), context: setup.gen_context() };
+        let addr = machine.address();
+
+        let shutdown_fut = async {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            addr.request_shutdown();
+        };
+        let (res, _) = tokio::join!(
+            tokio::time::timeout(std::time::Duration::from_secs(5), machine.start()),
+            shutdown_fut
+        );
+        res.expect("state machine should stop once the shutdown is requested");
+    }
 }
@@ -0,0 +1,183 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use sdk::api::info::{download_progress as api, install_progress};
+use std::sync::{Arc, Mutex};
+
+/// Download progress counters, updated by the download states as data is
+/// received and read when building the `info` response.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DownloadProgress(Arc<Mutex<Option<api::DownloadProgress>>>);
+
+impl DownloadProgress {
+    /// Starts tracking a new download for the given objects, described by
+    /// their filename, size, when known, and amount of bytes already
+    /// available on disk.
+    pub(crate) fn start(&self, objects: impl IntoIterator<Item = (String, Option<u64>, u64)>) {
+        let objects = objects
+            .into_iter()
+            .map(|(filename, size, downloaded_bytes)| api::ObjectProgress {
+                filename,
+                size,
+                downloaded_bytes,
+                completed: false,
+            })
+            .collect::<Vec<_>>();
+
+        *self.0.lock().unwrap() = Some(api::DownloadProgress {
+            total_bytes: objects.iter().map(|o| o.size).sum(),
+            downloaded_bytes: objects.iter().map(|o| o.downloaded_bytes).sum(),
+            current_object: 0,
+            total_objects: objects.len(),
+            objects,
+        });
+    }
+
+    pub(crate) fn start_object(&self) {
+        if let Some(progress) = self.0.lock().unwrap().as_mut() {
+            progress.current_object += 1;
+        }
+    }
+
+    pub(crate) fn add(&self, index: usize, read: u64) {
+        if let Some(progress) = self.0.lock().unwrap().as_mut() {
+            progress.downloaded_bytes += read;
+            if let Some(object) = progress.objects.get_mut(index) {
+                object.downloaded_bytes += read;
+            }
+        }
+    }
+
+    pub(crate) fn complete_object(&self, index: usize) {
+        if let Some(object) =
+            self.0.lock().unwrap().as_mut().and_then(|progress| progress.objects.get_mut(index))
+        {
+            object.completed = true;
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        self.0.lock().unwrap().take();
+    }
+
+    pub(crate) fn get(&self) -> Option<api::DownloadProgress> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Install status of the objects, updated by the install state as each
+/// object is installed and read when building the `info` response.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct InstallProgress(Arc<Mutex<Option<install_progress::InstallProgress>>>);
+
+impl InstallProgress {
+    /// Starts tracking the install of the given objects, described by
+    /// their filename and mode, in the order they are installed.
+    pub(crate) fn start(&self, objects: impl IntoIterator<Item = (String, String)>) {
+        let objects = objects
+            .into_iter()
+            .map(|(filename, mode)| install_progress::ObjectInstallStatus {
+                filename,
+                mode,
+                status: install_progress::InstallStatus::Pending,
+            })
+            .collect::<Vec<_>>();
+
+        *self.0.lock().unwrap() = Some(install_progress::InstallProgress {
+            current_object: 0,
+            total_objects: objects.len(),
+            objects,
+        });
+    }
+
+    pub(crate) fn set_status(&self, index: usize, status: install_progress::InstallStatus) {
+        if let Some(progress) = self.0.lock().unwrap().as_mut() {
+            if status == install_progress::InstallStatus::Installing {
+                progress.current_object = index + 1;
+            }
+            if let Some(object) = progress.objects.get_mut(index) {
+                object.status = status;
+            }
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        self.0.lock().unwrap().take();
+    }
+
+    pub(crate) fn get(&self) -> Option<install_progress::InstallProgress> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn track_objects() {
+        let progress = DownloadProgress::default();
+        assert_eq!(progress.get(), None);
+
+        progress.start(vec![("a".to_owned(), Some(10), 0), ("b".to_owned(), Some(20), 5)]);
+        progress.start_object();
+        progress.add(0, 10);
+        progress.complete_object(0);
+        progress.start_object();
+        progress.add(1, 3);
+
+        let current = progress.get().unwrap();
+        assert_eq!(current.total_bytes, Some(30));
+        assert_eq!(current.downloaded_bytes, 18);
+        assert_eq!(current.current_object, 2);
+        assert_eq!(current.total_objects, 2);
+        assert!(current.objects[0].completed);
+        assert!(!current.objects[1].completed);
+        assert_eq!(current.objects[1].downloaded_bytes, 8);
+
+        progress.clear();
+        assert_eq!(progress.get(), None);
+    }
+
+    #[test]
+    fn unknown_size() {
+        let progress = DownloadProgress::default();
+        progress.start(vec![("pkg".to_owned(), None, 0)]);
+        progress.add(0, 42);
+
+        let current = progress.get().unwrap();
+        assert_eq!(current.total_bytes, None);
+        assert_eq!(current.downloaded_bytes, 42);
+    }
+
+    #[test]
+    fn track_install() {
+        use install_progress::InstallStatus;
+
+        let progress = InstallProgress::default();
+        assert_eq!(progress.get(), None);
+
+        progress.start(vec![
+            ("kernel".to_owned(), "raw".to_owned()),
+            ("rootfs".to_owned(), "copy".to_owned()),
+        ]);
+        progress.set_status(0, InstallStatus::Installing);
+        progress.set_status(0, InstallStatus::Done);
+        progress.set_status(1, InstallStatus::Installing);
+
+        let current = progress.get().unwrap();
+        assert_eq!(current.current_object, 2);
+        assert_eq!(current.total_objects, 2);
+        assert_eq!(current.objects[0].status, InstallStatus::Done);
+        assert_eq!(current.objects[1].status, InstallStatus::Installing);
+        assert_eq!(current.objects[1].filename, "rootfs");
+
+        progress.set_status(1, InstallStatus::Failed);
+        assert_eq!(progress.get().unwrap().objects[1].status, InstallStatus::Failed);
+
+        progress.clear();
+        assert_eq!(progress.get(), None);
+    }
+}
@@ -0,0 +1,62 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Commissioning self-test, checking the device is able to install
+//! updates without changing any of its state.
+
+use super::Context;
+use crate::{firmware, utils::definitions::TargetTypeExt};
+use pkg_schema::definitions::TargetType;
+use sdk::api::selftest::{Check, Response, Target};
+use slog_scope::{info, warn};
+use std::{fmt::Display, path::Path};
+
+pub(super) fn run(context: &Context, targets: &[Target]) -> Response {
+    info!("running self-test");
+    let mut checks = Vec::new();
+
+    for target in targets {
+        let res = serde_json::from_value::<TargetType>(serde_json::json!({
+            "target-type": target.target_type,
+            "target": target.target,
+        }))
+        .map_err(|e| e.to_string())
+        .and_then(|target| target.valid().map(|_| ()).map_err(|e| e.to_string()));
+        checks.push(check(format!("target {} {}", target.target_type, target.target), res));
+    }
+
+    let firmware = &context.settings.firmware;
+    for (name, res) in
+        firmware::check_callbacks(&firmware.metadata, firmware.validate_on_missing_callback)
+    {
+        checks.push(check(name.to_owned(), res));
+    }
+
+    let temp_dir = std::env::temp_dir();
+    for (name, dir) in
+        [("download_dir", context.settings.update.download_dir.as_path()), ("temp_dir", &temp_dir)]
+    {
+        checks.push(check(name.to_owned(), writable(dir)));
+    }
+
+    Response { passed: checks.iter().all(|c| c.passed), checks }
+}
+
+fn check<E: Display>(name: String, res: Result<(), E>) -> Check {
+    match res {
+        Ok(()) => Check { name, passed: true, error: None },
+        Err(e) => {
+            warn!("self-test check '{}' has failed: {}", name, e);
+            Check { name, passed: false, error: Some(e.to_string()) }
+        }
+    }
+}
+
+/// Checks the directory is writable without writing into it. Directories
+/// are created on demand, so a missing one is checked through its nearest
+/// existing ancestor.
+fn writable(dir: &Path) -> nix::Result<()> {
+    let existing = dir.ancestors().find(|p| p.exists()).unwrap_or_else(|| Path::new("/"));
+    nix::unistd::access(existing, nix::unistd::AccessFlags::W_OK)
+}
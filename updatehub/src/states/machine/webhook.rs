@@ -0,0 +1,208 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use slog_scope::{debug, warn};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Header holding the shared secret of the webhook requests.
+const SECRET_HEADER: &str = "X-UpdateHub-Webhook-Secret";
+
+/// Time limit of each webhook request.
+pub(super) const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Point of the update lifecycle notified to the webhooks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum WebhookEvent {
+    UpdateAvailable,
+    DownloadCompleted,
+    InstallCompleted,
+    UpdateFailed,
+}
+
+/// Event posted, as JSON, to the webhooks.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(super) struct Payload {
+    pub(super) event: WebhookEvent,
+    pub(super) timestamp: DateTime<Utc>,
+    pub(super) product_uid: String,
+    /// Uid of the package, once it's known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) package_uid: Option<String>,
+    /// Version of the package, when it's known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) version: Option<String>,
+    /// State the update has failed on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) error_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) error_message: Option<String>,
+}
+
+/// Client settings the webhooks are reached with, the same the server is.
+#[derive(Clone, Default)]
+pub(super) struct Connection {
+    pub(super) identity: Option<cloud::ClientIdentity>,
+    pub(super) proxy: Option<cloud::Proxy>,
+    pub(super) host_aliases: cloud::HostAliases,
+}
+
+/// Posts the payload to every webhook on the background, so a slow or
+/// failing receiver never delays nor fails the update. The returned
+/// handles complete once the requests are done.
+pub(super) fn notify(
+    webhooks: &[String],
+    secret: Option<&str>,
+    connection: &Connection,
+    payload: Payload,
+) -> Vec<JoinHandle<()>> {
+    webhooks
+        .iter()
+        .map(|url| {
+            let url = url.clone();
+            let secret = secret.map(str::to_owned);
+            let connection = connection.clone();
+            let payload = payload.clone();
+            tokio::spawn(async move {
+                match post(&url, secret.as_deref(), &connection, &payload).await {
+                    Ok(()) => debug!("webhook {} notified of {:?}", url, payload.event),
+                    Err(e) => {
+                        warn!("failed to notify webhook {} of {:?}: {}", url, payload.event, e)
+                    }
+                }
+            })
+        })
+        .collect()
+}
+
+async fn post(
+    url: &str,
+    secret: Option<&str>,
+    connection: &Connection,
+    payload: &Payload,
+) -> cloud::Result<()> {
+    let mut client =
+        cloud::Client::new(url, connection.identity.as_ref(), connection.proxy.as_ref())
+            .with_host_aliases(&connection.host_aliases);
+    if let Some(secret) = secret {
+        client = client.with_headers(&cloud::Headers::new(None, [(SECRET_HEADER, secret)])?);
+    }
+    client.post(url, payload, TIMEOUT).await
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    /// Answers a single webhook request, returning its lowercased
+    /// headers and body.
+    pub(crate) fn receive(listener: TcpListener) -> std::thread::JoinHandle<(String, Vec<u8>)> {
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut headers = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                headers.push_str(&line.to_lowercase());
+            }
+            let len = headers
+                .lines()
+                .find_map(|l| l.strip_prefix("content-length:"))
+                .map_or(0, |l| l.trim().parse().unwrap());
+            let mut body = vec![0; len];
+            reader.read_exact(&mut body).unwrap();
+            reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n").unwrap();
+            (headers, body)
+        })
+    }
+
+    #[tokio::test]
+    async fn post_payload() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = receive(listener);
+
+        let payload = Payload {
+            event: WebhookEvent::UpdateFailed,
+            timestamp: Utc::now(),
+            product_uid: "229ffd7e08721d716163fc81a2dbaf6c90d449f0a3b009b6a2defe8a0b0d7381".into(),
+            package_uid: Some("package-uid".into()),
+            version: None,
+            state: Some("installing".into()),
+            error_code: Some("io_error".into()),
+            error_message: Some("install has failed".into()),
+        };
+        post(&url, Some("secret"), &Connection::default(), &payload).await.unwrap();
+
+        let (headers, body) = server.join().unwrap();
+        assert!(headers.starts_with("post /hook "), "{}", headers);
+        assert!(headers.contains("x-updatehub-webhook-secret: secret\r\n"), "{}", headers);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["event"], "update_failed");
+        assert_eq!(body["package_uid"], "package-uid");
+        assert_eq!(body["state"], "installing");
+        assert_eq!(body["error_code"], "io_error");
+        assert!(body.get("version").is_none());
+    }
+
+    #[tokio::test]
+    async fn post_through_host_alias() {
+        // The webhook host doesn't resolve, so it can only be reached
+        // through the alias the server is also reached with
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://webhook.invalid:{}/hook", listener.local_addr().unwrap().port());
+        let server = receive(listener);
+
+        let connection = Connection {
+            host_aliases: cloud::HostAliases::new([("webhook.invalid", "127.0.0.1")]).unwrap(),
+            ..Connection::default()
+        };
+        let payload = Payload {
+            event: WebhookEvent::DownloadCompleted,
+            timestamp: Utc::now(),
+            product_uid: "product-uid".into(),
+            package_uid: Some("package-uid".into()),
+            version: Some("1.0".into()),
+            state: None,
+            error_code: None,
+            error_message: None,
+        };
+        post(&url, None, &connection, &payload).await.unwrap();
+
+        let (headers, _) = server.join().unwrap();
+        assert!(headers.contains("host: webhook.invalid:"), "{}", headers);
+    }
+
+    #[tokio::test]
+    async fn post_failure() {
+        let payload = Payload {
+            event: WebhookEvent::InstallCompleted,
+            timestamp: Utc::now(),
+            product_uid: "product-uid".into(),
+            package_uid: Some("package-uid".into()),
+            version: Some("1.0".into()),
+            state: None,
+            error_code: None,
+            error_message: None,
+        };
+        // Nothing listens on the discard port
+        assert!(post("http://127.0.0.1:9/hook", None, &Connection::default(), &payload)
+            .await
+            .is_err());
+    }
+}
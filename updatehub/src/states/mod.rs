@@ -4,6 +4,7 @@
 
 #[macro_use]
 mod macros;
+mod awaiting_approval;
 mod direct_download;
 mod download;
 mod entry_point;
@@ -11,6 +12,7 @@ mod error;
 pub(crate) mod install;
 pub(crate) mod machine;
 mod park;
+mod paused_download;
 mod poll;
 mod prepare_local_install;
 mod probe;
@@ -21,8 +23,9 @@ mod validation;
 mod tests;
 
 use self::{
-    direct_download::DirectDownload, download::Download, entry_point::EntryPoint, error::Error,
-    install::Install, park::Park, poll::Poll, prepare_local_install::PrepareLocalInstall,
+    awaiting_approval::AwaitingApproval, direct_download::DirectDownload, download::Download,
+    entry_point::EntryPoint, error::Error, install::Install, park::Park,
+    paused_download::PausedDownload, poll::Poll, prepare_local_install::PrepareLocalInstall,
     probe::Probe, reboot::Reboot, validation::Validation,
 };
 use crate::{
@@ -33,7 +36,8 @@ use crate::{
 };
 use async_trait::async_trait;
 use derive_more::{Display, Error, From};
-use slog_scope::{error, info, trace, warn};
+use sdk::api::info::runtime_settings::{UpdateResult, UpdateStatus};
+use slog_scope::{debug, error, info, trace, warn};
 use std::path::Path;
 
 pub type Result<T> = std::result::Result<T, TransitionError>;
@@ -44,6 +48,46 @@ pub enum TransitionError {
     SomeObjectsAreNotReady,
     #[display(fmt = "signature not found")]
     SignatureNotFound,
+    #[display(fmt = "missing objects: {}", _0)]
+    MissingObjects(#[error(not(source))] String),
+    #[display(
+        fmt = "not enough space on download dir: {} bytes required, {} available",
+        required,
+        available
+    )]
+    NotEnoughDownloadSpace {
+        available: u64,
+        required: u64,
+    },
+    #[display(fmt = "package size of {} bytes exceeds the maximum allowed of {} bytes", size, max)]
+    #[from(ignore)]
+    PackageTooLarge {
+        size: u64,
+        max: u64,
+    },
+    #[display(fmt = "package checksum mismatch: expected {}, found {}", expected, found)]
+    PackageChecksumMismatch {
+        expected: String,
+        found: String,
+    },
+    #[display(fmt = "checksum of an extracted package can't be verified")]
+    UnverifiableChecksum,
+    #[display(
+        fmt = "installation has failed validation {} times in a row, giving up rolling it back",
+        attempts
+    )]
+    #[from(ignore)]
+    RollbackExhausted {
+        attempts: u32,
+    },
+    #[display(fmt = "'{}' state has not completed within {} seconds", state, timeout)]
+    StateTimeout {
+        state: &'static str,
+        timeout: u64,
+    },
+    #[display(fmt = "install has not been confirmed within {} seconds", _0)]
+    #[from(ignore)]
+    InstallNotConfirmed(#[error(not(source))] i64),
     #[display(fmt = "channel communication as failed")]
     CommunicationFailed,
 
@@ -59,6 +103,93 @@ pub enum TransitionError {
     Process(easy_process::Error),
 }
 
+impl TransitionError {
+    /// Gets the stable, machine readable, code of the error, so failures
+    /// can be grouped by their cause.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            TransitionError::SomeObjectsAreNotReady => "objects_not_ready",
+            TransitionError::SignatureNotFound => "signature_not_found",
+            TransitionError::MissingObjects(_) => "missing_objects",
+            TransitionError::NotEnoughDownloadSpace { .. } => "no_space",
+            TransitionError::PackageTooLarge { .. } => "package_too_large",
+            TransitionError::PackageChecksumMismatch { .. } => "checksum_mismatch",
+            TransitionError::UnverifiableChecksum => "unverifiable_checksum",
+            TransitionError::RollbackExhausted { .. } => "rollback_exhausted",
+            TransitionError::StateTimeout { .. } => "state_timeout",
+            TransitionError::InstallNotConfirmed(_) => "install_not_confirmed",
+            TransitionError::CommunicationFailed => "internal_error",
+            TransitionError::Firmware(crate::firmware::Error::Process(_)) => "callback_failed",
+            TransitionError::Firmware(_) => "firmware_error",
+            TransitionError::Installation(e) => installation_error_code(e),
+            TransitionError::RuntimeSettings(_) => "runtime_settings_error",
+            TransitionError::UpdatePackage(e) => update_package_error_code(e),
+            TransitionError::Client(e) => client_error_code(e),
+            TransitionError::Uncompress(_) => "uncompress_failed",
+            TransitionError::SerdeJson(_) | TransitionError::NonUtf8(_) => "parse_error",
+            TransitionError::Io(e) => io_error_code(e),
+            TransitionError::Process(_) => "process_failed",
+        }
+    }
+
+    /// Checks if the error has a transient cause, so the failed update
+    /// may succeed if retried right away.
+    pub(crate) fn is_retryable(&self) -> bool {
+        matches!(self.code(), "network_error" | "network_timeout" | "state_timeout" | "io_error")
+    }
+}
+
+fn installation_error_code(error: &crate::object::Error) -> &'static str {
+    use crate::object::Error;
+
+    match error {
+        Error::DirtyTargets(_)
+        | Error::WrittenChecksumMismatch { .. }
+        | Error::ReadbackMismatch { .. }
+        | Error::PatchResultMismatch { .. } => "checksum_mismatch",
+        Error::PatchBaseMismatch { .. } => "patch_base_mismatch",
+        Error::CommandTimeout(_) => "install_timeout",
+        Error::Utils(crate::utils::Error::NotEnoughSpace { .. }) => "no_space",
+        Error::Utils(crate::utils::Error::Io(e)) | Error::Io(e) => io_error_code(e),
+        Error::Cloud(e) => client_error_code(e),
+        Error::Firmware(crate::firmware::Error::Process(_)) => "callback_failed",
+        _ => "install_failed",
+    }
+}
+
+fn update_package_error_code(error: &crate::update_package::Error) -> &'static str {
+    use crate::update_package::Error;
+
+    match error {
+        Error::IncompatibleHardware { .. } => "incompatible_hardware",
+        Error::IncompatibleInstallMode(_) => "incompatible_install_mode",
+        Error::InvalidMetadata(_) => "invalid_metadata",
+        Error::InvalidObjectSubset(_) => "invalid_object_subset",
+        Error::NotNewerVersion { .. } => "downgrade_refused",
+        Error::Firmware(crate::firmware::Error::Process(_)) => "callback_failed",
+        Error::CloudSDK(e) => client_error_code(e),
+        Error::Io(e) => io_error_code(e),
+        _ => "invalid_package",
+    }
+}
+
+fn client_error_code(error: &cloud::Error) -> &'static str {
+    match error {
+        cloud::Error::InvalidSignature => "invalid_signature",
+        cloud::Error::Unauthorized => "unauthorized",
+        cloud::Error::Http(e) if e.is_timeout() => "network_timeout",
+        cloud::Error::Io(e) if e.kind() == std::io::ErrorKind::TimedOut => "network_timeout",
+        _ => "network_error",
+    }
+}
+
+fn io_error_code(error: &std::io::Error) -> &'static str {
+    match error.raw_os_error() {
+        Some(nix::libc::ENOSPC) => "no_space",
+        _ => "io_error",
+    }
+}
+
 #[async_trait(?Send)]
 trait StateChangeImpl {
     async fn handle(
@@ -74,6 +205,13 @@ trait StateChangeImpl {
         false
     }
 
+    /// States handling a download should overwrite this to remove the
+    /// files they have staged, when the download is aborted and a purge
+    /// is requested. Returns the number of removed files.
+    fn purge_download(&self, _: &machine::Context) -> std::io::Result<usize> {
+        Ok(0)
+    }
+
     /// A preemptive state is a state whose transition can be yield
     /// to handle a user's request. Any preemptive state should overwrite
     /// this method to return true.
@@ -97,7 +235,7 @@ trait CallbackReporter: Sized + StateChangeImpl {
         context: &mut machine::Context,
     ) -> Result<(State, machine::StepTransition)> {
         let transition =
-            firmware::state_change_callback(&context.settings.firmware.metadata, self.name());
+            firmware::state_change_callback(&context.settings.firmware.metadata, self.name()).await;
 
         match transition {
             Ok(Transition::Continue) => return self.handle(context).await,
@@ -138,49 +276,72 @@ trait ProgressReporter: CallbackReporter {
     fn report_enter_state_name(&self) -> &'static str;
     fn report_leave_state_name(&self) -> &'static str;
 
+    /// Whether the reports must be sent as soon as the state is
+    /// entered, instead of being queued.
+    fn flush_reports_on_enter(&self) -> bool {
+        false
+    }
+
+    /// Whether the time spent on the states of the update is sent along
+    /// with the report of leaving the state, as it concludes the update.
+    fn report_state_timings_on_leave(&self) -> bool {
+        false
+    }
+
+    /// Event notified to the webhooks when the state is left, as it
+    /// concludes a step of the update.
+    fn leave_webhook_event(&self) -> Option<machine::WebhookEvent> {
+        None
+    }
+
     async fn handle_and_report_progress(
         self,
         context: &mut machine::Context,
     ) -> Result<(State, machine::StepTransition)> {
-        let server = context.server_address().to_owned();
-        let firmware = &context.firmware.clone();
-        let package_uid = &self.package_uid();
+        let package_uid = self.package_uid();
         let enter_state = self.report_enter_state_name();
         let leave_state = self.report_leave_state_name();
-        let api = crate::CloudClient::new(&server);
-
-        let report = |state, previous_state, error_message, current_log| {
-            api.report(
-                state,
-                firmware.as_cloud_metadata(),
-                package_uid,
-                previous_state,
-                error_message,
-                current_log,
-            )
+
+        let report = |state: &str,
+                      previous_state: Option<&str>,
+                      error: Option<&TransitionError>,
+                      state_timings: Vec<cloud::api::StateTiming>| {
+            cloud::api::Report {
+                state: state.to_owned(),
+                package_uid: package_uid.clone(),
+                previous_state: previous_state.map(str::to_owned),
+                error_code: error.map(|e| e.code().to_owned()),
+                error_message: error.map(ToString::to_string),
+                current_log: error.map(|_| crate::logger::get_memory_log()),
+                state_timings,
+            }
         };
 
-        if let Err(e) = report(enter_state, None, None, None).await {
-            warn!("report failed: {}", e);
-        }
+        context
+            .report(report(enter_state, None, None, Vec::new()), self.flush_reports_on_enter())
+            .await;
+        let report_state_timings_on_leave = self.report_state_timings_on_leave();
+        let leave_webhook_event = self.leave_webhook_event();
         match self.handle(context).await {
             Ok((state, trans)) => {
-                if let Err(e) = report(leave_state, None, None, None).await {
-                    warn!("report failed: {}", e);
+                let state_timings = if report_state_timings_on_leave {
+                    context.state_timings()
+                } else {
+                    Vec::new()
                 };
+                context.report(report(leave_state, None, None, state_timings), false).await;
+                if let Some(event) = leave_webhook_event {
+                    context.notify_webhooks(event, Some(package_uid.clone()), None, None);
+                }
                 Ok((state, trans))
             }
             Err(e) => {
-                if let Err(e) = report(
-                    "error",
-                    Some(enter_state),
-                    Some(e.to_string()),
-                    Some(crate::logger::get_memory_log()),
-                )
-                .await
-                {
-                    warn!("report failed: {}", e);
-                }
+                // Failures are sent right away, along with any queued
+                // report, so they aren't delayed.
+                let state_timings = context.state_timings();
+                context
+                    .report(report("error", Some(enter_state), Some(&e), state_timings), true)
+                    .await;
                 Err(e)
             }
         }
@@ -191,7 +352,8 @@ trait ProgressReporter: CallbackReporter {
         context: &mut machine::Context,
     ) -> Result<(State, machine::StepTransition)> {
         let transition =
-            firmware::state_change_callback(&context.settings.firmware.metadata, self.name())?;
+            firmware::state_change_callback(&context.settings.firmware.metadata, self.name())
+                .await?;
 
         match transition {
             Transition::Continue => Ok(self.handle_and_report_progress(context).await?),
@@ -214,6 +376,8 @@ enum State {
     Probe(Probe),
     Validation(Validation),
     Download(Download),
+    PausedDownload(PausedDownload),
+    AwaitingApproval(AwaitingApproval),
     Install(Install),
     Reboot(Reboot),
     DirectDownload(DirectDownload),
@@ -221,16 +385,54 @@ enum State {
     Error(Error),
 }
 
-fn handle_startup_callbacks(
+async fn handle_startup_callbacks(
     settings: &Settings,
     runtime_settings: &mut RuntimeSettings,
 ) -> crate::Result<()> {
     if let Some(expected_set) = runtime_settings.update.upgrade_to_installation {
         info!("booting from a recent installation");
         if expected_set == firmware::installation_set::active()?.0 {
-            match firmware::validate_callback(&settings.firmware.metadata)? {
+            let transition = match firmware::validate_callback(
+                &settings.firmware.metadata,
+                settings.firmware.validate_on_missing_callback,
+            )? {
                 Transition::Cancel => {
                     warn!("validate callback has failed");
+                    Transition::Cancel
+                }
+                Transition::Continue => match &settings.firmware.health_check {
+                    Some(command) => {
+                        firmware::health_check(
+                            command,
+                            settings.firmware.health_check_attempts,
+                            settings.firmware.health_check_interval,
+                        )
+                        .await
+                    }
+                    None => Transition::Continue,
+                },
+            };
+
+            match transition {
+                Transition::Cancel => {
+                    runtime_settings
+                        .inc_rollback_attempts()?
+                        .log_if_not_persisted("rollback attempts");
+                    let attempts = runtime_settings.rollback_attempts();
+                    if attempts > settings.firmware.rollback_attempts {
+                        // Both installation sets may be failing, so
+                        // rebooting into the other one would loop forever
+                        error!(
+                            "installation has failed validation {} times in a row, not rolling \
+                             it back again",
+                            attempts
+                        );
+                        runtime_settings
+                            .set_rollback_exhausted()?
+                            .log_if_not_persisted("exhausted rollback");
+                        return Ok(());
+                    }
+
                     firmware::installation_set::swap_active()?;
                     warn!("swapped active installation set and running rollback");
                     firmware::rollback_callback(&settings.firmware.metadata)?;
@@ -254,8 +456,12 @@ fn handle_startup_callbacks(
             firmware::installation_set::validate()?;
         }
 
-        runtime_settings.reset_installation_settings()?;
+        runtime_settings
+            .reset_installation_settings()?
+            .log_if_not_persisted("installation settings");
     }
+
+    runtime_settings.inc_boot_count()?.log_if_not_persisted("boot count");
     Ok(())
 }
 
@@ -274,6 +480,10 @@ impl StateChangeImpl for State {
         self.inner_state().is_handling_download()
     }
 
+    fn purge_download(&self, context: &machine::Context) -> std::io::Result<usize> {
+        self.inner_state().purge_download(context)
+    }
+
     fn is_preemptive_state(&self) -> bool {
         self.inner_state().is_preemptive_state()
     }
@@ -284,11 +494,57 @@ impl State {
         State::EntryPoint(EntryPoint {})
     }
 
+    /// Gets the state the machine starts from, resuming the update
+    /// selected by a probe if the agent has been restarted before
+    /// installing it.
+    fn initial(settings: &Settings, runtime_settings: &RuntimeSettings) -> Self {
+        let pending = match runtime_settings.pending_update() {
+            Some(pending) => pending,
+            None if settings.polling.enabled && settings.polling.probe_on_startup => {
+                info!("probing server on startup");
+                return State::Probe(Probe {});
+            }
+            None => return State::new(),
+        };
+
+        let update = cloud::api::UpdatePackage::parse(pending.package.as_bytes()).and_then(|p| {
+            let sign = pending
+                .signature
+                .as_deref()
+                .map(cloud::api::Signature::from_base64_str)
+                .transpose()?;
+            Ok((p, sign))
+        });
+        match update {
+            Ok((package, _)) if settings.update.revalidate_on_resume => {
+                info!(
+                    "probing the server before resuming update {} ({})",
+                    package.version(),
+                    package.package_uid()
+                );
+                State::Probe(Probe {})
+            }
+            Ok((package, sign)) => {
+                info!("resuming update {} ({})", package.version(), package.package_uid());
+                State::Validation(Validation { package, sign, require_download: true })
+            }
+            Err(e) => {
+                // Entry point clears the pending update, so it is not
+                // tried again
+                warn!("discarding pending update which failed to load: {}", e);
+                State::new()
+            }
+        }
+    }
+
     async fn move_to_next_state(
         self,
         context: &mut machine::Context,
     ) -> Result<(Self, machine::StepTransition)> {
-        match self {
+        let package_uid = self.package_uid();
+        let name = self.name();
+        let handling_update = self.is_handling_update();
+        let res = match self {
             State::Park(s) => s.handle(context).await,
             State::EntryPoint(s) => s.handle(context).await,
             State::Poll(s) => s.handle(context).await,
@@ -298,11 +554,144 @@ impl State {
             State::PrepareLocalInstall(s) => s.handle_with_callback(context).await,
             State::Error(s) => s.handle_with_callback(context).await,
             State::Download(s) => s.handle_with_callback_and_report_progress(context).await,
-            State::Install(s) => s.handle_with_callback_and_report_progress(context).await,
+            State::PausedDownload(s) => s.handle(context).await,
+            State::AwaitingApproval(s) => s.handle(context).await,
+            State::Install(s) => {
+                let postponed = match s.postpone_to_update_window(context)? {
+                    Some(postponed) => Some(postponed),
+                    None => s.postpone_to_minimum_uptime(context)?,
+                };
+                match postponed {
+                    Some(postponed) => Ok(postponed),
+                    None => match s.run_pre_install_callback(context).await? {
+                        Some(canceled) => Ok(canceled),
+                        None => s.handle_with_callback_and_report_progress(context).await,
+                    },
+                }
+            }
+            // The callback and reports were already handled before the delay
+            State::Reboot(s) if s.delay_elapsed => s.handle(context).await,
+            State::Reboot(s) if s.update_package.is_none() => s.handle_with_callback(context).await,
             State::Reboot(s) => s.handle_with_callback_and_report_progress(context).await,
+        };
+
+        if let Err(e) = &res {
+            if handling_update {
+                context.notify_webhooks(
+                    machine::WebhookEvent::UpdateFailed,
+                    package_uid.clone(),
+                    None,
+                    Some((name, e)),
+                );
+            }
+            if let Some(package_uid) = package_uid {
+                match context.runtime_settings.set_last_update_result(
+                    UpdateResult {
+                        status: UpdateStatus::Failure,
+                        package_uid,
+                        error: Some(e.to_string()),
+                    },
+                    Some(e.code()),
+                    context.settings.update.history_size,
+                ) {
+                    Ok(saved) => saved.log_if_not_persisted("update result"),
+                    Err(e) => warn!("failed to record update result: {}", e),
+                }
+            }
+        }
+
+        res
+    }
+
+    /// Handles the state within its time budget, if any, so a state which
+    /// is stuck leads to the error state instead of hanging the machine.
+    /// The budget is only checked when the state awaits, so blocking
+    /// calls still have to bound themselves. Callbacks run as child
+    /// processes, which are killed once the budget runs out.
+    async fn handle_with_timeout(
+        self,
+        context: &mut machine::Context,
+    ) -> Result<(Self, machine::StepTransition)> {
+        let name = self.name();
+        let timeout = match context.settings.state_timeout(name) {
+            Some(timeout) => timeout,
+            None => return self.handle(context).await,
+        };
+
+        let package_uid = self.package_uid();
+        match tokio::time::timeout(timeout, self.handle(context)).await {
+            Ok(res) => res,
+            Err(_) => {
+                let err = TransitionError::StateTimeout { state: name, timeout: timeout.as_secs() };
+                error!("{}", err);
+                // Going back to the error state would run its stuck
+                // callback again, so the error is given up on instead
+                if name == "error" {
+                    info!("returning to machine's entry point");
+                    return Ok((
+                        State::EntryPoint(EntryPoint {}),
+                        machine::StepTransition::Immediate,
+                    ));
+                }
+                if let Some(package_uid) = package_uid {
+                    match context.runtime_settings.set_last_update_result(
+                        UpdateResult {
+                            status: UpdateStatus::Failure,
+                            package_uid: package_uid.clone(),
+                            error: Some(err.to_string()),
+                        },
+                        Some(err.code()),
+                        context.settings.update.history_size,
+                    ) {
+                        Ok(saved) => saved.log_if_not_persisted("update result"),
+                        Err(e) => warn!("failed to record update result: {}", e),
+                    }
+                    context
+                        .report(
+                            cloud::api::Report {
+                                state: "error".to_owned(),
+                                package_uid,
+                                previous_state: Some(name.to_owned()),
+                                error_code: Some(err.code().to_owned()),
+                                error_message: Some(err.to_string()),
+                                current_log: Some(crate::logger::get_memory_log()),
+                                state_timings: context.state_timings(),
+                            },
+                            true,
+                        )
+                        .await;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Gets the uid of the package being updated, if any.
+    fn package_uid(&self) -> Option<String> {
+        match self {
+            State::Validation(s) => Some(s.package.package_uid()),
+            State::Download(s) => Some(s.update_package.package_uid()),
+            State::PausedDownload(s) => Some(s.update_package.package_uid()),
+            State::AwaitingApproval(s) => Some(s.update_package.package_uid()),
+            State::Install(s) => Some(s.update_package.package_uid()),
+            State::Reboot(s) => s.update_package.as_ref().map(|p| p.package_uid()),
+            State::Park(_)
+            | State::EntryPoint(_)
+            | State::Poll(_)
+            | State::Probe(_)
+            | State::DirectDownload(_)
+            | State::PrepareLocalInstall(_)
+            | State::Error(_) => None,
         }
     }
 
+    /// Whether the state handles an update, even if its package isn't
+    /// known yet, so its failure is notified to the webhooks.
+    fn is_handling_update(&self) -> bool {
+        self.package_uid().is_some()
+            || matches!(self, State::DirectDownload(_) | State::PrepareLocalInstall(_))
+    }
+
     fn inner_state(&self) -> &dyn StateChangeImpl {
         match self {
             State::Error(s) => s,
@@ -314,6 +703,8 @@ impl State {
             State::DirectDownload(s) => s,
             State::PrepareLocalInstall(s) => s,
             State::Download(s) => s,
+            State::PausedDownload(s) => s,
+            State::AwaitingApproval(s) => s,
             State::Install(s) => s,
             State::Reboot(s) => s,
         }
@@ -338,6 +729,17 @@ impl State {
 ///             `-----------------------------------------'
 /// ```
 ///
+/// On Unix, a probe can also be triggered by sending `SIGUSR1` to the
+/// agent, as done by a `POST /probe` without a custom server. Signals
+/// received while the previous one is still being handled are
+/// coalesced into a single probe.
+///
+/// Sending `SIGTERM`, or `SIGINT`, stops the agent gracefully. The HTTP
+/// API stops serving requests and the state machine is given up to the
+/// configured shutdown timeout to finish the step it is running, saving
+/// the runtime settings before leaving. Partially downloaded objects are
+/// kept and resumed on next start.
+///
 /// # Example
 /// ```no_run
 /// # extern crate updatehub;
@@ -354,28 +756,132 @@ impl State {
 pub async fn run(settings: &Path) -> crate::Result<()> {
     crate::logger::start_memory_logging();
     let settings = Settings::load(settings)?;
+    crate::logger::set_format(settings.network.log_format);
+    crate::logger::set_buffer_size(settings.firmware.log_buffer_size);
     let listen_socket = settings.network.listen_socket.clone();
-    let mut runtime_settings = RuntimeSettings::load(&settings.storage.runtime_settings)?;
-    if !settings.storage.read_only {
-        runtime_settings.enable_persistency();
+    let listen_socket_mode = settings.network.listen_socket_mode;
+    let max_api_connections = settings.network.max_api_connections;
+    let api_token = settings.network.api_token.clone();
+    let shutdown_timeout = settings.update.shutdown_timeout;
+    let mut runtime_settings = RuntimeSettings::load_from_storage(&settings.storage)?;
+    let mut firmware = Metadata::from_path(&settings.firmware.metadata)?;
+    if let Some(public_key) = &settings.firmware.public_key_path {
+        firmware.load_public_key(public_key)?;
     }
-    let firmware = Metadata::from_path(&settings.firmware.metadata)?;
 
-    if let Err(e) = handle_startup_callbacks(&settings, &mut runtime_settings) {
+    let client_identity = match (&settings.network.client_certificate, &settings.network.client_key)
+    {
+        (Some(certificate), Some(key)) => {
+            Some(cloud::ClientIdentity::from_pem_files(certificate, key)?)
+        }
+        _ => None,
+    };
+    let proxy = settings
+        .network
+        .proxy
+        .as_deref()
+        .map(|proxy| cloud::Proxy::new(proxy, settings.network.no_proxy.as_deref()))
+        .transpose()?;
+    let headers = settings.headers()?;
+
+    if let Err(e) = handle_startup_callbacks(&settings, &mut runtime_settings).await {
         error!("Failed to handle startup callbacks: {}", e);
     }
 
-    let machine = machine::StateMachine::new(State::new(), settings, runtime_settings, firmware);
+    let machine = machine::StateMachine::new(
+        State::initial(&settings, &runtime_settings),
+        settings,
+        runtime_settings,
+        firmware,
+        client_identity,
+        proxy,
+        headers,
+    );
     let addr = machine.address();
 
     // Use a local spawn since running features are !Send
-    tokio::task::spawn_local(machine.start());
-
-    // FIXME: handle failiure to parse the listen socket
-    http_api::Api::server(addr)
-        .run(listen_socket.replace("localhost", "127.0.0.1").parse::<std::net::SocketAddr>()?)
-        .await;
+    let machine = tokio::task::spawn_local(machine.start());
+
+    #[cfg(unix)]
+    tokio::task::spawn_local(probe_on_signal(addr.clone()));
+
+    let server = http_api::Api::bind(
+        addr.clone(),
+        &listen_socket,
+        listen_socket_mode,
+        max_api_connections,
+        api_token,
+        shutdown_signal(),
+    )?;
+
+    #[cfg(feature = "systemd")]
+    {
+        crate::systemd::notify_ready();
+        crate::systemd::spawn_watchdog();
+    }
 
+    server.await;
     info!("Server has gracefully stopped");
+
+    addr.request_shutdown();
+    if tokio::time::timeout(shutdown_timeout.to_std().unwrap_or_default(), machine).await.is_err() {
+        warn!(
+            "state machine has not stopped after {} seconds, exiting anyway",
+            shutdown_timeout.num_seconds()
+        );
+    }
+
     Ok(())
 }
+
+/// Waits for the agent to be asked to stop, either by `SIGTERM` or
+/// `SIGINT`.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut signals) => {
+                let terminate = signals.recv();
+                let interrupt = tokio::signal::ctrl_c();
+                futures_util::pin_mut!(terminate);
+                futures_util::pin_mut!(interrupt);
+                futures_util::future::select(terminate, interrupt).await;
+            }
+            Err(e) => {
+                error!("failed to install SIGTERM handler: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+
+    info!("shutting down as requested");
+}
+
+/// Requests a probe on every `SIGUSR1` received. The signal handler only
+/// wakes this task up, so the probe request itself is done out of the
+/// signal context.
+#[cfg(unix)]
+async fn probe_on_signal(addr: machine::Addr) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("failed to install SIGUSR1 handler: {}", e);
+            return;
+        }
+    };
+
+    while signals.recv().await.is_some() {
+        info!("probe requested by SIGUSR1");
+        match addr.request_probe(None).await {
+            Ok(res) => debug!("probe request by SIGUSR1 has been handled: {:?}", res),
+            Err(e) => error!("failed to request probe: {}", e),
+        }
+    }
+}
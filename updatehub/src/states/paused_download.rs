@@ -0,0 +1,45 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{
+    machine::{self, Context},
+    Download, Result, State, StateChangeImpl,
+};
+use crate::update_package::{UpdatePackage, UpdatePackageExt};
+use slog_scope::info;
+
+/// Holds a download which has been paused by the user. The objects
+/// already downloaded, even if partially, are kept on disk so the
+/// download can be resumed later.
+#[derive(Debug)]
+pub(super) struct PausedDownload {
+    pub(super) update_package: UpdatePackage,
+    pub(super) sign: Option<cloud::api::Signature>,
+}
+
+impl PausedDownload {
+    pub(super) fn resume(&self) -> Download {
+        Download { update_package: self.update_package.clone(), sign: self.sign.clone() }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl StateChangeImpl for PausedDownload {
+    fn name(&self) -> &'static str {
+        "paused_download"
+    }
+
+    fn is_handling_download(&self) -> bool {
+        true
+    }
+
+    fn purge_download(&self, context: &Context) -> std::io::Result<usize> {
+        self.update_package.remove_staged_objects(&context.settings.update.download_dir)
+    }
+
+    async fn handle(self, _: &mut Context) -> Result<(State, machine::StepTransition)> {
+        info!("download paused, waiting to be resumed or aborted");
+        Ok((State::PausedDownload(self), machine::StepTransition::Never))
+    }
+}
@@ -6,7 +6,7 @@ use super::{
     machine::{self, Context},
     Probe, Result, State, StateChangeImpl,
 };
-use chrono::Utc;
+use chrono::{Duration, Utc};
 use slog_scope::{debug, info};
 
 #[derive(Debug)]
@@ -26,14 +26,22 @@ impl StateChangeImpl for Poll {
     }
 
     async fn handle(self, context: &mut Context) -> Result<(State, machine::StepTransition)> {
-        let interval = context.settings.polling.interval;
+        let interval = context.polling_interval();
         let delay =
             interval - Utc::now().signed_duration_since(context.runtime_settings.last_polling());
 
+        let jitter = random_jitter(context.settings.polling.jitter);
+
         let transition = if delay > interval || delay.num_seconds() < 0 {
             info!("probing server as we are in time");
-            machine::StepTransition::Immediate
+            if jitter.is_zero() {
+                machine::StepTransition::Immediate
+            } else {
+                debug!("delaying {} seconds of jitter before probing", jitter.num_seconds());
+                machine::StepTransition::Delayed(jitter)
+            }
         } else {
+            let delay = delay + jitter;
             debug!("delaying {} seconds till next probe", delay.num_seconds());
             machine::StepTransition::Delayed(delay)
         };
@@ -42,10 +50,20 @@ impl StateChangeImpl for Poll {
     }
 }
 
+/// Picks a random delay, up to `max`, to be added to the next probe.
+pub(super) fn random_jitter(max: Duration) -> Duration {
+    use rand::Rng;
+
+    if max <= Duration::zero() {
+        return Duration::zero();
+    }
+
+    Duration::milliseconds(rand::thread_rng().gen_range(0..=max.num_milliseconds()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::{Duration, Utc};
 
     #[tokio::test]
     async fn normal_delay() {
@@ -90,4 +108,29 @@ mod tests {
             _ => panic!("Unexpected StepTransition: {:?}", trans),
         }
     }
+
+    #[tokio::test]
+    async fn delay_with_jitter() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context.settings.polling.jitter = Duration::minutes(5);
+
+        let (machine, trans) = State::Poll(Poll {}).move_to_next_state(&mut context).await.unwrap();
+
+        assert_state!(machine, Probe);
+        match trans {
+            machine::StepTransition::Delayed(d) if d <= Duration::minutes(5) => {}
+            _ => panic!("Unexpected StepTransition: {:?}", trans),
+        }
+    }
+
+    #[test]
+    fn jitter_range() {
+        assert_eq!(random_jitter(Duration::zero()), Duration::zero());
+        assert_eq!(random_jitter(Duration::seconds(-1)), Duration::zero());
+        for _ in 0..100 {
+            let jitter = random_jitter(Duration::seconds(10));
+            assert!(jitter >= Duration::zero() && jitter <= Duration::seconds(10), "{}", jitter);
+        }
+    }
 }
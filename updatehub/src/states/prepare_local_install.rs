@@ -8,20 +8,51 @@ use super::{
 };
 use crate::{
     firmware::installation_set,
-    update_package::{Signature, UpdatePackage, UpdatePackageExt},
-    utils::log::LogContent,
+    object::{self, Info},
+    update_package::{mender, Signature, UpdatePackage, UpdatePackageExt},
+    utils::{self, log::LogContent},
 };
+use pkg_schema::definitions::HashAlgorithm;
 use slog_scope::{debug, error, info};
 use std::{
     fs,
-    io::{self, Seek, SeekFrom},
-    path::PathBuf,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
     str,
 };
 
 #[derive(Debug)]
 pub(super) struct PrepareLocalInstall {
     pub(super) update_file: PathBuf,
+    /// Expected sha256sum of the whole package file.
+    pub(super) sha256sum: Option<String>,
+    /// Filenames of the objects to be installed, overriding the install
+    /// subset of the package.
+    pub(super) objects: Vec<String>,
+}
+
+impl PrepareLocalInstall {
+    /// Gets the expected sha256sum of the package, given along with the
+    /// request or written to a `<file>.sha256` file next to the package.
+    fn expected_checksum(&self) -> Result<Option<String>> {
+        if let Some(sha256sum) = &self.sha256sum {
+            return Ok(Some(sha256sum.clone()));
+        }
+
+        let mut checksum_file = self.update_file.clone().into_os_string();
+        checksum_file.push(".sha256");
+        match fs::read_to_string(&checksum_file) {
+            Ok(content) => {
+                debug!("using package checksum from {:?}", checksum_file);
+                Ok(content.split_whitespace().next().map(str::to_owned))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => {
+                error!("unable to read package checksum file: {}", e);
+                Err(e.into())
+            }
+        }
+    }
 }
 
 impl CallbackReporter for PrepareLocalInstall {}
@@ -34,56 +65,40 @@ impl StateChangeImpl for PrepareLocalInstall {
 
     async fn handle(self, context: &mut Context) -> Result<(State, machine::StepTransition)> {
         info!("installing local package: {:?}", self.update_file);
+        context.local_update = true;
         let dest_path = context.settings.update.download_dir.clone();
         std::fs::create_dir_all(&dest_path).log_error_msg("unable to create download dir")?;
 
-        let mut metadata = Vec::with_capacity(1024);
-        let mut source = fs::File::open(self.update_file).log_error_msg("unable to open uhupkg")?;
-        compress_tools::uncompress_archive_file(&mut source, &mut metadata, "metadata")
-            .log_error_msg("failed to uncompress metadata from uhupkg")?;
-        let update_package =
-            UpdatePackage::parse(&metadata).log_error_msg("failed to parse extracted metadata")?;
-        debug!("successfuly uncompressed metadata file");
-
-        let sign = {
-            let mut sign = Vec::with_capacity(512);
-            source
-                .seek(SeekFrom::Start(0))
-                .log_error_msg("failed to seek uhupkg back to the start")?;
-            match compress_tools::uncompress_archive_file(&mut source, &mut sign, "signature") {
-                Ok(_) => {
-                    let sign = Signature::from_base64_str(
-                        str::from_utf8(&sign)
-                            .log_error_msg("failed to parse utf8 from signature")?,
-                    )
-                    .log_error_msg("failed to parse base64 from signature")?;
-                    Some(sign)
-                }
-                Err(compress_tools::Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
-                    error!("package does not contain a signature file");
-                    return Err(super::TransitionError::SignatureNotFound);
-                }
-                Err(e) => return Err(e.into()),
+        match self.expected_checksum()? {
+            // There is no package file to be hashed, so an extracted
+            // package can't be installed when a checksum is required
+            Some(_) if self.update_file.is_dir() => {
+                error!("package checksum can't be verified for an extracted package");
+                return Err(super::TransitionError::UnverifiableChecksum);
             }
-        };
+            Some(expected) => verify_checksum(&self.update_file, &expected)?,
+            None => {}
+        }
 
-        for object in update_package
-            .objects(
-                installation_set::active()
-                    .log_error_msg("failed to get active installation set")?,
+        // An already extracted package is used in place, avoiding
+        // the decompression of its objects.
+        let (mut update_package, sign) = if self.update_file.is_dir() {
+            link_extracted_package(&self.update_file, &dest_path)?
+        } else if mender::is_artifact(&self.update_file) {
+            info!("package is a mender artifact, installing its rootfs image");
+            let rootfs_parts =
+                mender::rootfs_parts().log_error_msg("unable to read mender rootfs partitions")?;
+            let update_package = mender::extract(
+                &self.update_file,
+                &dest_path,
+                &context.firmware.product_uid,
+                &rootfs_parts,
             )
-            .iter()
-            // We ignore object's allow_remote_install property since we are doing
-            // a local install and hence offline update is implied
-            .map(crate::object::Info::sha256sum)
-        {
-            source.seek(SeekFrom::Start(0)).log_error_msg("failed to seek uhupkg to the start")?;
-
-            let mut target = fs::File::create(dest_path.join(object))
-                .log_error_msg("failed to create output file for object")?;
-            compress_tools::uncompress_archive_file(&mut source, &mut target, object)
-                .log_error_msg("failed to uncompress object")?;
-        }
+            .log_error_msg("failed to extract mender artifact")?;
+            (update_package, None)
+        } else {
+            extract_package(&self.update_file, &dest_path)?
+        };
 
         update_package
             .clear_unrelated_files(
@@ -94,6 +109,10 @@ impl StateChangeImpl for PrepareLocalInstall {
             )
             .log_error_msg("unable to cleanup unrequired files from download dir")?;
 
+        if !self.objects.is_empty() {
+            update_package.inner.install_subset = self.objects;
+        }
+
         info!(
             "update package extracted: {} ({})",
             update_package.version(),
@@ -109,3 +128,283 @@ impl StateChangeImpl for PrepareLocalInstall {
         ))
     }
 }
+
+/// Verifies the whole package file, so a truncated or corrupted one is
+/// caught before any of its objects is extracted.
+fn verify_checksum(update_file: &Path, expected: &str) -> Result<()> {
+    let mut reader =
+        io::BufReader::new(fs::File::open(update_file).log_error_msg("unable to open uhupkg")?);
+    let mut hasher = utils::Hasher::new(HashAlgorithm::Sha256);
+    let mut buf = [0; 8192];
+    loop {
+        let len = reader.read(&mut buf).log_error_msg("failed to read uhupkg")?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+    }
+
+    let found = hasher.finish();
+    if !found.eq_ignore_ascii_case(expected) {
+        error!("package checksum mismatch: expected {}, found {}", expected, found);
+        return Err(super::TransitionError::PackageChecksumMismatch {
+            expected: expected.to_owned(),
+            found,
+        });
+    }
+    debug!("package checksum verified");
+
+    Ok(())
+}
+
+fn extract_package(
+    update_file: &Path,
+    dest_path: &Path,
+) -> Result<(UpdatePackage, Option<Signature>)> {
+    let mut metadata = Vec::with_capacity(1024);
+    let mut source = fs::File::open(update_file).log_error_msg("unable to open uhupkg")?;
+    compress_tools::uncompress_archive_file(&mut source, &mut metadata, "metadata")
+        .log_error_msg("failed to uncompress metadata from uhupkg")?;
+    let update_package =
+        UpdatePackage::parse(&metadata).log_error_msg("failed to parse extracted metadata")?;
+    debug!("successfuly uncompressed metadata file");
+
+    let sign = {
+        let mut sign = Vec::with_capacity(512);
+        source.seek(SeekFrom::Start(0)).log_error_msg("failed to seek uhupkg back to the start")?;
+        match compress_tools::uncompress_archive_file(&mut source, &mut sign, "signature") {
+            Ok(_) => {
+                let sign = Signature::from_base64_str(
+                    str::from_utf8(&sign).log_error_msg("failed to parse utf8 from signature")?,
+                )
+                .log_error_msg("failed to parse base64 from signature")?;
+                Some(sign)
+            }
+            Err(compress_tools::Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+                error!("package does not contain a signature file");
+                return Err(super::TransitionError::SignatureNotFound);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    // We ignore object's allow_remote_install property since we are doing
+    // a local install and hence offline update is implied
+    for object in update_package
+        .objects(installation_set::active().log_error_msg("failed to get active installation set")?)
+        .iter()
+    {
+        // Objects left by an interrupted extraction are kept when they are
+        // complete, so a retry only extracts the missing or corrupt ones
+        let status = object.status(dest_path).log_error_msg("failed to check extracted object")?;
+        let sha256sum = object.sha256sum();
+        match status {
+            object::info::Status::Ready => {
+                debug!("object {} is already extracted, skipping it", sha256sum);
+                continue;
+            }
+            object::info::Status::Missing => {}
+            status => {
+                info!("discarding {:?} extracted object {}", status, sha256sum);
+                fs::remove_file(dest_path.join(sha256sum))
+                    .log_error_msg("failed to remove partially extracted object")?;
+            }
+        }
+
+        source.seek(SeekFrom::Start(0)).log_error_msg("failed to seek uhupkg to the start")?;
+
+        let mut target = fs::File::create(dest_path.join(sha256sum))
+            .log_error_msg("failed to create output file for object")?;
+        compress_tools::uncompress_archive_file(&mut source, &mut target, sha256sum)
+            .log_error_msg("failed to uncompress object")?;
+    }
+
+    Ok((update_package, sign))
+}
+
+/// Uses a directory holding the package's `metadata`, `signature` and
+/// objects, named after their sha256sum, as they are extracted from an
+/// uhupkg. The objects are linked into the download dir.
+fn link_extracted_package(
+    update_dir: &Path,
+    dest_path: &Path,
+) -> Result<(UpdatePackage, Option<Signature>)> {
+    let update_dir =
+        fs::canonicalize(update_dir).log_error_msg("unable to resolve update directory")?;
+    let metadata = fs::read(update_dir.join("metadata"))
+        .log_error_msg("unable to read metadata from update directory")?;
+    let update_package =
+        UpdatePackage::parse(&metadata).log_error_msg("failed to parse directory metadata")?;
+
+    let sign = match fs::read_to_string(update_dir.join("signature")) {
+        Ok(sign) => Some(
+            Signature::from_base64_str(&sign)
+                .log_error_msg("failed to parse base64 from signature")?,
+        ),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            error!("update directory does not contain a signature file");
+            return Err(super::TransitionError::SignatureNotFound);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let objects = update_package
+        .objects(installation_set::active().log_error_msg("failed to get active installation set")?)
+        .iter()
+        .map(|o| o.sha256sum().to_owned())
+        .collect::<Vec<_>>();
+
+    let missing = objects.iter().filter(|o| !update_dir.join(o).is_file()).collect::<Vec<_>>();
+    if !missing.is_empty() {
+        error!("update directory is missing the objects: {:?}", missing);
+        return Err(super::TransitionError::MissingObjects(
+            missing.into_iter().cloned().collect::<Vec<_>>().join(", "),
+        ));
+    }
+
+    for object in objects.iter() {
+        let target = dest_path.join(object);
+        if target.symlink_metadata().is_ok() {
+            fs::remove_file(&target).log_error_msg("failed to remove previous object")?;
+        }
+        std::os::unix::fs::symlink(update_dir.join(object), &target)
+            .log_error_msg("failed to link object into download dir")?;
+    }
+    debug!("linked objects from update directory");
+
+    Ok((update_package, sign))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::update_package::tests::{get_update_json, OBJECT, SHA256SUM};
+
+    fn update_dir(with_object: bool) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("metadata"), get_update_json(SHA256SUM).to_string()).unwrap();
+        fs::write(dir.path().join("signature"), openssl::base64::encode_block(b"signature"))
+            .unwrap();
+        if with_object {
+            fs::write(dir.path().join(SHA256SUM), OBJECT).unwrap();
+        }
+        dir
+    }
+
+    #[tokio::test]
+    async fn install_from_directory() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let dir = update_dir(true);
+
+        let machine = State::PrepareLocalInstall(PrepareLocalInstall {
+            update_file: dir.path().to_owned(),
+            sha256sum: None,
+            objects: Vec::new(),
+        })
+        .move_to_next_state(&mut context)
+        .await
+        .unwrap()
+        .0;
+
+        assert_state!(machine, Validation);
+        let object = context.settings.update.download_dir.join(SHA256SUM);
+        assert!(object.symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(fs::read(object).unwrap(), OBJECT);
+    }
+
+    #[tokio::test]
+    async fn directory_missing_objects() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let dir = update_dir(false);
+
+        let res = State::PrepareLocalInstall(PrepareLocalInstall {
+            update_file: dir.path().to_owned(),
+            sha256sum: None,
+            objects: Vec::new(),
+        })
+        .move_to_next_state(&mut context)
+        .await;
+
+        assert!(matches!(res, Err(super::super::TransitionError::MissingObjects(_))));
+    }
+
+    #[tokio::test]
+    async fn directory_with_checksum() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let dir = update_dir(true);
+
+        let res = State::PrepareLocalInstall(PrepareLocalInstall {
+            update_file: dir.path().to_owned(),
+            sha256sum: Some(SHA256SUM.to_owned()),
+            objects: Vec::new(),
+        })
+        .move_to_next_state(&mut context)
+        .await;
+
+        assert!(matches!(res, Err(super::super::TransitionError::UnverifiableChecksum)));
+    }
+
+    #[tokio::test]
+    async fn package_checksum() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let dir = tempfile::tempdir().unwrap();
+        let package = dir.path().join("package.uhupkg");
+        fs::write(&package, b"truncated package").unwrap();
+        let found = utils::sha256sum(b"truncated package");
+
+        let res = State::PrepareLocalInstall(PrepareLocalInstall {
+            update_file: package.clone(),
+            sha256sum: Some(SHA256SUM.to_owned()),
+            objects: Vec::new(),
+        })
+        .move_to_next_state(&mut context)
+        .await;
+        match res {
+            Err(super::super::TransitionError::PackageChecksumMismatch {
+                expected,
+                found: actual,
+            }) => {
+                assert_eq!(expected, SHA256SUM);
+                assert_eq!(actual, found);
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        verify_checksum(&package, &found.to_uppercase()).unwrap();
+
+        let state =
+            PrepareLocalInstall { update_file: package, sha256sum: None, objects: Vec::new() };
+        assert_eq!(state.expected_checksum().unwrap(), None);
+        fs::write(
+            dir.path().join("package.uhupkg.sha256"),
+            format!("{}  package.uhupkg\n", SHA256SUM),
+        )
+        .unwrap();
+        assert_eq!(state.expected_checksum().unwrap(), Some(SHA256SUM.to_owned()));
+    }
+
+    #[tokio::test]
+    async fn resume_extraction() {
+        let _setup = crate::tests::TestEnvironment::build().finish();
+        let package = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/test.uhupkg");
+        // The fixture holds a single empty object
+        let sha256sum = utils::sha256sum(b"");
+        let dest_dir = tempfile::tempdir().unwrap();
+        let object = dest_dir.path().join(&sha256sum);
+
+        // A corrupt object is discarded and extracted again
+        fs::write(&object, b"partially extracted").unwrap();
+        extract_package(&package, dest_dir.path()).unwrap();
+        assert_eq!(fs::read(&object).unwrap(), b"");
+
+        // An already extracted object is kept as is
+        let extracted = tempfile::NamedTempFile::new().unwrap();
+        fs::remove_file(&object).unwrap();
+        std::os::unix::fs::symlink(extracted.path(), &object).unwrap();
+        extract_package(&package, dest_dir.path()).unwrap();
+        assert!(object.symlink_metadata().unwrap().file_type().is_symlink());
+    }
+}
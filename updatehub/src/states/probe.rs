@@ -9,7 +9,15 @@ use super::{
 use crate::utils::log::LogContent;
 use chrono::{Duration, Utc};
 use cloud::api::ProbeResponse;
-use slog_scope::{error, info};
+use sdk::api::info::runtime_settings::PendingUpdate;
+use slog_scope::{error, info, warn};
+
+/// Time the connectivity check waits for the connection to be
+/// established.
+const CONNECTIVITY_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Delay before probing again when there is no connectivity.
+const CONNECTIVITY_RETRY_DELAY: i64 = 30;
 
 #[derive(Debug)]
 pub(super) struct Probe;
@@ -44,26 +52,49 @@ impl StateChangeImpl for Probe {
     }
 
     async fn handle(self, context: &mut Context) -> Result<(State, machine::StepTransition)> {
-        let server_address = context.server_address();
+        if context.settings.network.connectivity_check && !has_connectivity(context).await {
+            return Ok((
+                State::Probe(self),
+                machine::StepTransition::Delayed(Duration::seconds(CONNECTIVITY_RETRY_DELAY)),
+            ));
+        }
 
-        let probe = match crate::CloudClient::new(server_address)
-            .probe(context.runtime_settings.retries(), context.firmware.as_cloud_metadata())
-            .await
-        {
+        let probe = match probe_servers(context).await {
             Err(err @ cloud::Error::UrlParse(_)) => {
                 return Err(err.into());
             }
             Err(e) => {
-                error!("Probe failed: {}", e);
-                context.runtime_settings.inc_retries();
-                return Ok((
-                    State::Probe(self),
-                    machine::StepTransition::Delayed(Duration::seconds(1)),
-                ));
+                match e {
+                    cloud::Error::Unauthorized => error!(
+                        "Probe refused as the server doesn't accept the device authorization, \
+                         check the authorization settings"
+                    ),
+                    e => error!("Probe failed: {}", e),
+                }
+                let max_backoff = context.settings.polling.max_backoff;
+                let previous_delay = backoff_delay(context.runtime_settings.retries(), max_backoff);
+                context
+                    .runtime_settings
+                    .inc_retries()
+                    .log_error_msg("unable to update probe retries to runtime settings")?
+                    .log_if_not_persisted("probe retries");
+                let delay = backoff_delay(context.runtime_settings.retries(), max_backoff);
+                if delay > previous_delay {
+                    info!(
+                        "backing off probe for {} seconds after {} consecutive failures",
+                        delay.num_seconds(),
+                        context.runtime_settings.retries()
+                    );
+                }
+                return Ok((State::Probe(self), machine::StepTransition::Delayed(delay)));
             }
             Ok(probe) => probe,
         };
-        context.runtime_settings.clear_retries();
+        context
+            .runtime_settings
+            .clear_retries()
+            .log_error_msg("unable to clear probe retries on runtime settings")?;
+        record_probe(context, &probe)?;
 
         match probe {
             ProbeResponse::NoUpdate => {
@@ -79,7 +110,9 @@ impl StateChangeImpl for Probe {
 
             ProbeResponse::ExtraPoll(s) => {
                 info!("delaying the probing for {} seconds as requested by the server", s);
-                Ok((State::Probe(self), machine::StepTransition::Delayed(Duration::seconds(s))))
+                let delay = Duration::seconds(s)
+                    + super::poll::random_jitter(context.settings.polling.jitter);
+                Ok((State::Probe(self), machine::StepTransition::Delayed(delay)))
             }
 
             ProbeResponse::Update(package, sign) => {
@@ -94,6 +127,12 @@ impl StateChangeImpl for Probe {
                 crate::logger::start_memory_logging();
 
                 info!("update received: {} ({})", package.version(), package.package_uid());
+                context.notify_webhooks(
+                    machine::WebhookEvent::UpdateAvailable,
+                    Some(package.package_uid()),
+                    Some(package.version().to_owned()),
+                    None,
+                );
                 Ok((
                     State::Validation(Validation { package, sign, require_download: true }),
                     machine::StepTransition::Immediate,
@@ -103,6 +142,135 @@ impl StateChangeImpl for Probe {
     }
 }
 
+/// Checks if the connectivity check target, or the server host, accepts
+/// a TCP connection.
+async fn has_connectivity(context: &Context) -> bool {
+    let target = match &context.settings.network.connectivity_check_target {
+        Some(target) => target.clone(),
+        None => match url::Url::parse(context.server_address())
+            .ok()
+            .and_then(|url| Some(format!("{}:{}", url.host_str()?, url.port_or_known_default()?)))
+        {
+            Some(target) => target,
+            None => {
+                warn!(
+                    "unable to get the host of {} to check connectivity",
+                    context.server_address()
+                );
+                return true;
+            }
+        },
+    };
+
+    match tokio::time::timeout(CONNECTIVITY_CHECK_TIMEOUT, tokio::net::TcpStream::connect(&target))
+        .await
+    {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+            info!("no connectivity to {}, deferring probe: {}", target, e);
+            false
+        }
+        Err(_) => {
+            info!("no connectivity to {}, deferring probe: connection timed out", target);
+            false
+        }
+    }
+}
+
+/// Probes each server in order until one of them responds. The configured
+/// server which responded is remembered so it's probed first next time.
+pub(super) async fn probe_servers(
+    context: &mut Context,
+) -> std::result::Result<ProbeResponse, cloud::Error> {
+    let headers = context.request_headers()?;
+    let mut last_err = None;
+    for server_address in context.probe_server_addresses() {
+        match crate::CloudClient::new(
+            &server_address,
+            context.client_identity.as_ref(),
+            context.proxy.as_ref(),
+        )
+        .with_headers(&headers)
+        .with_host_aliases(&context.host_aliases())
+        .with_probe_timeout(context.settings.probe_timeout())
+        .probe(context.runtime_settings.retries(), context.firmware.as_cloud_metadata())
+        .await
+        {
+            Ok(probe) => {
+                info!("probe served by {}", server_address);
+                if context.runtime_settings.custom_server_address().is_none() {
+                    if let Ok(saved) = context
+                        .runtime_settings
+                        .set_working_server_address(&server_address)
+                        .log_error_msg("unable to store working server address")
+                    {
+                        saved.log_if_not_persisted("working server address");
+                    }
+                }
+                return Ok(probe);
+            }
+            Err(err @ cloud::Error::UrlParse(_)) => return Err(err),
+            Err(e) => {
+                warn!("probe to {} failed: {}", server_address, e);
+                last_err = Some(e);
+            }
+        }
+    }
+
+    Err(last_err.expect("at least one server is always probed"))
+}
+
+/// Stores the summary of the probe response on the runtime settings,
+/// along with the update package received, so it can be resumed across
+/// restarts.
+pub(super) fn record_probe(context: &mut Context, probe: &ProbeResponse) -> Result<()> {
+    let (update_available, extra_poll) = match probe {
+        ProbeResponse::NoUpdate => (false, None),
+        ProbeResponse::ExtraPoll(s) => (false, Some(*s)),
+        ProbeResponse::Update(..) => (true, None),
+    };
+    context
+        .runtime_settings
+        .set_last_probe(update_available, extra_poll)
+        .log_error_msg("unable to update last probe to runtime settings")?
+        .log_if_not_persisted("last probe");
+
+    match probe {
+        ProbeResponse::Update(package, sign) => {
+            context
+                .runtime_settings
+                .set_pending_update(PendingUpdate {
+                    package: String::from_utf8_lossy(&package.raw).into_owned(),
+                    signature: sign.as_ref().map(cloud::api::Signature::to_base64_string),
+                })
+                .log_error_msg("unable to store pending update to runtime settings")?
+                .log_if_not_persisted("pending update");
+        }
+        // The server no longer offers the package, so it must not be
+        // resumed
+        ProbeResponse::NoUpdate => {
+            context
+                .runtime_settings
+                .clear_pending_update()
+                .log_error_msg("unable to clear pending update from runtime settings")?
+                .log_if_not_persisted("cleared pending update");
+        }
+        ProbeResponse::ExtraPoll(_) => {}
+    }
+
+    Ok(())
+}
+
+/// Delay before probing again after a number of consecutive failed
+/// probes, starting from 1 second and doubling on each failure up to `max`.
+fn backoff_delay(failures: usize, max: Duration) -> Duration {
+    if failures == 0 {
+        return Duration::zero();
+    }
+
+    Duration::seconds(1 << (failures - 1).min(30)).min(max)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -129,9 +297,15 @@ mod tests {
         let mut context = setup.gen_context();
         cloud_mock::setup_fake_response(cloud_mock::FakeResponse::NoUpdate);
 
+        context
+            .runtime_settings
+            .set_pending_update(PendingUpdate { package: "{}".to_owned(), signature: None })
+            .unwrap();
+
         let machine = State::Probe(Probe {}).move_to_next_state(&mut context).await.unwrap().0;
 
         assert_state!(machine, EntryPoint);
+        assert_eq!(context.runtime_settings.pending_update(), None);
     }
 
     #[tokio::test]
@@ -143,6 +317,47 @@ mod tests {
         let machine = State::Probe(Probe {}).move_to_next_state(&mut context).await.unwrap().0;
 
         assert_state!(machine, Validation);
+        let pending = context.runtime_settings.pending_update().unwrap();
+        assert_eq!(
+            cloud::api::UpdatePackage::parse(pending.package.as_bytes()).unwrap().package_uid(),
+            crate::update_package::tests::get_update_package().package_uid()
+        );
+    }
+
+    #[test]
+    fn backoff() {
+        let max = Duration::minutes(1);
+
+        assert_eq!(backoff_delay(0, max), Duration::zero());
+        assert_eq!(backoff_delay(1, max), Duration::seconds(1));
+        assert_eq!(backoff_delay(2, max), Duration::seconds(2));
+        assert_eq!(backoff_delay(6, max), Duration::seconds(32));
+        assert_eq!(backoff_delay(7, max), max);
+        assert_eq!(backoff_delay(usize::MAX, max), max);
+    }
+
+    #[tokio::test]
+    async fn server_error_backs_off() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        cloud_mock::setup_fake_response(cloud_mock::FakeResponse::ServerError);
+
+        let mut delays = Vec::new();
+        for _ in 0..3 {
+            let (machine, trans) =
+                State::Probe(Probe {}).move_to_next_state(&mut context).await.unwrap();
+            assert_state!(machine, Probe);
+            match trans {
+                machine::StepTransition::Delayed(d) => delays.push(d),
+                _ => panic!("Unexpected StepTransition: {:?}", trans),
+            }
+        }
+        assert_eq!(delays, [Duration::seconds(1), Duration::seconds(2), Duration::seconds(4)]);
+        assert_eq!(context.runtime_settings.retries(), 3);
+
+        cloud_mock::setup_fake_response(cloud_mock::FakeResponse::NoUpdate);
+        State::Probe(Probe {}).move_to_next_state(&mut context).await.unwrap();
+        assert_eq!(context.runtime_settings.retries(), 0);
     }
 
     #[tokio::test]
@@ -154,5 +369,50 @@ mod tests {
         let machine = State::Probe(Probe {}).move_to_next_state(&mut context).await.unwrap().0;
 
         assert_state!(machine, Probe);
+        let last_probe = context.runtime_settings.last_probe().unwrap();
+        assert!(!last_probe.update_available);
+        assert_eq!(last_probe.extra_poll, Some(10));
+    }
+
+    #[tokio::test]
+    async fn defer_without_connectivity() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        cloud_mock::setup_fake_response(cloud_mock::FakeResponse::NoUpdate);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        context.settings.network.connectivity_check = true;
+        context.settings.network.connectivity_check_target = Some(target);
+
+        let machine = State::Probe(Probe {}).move_to_next_state(&mut context).await.unwrap().0;
+        assert_state!(machine, EntryPoint);
+
+        drop(listener);
+        let (machine, transition) =
+            State::Probe(Probe {}).move_to_next_state(&mut context).await.unwrap();
+        assert_state!(machine, Probe);
+        match transition {
+            machine::StepTransition::Delayed(d) => {
+                assert_eq!(d, Duration::seconds(CONNECTIVITY_RETRY_DELAY))
+            }
+            t => panic!("Unexpected transition: {:?}", t),
+        }
+        assert_eq!(context.runtime_settings.retries(), 0);
+    }
+
+    #[tokio::test]
+    async fn stuck_callback_times_out() {
+        let setup = crate::tests::TestEnvironment::build()
+            .state_change_callback("#!/bin/sh\nsleep 30".to_owned())
+            .finish();
+        let mut context = setup.gen_context();
+        context.settings.update.state_timeouts.insert("probe".into(), chrono::Duration::seconds(1));
+
+        let start = std::time::Instant::now();
+        match State::Probe(Probe {}).handle_with_timeout(&mut context).await {
+            Err(super::super::TransitionError::StateTimeout { state: "probe", timeout: 1 }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert!(start.elapsed() < std::time::Duration::from_secs(10), "{:?}", start.elapsed());
     }
 }
@@ -11,14 +11,18 @@ use slog_scope::{info, warn};
 
 #[derive(Debug)]
 pub(super) struct Reboot {
-    pub(super) update_package: UpdatePackage,
+    /// Package which has been installed, if any. A reboot requested
+    /// without installing a package, as for a rollback, is not reported.
+    pub(super) update_package: Option<UpdatePackage>,
+    /// Whether the configured reboot delay has already been waited.
+    pub(super) delay_elapsed: bool,
 }
 
 impl CallbackReporter for Reboot {}
 
 impl ProgressReporter for Reboot {
     fn package_uid(&self) -> String {
-        self.update_package.package_uid()
+        self.update_package.as_ref().map(UpdatePackage::package_uid).unwrap_or_default()
     }
 
     fn report_enter_state_name(&self) -> &'static str {
@@ -28,6 +32,12 @@ impl ProgressReporter for Reboot {
     fn report_leave_state_name(&self) -> &'static str {
         "rebooting"
     }
+
+    // The device is restarted while handling the state, so the queued
+    // reports must be sent beforehand.
+    fn flush_reports_on_enter(&self) -> bool {
+        true
+    }
 }
 
 #[async_trait::async_trait(?Send)]
@@ -36,9 +46,26 @@ impl StateChangeImpl for Reboot {
         "reboot"
     }
 
-    async fn handle(self, _: &mut Context) -> Result<(State, machine::StepTransition)> {
-        info!("triggering reboot");
-        let output = easy_process::run("reboot").log_error_msg("failed to run reboot command")?;
+    async fn handle(self, context: &mut Context) -> Result<(State, machine::StepTransition)> {
+        if context.settings.storage.dry_run {
+            info!("dry-run: skipping reboot");
+            return Ok((State::EntryPoint(EntryPoint {}), machine::StepTransition::Immediate));
+        }
+
+        let delay = context.settings.firmware.reboot_delay;
+        if !self.delay_elapsed && !delay.is_zero() {
+            info!("delaying reboot for {} seconds", delay.num_seconds());
+            return Ok((
+                State::Reboot(Reboot { update_package: self.update_package, delay_elapsed: true }),
+                machine::StepTransition::Delayed(delay),
+            ));
+        }
+
+        // The device goes down along with any notification in flight
+        context.flush_webhooks().await;
+        let command = &context.settings.firmware.reboot_command;
+        info!("triggering reboot: {}", command);
+        let output = easy_process::run(command).log_error_msg("failed to run reboot command")?;
         if !output.stdout.is_empty() || !output.stderr.is_empty() {
             warn!("  reboot output: stdout: {}, stderr: {}", output.stdout, output.stderr);
         }
@@ -56,16 +83,79 @@ mod test {
     async fn runs() {
         let setup = crate::tests::TestEnvironment::build().add_echo_binary("reboot").finish();
         let mut context = setup.gen_context();
-        let state = Reboot { update_package: get_update_package() };
+        let state = Reboot { update_package: Some(get_update_package()), delay_elapsed: false };
+
+        let machine = State::Reboot(state).move_to_next_state(&mut context).await.unwrap().0;
+
+        assert_state!(machine, EntryPoint);
+    }
+
+    #[tokio::test]
+    async fn runs_without_package() {
+        let setup = crate::tests::TestEnvironment::build().add_echo_binary("reboot").finish();
+        let mut context = setup.gen_context();
+        let state = Reboot { update_package: None, delay_elapsed: false };
+
+        let machine = State::Reboot(state).move_to_next_state(&mut context).await.unwrap().0;
+
+        assert_state!(machine, EntryPoint);
+    }
+
+    #[tokio::test]
+    async fn skipped_on_dry_run() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context.settings.storage.dry_run = true;
+        let state = Reboot { update_package: Some(get_update_package()), delay_elapsed: false };
 
         let machine = State::Reboot(state).move_to_next_state(&mut context).await.unwrap().0;
 
         assert_state!(machine, EntryPoint);
     }
 
+    #[tokio::test]
+    async fn runs_configured_command() {
+        let setup =
+            crate::tests::TestEnvironment::build().add_echo_binary("custom-reboot").finish();
+        let mut context = setup.gen_context();
+        context.settings.firmware.reboot_command = "custom-reboot --now".to_owned();
+        let state = Reboot { update_package: None, delay_elapsed: false };
+
+        let machine = State::Reboot(state).move_to_next_state(&mut context).await.unwrap().0;
+
+        assert_state!(machine, EntryPoint);
+    }
+
+    #[tokio::test]
+    async fn failing_command() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context.settings.firmware.reboot_command = "/nonexistent/reboot".to_owned();
+        let state = Reboot { update_package: None, delay_elapsed: false };
+
+        assert!(State::Reboot(state).move_to_next_state(&mut context).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn delayed() {
+        let setup = crate::tests::TestEnvironment::build().add_echo_binary("reboot").finish();
+        let mut context = setup.gen_context();
+        context.settings.firmware.reboot_delay = chrono::Duration::seconds(5);
+        let state = Reboot { update_package: Some(get_update_package()), delay_elapsed: false };
+
+        let (machine, trans) = State::Reboot(state).move_to_next_state(&mut context).await.unwrap();
+        assert_state!(machine, Reboot);
+        assert!(
+            matches!(trans, machine::StepTransition::Delayed(d) if d == chrono::Duration::seconds(5))
+        );
+
+        let machine = machine.move_to_next_state(&mut context).await.unwrap().0;
+        assert_state!(machine, EntryPoint);
+    }
+
     #[test]
     fn reboot_has_transition_callback_trait() {
-        let state = Reboot { update_package: get_update_package() };
+        let state = Reboot { update_package: Some(get_update_package()), delay_elapsed: false };
         assert_eq!(state.name(), "reboot");
     }
 }
@@ -4,14 +4,14 @@
 
 use super::*;
 use crate::firmware::installation_set::Set;
-use sdk::api::info::runtime_settings::InstallationSet;
+use sdk::api::info::runtime_settings::{InstallationSet, PendingUpdate};
 use std::{fs, io};
 
-#[test]
-fn startup_without_upgrade() {
+#[tokio::test]
+async fn startup_without_upgrade() {
     let mut setup = crate::tests::TestEnvironment::build().finish();
 
-    handle_startup_callbacks(&setup.settings.data, &mut setup.runtime_settings.data).unwrap();
+    handle_startup_callbacks(&setup.settings.data, &mut setup.runtime_settings.data).await.unwrap();
 
     match fs::read_to_string(&setup.binaries.data) {
         Err(e) if e.kind() == io::ErrorKind::NotFound => (),
@@ -20,13 +20,13 @@ fn startup_without_upgrade() {
     }
 }
 
-#[test]
-fn startup_with_normal_upgrade() {
+#[tokio::test]
+async fn startup_with_normal_upgrade() {
     let mut setup = crate::tests::TestEnvironment::build().finish();
     let output_file_path = &setup.binaries.data;
     setup.runtime_settings.data.set_upgrading_to(Set(InstallationSet::A)).unwrap();
 
-    handle_startup_callbacks(&setup.settings.data, &mut setup.runtime_settings.data).unwrap();
+    handle_startup_callbacks(&setup.settings.data, &mut setup.runtime_settings.data).await.unwrap();
 
     assert!(
         fs::read_to_string(output_file_path).unwrap().contains("validate-callback"),
@@ -38,8 +38,8 @@ fn startup_with_normal_upgrade() {
     );
 }
 
-#[test]
-fn startup_on_faulty_upgrade() {
+#[tokio::test]
+async fn startup_on_faulty_upgrade() {
     let mut setup = crate::tests::TestEnvironment::build().add_echo_binary("reboot").finish();
     let output_file_path = &setup.binaries.data;
     // Setup validation callback to always fail
@@ -50,7 +50,7 @@ fn startup_on_faulty_upgrade() {
     .unwrap();
     setup.runtime_settings.data.set_upgrading_to(Set(InstallationSet::A)).unwrap();
 
-    handle_startup_callbacks(&setup.settings.data, &mut setup.runtime_settings.data).unwrap();
+    handle_startup_callbacks(&setup.settings.data, &mut setup.runtime_settings.data).await.unwrap();
 
     assert!(
         fs::read_to_string(output_file_path).unwrap().contains("rollback-callback"),
@@ -66,12 +66,68 @@ fn startup_on_faulty_upgrade() {
     );
 }
 
-#[test]
-fn startup_on_wrong_install_set() {
+#[tokio::test]
+async fn startup_on_failing_health_check() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut setup = crate::tests::TestEnvironment::build().add_echo_binary("reboot").finish();
+    let output_file_path = &setup.binaries.data;
+    let health_check = setup.firmware.stored_path.join("health-check");
+    fs::write(
+        &health_check,
+        format!("#!/bin/sh\necho $0 >> {}\nexit 1", output_file_path.to_string_lossy()),
+    )
+    .unwrap();
+    fs::set_permissions(&health_check, fs::Permissions::from_mode(0o755)).unwrap();
+    setup.settings.data.firmware.health_check = Some(health_check.to_string_lossy().to_string());
+    setup.settings.data.firmware.health_check_attempts = 2;
+    setup.settings.data.firmware.health_check_interval = chrono::Duration::zero();
+    setup.runtime_settings.data.set_upgrading_to(Set(InstallationSet::A)).unwrap();
+
+    handle_startup_callbacks(&setup.settings.data, &mut setup.runtime_settings.data).await.unwrap();
+
+    let output = fs::read_to_string(output_file_path).unwrap();
+    assert_eq!(output.matches("health-check").count(), 2, "Health check was not retried");
+    assert!(output.contains("rollback-callback"), "Rollback callback was not called");
+    assert!(output.contains("reboot"), "Reboot was not called");
+}
+
+#[tokio::test]
+async fn startup_on_exhausted_rollbacks() {
+    let mut setup = crate::tests::TestEnvironment::build().add_echo_binary("reboot").finish();
+    let output_file_path = &setup.binaries.data;
+    // Setup validation callback to always fail
+    fs::write(
+        setup.firmware.stored_path.join("validate-callback"),
+        format!("#!/bin/sh\necho $0 >> {}\nexit 1", output_file_path.to_string_lossy()),
+    )
+    .unwrap();
+    setup.settings.data.firmware.rollback_attempts = 1;
+    setup.runtime_settings.data.set_upgrading_to(Set(InstallationSet::A)).unwrap();
+
+    handle_startup_callbacks(&setup.settings.data, &mut setup.runtime_settings.data).await.unwrap();
+    assert!(fs::read_to_string(output_file_path).unwrap().contains("reboot"));
+    assert!(!setup.runtime_settings.data.is_rollback_exhausted());
+
+    // The other installation set has failed booting, so the failing one
+    // is booted again
+    fs::remove_file(output_file_path).unwrap();
+    handle_startup_callbacks(&setup.settings.data, &mut setup.runtime_settings.data).await.unwrap();
+
+    let output = fs::read_to_string(output_file_path).unwrap();
+    assert!(output.contains("validate-callback"), "Validate callback was not called");
+    assert!(!output.contains("rollback-callback"), "Rollback callback should not be called");
+    assert!(!output.contains("reboot"), "Reboot should not be called");
+    assert!(setup.runtime_settings.data.is_rollback_exhausted());
+    assert_eq!(setup.runtime_settings.data.rollback_attempts(), 2);
+}
+
+#[tokio::test]
+async fn startup_on_wrong_install_set() {
     let mut setup = crate::tests::TestEnvironment::build().finish();
     setup.runtime_settings.data.set_upgrading_to(Set(InstallationSet::B)).unwrap();
 
-    handle_startup_callbacks(&setup.settings.data, &mut setup.runtime_settings.data).unwrap();
+    handle_startup_callbacks(&setup.settings.data, &mut setup.runtime_settings.data).await.unwrap();
 
     match fs::read_to_string(&setup.binaries.data) {
         Err(e) if e.kind() == io::ErrorKind::NotFound => (),
@@ -80,9 +136,9 @@ fn startup_on_wrong_install_set() {
     }
 }
 
-#[test]
+#[tokio::test]
 #[cfg(feature = "v1-parsing")]
-fn validate_v1_restored_runtime_settings() {
+async fn validate_v1_restored_runtime_settings() {
     let setup = crate::tests::TestEnvironment::build().add_echo_binary("reboot").finish();
     let output_file_path = &setup.binaries.data;
     // Setup validation callback to always fail
@@ -110,7 +166,7 @@ UpgradeToInstallation=0
     // Remove the file to make sure the function will recreate it
     std::fs::remove_file(&setup.runtime_settings.stored_path).unwrap();
 
-    handle_startup_callbacks(&setup.settings.data, &mut loaded_runtime_settings).unwrap();
+    handle_startup_callbacks(&setup.settings.data, &mut loaded_runtime_settings).await.unwrap();
 
     assert_eq!(
         std::fs::read_to_string(&setup.runtime_settings.stored_path).unwrap(),
@@ -118,3 +174,92 @@ UpgradeToInstallation=0
         "Reverted runtime settings did not match original v1 file"
     );
 }
+
+#[test]
+fn initial_state_resumes_pending_update() {
+    let mut setup = crate::tests::TestEnvironment::build().finish();
+    let state = State::initial(&setup.settings.data, &setup.runtime_settings.data);
+    assert_state!(state, EntryPoint);
+
+    let package = crate::update_package::tests::get_update_package();
+    setup
+        .runtime_settings
+        .data
+        .set_pending_update(PendingUpdate {
+            package: String::from_utf8(package.raw.clone()).unwrap(),
+            signature: None,
+        })
+        .unwrap();
+    match State::initial(&setup.settings.data, &setup.runtime_settings.data) {
+        State::Validation(validation) => {
+            assert_eq!(validation.package.package_uid(), package.package_uid());
+            assert!(validation.require_download);
+        }
+        state => panic!("Unexpected state: {:?}", state),
+    }
+
+    setup.settings.data.update.revalidate_on_resume = true;
+    let state = State::initial(&setup.settings.data, &setup.runtime_settings.data);
+    assert_state!(state, Probe);
+}
+
+#[test]
+fn initial_state_probes_on_startup() {
+    let mut setup = crate::tests::TestEnvironment::build().finish();
+    setup.settings.data.polling.probe_on_startup = true;
+    let state = State::initial(&setup.settings.data, &setup.runtime_settings.data);
+    assert_state!(state, Probe);
+
+    // Startup probes are only done when polling is enabled
+    setup.settings.data.polling.enabled = false;
+    let state = State::initial(&setup.settings.data, &setup.runtime_settings.data);
+    assert_state!(state, EntryPoint);
+}
+
+#[test]
+fn initial_state_discards_invalid_pending_update() {
+    let mut setup = crate::tests::TestEnvironment::build().finish();
+    setup
+        .runtime_settings
+        .data
+        .set_pending_update(PendingUpdate { package: "invalid".to_owned(), signature: None })
+        .unwrap();
+
+    let state = State::initial(&setup.settings.data, &setup.runtime_settings.data);
+    assert_state!(state, EntryPoint);
+}
+
+#[test]
+fn error_codes() {
+    assert_eq!(
+        TransitionError::NotEnoughDownloadSpace { available: 0, required: 1 }.code(),
+        "no_space"
+    );
+    assert_eq!(
+        TransitionError::Installation(crate::object::Error::Utils(
+            crate::utils::Error::NotEnoughSpace { available: 0, required: 1 }
+        ))
+        .code(),
+        "no_space"
+    );
+    assert_eq!(
+        TransitionError::Io(io::Error::from_raw_os_error(nix::libc::ENOSPC)).code(),
+        "no_space"
+    );
+    assert_eq!(
+        TransitionError::PackageChecksumMismatch { expected: "a".into(), found: "b".into() }.code(),
+        "checksum_mismatch"
+    );
+    assert_eq!(
+        TransitionError::Client(cloud::Error::Io(io::ErrorKind::TimedOut.into())).code(),
+        "network_timeout"
+    );
+    assert_eq!(
+        TransitionError::Firmware(crate::firmware::Error::Process(
+            easy_process::run("false").unwrap_err()
+        ))
+        .code(),
+        "callback_failed"
+    );
+    assert_eq!(TransitionError::Io(io::ErrorKind::NotFound.into()).code(), "io_error");
+}
@@ -5,14 +5,16 @@
 use super::{
     install::Install,
     machine::{self, Context},
-    Download, EntryPoint, Result, State, StateChangeImpl, TransitionError,
+    AwaitingApproval, Download, EntryPoint, Result, State, StateChangeImpl, TransitionError,
 };
 use crate::{
     object::{self, Info, Installer},
     update_package::UpdatePackageExt,
     utils::log::LogContent,
 };
-use slog_scope::{debug, error, info};
+use slog_scope::{debug, error, info, warn};
+
+static MISSING_KEY_WARNING: std::sync::Once = std::sync::Once::new();
 
 #[derive(Debug)]
 pub(super) struct Validation {
@@ -32,13 +34,34 @@ impl StateChangeImpl for Validation {
         true
     }
 
-    async fn handle(self, context: &mut Context) -> Result<(State, machine::StepTransition)> {
+    async fn handle(mut self, context: &mut Context) -> Result<(State, machine::StepTransition)> {
         if let Some(key) = context.firmware.pub_key.as_ref() {
             match self.sign.as_ref() {
                 Some(sign) => {
                     debug!("validating signature");
-                    sign.validate(key, &self.package)
-                        .log_error_msg("uhupkg failed signature validation")?;
+                    let keys = crate::firmware::public_keys(key)
+                        .log_error_msg("failed to find public keys")?;
+                    let mut verified_by = None;
+                    for key in &keys {
+                        match sign.validate(key, &self.package) {
+                            Ok(()) => {
+                                verified_by = Some(key);
+                                break;
+                            }
+                            Err(e) => debug!("signature not verified by {:?}: {}", key, e),
+                        }
+                    }
+                    match verified_by {
+                        Some(key) => info!("signature verified by public key {:?}", key),
+                        None => {
+                            error!(
+                                "uhupkg failed signature validation, it doesn't verify against \
+                                 any of the {} configured public keys",
+                                keys.len()
+                            );
+                            return Err(cloud::Error::InvalidSignature.into());
+                        }
+                    }
                 }
                 None => {
                     error!("missing signature key");
@@ -46,7 +69,46 @@ impl StateChangeImpl for Validation {
                 }
             }
         } else {
-            info!("no signature key available on device, ignoring signature validation");
+            MISSING_KEY_WARNING.call_once(|| {
+                warn!("no signature key available on device, ignoring signature validation")
+            });
+        }
+
+        let inactive_installation_set = context
+            .runtime_settings
+            .get_inactive_installation_set(context.settings.target_installation_set())
+            .log_error_msg("unable to get inactive installation set")?;
+        let already_applied = context
+            .runtime_settings
+            .applied_package_uid()
+            .map(|u| *u == self.package.package_uid())
+            .unwrap_or_default();
+        if let Err(e) = self.package.validate_metadata(inactive_installation_set).and_then(|_| {
+            // The package already applied is skipped below, even though it
+            // is the running version
+            if already_applied {
+                return Ok(());
+            }
+            self.package.check_version(&context.firmware, &context.settings)
+        }) {
+            // Rejected packages are reported right away so the problems
+            // are visible on the server
+            let e = TransitionError::from(e);
+            context
+                .report(
+                    cloud::api::Report {
+                        state: "error".to_owned(),
+                        package_uid: self.package.package_uid(),
+                        previous_state: None,
+                        error_code: Some(e.code().to_owned()),
+                        error_message: Some(e.to_string()),
+                        current_log: Some(crate::logger::get_memory_log()),
+                        state_timings: context.state_timings(),
+                    },
+                    true,
+                )
+                .await;
+            return Err(e);
         }
 
         let object_context = object::installer::Context {
@@ -58,19 +120,82 @@ impl StateChangeImpl for Validation {
                 product_uid = &context.firmware.product_uid,
                 package_uid = &self.package.package_uid(),
             ),
+            download_client: object::installer::DownloadClient {
+                server: context.server_address().to_owned(),
+                identity: context.client_identity.clone(),
+                proxy: context.proxy.clone(),
+                // Offline updates never reach the server, so they don't
+                // need its credentials
+                headers: if self.require_download {
+                    context.request_headers()?
+                } else {
+                    cloud::Headers::default()
+                },
+                host_aliases: context.host_aliases(),
+                rate_limiter: context
+                    .settings
+                    .download_rate_limit_at(chrono::Local::now().time())
+                    .and_then(std::num::NonZeroU64::new)
+                    .map(cloud::RateLimiter::new),
+                timeout: context.settings.download_timeout(),
+                retries: context.settings.network.download_retries,
+                retry_backoff: context
+                    .settings
+                    .network
+                    .download_retry_backoff
+                    .to_std()
+                    .unwrap_or_default(),
+                strict_content_length: context.settings.strict_content_length(),
+            },
+            write_chunk_size: context.settings.storage.write_chunk_size,
+            stream_install: context.settings.storage.stream_install,
+            decompress_threads: context.settings.storage.decompress_threads,
+            decompress_memory_limit: context.settings.storage.decompress_memory_limit,
         };
 
+        // Skipped objects are dropped from the package, so they are neither
+        // downloaded nor installed
+        self.package
+            .select_objects(inactive_installation_set)
+            .log_error_msg("failed to select the objects to install")?;
+
         // Ensure the package is compatible
-        let inactive_installation_set = context
-            .runtime_settings
-            .get_inactive_installation_set()
-            .log_error_msg("unable to get inactive installation set")?;
         self.package
             .compatible_with(&context.firmware)
+            .map_err(|e| {
+                context.rejected_packages += 1;
+                e
+            })
             .log_error_msg("uhupkg is not compatible with this device")?;
         self.package
             .validate_install_modes(&context.settings, inactive_installation_set)
             .log_error_msg("install mode failed validation")?;
+        if let (true, Some(max)) =
+            (self.require_download, context.settings.network.max_package_size)
+        {
+            let size =
+                self.package.objects(inactive_installation_set).iter().map(|o| o.len()).sum();
+            if size > max {
+                error!("package size of {} bytes exceeds the maximum of {} bytes", size, max);
+                return Err(TransitionError::PackageTooLarge { size, max });
+            }
+        }
+        let set_devices = &context.settings.firmware.installation_set_devices;
+        object::resolve_set_targets(
+            self.package.objects_mut(inactive_installation_set),
+            set_devices,
+            inactive_installation_set,
+        )
+        .log_error_msg("failed to resolve installation set targets")?;
+        object::check_active_set_targets(
+            self.package.objects(inactive_installation_set),
+            set_devices,
+            inactive_installation_set.complement(),
+        )
+        .log_error_msg("update package targets the active installation set")?;
+        // Validate the targets before any download so we fail early
+        object::validate_targets(self.package.objects(inactive_installation_set))
+            .log_error_msg("update package has invalid targets")?;
         for obj in self.package.objects(inactive_installation_set).iter() {
             if let Err(e) = obj.check_requirements(&object_context).await {
                 error!(
@@ -85,12 +210,7 @@ impl StateChangeImpl for Validation {
         let update_package = self.package.clone();
         let sign = self.sign.clone();
 
-        if context
-            .runtime_settings
-            .applied_package_uid()
-            .map(|u| *u == update_package.package_uid())
-            .unwrap_or_default()
-        {
+        if already_applied {
             info!("not downloading update package, the same package has already been installed");
             Ok((State::EntryPoint(EntryPoint {}), machine::StepTransition::Immediate))
         } else {
@@ -103,13 +223,16 @@ impl StateChangeImpl for Validation {
                     .objects(inactive_installation_set)
                     .iter()
                     .filter(|o| !o.allow_remote_install())
+                    .filter(|o| !(object_context.stream_install && object::is_streamable(o)))
                     .filter_map(|o| match (o.filename(), o.status(download_dir)) {
                         (_, Ok(object::info::Status::Ready)) => None,
                         (filename, status) => Some((filename, status)),
                     })
                     .collect();
 
-                if not_ready.is_empty() {
+                if not_ready.is_empty() && context.settings.update.require_install_confirmation {
+                    State::AwaitingApproval(AwaitingApproval::new(update_package, object_context))
+                } else if not_ready.is_empty() {
                     State::Install(Install { update_package, object_context })
                 } else {
                     error!("some objects are not ready for use:");
@@ -164,9 +287,13 @@ mod tests {
             .await;
 
         match machine {
-            Err(TransitionError::UpdatePackage(_)) => {}
+            Err(TransitionError::UpdatePackage(e)) => assert_eq!(
+                e.to_string(),
+                "Incompatible hardware: device hardware is 'invalid', package supports: board"
+            ),
             res => panic!("Unexpected result from transition: {:?}", res),
         }
+        assert_eq!(context.rejected_packages, 1);
     }
 
     #[tokio::test]
@@ -204,4 +331,249 @@ mod tests {
             Ok(_) => panic!("Unexpected ok result returned"),
         }
     }
+
+    fn sign_package(package: &cloud::api::UpdatePackage) -> (tempfile::NamedTempFile, Vec<u8>) {
+        use openssl::{pkey::PKey, sign::Signer};
+        use std::io::Write;
+
+        let key = PKey::generate_ed25519().unwrap();
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        key_file.write_all(&key.public_key_to_pem().unwrap()).unwrap();
+        let signature =
+            Signer::new_without_digest(&key).unwrap().sign_oneshot_to_vec(&package.raw).unwrap();
+
+        (key_file, signature)
+    }
+
+    #[tokio::test]
+    async fn valid_ed25519_signature() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let package = get_update_package();
+        let (key_file, signature) = sign_package(&package);
+        context.firmware.load_public_key(key_file.path()).unwrap();
+        let sign = Some(
+            cloud::api::Signature::from_base64_str(&openssl::base64::encode_block(&signature))
+                .unwrap(),
+        );
+
+        let machine = State::Validation(Validation { package, sign, require_download: true })
+            .move_to_next_state(&mut context)
+            .await
+            .unwrap()
+            .0;
+        assert_state!(machine, Download);
+    }
+
+    #[tokio::test]
+    async fn invalid_ed25519_signature() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let package = get_update_package();
+        let (key_file, mut signature) = sign_package(&package);
+        context.firmware.load_public_key(key_file.path()).unwrap();
+        signature[0] ^= 0xFF;
+        let sign = Some(
+            cloud::api::Signature::from_base64_str(&openssl::base64::encode_block(&signature))
+                .unwrap(),
+        );
+
+        let res = State::Validation(Validation { package, sign, require_download: true })
+            .move_to_next_state(&mut context)
+            .await;
+        match res {
+            Err(TransitionError::Client(cloud::Error::InvalidSignature)) => {}
+            Err(e) => panic!("Unexpected error returned: {}", e),
+            Ok(_) => panic!("Unexpected ok result returned"),
+        }
+    }
+
+    #[tokio::test]
+    async fn rotated_public_keys() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let package = get_update_package();
+        let (old_key, _) = sign_package(&package);
+        let (new_key, signature) = sign_package(&package);
+        let keys_dir = tempfile::tempdir().unwrap();
+        std::fs::copy(old_key.path(), keys_dir.path().join("old.pem")).unwrap();
+        std::fs::copy(new_key.path(), keys_dir.path().join("new.pem")).unwrap();
+        context.firmware.load_public_key(keys_dir.path()).unwrap();
+        let sign = || {
+            Some(
+                cloud::api::Signature::from_base64_str(&openssl::base64::encode_block(&signature))
+                    .unwrap(),
+            )
+        };
+
+        let machine =
+            State::Validation(Validation { package, sign: sign(), require_download: true })
+                .move_to_next_state(&mut context)
+                .await
+                .unwrap()
+                .0;
+        assert_state!(machine, Download);
+
+        // Packages signed by a removed key are refused
+        std::fs::remove_file(keys_dir.path().join("new.pem")).unwrap();
+        let package = get_update_package();
+        let res = State::Validation(Validation { package, sign: sign(), require_download: true })
+            .move_to_next_state(&mut context)
+            .await;
+        match res {
+            Err(TransitionError::Client(cloud::Error::InvalidSignature)) => {}
+            Err(e) => panic!("Unexpected error returned: {}", e),
+            Ok(_) => panic!("Unexpected ok result returned"),
+        }
+    }
+
+    #[tokio::test]
+    async fn package_too_large() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context.settings.network.max_package_size = Some(1);
+        let package = get_update_package();
+
+        let machine = State::Validation(Validation { package, sign: None, require_download: true })
+            .move_to_next_state(&mut context)
+            .await;
+
+        match machine {
+            Err(e @ TransitionError::PackageTooLarge { max: 1, .. }) => assert!(
+                e.to_string().ends_with("exceeds the maximum allowed of 1 bytes"),
+                "Unexpected message: {}",
+                e
+            ),
+            res => panic!("Unexpected result from transition: {:?}", res),
+        }
+
+        context.settings.network.max_package_size = Some(u64::MAX);
+        let package = get_update_package();
+        let machine = State::Validation(Validation { package, sign: None, require_download: true })
+            .move_to_next_state(&mut context)
+            .await
+            .unwrap()
+            .0;
+        assert_state!(machine, Download);
+    }
+
+    #[tokio::test]
+    async fn refuse_downgrade() {
+        use sdk::api::info::settings::VersionScheme;
+
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        context.settings.firmware.version_scheme = VersionScheme::Semver;
+
+        // The running version is 1.1 while the package has 1.0
+        let machine = State::Validation(Validation {
+            package: get_update_package(),
+            sign: None,
+            require_download: true,
+        })
+        .move_to_next_state(&mut context)
+        .await;
+        match machine {
+            Err(e @ TransitionError::UpdatePackage(_)) => {
+                assert_eq!(e.code(), "downgrade_refused");
+                assert_eq!(
+                    e.to_string(),
+                    "Package version 1.0 is not newer than the running version 1.1"
+                );
+            }
+            res => panic!("Unexpected result from transition: {:?}", res),
+        }
+
+        context.settings.firmware.version_scheme = VersionScheme::Lexical;
+        context.firmware.version = "0.9".to_owned();
+        let machine = State::Validation(Validation {
+            package: get_update_package(),
+            sign: None,
+            require_download: true,
+        })
+        .move_to_next_state(&mut context)
+        .await
+        .unwrap()
+        .0;
+        assert_state!(machine, Download);
+
+        context.firmware.version = "1.0".to_owned();
+        context.settings.firmware.allow_downgrade = true;
+        let machine = State::Validation(Validation {
+            package: get_update_package(),
+            sign: None,
+            require_download: true,
+        })
+        .move_to_next_state(&mut context)
+        .await
+        .unwrap()
+        .0;
+        assert_state!(machine, Download);
+    }
+
+    #[tokio::test]
+    async fn invalid_metadata() {
+        let setup = crate::tests::TestEnvironment::build().finish();
+        let mut context = setup.gen_context();
+        let mut json = crate::update_package::tests::get_update_json("invalid");
+        json["unknown-field"] = serde_json::json!("tolerated");
+        let objects = serde_json::json!([{
+            "mode": "ubifs",
+            "filename": "rootfs",
+            "sha256sum": "invalid",
+            "size": 10,
+            "target-type": "device",
+            "target": "/dev/device2",
+        }]);
+        json["objects"] = serde_json::json!([objects, objects]);
+        let package = cloud::api::UpdatePackage::parse(json.to_string().as_bytes()).unwrap();
+
+        let machine = State::Validation(Validation { package, sign: None, require_download: true })
+            .move_to_next_state(&mut context)
+            .await;
+
+        match machine {
+            Err(TransitionError::UpdatePackage(e)) => assert_eq!(
+                e.to_string(),
+                "Invalid update package metadata: object 'rootfs' has an invalid checksum \
+                 'invalid', object 'rootfs' of mode 'ubifs' can't be installed into \
+                 Device(\"/dev/device2\")"
+            ),
+            res => panic!("Unexpected result from transition: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn volume_created_on_install() {
+        let setup = crate::tests::TestEnvironment::build()
+            .supported_install_modes(vec!["ubifs"])
+            .add_echo_binary("ubiupdatevol")
+            .add_echo_binary("ubinfo")
+            .add_echo_binary("ubimkvol")
+            .finish();
+        let mut context = setup.gen_context();
+        let ubi_device = tempfile::NamedTempFile::new().unwrap();
+        let mut json =
+            crate::update_package::tests::get_update_json(crate::update_package::tests::SHA256SUM);
+        let objects = serde_json::json!([{
+            "mode": "ubifs",
+            "filename": "data",
+            "sha256sum": crate::update_package::tests::SHA256SUM,
+            "size": 10,
+            "target-type": "ubivolume",
+            "target": "missing-volume",
+            "create-volume": true,
+            "ubi-device": ubi_device.path(),
+        }]);
+        json["objects"] = serde_json::json!([objects, objects]);
+        let package = cloud::api::UpdatePackage::parse(json.to_string().as_bytes()).unwrap();
+
+        // The volume doesn't exist yet, but its UBI device does
+        let machine = State::Validation(Validation { package, sign: None, require_download: true })
+            .move_to_next_state(&mut context)
+            .await
+            .unwrap()
+            .0;
+        assert_state!(machine, Download);
+    }
 }
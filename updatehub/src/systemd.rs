@@ -0,0 +1,92 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Notifications to systemd, for services of `Type=notify`, as described
+//! on sd_notify(3). Nothing is sent when the agent is not run by systemd.
+
+use slog_scope::{debug, warn};
+use std::{env, ffi::OsStr, io, os::unix::net::UnixDatagram, time::Duration};
+
+/// Tells systemd the agent has started up.
+pub(crate) fn notify_ready() {
+    if let Err(e) = notify("READY=1") {
+        warn!("failed to notify systemd the agent is ready: {}", e);
+    }
+}
+
+/// Spawns a task pinging systemd's watchdog at half its interval, when
+/// the watchdog is enabled for the agent.
+pub(crate) fn spawn_watchdog() {
+    let interval = match watchdog_interval() {
+        Some(interval) => interval / 2,
+        None => return,
+    };
+
+    debug!("pinging systemd watchdog every {:?}", interval);
+    tokio::task::spawn_local(async move {
+        loop {
+            if let Err(e) = notify("WATCHDOG=1") {
+                warn!("failed to ping systemd watchdog: {}", e);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+fn notify(state: &str) -> io::Result<()> {
+    match env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => send(&socket, state),
+        None => Ok(()),
+    }
+}
+
+/// Gets the watchdog interval, if it has been enabled for this process.
+fn watchdog_interval() -> Option<Duration> {
+    // The watchdog may be meant for another process of the service
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    env::var("WATCHDOG_USEC").ok()?.parse().ok().filter(|usec| *usec > 0).map(Duration::from_micros)
+}
+
+fn send(socket: &OsStr, state: &str) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let sock = UnixDatagram::unbound()?;
+    match socket.as_bytes() {
+        // Sockets in the abstract namespace are prefixed by '@'
+        [b'@', name @ ..] => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+            sock.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?;
+        }
+        _ => {
+            sock.send_to(state.as_bytes(), socket)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn send_state() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("notify.sock");
+        let listener = UnixDatagram::bind(&path).unwrap();
+
+        send(path.as_os_str(), "READY=1").unwrap();
+
+        let mut buf = [0; 16];
+        let n = listener.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+    }
+}
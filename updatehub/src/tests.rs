@@ -55,6 +55,9 @@ impl TestEnvironment {
             self.settings.data.clone(),
             self.runtime_settings.data.clone(),
             self.firmware.data.clone(),
+            None,
+            None,
+            cloud::Headers::default(),
         )
     }
 }
@@ -0,0 +1,254 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Support for installing Mender artifacts, mapping their rootfs image into
+//! a `raw` object targeting Mender's rootfs partitions.
+
+use super::{Error, Result, UpdatePackage};
+use serde::Deserialize;
+use serde_json::json;
+use slog_scope::{debug, error};
+use std::{
+    fs,
+    io::{self, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// Mender's client configuration holding the rootfs partitions.
+const MENDER_CONF: &str = "/etc/mender/mender.conf";
+const ROOTFS_IMAGE: &str = "rootfs-image";
+
+#[derive(Deserialize)]
+struct Version {
+    format: String,
+    version: u64,
+}
+
+/// Merges the header-info layouts of version 2 and 3 artifacts.
+#[derive(Deserialize)]
+struct HeaderInfo {
+    #[serde(default, alias = "updates")]
+    payloads: Vec<Payload>,
+    artifact_name: Option<String>,
+    #[serde(default)]
+    device_types_compatible: Vec<String>,
+    artifact_provides: Option<ArtifactProvides>,
+    artifact_depends: Option<ArtifactDepends>,
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Deserialize)]
+struct ArtifactProvides {
+    artifact_name: String,
+}
+
+#[derive(Deserialize)]
+struct ArtifactDepends {
+    #[serde(default)]
+    device_type: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Config {
+    rootfs_part_a: PathBuf,
+    rootfs_part_b: PathBuf,
+}
+
+/// Checks if the file is a Mender artifact by looking into the archive
+/// layout, as artifacts are plain tar files.
+pub(crate) fn is_artifact(path: &Path) -> bool {
+    let entries = match fs::File::open(path)
+        .map_err(Error::from)
+        .and_then(|mut source| compress_tools::list_archive_files(&mut source).map_err(Error::from))
+    {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+
+    entries.iter().any(|e| e == "version")
+        && entries.iter().any(|e| e == "manifest")
+        && entries.iter().any(|e| e.starts_with("header.tar"))
+        && read_version(path).map(|v| v.format == "mender").unwrap_or_default()
+}
+
+/// Reads the rootfs partitions from Mender's client configuration.
+pub(crate) fn rootfs_parts() -> Result<[PathBuf; 2]> {
+    let config: Config = serde_json::from_slice(&fs::read(MENDER_CONF)?)?;
+    Ok([config.rootfs_part_a, config.rootfs_part_b])
+}
+
+/// Extracts the rootfs image from the artifact into `dest_path`, named
+/// after its sha256sum, returning an update package installing it into the
+/// given rootfs partitions.
+pub(crate) fn extract(
+    artifact: &Path,
+    dest_path: &Path,
+    product: &str,
+    rootfs_parts: &[PathBuf; 2],
+) -> Result<UpdatePackage> {
+    let version = read_version(artifact)?;
+    if version.format != "mender" {
+        return Err(Error::InvalidMenderArtifact(format!("unknown format {}", version.format)));
+    }
+    if !matches!(version.version, 2 | 3) {
+        error!("mender artifact version {} is not supported", version.version);
+        return Err(Error::UnsupportedMenderVersion(version.version));
+    }
+
+    let mut source = fs::File::open(artifact)?;
+    let entries = compress_tools::list_archive_files(&mut source)?;
+    let entry = |prefix: &str| {
+        entries
+            .iter()
+            .find(|e| e.starts_with(prefix))
+            .ok_or_else(|| Error::InvalidMenderArtifact(format!("missing {} in artifact", prefix)))
+    };
+
+    let header_info = {
+        let mut header = Vec::default();
+        source.seek(SeekFrom::Start(0))?;
+        compress_tools::uncompress_archive_file(&mut source, &mut header, entry("header.tar")?)?;
+        let mut header_info = Vec::default();
+        compress_tools::uncompress_archive_file(
+            io::Cursor::new(header),
+            &mut header_info,
+            "header-info",
+        )?;
+        serde_json::from_slice::<HeaderInfo>(&header_info)?
+    };
+    match header_info.payloads.as_slice() {
+        [payload] if payload.kind == ROOTFS_IMAGE => {}
+        payloads => {
+            return Err(Error::InvalidMenderArtifact(format!(
+                "only a single {} payload is supported, found: {}",
+                ROOTFS_IMAGE,
+                payloads.iter().map(|p| p.kind.as_str()).collect::<Vec<_>>().join(", ")
+            )));
+        }
+    }
+
+    let (sha256sum, filename) = {
+        let mut manifest = Vec::default();
+        source.seek(SeekFrom::Start(0))?;
+        compress_tools::uncompress_archive_file(&mut source, &mut manifest, "manifest")?;
+        String::from_utf8_lossy(&manifest)
+            .lines()
+            .filter_map(|l| l.split_once(char::is_whitespace))
+            .map(|(sum, path)| (sum.to_owned(), path.trim()))
+            .find_map(|(sum, path)| path.strip_prefix("data/0000/").map(|f| (sum, f.to_owned())))
+            .ok_or_else(|| {
+                Error::InvalidMenderArtifact("missing rootfs image in manifest".to_owned())
+            })?
+    };
+
+    let mut data = tempfile::tempfile_in(dest_path)?;
+    source.seek(SeekFrom::Start(0))?;
+    compress_tools::uncompress_archive_file(&mut source, &mut data, entry("data/0000.tar")?)?;
+    data.seek(SeekFrom::Start(0))?;
+    let object = dest_path.join(&sha256sum);
+    compress_tools::uncompress_archive_file(&mut data, &mut fs::File::create(&object)?, &filename)?;
+    let size = object.metadata()?.len();
+    debug!("extracted {} ({}) from mender artifact", filename, sha256sum);
+
+    let (version, hardware) = match (header_info.artifact_provides, header_info.artifact_depends) {
+        (Some(provides), depends) => {
+            (provides.artifact_name, depends.map(|d| d.device_type).unwrap_or_default())
+        }
+        (None, _) => {
+            (header_info.artifact_name.unwrap_or_default(), header_info.device_types_compatible)
+        }
+    };
+    let objects = rootfs_parts
+        .iter()
+        .map(|part| {
+            json!([{
+                "mode": "raw",
+                "filename": filename,
+                "size": size,
+                "sha256sum": sha256sum,
+                "target-type": "device",
+                "target": part,
+            }])
+        })
+        .collect::<Vec<_>>();
+    let supported_hardware = if hardware.is_empty() { json!("any") } else { json!(hardware) };
+    let metadata = json!({
+        "product": product,
+        "version": version,
+        "supported-hardware": supported_hardware,
+        "objects": objects,
+    });
+
+    Ok(UpdatePackage::parse(&serde_json::to_vec(&metadata)?)?)
+}
+
+fn read_version(artifact: &Path) -> Result<Version> {
+    let mut version = Vec::default();
+    compress_tools::uncompress_archive_file(fs::File::open(artifact)?, &mut version, "version")?;
+    Ok(serde_json::from_slice(&version)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        firmware::installation_set::Set,
+        object::Info,
+        update_package::{
+            tests::{OBJECT, SHA256SUM},
+            UpdatePackageExt,
+        },
+    };
+    use pretty_assertions::assert_eq;
+    use sdk::api::info::runtime_settings::InstallationSet;
+
+    const ARTIFACT: &str = "fixtures/test.mender";
+    const UNSUPPORTED_ARTIFACT: &str = "fixtures/unsupported.mender";
+
+    fn parts() -> [PathBuf; 2] {
+        [PathBuf::from("/dev/rootfs-a"), PathBuf::from("/dev/rootfs-b")]
+    }
+
+    #[test]
+    fn detect_artifact() {
+        assert!(is_artifact(Path::new(ARTIFACT)));
+        assert!(!is_artifact(Path::new("fixtures/test.uhupkg")));
+        assert!(!is_artifact(Path::new("fixtures/tree.tar")));
+    }
+
+    #[test]
+    fn extract_rootfs_image() {
+        let dest = tempfile::tempdir().unwrap();
+        let package = extract(Path::new(ARTIFACT), dest.path(), "product", &parts()).unwrap();
+
+        assert_eq!(package.version(), "release-1");
+        assert_eq!(fs::read(dest.path().join(SHA256SUM)).unwrap(), OBJECT);
+        for (set, part) in [InstallationSet::A, InstallationSet::B].into_iter().zip(parts()) {
+            match package.objects(Set(set)).as_slice() {
+                [pkg_schema::Object::Raw(raw)] => {
+                    assert_eq!(raw.sha256sum(), SHA256SUM);
+                    assert_eq!(raw.len(), OBJECT.len() as u64);
+                    assert_eq!(raw.target_type, pkg_schema::definitions::TargetType::Device(part));
+                }
+                objects => panic!("unexpected objects: {:?}", objects),
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let dest = tempfile::tempdir().unwrap();
+        let err =
+            extract(Path::new(UNSUPPORTED_ARTIFACT), dest.path(), "product", &parts()).unwrap_err();
+
+        assert!(matches!(err, Error::UnsupportedMenderVersion(1)));
+        assert_eq!(err.to_string(), "Unsupported mender artifact version: 1");
+    }
+}
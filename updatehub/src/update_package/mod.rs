@@ -2,7 +2,9 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+pub(crate) mod mender;
 mod supported_hardware;
+mod version;
 
 use self::supported_hardware::SupportedHardwareExt;
 use crate::{
@@ -12,9 +14,13 @@ use crate::{
 };
 use derive_more::{Display, Error, From};
 use pkg_schema::Object;
-use sdk::api::info::runtime_settings::InstallationSet;
-use slog_scope::error;
-use std::{fs, io, path::Path};
+use sdk::api::info::{runtime_settings::InstallationSet, settings::VersionScheme};
+use slog_scope::{error, info, warn};
+use std::{
+    cmp::Ordering,
+    fs, io,
+    path::{Path, PathBuf},
+};
 use walkdir::WalkDir;
 
 #[cfg(test)]
@@ -28,12 +34,48 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     Io(std::io::Error),
     CloudSDK(cloud::Error),
+    Uncompress(compress_tools::Error),
+    SerdeJson(serde_json::Error),
+    Firmware(crate::firmware::Error),
 
     #[from(ignore)]
-    IncompatibleHardware(#[error(not(source))] String),
+    #[display(
+        fmt = "Incompatible hardware: device hardware is '{}', package supports: {}",
+        hardware,
+        supported
+    )]
+    IncompatibleHardware {
+        hardware: String,
+        supported: String,
+    },
     #[from(ignore)]
     #[display(fmt = "Install mode not accepted: {}", _0)]
     IncompatibleInstallMode(#[error(not(source))] String),
+    #[from(ignore)]
+    #[display(fmt = "Invalid update package metadata: {}", _0)]
+    InvalidMetadata(#[error(not(source))] String),
+    #[from(ignore)]
+    #[display(fmt = "Invalid object subset: {}", _0)]
+    InvalidObjectSubset(#[error(not(source))] String),
+    #[from(ignore)]
+    #[display(fmt = "Invalid mender artifact: {}", _0)]
+    InvalidMenderArtifact(#[error(not(source))] String),
+    #[from(ignore)]
+    #[display(fmt = "Unsupported mender artifact version: {}", _0)]
+    UnsupportedMenderVersion(#[error(not(source))] u64),
+    #[from(ignore)]
+    #[display(fmt = "Invalid version: {}", _0)]
+    InvalidVersion(#[error(not(source))] String),
+    #[from(ignore)]
+    #[display(
+        fmt = "Package version {} is not newer than the running version {}",
+        target,
+        current
+    )]
+    NotNewerVersion {
+        current: String,
+        target: String,
+    },
 }
 
 pub(crate) trait UpdatePackageExt {
@@ -41,10 +83,22 @@ pub(crate) trait UpdatePackageExt {
 
     fn validate_install_modes(&self, settings: &Settings, installation_set: Set) -> Result<()>;
 
+    /// Ensures the package version is newer than the running firmware
+    /// version, unless downgrades are allowed.
+    fn check_version(&self, firmware: &Metadata, settings: &Settings) -> Result<()>;
+
+    /// Checks the metadata for problems serde can't catch, reporting all
+    /// of them at once.
+    fn validate_metadata(&self, installation_set: Set) -> Result<()>;
+
     fn objects(&self, installation_set: Set) -> &Vec<Object>;
 
     fn objects_mut(&mut self, installation_set: Set) -> &mut Vec<Object>;
 
+    /// Keeps only the objects of the install subset, if any, ensuring it
+    /// holds the objects they depend on.
+    fn select_objects(&mut self, installation_set: Set) -> Result<()>;
+
     fn filter_objects(
         &self,
         settings: &Settings,
@@ -58,6 +112,14 @@ pub(crate) trait UpdatePackageExt {
         installation_set: Set,
         settings: &Settings,
     ) -> io::Result<()>;
+
+    /// Removes the objects of the package, complete or partial, staged on
+    /// the dir. Returns the number of removed files.
+    fn remove_staged_objects(&self, dir: &Path) -> io::Result<usize>;
+
+    /// Removes the objects of an installed package from the dir. Returns
+    /// the number of bytes reclaimed.
+    fn reclaim_installed_objects(&self, dir: &Path) -> io::Result<u64>;
 }
 
 impl UpdatePackageExt for UpdatePackage {
@@ -79,6 +141,91 @@ impl UpdatePackageExt for UpdatePackage {
         Ok(())
     }
 
+    fn check_version(&self, firmware: &Metadata, settings: &Settings) -> Result<()> {
+        let current = &firmware.version;
+        let target = &self.inner.version;
+        let ordering = match settings.firmware.version_scheme {
+            VersionScheme::None => return Ok(()),
+            VersionScheme::Semver => version::semver_cmp(target, current)?,
+            VersionScheme::Lexical => target.cmp(current),
+            VersionScheme::Callback => crate::firmware::version_compare_callback(
+                &settings.firmware.metadata,
+                current,
+                target,
+            )?,
+        };
+
+        if ordering == Ordering::Greater {
+            return Ok(());
+        }
+
+        if settings.firmware.allow_downgrade {
+            warn!("installing version {} over the running version {}", target, current);
+            return Ok(());
+        }
+
+        error!("refusing to install version {} over the running version {}", target, current);
+        Err(Error::NotNewerVersion { current: current.clone(), target: target.clone() })
+    }
+
+    fn validate_metadata(&self, installation_set: Set) -> Result<()> {
+        let mut problems = Vec::new();
+        if self.inner.product_uid.is_empty() {
+            problems.push("product is empty".to_owned());
+        }
+        if self.inner.version.is_empty() {
+            problems.push("version is empty".to_owned());
+        }
+
+        let objects = self.objects(installation_set);
+        if objects.is_empty() {
+            problems.push(format!("no objects for installation set {:?}", installation_set.0));
+        }
+        for (index, obj) in objects.iter().enumerate() {
+            if obj.filename().is_empty() {
+                problems.push(format!("object {} has an empty filename", index));
+            }
+            let checksum = obj.sha256sum();
+            if checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+                problems.push(format!(
+                    "object '{}' has an invalid checksum '{}'",
+                    obj.filename(),
+                    checksum
+                ));
+            }
+            for dependency in
+                self.inner.object_dependencies.get(obj.filename()).into_iter().flatten()
+            {
+                if !objects.iter().any(|o| o.filename() == dependency) {
+                    problems.push(format!(
+                        "object '{}' depends on unknown object '{}'",
+                        obj.filename(),
+                        dependency
+                    ));
+                }
+            }
+            for target in object::target_types(obj) {
+                if !object::accepts_target(obj, target) {
+                    problems.push(format!(
+                        "object '{}' of mode '{}' can't be installed into {:?}",
+                        obj.filename(),
+                        obj.mode(),
+                        target
+                    ));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+
+        for problem in &problems {
+            error!("invalid update package metadata: {}", problem);
+        }
+        Err(Error::InvalidMetadata(problems.join(", ")))
+    }
+
     fn objects(&self, installation_set: Set) -> &Vec<Object> {
         match installation_set.0 {
             InstallationSet::A => &self.inner.objects.0,
@@ -93,6 +240,42 @@ impl UpdatePackageExt for UpdatePackage {
         }
     }
 
+    fn select_objects(&mut self, installation_set: Set) -> Result<()> {
+        let subset = &self.inner.install_subset;
+        if subset.is_empty() {
+            return Ok(());
+        }
+
+        let objects = self.objects(installation_set);
+        let mut problems = Vec::new();
+        for filename in subset {
+            if !objects.iter().any(|o| o.filename() == filename) {
+                problems.push(format!("object '{}' is not in the package", filename));
+            }
+            for dependency in self.inner.object_dependencies.get(filename).into_iter().flatten() {
+                if !subset.contains(dependency) {
+                    problems.push(format!(
+                        "object '{}' depends on '{}', which is not in the subset",
+                        filename, dependency
+                    ));
+                }
+            }
+        }
+        if !problems.is_empty() {
+            for problem in &problems {
+                error!("invalid object subset: {}", problem);
+            }
+            return Err(Error::InvalidObjectSubset(problems.join(", ")));
+        }
+
+        let subset = subset.clone();
+        let objects = self.objects_mut(installation_set);
+        let total = objects.len();
+        objects.retain(|o| subset.iter().any(|s| s == o.filename()));
+        info!("installing {} of the {} package objects", objects.len(), total);
+        Ok(())
+    }
+
     fn filter_objects(
         &self,
         settings: &Settings,
@@ -152,4 +335,41 @@ impl UpdatePackageExt for UpdatePackage {
 
         Ok(())
     }
+
+    fn remove_staged_objects(&self, dir: &Path) -> io::Result<usize> {
+        let staged = staged_objects(self, dir);
+        for file in &staged {
+            fs::remove_file(file)?;
+        }
+
+        Ok(staged.len())
+    }
+
+    fn reclaim_installed_objects(&self, dir: &Path) -> io::Result<u64> {
+        let mut reclaimed = 0;
+        for file in &staged_objects(self, dir) {
+            // Objects linked from a local install directory don't take
+            // space on the dir
+            let metadata = file.symlink_metadata()?;
+            if metadata.is_file() {
+                reclaimed += metadata.len();
+            }
+            fs::remove_file(file)?;
+        }
+
+        Ok(reclaimed)
+    }
+}
+
+/// Gets the objects of both installation sets staged on the dir.
+fn staged_objects(package: &UpdatePackage, dir: &Path) -> std::collections::BTreeSet<PathBuf> {
+    package
+        .inner
+        .objects
+        .0
+        .iter()
+        .chain(package.inner.objects.1.iter())
+        .map(|o| dir.join(o.sha256sum()))
+        .filter(|p| p.symlink_metadata().is_ok())
+        .collect()
 }
@@ -12,16 +12,13 @@ pub(crate) trait SupportedHardwareExt {
 
 impl SupportedHardwareExt for SupportedHardware {
     fn compatible_with(&self, hardware: &str) -> Result<(), Error> {
-        let hardware = hardware.to_string();
-        let compatible = match self {
-            SupportedHardware::Any => true,
-            SupportedHardware::HardwareList(l) => l.contains(&hardware),
-        };
-
-        if !compatible {
-            return Err(Error::IncompatibleHardware(hardware));
+        match self {
+            SupportedHardware::Any => Ok(()),
+            SupportedHardware::HardwareList(l) if l.iter().any(|h| h == hardware) => Ok(()),
+            SupportedHardware::HardwareList(l) => Err(Error::IncompatibleHardware {
+                hardware: hardware.to_owned(),
+                supported: l.join(", "),
+            }),
         }
-
-        Ok(())
     }
 }
@@ -117,3 +117,52 @@ fn complete_object_file() {
         1
     );
 }
+
+#[test]
+fn select_objects() {
+    let object = |filename: &str| {
+        json!({
+            "mode": "test",
+            "filename": filename,
+            "target": "/dev/device1",
+            "sha256sum": SHA256SUM,
+            "size": 10,
+            "force-check-requirements-fail": false
+        })
+    };
+    let package = |subset: serde_json::Value| {
+        UpdatePackage::parse(
+            json!({
+                "product": "0123456789",
+                "version": "1.0",
+                "objects": [[object("rootfs"), object("app"), object("app-config")], []],
+                "install-subset": subset,
+                "object-dependencies": { "app": ["app-config"] }
+            })
+            .to_string()
+            .as_bytes(),
+        )
+        .unwrap()
+    };
+    let set = Set(InstallationSet::A);
+    let filenames = |package: &UpdatePackage| {
+        package.objects(set).iter().map(|o| o.filename().to_owned()).collect::<Vec<_>>()
+    };
+
+    let mut update_package = package(json!([]));
+    update_package.validate_metadata(set).unwrap();
+    update_package.select_objects(set).unwrap();
+    assert_eq!(filenames(&update_package), ["rootfs", "app", "app-config"]);
+
+    let mut update_package = package(json!(["app", "app-config"]));
+    update_package.select_objects(set).unwrap();
+    assert_eq!(filenames(&update_package), ["app", "app-config"]);
+
+    // The subset must hold the objects its objects depend on
+    let mut update_package = package(json!(["app"]));
+    assert!(matches!(update_package.select_objects(set), Err(Error::InvalidObjectSubset(_))));
+    assert_eq!(filenames(&update_package), ["rootfs", "app", "app-config"]);
+
+    let mut update_package = package(json!(["unknown"]));
+    assert!(matches!(update_package.select_objects(set), Err(Error::InvalidObjectSubset(_))));
+}
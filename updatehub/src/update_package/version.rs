@@ -0,0 +1,108 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{Error, Result};
+use std::cmp::Ordering;
+
+/// Version made of dot separated numbers, optionally followed by a `-`
+/// separated pre-release and a `+` separated build metadata. Missing
+/// trailing numbers are taken as zero, so `1.2` is the same as `1.2.0`.
+struct SemVer<'a> {
+    numbers: Vec<u64>,
+    pre_release: Option<&'a str>,
+}
+
+impl<'a> SemVer<'a> {
+    fn parse(version: &'a str) -> Result<Self> {
+        let invalid = || Error::InvalidVersion(version.to_owned());
+        // Build metadata has no precedence
+        let (version, _) = version.split_once('+').unwrap_or((version, ""));
+        let (numbers, pre_release) = match version.split_once('-') {
+            Some((numbers, pre_release)) => (numbers, Some(pre_release)),
+            None => (version, None),
+        };
+        let numbers = numbers
+            .strip_prefix('v')
+            .unwrap_or(numbers)
+            .split('.')
+            .map(|n| n.parse().map_err(|_| invalid()))
+            .collect::<Result<_>>()?;
+
+        Ok(SemVer { numbers, pre_release })
+    }
+
+    fn number(&self, index: usize) -> u64 {
+        self.numbers.get(index).copied().unwrap_or_default()
+    }
+}
+
+/// Compares two versions as semantic versions.
+pub(super) fn semver_cmp(a: &str, b: &str) -> Result<Ordering> {
+    let (a, b) = (SemVer::parse(a)?, SemVer::parse(b)?);
+
+    let numbers = (0..a.numbers.len().max(b.numbers.len()))
+        .map(|i| a.number(i).cmp(&b.number(i)))
+        .find(|o| o.is_ne())
+        .unwrap_or(Ordering::Equal);
+
+    Ok(numbers.then_with(|| match (a.pre_release, b.pre_release) {
+        (None, None) => Ordering::Equal,
+        // A pre-release precedes its release
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => pre_release_cmp(a, b),
+    }))
+}
+
+fn pre_release_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.split('.'), b.split('.'));
+    loop {
+        let ordering = match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                // Numeric identifiers have lower precedence
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                (Err(_), Err(_)) => a.cmp(b),
+            },
+        };
+
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn semver_ordering() {
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.1",
+            "1.1.1",
+            "v1.10.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(semver_cmp(pair[0], pair[1]).unwrap(), Ordering::Less, "{:?}", pair);
+            assert_eq!(semver_cmp(pair[1], pair[0]).unwrap(), Ordering::Greater, "{:?}", pair);
+        }
+
+        assert_eq!(semver_cmp("1.2", "1.2.0+build.5").unwrap(), Ordering::Equal);
+        assert!(matches!(semver_cmp("1.2", "release-2"), Err(Error::InvalidVersion(_))));
+        assert!(matches!(semver_cmp("1..2", "1.2"), Err(Error::InvalidVersion(_))));
+    }
+}
@@ -3,12 +3,12 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::{Error, Result};
-use crate::utils::mtd;
+use crate::utils::{gpt, mtd};
 use pkg_schema::definitions::{
     target_permissions::{Gid, Uid},
     TargetType,
 };
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Utility functions for [TargetType](pkg_schema::definitions::TargetType)
 pub(crate) trait TargetTypeExt {
@@ -22,6 +22,21 @@ pub(crate) trait TargetTypeExt {
 
 impl TargetTypeExt for TargetType {
     fn valid(&self) -> Result<&Self> {
+        if let TargetType::File(file) = self {
+            valid_file(file)?;
+            return Ok(self);
+        }
+
+        if let TargetType::UBIVolume(volume) = self {
+            match mtd::ubi_volume(volume)? {
+                None => return Err(Error::NoUbiVolume(volume.clone())),
+                Some(ubi) if !ubi.device.exists() => {
+                    return Err(Error::DeviceDoesNotExist(ubi.device));
+                }
+                Some(_) => {}
+            }
+        }
+
         let device = self.get_target()?;
 
         if !device.exists() {
@@ -54,8 +69,36 @@ impl TargetTypeExt for TargetType {
             }
             TargetType::UBIVolume(s) => mtd::target_device_from_ubi_volume_name(s),
             TargetType::MTDName(s) => mtd::target_device_from_mtd_name(s),
+            TargetType::File(file) => Ok(file.clone()),
+            TargetType::GptLabel(label) => gpt::target_device_from_gpt_label(label),
+        }
+    }
+}
+
+/// A file target may not exist yet, so we check that its directory can
+/// hold it and, when it already exists, that it can be overwritten.
+fn valid_file(file: &Path) -> Result<()> {
+    let directory = match file.parent() {
+        Some(directory) if directory.is_dir() => directory,
+        _ => return Err(Error::DeviceDoesNotExist(file.to_path_buf())),
+    };
+
+    if directory.metadata()?.permissions().readonly() {
+        return Err(Error::MissingWritePermission(directory.to_path_buf()));
+    }
+
+    if file.exists() {
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(Error::NotARegularFile(file.to_path_buf()));
+        }
+
+        if metadata.permissions().readonly() {
+            return Err(Error::MissingWritePermission(file.to_path_buf()));
         }
     }
+
+    Ok(())
 }
 
 /// Utility functions for
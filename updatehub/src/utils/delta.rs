@@ -31,7 +31,12 @@ pub(crate) async fn get_required_size(seed: &str, output: &Path) -> Result<u64>
         }
     };
 
-    let current_size = fs::metadata(output).await?.len();
+    let current_size = match fs::metadata(output).await {
+        Ok(metadata) => metadata.len(),
+        // A file target may not exist before its first installation
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e.into()),
+    };
 
     Ok(archive_source_size.checked_sub(current_size).unwrap_or_default())
 }
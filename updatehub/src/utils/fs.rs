@@ -6,14 +6,17 @@ use super::{Error, Result};
 use crate::utils::definitions::IdExt;
 use pkg_schema::definitions::{
     target_permissions::{Gid, Uid},
-    Filesystem,
+    Filesystem, PostInstallAction,
 };
-use slog_scope::trace;
+use slog_scope::{error, info, trace, warn};
 use std::{io, path::Path};
 use sys_mount::{Mount, Unmount, UnmountDrop};
 
 pub(crate) struct MountGuard {
     _mount: UnmountDrop<Mount>,
+    // Declared after the mount so the loop device is only released
+    // once the image has been unmounted
+    _loop_device: Option<loopdev::LoopDevice>,
     directory: tempfile::TempDir,
 }
 
@@ -66,20 +69,99 @@ pub(crate) fn format(target: &Path, fs: Filesystem, options: &Option<String>) ->
     Ok(())
 }
 
+/// Gets the command running the post-install `action` on the `fs`
+/// filesystem, to be followed by the target.
+fn post_install_command(action: PostInstallAction, fs: Filesystem) -> Result<&'static str> {
+    match (action, fs) {
+        (PostInstallAction::Fsck, Filesystem::Ext2 | Filesystem::Ext3 | Filesystem::Ext4) => {
+            Ok("e2fsck -f -y")
+        }
+        (PostInstallAction::Fsck, Filesystem::Vfat) => Ok("fsck.vfat -a"),
+        (PostInstallAction::Fsck, Filesystem::F2fs) => Ok("fsck.f2fs -a"),
+        (PostInstallAction::Fsck, Filesystem::Btrfs) => Ok("btrfs check"),
+        (PostInstallAction::Resize, Filesystem::Ext2 | Filesystem::Ext3 | Filesystem::Ext4) => {
+            Ok("resize2fs")
+        }
+        (PostInstallAction::Resize, Filesystem::F2fs) => Ok("resize.f2fs"),
+        _ => Err(Error::UnsupportedPostInstall(action, fs)),
+    }
+}
+
+/// Checks the post-install `action` is supported on the `fs` filesystem
+/// and its tool is available.
+pub(crate) fn check_post_install(action: PostInstallAction, fs: Filesystem) -> Result<()> {
+    let cmd = post_install_command(action, fs)?;
+    is_executable_in_path(cmd.split(' ').next().unwrap_or(cmd))
+}
+
+/// Runs the post-install `action` on the `fs` filesystem at `target`,
+/// which must not be mounted. The tool output is kept in the log.
+pub(crate) fn post_install(target: &Path, fs: Filesystem, action: PostInstallAction) -> Result<()> {
+    let cmd = format!("{} {}", post_install_command(action, fs)?, target.display());
+    trace!("running {} on {:?}: {}", action, target, cmd);
+
+    match easy_process::run(&cmd) {
+        Ok(output) => {
+            for line in output.stdout.lines().chain(output.stderr.lines()) {
+                info!("{} (output): {}", action, line);
+            }
+            Ok(())
+        }
+        // The fsck tools exit with 1 once they have corrected the errors
+        // found, leaving a consistent filesystem
+        Err(easy_process::Error::Failure(status, output))
+            if action == PostInstallAction::Fsck && status.code() == Some(1) =>
+        {
+            warn!("{} has corrected errors on {:?}", action, target);
+            for line in output.stdout.lines().chain(output.stderr.lines()) {
+                info!("{} (output): {}", action, line);
+            }
+            Ok(())
+        }
+        Err(easy_process::Error::Failure(status, output)) => {
+            error!("{} of {:?} has failed with status: {}", action, target, status);
+            for line in output.stdout.lines() {
+                error!("{} (stdout): {}", action, line);
+            }
+            for line in output.stderr.lines() {
+                error!("{} (stderr): {}", action, line);
+            }
+            Err(easy_process::Error::Failure(status, output).into())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
 pub(crate) fn mount(source: &Path, fs: Filesystem, options: &str) -> io::Result<MountGuard> {
     let directory = tempfile::tempdir()?;
     let dest = directory.path();
 
+    // Image files are mounted through a loop device which is detached
+    // as soon as it gets unmounted
+    let _loop_device = if source.is_file() {
+        let loop_device = loopdev::LoopControl::open()?.next_free()?;
+        loop_device.with().autoclear(true).attach(source)?;
+        Some(loop_device)
+    } else {
+        None
+    };
+    let source = match &_loop_device {
+        Some(loop_device) => loop_device.path().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "unable to find loop device path")
+        })?,
+        None => source.to_path_buf(),
+    };
+
     trace!("mounting {:?} as {} at {:?}", source, fs, &dest);
 
     let _mount = Mount::builder()
         .fstype(format!("{}", fs).as_str())
         .data(options)
         .flags(sys_mount::MountFlags::empty())
-        .mount(source, dest)?
+        .mount(&source, dest)?
         .into_unmount_drop(sys_mount::UnmountFlags::FORCE);
 
-    Ok(MountGuard { _mount, directory })
+    Ok(MountGuard { _mount, _loop_device, directory })
 }
 
 pub(crate) fn chmod(path: &Path, mode: u32) -> Result<()> {
@@ -94,6 +176,51 @@ pub(crate) fn chmod(path: &Path, mode: u32) -> Result<()> {
     Ok(())
 }
 
+/// Sets the extended attribute of the file, as SELinux labels.
+pub(crate) fn set_xattr(path: &Path, name: &str, value: &[u8]) -> Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    trace!("setting {} extended attribute of {:?}", name, path);
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(io::Error::from)?;
+    let name = CString::new(name).map_err(io::Error::from)?;
+    // SAFETY: both strings are nul terminated and the value is only read
+    // up to its length
+    let res = unsafe {
+        nix::libc::setxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0)
+    };
+    nix::errno::Errno::result(res)?;
+
+    Ok(())
+}
+
+/// Creates the temporary file content is written to before it is renamed
+/// over `target`, in the same directory so the rename is atomic. It keeps
+/// the permissions and ownership of the current target, if any, and it is
+/// removed when dropped without being renamed.
+pub(crate) fn stage_file(target: &Path) -> Result<tempfile::NamedTempFile> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let dir = target.parent().unwrap_or_else(|| Path::new("."));
+    let staged = tempfile::Builder::new().prefix(".updatehub-").tempfile_in(dir)?;
+    match std::fs::metadata(target) {
+        Ok(metadata) => {
+            std::fs::set_permissions(staged.path(), metadata.permissions())?;
+            nix::unistd::chown(
+                staged.path(),
+                Some(nix::unistd::Uid::from_raw(metadata.uid())),
+                Some(nix::unistd::Gid::from_raw(metadata.gid())),
+            )?;
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            std::fs::set_permissions(staged.path(), std::fs::Permissions::from_mode(0o644))?
+        }
+        Err(e) => return Err(e.into()),
+    }
+    trace!("staging {:?} on {:?}", target, staged.path());
+
+    Ok(staged)
+}
+
 pub(crate) fn chown(path: &Path, uid: &Option<Uid>, gid: &Option<Gid>) -> Result<()> {
     trace!("applying ownership of uid:{:?} and gid:{:?} to {:?}", uid, gid, path);
     Ok(nix::unistd::chown(
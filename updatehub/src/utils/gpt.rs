@@ -0,0 +1,84 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{Error, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub(crate) fn target_device_from_gpt_label(label: &str) -> Result<PathBuf> {
+    find_partitions_by_label(Path::new("/sys/class/block"), label).and_then(|mut partitions| {
+        match partitions.len() {
+            0 => Err(Error::NoGptPartition(label.to_owned())),
+            1 => Ok(partitions.remove(0)),
+            _ => Err(Error::MultipleGptPartitions { label: label.to_owned(), partitions }),
+        }
+    })
+}
+
+/// Lists the device nodes of all partitions whose uevent reports the
+/// label as its partition name.
+fn find_partitions_by_label(sysfs: &Path, label: &str) -> Result<Vec<PathBuf>> {
+    let mut partitions = fs::read_dir(sysfs)?
+        .filter_map(std::result::Result::ok)
+        .filter_map(|entry| {
+            let uevent = fs::read_to_string(entry.path().join("uevent")).ok()?;
+            let mut devname = None;
+            let mut partname = None;
+            for line in uevent.lines() {
+                match line.split_once('=') {
+                    Some(("DEVNAME", v)) => devname = Some(v),
+                    Some(("PARTNAME", v)) => partname = Some(v),
+                    _ => {}
+                }
+            }
+
+            if partname? == label {
+                Some(PathBuf::from(format!("/dev/{}", devname?)))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    partitions.sort();
+
+    Ok(partitions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn add_block(sysfs: &Path, devname: &str, partname: Option<&str>) {
+        let dir = sysfs.join(devname);
+        fs::create_dir(&dir).unwrap();
+        let mut uevent = format!("MAJOR=8\nMINOR=1\nDEVNAME={}\nDEVTYPE=partition\n", devname);
+        if let Some(partname) = partname {
+            uevent.push_str(&format!("PARTN=1\nPARTNAME={}\n", partname));
+        }
+        fs::write(dir.join("uevent"), uevent).unwrap();
+    }
+
+    #[test]
+    fn find_by_label() {
+        let sysfs = tempfile::tempdir().unwrap();
+        add_block(sysfs.path(), "sda", None);
+        add_block(sysfs.path(), "sda1", Some("boot"));
+        add_block(sysfs.path(), "sda2", Some("rootfs-a"));
+        add_block(sysfs.path(), "sdb1", Some("data"));
+        add_block(sysfs.path(), "sdc1", Some("data"));
+
+        assert_eq!(
+            find_partitions_by_label(sysfs.path(), "rootfs-a").unwrap(),
+            vec![PathBuf::from("/dev/sda2")]
+        );
+        assert_eq!(
+            find_partitions_by_label(sysfs.path(), "data").unwrap(),
+            vec![PathBuf::from("/dev/sdb1"), PathBuf::from("/dev/sdc1")]
+        );
+        assert!(find_partitions_by_label(sysfs.path(), "rootfs-b").unwrap().is_empty());
+    }
+}
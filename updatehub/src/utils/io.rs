@@ -2,9 +2,18 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use slog_scope::trace;
-use std::{pin::Pin, time::Duration};
-use tokio::io::{AsyncRead, AsyncWrite, BufReader, BufWriter};
+use super::Result;
+use async_compression::{tokio::bufread::ZstdDecoder, zstd::DParameter};
+use pkg_schema::definitions::Compression;
+use slog_scope::{debug, error, trace, warn};
+use std::{
+    io,
+    pin::Pin,
+    process::Stdio,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, BufReader, BufWriter};
 use tokio_io_timeout::{TimeoutReader, TimeoutWriter};
 
 pub(crate) fn timed_buf_reader<R>(
@@ -32,3 +41,571 @@ where
     w.set_timeout(Some(Duration::from_secs(5)));
     Box::pin(BufWriter::with_capacity(chunk_size, w))
 }
+
+/// Writer duplicating the written data into every inner writer.
+///
+/// A write only completes once every writer has accepted the whole buffer,
+/// so the writers are kept in lockstep. As with any [AsyncWrite], a write
+/// interrupted by [Poll::Pending] must be retried with the same data.
+pub(crate) struct FanOut<W> {
+    writers: Vec<W>,
+    written: Vec<usize>,
+}
+
+impl<W> FanOut<W> {
+    pub(crate) fn new(writers: Vec<W>) -> Self {
+        let written = vec![0; writers.len()];
+        FanOut { writers, written }
+    }
+
+    fn poll_all(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut f: impl FnMut(Pin<&mut W>, &mut Context<'_>) -> Poll<io::Result<()>>,
+    ) -> Poll<io::Result<()>>
+    where
+        W: Unpin,
+    {
+        let mut ready = true;
+        for w in self.writers.iter_mut() {
+            match f(Pin::new(w), cx) {
+                Poll::Ready(res) => res?,
+                Poll::Pending => ready = false,
+            }
+        }
+
+        if ready {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for FanOut<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let mut ready = true;
+        for (w, written) in this.writers.iter_mut().zip(this.written.iter_mut()) {
+            while *written < buf.len() {
+                match Pin::new(&mut *w).poll_write(cx, &buf[*written..]) {
+                    Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                    Poll::Ready(Ok(n)) => *written += n,
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => {
+                        ready = false;
+                        break;
+                    }
+                }
+            }
+        }
+
+        if !ready {
+            return Poll::Pending;
+        }
+
+        this.written.iter_mut().for_each(|written| *written = 0);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_all(cx, |w, cx| w.poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_all(cx, |w, cx| w.poll_shutdown(cx))
+    }
+}
+
+/// Seeks every writer, which are kept at the same position as they are
+/// written the same data.
+impl<W: AsyncSeek + Unpin> AsyncSeek for FanOut<W> {
+    fn start_seek(self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        self.get_mut().writers.iter_mut().try_for_each(|w| Pin::new(w).start_seek(position))
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let mut position = 0;
+        let mut ready = true;
+        for w in self.get_mut().writers.iter_mut() {
+            match Pin::new(w).poll_complete(cx) {
+                Poll::Ready(Ok(pos)) => position = pos,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => ready = false,
+            }
+        }
+
+        if ready {
+            Poll::Ready(Ok(position))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Writer hashing the data accepted by the inner writer, so content can be
+/// verified while it is written.
+pub(crate) struct HashingWriter<W> {
+    writer: W,
+    hasher: super::Hasher,
+    written: u64,
+}
+
+impl<W> HashingWriter<W> {
+    pub(crate) fn new(writer: W, algorithm: pkg_schema::definitions::HashAlgorithm) -> Self {
+        HashingWriter { writer, hasher: super::Hasher::new(algorithm), written: 0 }
+    }
+
+    /// Gets the number of bytes written so far.
+    pub(crate) fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Consumes the writer returning the hex encoded digest of the written
+    /// data.
+    pub(crate) fn finish(self) -> String {
+        self.hasher.finish()
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for HashingWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.writer).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            this.hasher.update(&buf[..n]);
+            this.written += n as u64;
+        }
+        res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().writer).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().writer).poll_shutdown(cx)
+    }
+}
+
+/// Decompresses the input into the output, streaming it through the
+/// algorithm hinted by the object or auto detecting its format.
+///
+/// Objects hinted as xz are decompressed by the `xz` tool using up to
+/// `threads` threads, when it is available. Other formats don't support
+/// parallel decompression, so they are always decompressed in a single
+/// thread.
+///
+/// The `memory_limit` bounds the memory used by zstd and by the `xz`
+/// tool, failing the decompression of data which requires more. Bounding
+/// objects hinted as xz requires the `xz` tool, so the decompression
+/// fails when it isn't available. Other decompressors don't support it,
+/// so their own limit is used.
+pub(crate) async fn uncompress_data<R, W>(
+    compression: Option<Compression>,
+    threads: usize,
+    memory_limit: Option<u64>,
+    input: R,
+    mut output: W,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let start = Instant::now();
+    match compression {
+        Some(Compression::Xz)
+            if (threads > 1 || memory_limit.is_some()) && xz_in_path(memory_limit)? =>
+        {
+            trace!("uncompressing data using xz with {} threads", threads);
+            uncompress_xz(threads, memory_limit, input, &mut output).await?;
+        }
+        Some(Compression::Zstd) => {
+            trace!("uncompressing data using zstd");
+            let input = BufReader::new(input);
+            let mut decoder = match memory_limit {
+                Some(limit) => ZstdDecoder::with_params(
+                    input,
+                    &[DParameter::window_log_max(zstd_window_log_max(limit))],
+                ),
+                None => ZstdDecoder::new(input),
+            };
+            tokio::io::copy(&mut decoder, &mut output)
+                .await
+                .map_err(|e| zstd_error(e, memory_limit))?;
+        }
+        _ => {
+            if memory_limit.is_some() {
+                warn!("decompress memory limit is not supported for this format, ignoring it");
+            }
+            compress_tools::tokio_support::uncompress_data(input, output).await?;
+        }
+    }
+    debug!("data uncompressed in {:.2?}", start.elapsed());
+
+    Ok(())
+}
+
+/// Pipes the input through `xz`, which decompresses the independent
+/// blocks of the stream in parallel.
+async fn uncompress_xz<R, W>(
+    threads: usize,
+    memory_limit: Option<u64>,
+    mut input: R,
+    output: &mut W,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut child = tokio::process::Command::new("xz")
+        .args(xz_args(threads, memory_limit))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("xz stdin is piped");
+    let mut stdout = child.stdout.take().expect("xz stdout is piped");
+
+    let feed = async move {
+        tokio::io::copy(&mut input, &mut stdin).await?;
+        // Closing the pipe signals the end of the stream
+        drop(stdin);
+        Ok::<_, io::Error>(())
+    };
+    tokio::try_join!(feed, tokio::io::copy(&mut stdout, output))?;
+
+    let result = child.wait_with_output().await?;
+    if !result.status.success() {
+        return Err(xz_error(result.status, &result.stderr, memory_limit));
+    }
+
+    Ok(())
+}
+
+/// Blocking version of [uncompress_xz].
+fn uncompress_xz_sync<R, W>(memory_limit: Option<u64>, mut input: R, mut output: W) -> Result<()>
+where
+    R: std::io::Read + Send,
+    W: std::io::Write,
+{
+    let mut child = std::process::Command::new("xz")
+        .args(xz_args(1, memory_limit))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("xz stdin is piped");
+    let mut stdout = child.stdout.take().expect("xz stdout is piped");
+
+    let copied = std::thread::scope(|scope| {
+        // Closing the pipe, once the thread is done, signals the end of
+        // the stream
+        let feed = scope.spawn(move || std::io::copy(&mut input, &mut stdin));
+        let copied = std::io::copy(&mut stdout, &mut output);
+        if copied.is_err() {
+            // Unblocks the feeding of a process nobody reads from
+            let _ = child.kill();
+        }
+        feed.join().expect("xz input thread has panicked").and(copied)
+    });
+
+    let result = child.wait_with_output()?;
+    if !result.status.success() {
+        return Err(xz_error(result.status, &result.stderr, memory_limit));
+    }
+    copied?;
+
+    Ok(())
+}
+
+/// Checks if the `xz` tool can be used, which is required to bound the
+/// memory used for decompressing xz data.
+fn xz_in_path(memory_limit: Option<u64>) -> Result<bool> {
+    match super::fs::is_executable_in_path("xz") {
+        Ok(()) => Ok(true),
+        Err(e) if memory_limit.is_some() => {
+            error!("xz is required to enforce the decompress memory limit");
+            Err(e)
+        }
+        Err(_) => Ok(false),
+    }
+}
+
+fn xz_args(threads: usize, memory_limit: Option<u64>) -> Vec<String> {
+    let mut args =
+        vec!["--decompress".to_owned(), "--stdout".to_owned(), format!("--threads={}", threads)];
+    if let Some(limit) = memory_limit {
+        args.push(format!("--memlimit-decompress={}", limit));
+    }
+    args
+}
+
+/// Tells the data requiring more memory than the limit allows apart from
+/// the other failures of `xz`.
+fn xz_error(
+    status: std::process::ExitStatus,
+    stderr: &[u8],
+    memory_limit: Option<u64>,
+) -> super::Error {
+    let stderr = String::from_utf8_lossy(stderr);
+    match memory_limit {
+        Some(limit) if stderr.contains("Memory usage limit reached") => {
+            error!("xz requires more memory than the limit of {} bytes", limit);
+            super::Error::DecompressMemoryLimitExceeded(limit)
+        }
+        _ => io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("xz has failed with {}: {}", status, stderr.trim()),
+        )
+        .into(),
+    }
+}
+
+/// Blocking version of [uncompress_data].
+pub(crate) fn uncompress_data_sync<R, W>(
+    compression: Option<Compression>,
+    memory_limit: Option<u64>,
+    input: R,
+    mut output: W,
+) -> Result<()>
+where
+    R: std::io::Read + Send,
+    W: std::io::Write,
+{
+    match compression {
+        Some(Compression::Xz) if memory_limit.is_some() && xz_in_path(memory_limit)? => {
+            trace!("uncompressing data using xz");
+            uncompress_xz_sync(memory_limit, input, output)?;
+        }
+        Some(Compression::Zstd) => {
+            trace!("uncompressing data using zstd");
+            let mut decoder = zstd::stream::read::Decoder::new(input)?;
+            if let Some(limit) = memory_limit {
+                decoder.window_log_max(zstd_window_log_max(limit))?;
+            }
+            std::io::copy(&mut decoder, &mut output).map_err(|e| zstd_error(e, memory_limit))?;
+        }
+        _ => {
+            if memory_limit.is_some() {
+                warn!("decompress memory limit is not supported for this format, ignoring it");
+            }
+            compress_tools::uncompress_data(input, output)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Gets the largest zstd window, which is the bulk of the memory used for
+/// decompressing, fitting in the limit.
+fn zstd_window_log_max(memory_limit: u64) -> u32 {
+    (u64::BITS - 1 - memory_limit.max(1).leading_zeros()).clamp(10, 31)
+}
+
+/// Tells the data requiring a larger window than the memory limit
+/// allows apart from corrupted data.
+fn zstd_error(e: io::Error, memory_limit: Option<u64>) -> super::Error {
+    match memory_limit {
+        Some(limit) if e.to_string().contains("too much memory") => {
+            error!("zstd requires more memory than the limit of {} bytes", limit);
+            super::Error::DecompressMemoryLimitExceeded(limit)
+        }
+        _ => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn fan_out_writes_to_all_writers() {
+        let mut fan_out = FanOut::new(vec![Vec::new(), Vec::new()]);
+        fan_out.write_all(b"some data").await.unwrap();
+        fan_out.flush().await.unwrap();
+
+        assert_eq!(fan_out.writers, [b"some data".to_vec(), b"some data".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn hashing_writer_hashes_written_data() {
+        let mut writer = HashingWriter::new(
+            FanOut::new(vec![Vec::new(), Vec::new()]),
+            pkg_schema::definitions::HashAlgorithm::Sha256,
+        );
+        writer.write_all(b"some data").await.unwrap();
+        writer.flush().await.unwrap();
+
+        assert_eq!(writer.writer.writers, [b"some data".to_vec(), b"some data".to_vec()]);
+        assert_eq!(writer.finish(), crate::utils::sha256sum(b"some data"));
+    }
+
+    /// Writer counting the writes which reach it.
+    #[derive(Default)]
+    struct CountingWriter {
+        writes: usize,
+        written: usize,
+    }
+
+    impl AsyncWrite for CountingWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            this.writes += 1;
+            this.written += buf.len();
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn buffered_copy_writes_in_chunks() {
+        let data = vec![0xA5; 64 * 1024];
+
+        for chunk_size in [4096, 16384, 65536] {
+            let mut input = timed_buf_reader(chunk_size, data.as_slice());
+            let mut output = timed_buf_writer(chunk_size, CountingWriter::default());
+            tokio::io::copy_buf(&mut input, &mut output).await.unwrap();
+            output.flush().await.unwrap();
+
+            let writer = output.get_ref().get_ref();
+            assert_eq!(writer.written, data.len());
+            assert_eq!(writer.writes, data.len() / chunk_size, "chunk size: {}", chunk_size);
+        }
+    }
+
+    #[tokio::test]
+    async fn uncompress_xz_with_threads() {
+        // Holds 256 KiB of data compressed in 64 KiB blocks, which may be
+        // decompressed in parallel
+        let compressed = std::fs::read("fixtures/multiblock.xz").unwrap();
+        let data = (0..256 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        for threads in [1, 4] {
+            let mut output =
+                HashingWriter::new(Vec::new(), pkg_schema::definitions::HashAlgorithm::Sha256);
+            uncompress_data(
+                Some(Compression::Xz),
+                threads,
+                None,
+                compressed.as_slice(),
+                &mut output,
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(output.writer, data, "threads: {}", threads);
+            assert_eq!(output.finish(), crate::utils::sha256sum(&data));
+        }
+
+        let corrupted = &compressed[..compressed.len() / 2];
+        assert!(uncompress_data(Some(Compression::Xz), 4, None, corrupted, Vec::new())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn uncompress_zstd_with_memory_limit() {
+        // Compressed with a 1 MiB window, which the decompressor allocates
+        let data = (0..1024 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut encoder = zstd::stream::Encoder::new(Vec::new(), 3).unwrap();
+        encoder.window_log(20).unwrap();
+        std::io::Write::write_all(&mut encoder, &data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut output = Vec::new();
+        uncompress_data(Some(Compression::Zstd), 1, Some(4 << 20), &compressed[..], &mut output)
+            .await
+            .unwrap();
+        assert_eq!(output, data);
+
+        assert!(matches!(
+            uncompress_data(Some(Compression::Zstd), 1, Some(64 << 10), &compressed[..], Vec::new())
+                .await,
+            Err(crate::utils::Error::DecompressMemoryLimitExceeded(limit)) if limit == 64 << 10
+        ));
+        assert!(matches!(
+            uncompress_data_sync(
+                Some(Compression::Zstd),
+                Some(64 << 10),
+                &compressed[..],
+                Vec::new()
+            ),
+            Err(crate::utils::Error::DecompressMemoryLimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn xz_memory_limit_error() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let status = std::process::ExitStatus::from_raw(1 << 8);
+        let stderr =
+            b"xz: (stdin): Memory usage limit reached\nxz: 1,025 KiB of memory is required.";
+        assert!(matches!(
+            xz_error(status, stderr, Some(1024)),
+            crate::utils::Error::DecompressMemoryLimitExceeded(1024)
+        ));
+        assert!(matches!(xz_error(status, stderr, None), crate::utils::Error::Io(_)));
+        assert!(matches!(
+            xz_error(status, b"xz: (stdin): File format not recognized", Some(1024)),
+            crate::utils::Error::Io(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn uncompress_xz_with_memory_limit() {
+        let compressed = std::fs::read("fixtures/multiblock.xz").unwrap();
+        let data = (0..256 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        if crate::utils::fs::is_executable_in_path("xz").is_err() {
+            // The limit can't be enforced without the tool
+            assert!(matches!(
+                uncompress_data_sync(
+                    Some(Compression::Xz),
+                    Some(64 << 20),
+                    &compressed[..],
+                    Vec::new()
+                ),
+                Err(crate::utils::Error::ExecutableNotInPath(_))
+            ));
+            return;
+        }
+
+        let mut output = Vec::new();
+        uncompress_data_sync(Some(Compression::Xz), Some(64 << 20), &compressed[..], &mut output)
+            .unwrap();
+        assert_eq!(output, data);
+
+        assert!(matches!(
+            uncompress_data_sync(Some(Compression::Xz), Some(1), &compressed[..], Vec::new()),
+            Err(crate::utils::Error::DecompressMemoryLimitExceeded(1))
+        ));
+        assert!(matches!(
+            uncompress_data(Some(Compression::Xz), 1, Some(1), &compressed[..], Vec::new()).await,
+            Err(crate::utils::Error::DecompressMemoryLimitExceeded(1))
+        ));
+    }
+}
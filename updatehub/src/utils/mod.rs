@@ -5,14 +5,17 @@
 pub(crate) mod definitions;
 pub(crate) mod delta;
 pub(crate) mod fs;
+pub(crate) mod gpt;
 pub(crate) mod io;
 pub(crate) mod log;
 pub(crate) mod mtd;
+pub(crate) mod sparse;
 
 #[cfg(feature = "v1-parsing")]
 pub(crate) mod deserialize;
 
 use derive_more::{Display, Error, From};
+use pkg_schema::definitions::HashAlgorithm;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -32,6 +35,10 @@ pub enum Error {
     #[from(ignore)]
     MissingWritePermission(#[error(not(source))] std::path::PathBuf),
 
+    #[display(fmt = "{:?} target file is not a regular file", _0)]
+    #[from(ignore)]
+    NotARegularFile(#[error(not(source))] std::path::PathBuf),
+
     #[display(
         fmt = "{} is not enough storage space for installation, at least {} is required",
         available,
@@ -46,12 +53,30 @@ pub enum Error {
     #[display(fmt = "'{}' not found on PATH", _0)]
     #[from(ignore)]
     ExecutableNotInPath(#[error(not(source))] String),
+    #[display(fmt = "{} is not supported on {} filesystems", _0, _1)]
+    #[from(ignore)]
+    UnsupportedPostInstall(
+        pkg_schema::definitions::PostInstallAction,
+        pkg_schema::definitions::Filesystem,
+    ),
+    #[display(fmt = "invalid sparse image: {}", _0)]
+    #[from(ignore)]
+    InvalidSparseImage(#[error(not(source))] String),
     #[display(fmt = "unable to find Ubi Volume: {}" _0)]
     #[from(ignore)]
     NoUbiVolume(#[error(not(source))] String),
     #[display(fmt = "unable to find match for mtd device: {}", _0)]
     #[from(ignore)]
     NoMtdDevice(#[error(not(source))] String),
+    #[display(fmt = "unable to find GPT partition labeled: {}", _0)]
+    #[from(ignore)]
+    NoGptPartition(#[error(not(source))] String),
+    #[display(fmt = "multiple GPT partitions labeled {}: {:?}", label, partitions)]
+    #[from(ignore)]
+    MultipleGptPartitions {
+        label: String,
+        partitions: Vec<std::path::PathBuf>,
+    },
 
     #[display(fmt = "bita operation failed due to io error: {}", _0)]
     BitaArchiveIO(bitar::ArchiveError<std::io::Error>),
@@ -65,6 +90,23 @@ pub enum Error {
     BitaHashSum(bitar::HashSumMismatchError),
     #[display(fmt = "bita operation failed due to invalid url: {}", _0)]
     BitaUrl(url::ParseError),
+
+    #[display(
+        fmt = "decompression requires more memory than the limit of {} bytes, set by decompress_memory_limit",
+        _0
+    )]
+    #[from(ignore)]
+    DecompressMemoryLimitExceeded(#[error(not(source))] u64),
+}
+
+/// Exponential backoff, doubling the base delay on each attempt, with up
+/// to 50% of jitter so concurrent devices don't retry in lockstep.
+pub(crate) fn retry_delay(backoff: std::time::Duration, attempt: usize) -> std::time::Duration {
+    use rand::Rng;
+
+    let factor = 1 << attempt.saturating_sub(1).min(16);
+    let delay = backoff.saturating_mul(factor);
+    delay + delay.mul_f64(rand::thread_rng().gen_range(0.0..0.5))
 }
 
 /// Encode a bytes stream in hex
@@ -78,3 +120,44 @@ pub(crate) fn hex_encode(data: &[u8]) -> String {
 pub(crate) fn sha256sum(data: &[u8]) -> String {
     hex_encode(&openssl::sha::sha256(data))
 }
+
+/// Get the hash of a byte stream using the given algorithm
+#[inline]
+pub(crate) fn digest(algorithm: HashAlgorithm, data: &[u8]) -> String {
+    let mut hasher = Hasher::new(algorithm);
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// Incremental hasher for the checksum algorithms supported by the
+/// update package objects
+pub(crate) enum Hasher {
+    Sha256(openssl::sha::Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub(crate) fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha256 => Hasher::Sha256(openssl::sha::Sha256::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::default()),
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    /// Consumes the hasher returning the hex encoded digest
+    pub(crate) fn finish(self) -> String {
+        match self {
+            Hasher::Sha256(h) => hex_encode(&h.finish()),
+            Hasher::Blake3(h) => h.finalize().to_hex().to_string(),
+        }
+    }
+}
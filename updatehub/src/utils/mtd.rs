@@ -39,6 +39,57 @@ pub(crate) fn target_device_from_ubi_volume_name(volume: &str) -> Result<PathBuf
         .ok_or_else(|| Error::NoUbiVolume(volume.to_owned()))
 }
 
+/// UBI volume as described on sysfs.
+#[derive(Debug)]
+pub(crate) struct UbiVolume {
+    /// UBI device holding the volume.
+    pub(crate) device: PathBuf,
+    pub(crate) reserved_ebs: u64,
+    pub(crate) usable_eb_size: u64,
+}
+
+impl UbiVolume {
+    /// Checks if the volume already has the size, rounded up to whole
+    /// erase blocks as done by the UBI tools.
+    pub(crate) fn has_size(&self, size: u64) -> bool {
+        self.usable_eb_size != 0
+            && self.reserved_ebs == (size + self.usable_eb_size - 1) / self.usable_eb_size
+    }
+}
+
+pub(crate) fn ubi_volume(volume: &str) -> Result<Option<UbiVolume>> {
+    let sysfs = std::path::Path::new("/sys/class/ubi");
+    if !sysfs.exists() {
+        return Ok(None);
+    }
+
+    Ok(fs::read_dir(sysfs)?.filter_map(std::result::Result::ok).find_map(|entry| {
+        let path = entry.path();
+        let name = fs::read_to_string(path.join("name")).ok()?;
+        if name.trim() != volume {
+            return None;
+        }
+
+        let read_attr = |attr| {
+            fs::read_to_string(path.join(attr))
+                .ok()
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or_default()
+        };
+        let file_name = entry.file_name();
+        let (device, _) = file_name.to_str()?.split_once('_')?;
+        Some(UbiVolume {
+            device: PathBuf::from("/dev").join(device),
+            reserved_ebs: read_attr("reserved_ebs"),
+            usable_eb_size: read_attr("usable_eb_size"),
+        })
+    }))
+}
+
+pub(crate) fn ubi_volume_exists(volume: &str) -> Result<bool> {
+    Ok(ubi_volume(volume)?.is_some())
+}
+
 pub(crate) fn target_device_from_mtd_name(name: &str) -> Result<PathBuf> {
     let re =
         regex::Regex::new(r#"^(?P<dev>mtd\d): ([[:xdigit:]]+) ([[:xdigit:]]+) "(?P<name>.*)"$"#)
@@ -180,6 +231,20 @@ pub(crate) mod tests {
         pub static ref SERIALIZE: Arc<Mutex<()>> = Arc::new(Mutex::default());
     }
 
+    #[test]
+    fn ubi_volume_size() {
+        let volume = UbiVolume {
+            device: PathBuf::from("/dev/ubi0"),
+            reserved_ebs: 8,
+            usable_eb_size: 126976,
+        };
+
+        assert!(volume.has_size(8 * 126976));
+        assert!(volume.has_size(7 * 126976 + 1));
+        assert!(!volume.has_size(7 * 126976));
+        assert!(!volume.has_size(8 * 126976 + 1));
+    }
+
     #[test]
     #[ignore]
     fn device_from_mtd_name() {
@@ -0,0 +1,282 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{Error, Hasher, Result};
+use pkg_schema::definitions::HashAlgorithm;
+use slog_scope::trace;
+use std::{io::SeekFrom, path::Path};
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
+};
+
+const MAGIC: u32 = 0xed26_ff3a;
+const FILE_HEADER_SIZE: u16 = 28;
+const CHUNK_HEADER_SIZE: u16 = 12;
+
+const CHUNK_RAW: u16 = 0xcac1;
+const CHUNK_FILL: u16 = 0xcac2;
+const CHUNK_DONT_CARE: u16 = 0xcac3;
+const CHUNK_CRC32: u16 = 0xcac4;
+
+/// Checks if the file holds an Android sparse image.
+pub(crate) async fn is_sparse_image(path: &Path) -> Result<bool> {
+    let mut file = fs::File::open(path).await?;
+    match file.read_u32_le().await {
+        Ok(magic) => Ok(magic == MAGIC),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Content expanded from a sparse image.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Expanded {
+    /// Size, in bytes, of the expanded content.
+    pub(crate) len: u64,
+    /// Digest of the expanded content, when it has been hashed.
+    pub(crate) digest: Option<String>,
+}
+
+/// Expands the sparse image read from `input` into `output`, starting at
+/// its current position. Don't care chunks are seeked over, leaving their
+/// content untouched, so a trailing one doesn't extend the output. When
+/// an algorithm is given, the expanded content is hashed, with the don't
+/// care chunks read as zeros.
+pub(crate) async fn expand<R, W>(
+    input: &mut R,
+    output: &mut W,
+    algorithm: Option<HashAlgorithm>,
+) -> Result<Expanded>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + AsyncSeek + Unpin,
+{
+    let mut hasher = algorithm.map(Hasher::new);
+
+    if input.read_u32_le().await? != MAGIC {
+        return Err(invalid("bad magic"));
+    }
+    let major_version = input.read_u16_le().await?;
+    let _minor_version = input.read_u16_le().await?;
+    let file_header_size = input.read_u16_le().await?;
+    let chunk_header_size = input.read_u16_le().await?;
+    let block_size = input.read_u32_le().await? as u64;
+    let total_blocks = input.read_u32_le().await? as u64;
+    let total_chunks = input.read_u32_le().await?;
+    let _image_checksum = input.read_u32_le().await?;
+    if major_version != 1 {
+        return Err(invalid(format!("unsupported version {}", major_version)));
+    }
+    if file_header_size < FILE_HEADER_SIZE || chunk_header_size < CHUNK_HEADER_SIZE {
+        return Err(invalid("headers are too small"));
+    }
+    if block_size == 0 || block_size % 4 != 0 {
+        return Err(invalid(format!("invalid block size {}", block_size)));
+    }
+    skip(input, (file_header_size - FILE_HEADER_SIZE) as u64).await?;
+    trace!(
+        "expanding sparse image of {} blocks of {} bytes in {} chunks",
+        total_blocks,
+        block_size,
+        total_chunks
+    );
+
+    // Seeks over don't care chunks are delayed until data is written, so
+    // consecutive ones are merged
+    let mut pending_seek = 0;
+    let mut blocks = 0;
+    for _ in 0..total_chunks {
+        let chunk_type = input.read_u16_le().await?;
+        let _reserved = input.read_u16_le().await?;
+        let chunk_blocks = input.read_u32_le().await? as u64;
+        let total_size = input.read_u32_le().await? as u64;
+        skip(input, (chunk_header_size - CHUNK_HEADER_SIZE) as u64).await?;
+        let data_size = total_size.saturating_sub(chunk_header_size as u64);
+        let len = chunk_blocks * block_size;
+
+        match chunk_type {
+            CHUNK_RAW => {
+                if data_size != len {
+                    return Err(invalid("raw chunk size mismatch"));
+                }
+                seek_pending(output, &mut pending_seek).await?;
+                let mut input = (&mut *input).take(len);
+                let mut buf = vec![0; block_size as usize];
+                loop {
+                    let n = input.read(&mut buf).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    if let Some(hasher) = hasher.as_mut() {
+                        hasher.update(&buf[..n]);
+                    }
+                    output.write_all(&buf[..n]).await?;
+                }
+                if input.limit() != 0 {
+                    return Err(invalid("truncated raw chunk"));
+                }
+            }
+            CHUNK_FILL => {
+                if data_size != 4 {
+                    return Err(invalid("fill chunk size mismatch"));
+                }
+                let mut pattern = [0; 4];
+                input.read_exact(&mut pattern).await?;
+                seek_pending(output, &mut pending_seek).await?;
+                let block = pattern.repeat(block_size as usize / 4);
+                for _ in 0..chunk_blocks {
+                    if let Some(hasher) = hasher.as_mut() {
+                        hasher.update(&block);
+                    }
+                    output.write_all(&block).await?;
+                }
+            }
+            CHUNK_DONT_CARE => {
+                if let Some(hasher) = hasher.as_mut() {
+                    let zeros = vec![0; block_size as usize];
+                    (0..chunk_blocks).for_each(|_| hasher.update(&zeros));
+                }
+                pending_seek += len;
+            }
+            CHUNK_CRC32 => {
+                skip(input, data_size).await?;
+                continue;
+            }
+            t => return Err(invalid(format!("unknown chunk type {:#x}", t))),
+        }
+        blocks += chunk_blocks;
+    }
+
+    if blocks != total_blocks {
+        return Err(invalid(format!("{} blocks expanded instead of {}", blocks, total_blocks)));
+    }
+    output.flush().await?;
+
+    Ok(Expanded { len: blocks * block_size, digest: hasher.map(Hasher::finish) })
+}
+
+async fn seek_pending<W>(output: &mut W, pending_seek: &mut u64) -> Result<()>
+where
+    W: AsyncWrite + AsyncSeek + Unpin,
+{
+    if *pending_seek > 0 {
+        // Buffered data must reach the output before it is seeked
+        output.flush().await?;
+        output.seek(SeekFrom::Current(*pending_seek as i64)).await?;
+        *pending_seek = 0;
+    }
+    Ok(())
+}
+
+async fn skip<R: AsyncRead + Unpin>(input: &mut R, len: u64) -> Result<()> {
+    let skipped = tokio::io::copy(&mut (&mut *input).take(len), &mut tokio::io::sink()).await?;
+    if skipped != len {
+        return Err(invalid("unexpected end of image"));
+    }
+    Ok(())
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::InvalidSparseImage(reason.into())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    pub(crate) const BLOCK_SIZE: u32 = 8;
+
+    /// Crafts a sparse image of 6 blocks, holding a raw, a fill, a crc32
+    /// and a don't care chunk, along with its expanded content.
+    pub(crate) fn crafted_image() -> (Vec<u8>, Vec<u8>) {
+        let raw = &b"raw data and more"[..2 * BLOCK_SIZE as usize];
+        let chunk = |t: u16, blocks: u32, data: &[u8]| {
+            let parts: [&[u8]; 5] = [
+                &t.to_le_bytes(),
+                &0u16.to_le_bytes(),
+                &blocks.to_le_bytes(),
+                &(CHUNK_HEADER_SIZE as u32 + data.len() as u32).to_le_bytes(),
+                data,
+            ];
+            parts.concat()
+        };
+
+        let parts: [&[u8]; 13] = [
+            &MAGIC.to_le_bytes(),
+            &1u16.to_le_bytes(),
+            &0u16.to_le_bytes(),
+            &FILE_HEADER_SIZE.to_le_bytes(),
+            &CHUNK_HEADER_SIZE.to_le_bytes(),
+            &BLOCK_SIZE.to_le_bytes(),
+            &6u32.to_le_bytes(),
+            &4u32.to_le_bytes(),
+            &0u32.to_le_bytes(),
+            &chunk(CHUNK_RAW, 2, raw),
+            &chunk(CHUNK_FILL, 1, &[0xab; 4]),
+            &chunk(CHUNK_CRC32, 0, &[0; 4]),
+            &chunk(CHUNK_DONT_CARE, 3, &[]),
+        ];
+        let expanded: [&[u8]; 3] =
+            [raw, &[0xab; BLOCK_SIZE as usize], &[0; 3 * BLOCK_SIZE as usize]];
+
+        (parts.concat(), expanded.concat())
+    }
+
+    #[tokio::test]
+    async fn expand_image() {
+        let (image, expanded) = crafted_image();
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("image");
+        std::fs::write(&source, &image).unwrap();
+        // The don't care chunk keeps the previous content
+        let target = dir.path().join("target");
+        std::fs::write(&target, vec![0xff; expanded.len()]).unwrap();
+
+        assert!(is_sparse_image(&source).await.unwrap());
+        let mut input = fs::File::open(&source).await.unwrap();
+        let mut output = fs::OpenOptions::new().write(true).open(&target).await.unwrap();
+        let res = expand(&mut input, &mut output, Some(HashAlgorithm::Sha256)).await.unwrap();
+
+        assert_eq!(
+            res,
+            Expanded {
+                len: expanded.len() as u64,
+                digest: Some(super::super::sha256sum(&expanded))
+            }
+        );
+        let written = std::fs::read(&target).unwrap();
+        let data_len = 3 * BLOCK_SIZE as usize;
+        assert_eq!(written[..data_len], expanded[..data_len]);
+        assert_eq!(written[data_len..], [0xff; 3 * BLOCK_SIZE as usize]);
+    }
+
+    #[tokio::test]
+    async fn trailing_dont_care_is_not_written() {
+        let (image, expanded) = crafted_image();
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+
+        let mut output = fs::File::create(&target).await.unwrap();
+        let res = expand(&mut &image[..], &mut output, None).await.unwrap();
+
+        assert_eq!(res, Expanded { len: expanded.len() as u64, digest: None });
+        assert_eq!(std::fs::read(&target).unwrap(), expanded[..3 * BLOCK_SIZE as usize]);
+    }
+
+    #[tokio::test]
+    async fn not_sparse() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("image");
+        std::fs::write(&source, b"plain").unwrap();
+        assert!(!is_sparse_image(&source).await.unwrap());
+
+        let mut output = fs::File::create(dir.path().join("target")).await.unwrap();
+        assert!(matches!(
+            expand(&mut &b"plain image"[..], &mut output, None).await,
+            Err(Error::InvalidSparseImage(_))
+        ));
+    }
+}
@@ -35,7 +35,7 @@ fn failing_invalid_download_dir() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> INFO probing server as we are in time
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> ERRO failed to download object from update package: Not a directory (os error 20) (Io(Os { code: 20, kind: NotADirectory, message: "Not a directory" }))
     <timestamp> ERRO error state reached: Not a directory (os error 20)
     <timestamp> INFO returning to machine's entry point
@@ -45,6 +45,8 @@ fn failing_invalid_download_dir() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> DEBG loading system settings from "<file>"
     <timestamp> DEBG runtime settings file "<file>" does not exists, using default settings
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> DEBG polling is enabled
     <timestamp> TRCE starting to handle 'poll' state
@@ -54,11 +56,13 @@ fn failing_invalid_download_dir() {
     <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> TRCE starting to handle 'download' state
-    <timestamp> TRCE the following objects are missing: [("testfile", "23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4")]
+    <timestamp> TRCE the following objects are missing: [("testfile", "23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4", 40960)]
     <timestamp> DEBG starting download of: testfile (23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4)
     <timestamp> ERRO failed to download object from update package: Not a directory (os error 20) (Io(Os { code: 20, kind: NotADirectory, message: "Not a directory" }))
+    <timestamp> DEBG recording Failure as last update result of 87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'error' state
     <timestamp> ERRO error state reached: Not a directory (os error 20)
     <timestamp> INFO returning to machine's entry point
@@ -71,11 +75,13 @@ fn failing_invalid_download_dir() {
     insta::assert_snapshot!(output_log, @r###"
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> TRCE starting to handle 'download' state
-    <timestamp> TRCE the following objects are missing: [("testfile", "23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4")]
+    <timestamp> TRCE the following objects are missing: [("testfile", "23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4", 40960)]
     <timestamp> DEBG starting download of: testfile (23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4)
     <timestamp> ERRO failed to download object from update package: Not a directory (os error 20) (Io(Os { code: 20, kind: NotADirectory, message: "Not a directory" }))
+    <timestamp> DEBG recording Failure as last update result of 87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'error' state
     <timestamp> ERRO error state reached: Not a directory (os error 20)
     <timestamp> INFO returning to machine's entry point
@@ -188,6 +194,8 @@ fn failing_invalid_server_address() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> DEBG loading system settings from "<file>"
     <timestamp> DEBG runtime settings file "<file>" does not exists, using default settings
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> DEBG polling is disabled
     <timestamp> TRCE starting to handle 'park' state
@@ -226,7 +234,7 @@ fn failing_fail_check_requirements() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> INFO probing server as we are in time
     <timestamp> INFO update received: 1.2 (fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3)
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> ERRO update package: 1.2 (fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3) has failed to meet the install requirements
     <timestamp> ERRO error state reached: fail to check the requirements
     <timestamp> INFO returning to machine's entry point
@@ -236,6 +244,8 @@ fn failing_fail_check_requirements() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> DEBG loading system settings from "<file>"
     <timestamp> DEBG runtime settings file "<file>" does not exists, using default settings
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> DEBG polling is enabled
     <timestamp> TRCE starting to handle 'poll' state
@@ -245,8 +255,10 @@ fn failing_fail_check_requirements() {
     <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> INFO update received: 1.2 (fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> ERRO update package: 1.2 (fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3) has failed to meet the install requirements
+    <timestamp> DEBG recording Failure as last update result of fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'error' state
     <timestamp> ERRO error state reached: fail to check the requirements
     <timestamp> INFO returning to machine's entry point
@@ -259,8 +271,10 @@ fn failing_fail_check_requirements() {
     insta::assert_snapshot!(output_log, @r###"
     <timestamp> INFO update received: 1.2 (fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> ERRO update package: 1.2 (fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3) has failed to meet the install requirements
+    <timestamp> DEBG recording Failure as last update result of fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'error' state
     <timestamp> ERRO error state reached: fail to check the requirements
     <timestamp> INFO returning to machine's entry point
@@ -294,7 +308,7 @@ fn failing_supported_install_modes() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> INFO probing server as we are in time
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> ERRO install mode failed validation: Install mode not accepted: test (IncompatibleInstallMode("test"))
     <timestamp> ERRO error state reached: Install mode not accepted: test
     <timestamp> INFO returning to machine's entry point
@@ -304,6 +318,8 @@ fn failing_supported_install_modes() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> DEBG loading system settings from "<file>"
     <timestamp> DEBG runtime settings file "<file>" does not exists, using default settings
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> DEBG polling is enabled
     <timestamp> TRCE starting to handle 'poll' state
@@ -313,8 +329,10 @@ fn failing_supported_install_modes() {
     <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> ERRO install mode failed validation: Install mode not accepted: test (IncompatibleInstallMode("test"))
+    <timestamp> DEBG recording Failure as last update result of 87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'error' state
     <timestamp> ERRO error state reached: Install mode not accepted: test
     <timestamp> INFO returning to machine's entry point
@@ -327,8 +345,10 @@ fn failing_supported_install_modes() {
     insta::assert_snapshot!(output_log, @r###"
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> ERRO install mode failed validation: Install mode not accepted: test (IncompatibleInstallMode("test"))
+    <timestamp> DEBG recording Failure as last update result of 87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'error' state
     <timestamp> ERRO error state reached: Install mode not accepted: test
     <timestamp> INFO returning to machine's entry point
@@ -356,7 +376,7 @@ fn invalid_server_response() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> INFO probing server as we are in time
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> ERRO failed to download object from update package: Invalid status response: 501 Not Implemented (InvalidStatusResponse(501))
     <timestamp> ERRO error state reached: Invalid status response: 501 Not Implemented
     <timestamp> INFO returning to machine's entry point
@@ -366,6 +386,8 @@ fn invalid_server_response() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> DEBG loading system settings from "<file>"
     <timestamp> DEBG runtime settings file "<file>" does not exists, using default settings
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> DEBG polling is enabled
     <timestamp> TRCE starting to handle 'poll' state
@@ -375,11 +397,13 @@ fn invalid_server_response() {
     <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> TRCE starting to handle 'download' state
-    <timestamp> TRCE the following objects are missing: [("testfile", "23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4")]
+    <timestamp> TRCE the following objects are missing: [("testfile", "23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4", 40960)]
     <timestamp> DEBG starting download of: testfile (23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4)
     <timestamp> ERRO failed to download object from update package: Invalid status response: 501 Not Implemented (InvalidStatusResponse(501))
+    <timestamp> DEBG recording Failure as last update result of 87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'error' state
     <timestamp> ERRO error state reached: Invalid status response: 501 Not Implemented
     <timestamp> INFO returning to machine's entry point
@@ -392,11 +416,13 @@ fn invalid_server_response() {
     insta::assert_snapshot!(output_log, @r###"
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> TRCE starting to handle 'download' state
-    <timestamp> TRCE the following objects are missing: [("testfile", "23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4")]
+    <timestamp> TRCE the following objects are missing: [("testfile", "23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4", 40960)]
     <timestamp> DEBG starting download of: testfile (23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4)
     <timestamp> ERRO failed to download object from update package: Invalid status response: 501 Not Implemented (InvalidStatusResponse(501))
+    <timestamp> DEBG recording Failure as last update result of 87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'error' state
     <timestamp> ERRO error state reached: Invalid status response: 501 Not Implemented
     <timestamp> INFO returning to machine's entry point
@@ -434,7 +460,7 @@ exit 1
     insta::assert_snapshot!(output_server_info, @r###"
     <timestamp> INFO Probing the server as requested by the user
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> INFO running state change callback for 'download' state
     <timestamp> ERRO download callback has failed with status: exit status: 1
     <timestamp> INFO running state change callback for 'error' state
@@ -451,10 +477,12 @@ exit 1
     <timestamp> DEBG updating last polling time
     <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> TRCE starting to handle 'download' state
     <timestamp> INFO running state change callback for 'download' state
     <timestamp> ERRO download callback has failed with status: exit status: 1
+    <timestamp> DEBG recording Failure as last update result of 87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'error' state
     <timestamp> INFO running state change callback for 'error' state
     <timestamp> ERRO error callback has failed with status: exit status: 1
@@ -471,10 +499,12 @@ exit 1
     <timestamp> DEBG updating last polling time
     <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> TRCE starting to handle 'download' state
     <timestamp> INFO running state change callback for 'download' state
     <timestamp> ERRO download callback has failed with status: exit status: 1
+    <timestamp> DEBG recording Failure as last update result of 87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'error' state
     <timestamp> INFO running state change callback for 'error' state
     <timestamp> ERRO error callback has failed with status: exit status: 1
@@ -27,6 +27,8 @@ fn correct_config_no_update_no_polling() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> DEBG loading system settings from "<file>"
     <timestamp> DEBG runtime settings file "<file>" does not exists, using default settings
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> DEBG polling is disabled
     <timestamp> TRCE starting to handle 'park' state
@@ -36,6 +38,8 @@ fn correct_config_no_update_no_polling() {
     insta::assert_snapshot!(output_log, @r###"
     <timestamp> DEBG loading system settings from "<file>"
     <timestamp> DEBG runtime settings file "<file>" does not exists, using default settings
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> DEBG polling is disabled
     <timestamp> TRCE starting to handle 'park' state
@@ -66,6 +70,8 @@ fn correct_config_no_update_polling() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> DEBG loading system settings from "<file>"
     <timestamp> DEBG runtime settings file "<file>" does not exists, using default settings
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> DEBG polling is enabled
     <timestamp> TRCE starting to handle 'poll' state
@@ -83,6 +89,8 @@ fn correct_config_no_update_polling() {
     insta::assert_snapshot!(output_log, @r###"
     <timestamp> DEBG loading system settings from "<file>"
     <timestamp> DEBG runtime settings file "<file>" does not exists, using default settings
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> DEBG polling is enabled
     <timestamp> TRCE starting to handle 'poll' state
@@ -131,6 +139,8 @@ fn correct_config_no_update_polling_with_probe_api() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> DEBG loading system settings from "<file>"
     <timestamp> DEBG runtime settings file "<file>" does not exists, using default settings
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> DEBG polling is enabled
     <timestamp> TRCE starting to handle 'poll' state
@@ -207,6 +217,8 @@ fn correct_config_no_update_no_polling_with_probe_api() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> DEBG loading system settings from "<file>"
     <timestamp> DEBG runtime settings file "<file>" does not exists, using default settings
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> DEBG polling is disabled
     <timestamp> TRCE starting to handle 'park' state
@@ -262,8 +274,7 @@ fn correct_config_update_polling() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> INFO probing server as we are in time
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
-    <timestamp> INFO no signature key available on device, ignoring signature validation
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> INFO installing update: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
     <timestamp> INFO using installation set as target 1
     <timestamp> INFO swapping active installation set
@@ -275,6 +286,8 @@ fn correct_config_update_polling() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> DEBG loading system settings from "<file>"
     <timestamp> DEBG runtime settings file "<file>" does not exists, using default settings
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> DEBG polling is enabled
     <timestamp> TRCE starting to handle 'poll' state
@@ -284,15 +297,14 @@ fn correct_config_update_polling() {
     <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> TRCE starting to handle 'download' state
-    <timestamp> TRCE the following objects are missing: [("testfile", "23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4")]
+    <timestamp> TRCE the following objects are missing: [("testfile", "23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4", 40960)]
     <timestamp> DEBG starting download of: testfile (23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4)
     <timestamp> DEBG <percentage>% of the file has been downloaded
     <timestamp> DEBG <percentage>% of the file has been downloaded
     <timestamp> DEBG 100% of the file has been downloaded
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
     <timestamp> TRCE starting to handle 'install' state
     <timestamp> INFO installing update: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
     <timestamp> INFO using installation set as target 1
@@ -302,6 +314,8 @@ fn correct_config_update_polling() {
     <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> INFO swapping active installation set
     <timestamp> INFO update installed successfully
+    <timestamp> DEBG recording Success as last update result of 87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'reboot' state
     <timestamp> INFO triggering reboot
     <timestamp> TRCE starting to handle 'entry_point' state
@@ -313,15 +327,14 @@ fn correct_config_update_polling() {
     insta::assert_snapshot!(output_log, @r###"
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> TRCE starting to handle 'download' state
-    <timestamp> TRCE the following objects are missing: [("testfile", "23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4")]
+    <timestamp> TRCE the following objects are missing: [("testfile", "23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4", 40960)]
     <timestamp> DEBG starting download of: testfile (23c3c412177bd37b9b61bf4738b18dc1fe003811c2583a14d2d9952d8b6a75b4)
     <timestamp> DEBG <percentage>% of the file has been downloaded
     <timestamp> DEBG <percentage>% of the file has been downloaded
     <timestamp> DEBG 100% of the file has been downloaded
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
     <timestamp> TRCE starting to handle 'install' state
     <timestamp> INFO installing update: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
     <timestamp> INFO using installation set as target 1
@@ -331,6 +344,8 @@ fn correct_config_update_polling() {
     <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> INFO swapping active installation set
     <timestamp> INFO update installed successfully
+    <timestamp> DEBG recording Success as last update result of 87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'reboot' state
     <timestamp> INFO triggering reboot
     <timestamp> TRCE starting to handle 'entry_point' state
@@ -369,7 +384,7 @@ fn correct_config_statechange_callback() {
     <timestamp> INFO running state change callback for 'probe' state
     <timestamp> INFO probe callback has exit with success
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> INFO running state change callback for 'download' state
     <timestamp> INFO download callback has exit with success
     <timestamp> INFO canceling transition to 'download' due to state change callback request
@@ -379,6 +394,8 @@ fn correct_config_statechange_callback() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> DEBG loading system settings from "<file>"
     <timestamp> DEBG runtime settings file "<file>" does not exists, using default settings
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> DEBG polling is enabled
     <timestamp> TRCE starting to handle 'poll' state
@@ -390,7 +407,7 @@ fn correct_config_statechange_callback() {
     <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> TRCE starting to handle 'download' state
     <timestamp> INFO running state change callback for 'download' state
     <timestamp> INFO download callback has exit with success
@@ -404,7 +421,7 @@ fn correct_config_statechange_callback() {
     insta::assert_snapshot!(output_log, @r###"
     <timestamp> INFO update received: 1.2 (87effe73b80453f397cee4db3c3589a8630b220876dff8fb23447315037ff96d)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> TRCE starting to handle 'download' state
     <timestamp> INFO running state change callback for 'download' state
     <timestamp> INFO download callback has exit with success
@@ -445,7 +462,7 @@ fn correct_config_error_state_callback() {
     <timestamp> INFO running state change callback for 'probe' state
     <timestamp> INFO probe callback has exit with success
     <timestamp> INFO update received: 1.2 (fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3)
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> ERRO update package: 1.2 (fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3) has failed to meet the install requirements
     <timestamp> INFO running state change callback for 'error' state
     <timestamp> INFO error callback has exit with success
@@ -456,6 +473,8 @@ fn correct_config_error_state_callback() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> DEBG loading system settings from "<file>"
     <timestamp> DEBG runtime settings file "<file>" does not exists, using default settings
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> DEBG polling is enabled
     <timestamp> TRCE starting to handle 'poll' state
@@ -467,8 +486,10 @@ fn correct_config_error_state_callback() {
     <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> INFO update received: 1.2 (fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> ERRO update package: 1.2 (fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3) has failed to meet the install requirements
+    <timestamp> DEBG recording Failure as last update result of fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'error' state
     <timestamp> INFO running state change callback for 'error' state
     <timestamp> INFO error callback has exit with success
@@ -482,8 +503,10 @@ fn correct_config_error_state_callback() {
     insta::assert_snapshot!(output_log, @r###"
     <timestamp> INFO update received: 1.2 (fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> ERRO update package: 1.2 (fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3) has failed to meet the install requirements
+    <timestamp> DEBG recording Failure as last update result of fb21b217cb83e8af368c773eb13bad0a94e1b0088c6bf561072decf3c1ae9df3
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'error' state
     <timestamp> INFO running state change callback for 'error' state
     <timestamp> INFO error callback has exit with success
@@ -522,7 +545,7 @@ fn correct_config_remote_install() {
     <timestamp> INFO fetching update package directly from url: "http://127.0.0.1:[port]/some-direct-package-url"
     <timestamp> INFO installing local package: "<file>"
     <timestamp> INFO update package extracted: fake-test-package-01 (9fbf06c2ad11c611f1d5601d5daa13ea6b6f7bfd2ec32c935991684a80d6e1d0)
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> INFO installing update: fake-test-package-01 (9fbf06c2ad11c611f1d5601d5daa13ea6b6f7bfd2ec32c935991684a80d6e1d0)
     <timestamp> INFO using installation set as target 1
     <timestamp> INFO swapping active installation set
@@ -535,6 +558,8 @@ fn correct_config_remote_install() {
     <timestamp> INFO starting UpdateHub Agent <version>
     <timestamp> DEBG loading system settings from "<file>"
     <timestamp> DEBG runtime settings file "<file>" does not exists, using default settings
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> DEBG polling is disabled
     <timestamp> TRCE starting to handle 'park' state
@@ -553,7 +578,7 @@ fn correct_config_remote_install() {
     <timestamp> DEBG successfuly uncompressed metadata file
     <timestamp> INFO update package extracted: fake-test-package-01 (9fbf06c2ad11c611f1d5601d5daa13ea6b6f7bfd2ec32c935991684a80d6e1d0)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> TRCE starting to handle 'install' state
     <timestamp> INFO installing update: fake-test-package-01 (9fbf06c2ad11c611f1d5601d5daa13ea6b6f7bfd2ec32c935991684a80d6e1d0)
     <timestamp> INFO using installation set as target 1
@@ -563,6 +588,8 @@ fn correct_config_remote_install() {
     <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> INFO swapping active installation set
     <timestamp> INFO update installed successfully
+    <timestamp> DEBG recording Success as last update result of 9fbf06c2ad11c611f1d5601d5daa13ea6b6f7bfd2ec32c935991684a80d6e1d0
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'reboot' state
     <timestamp> INFO triggering reboot
     <timestamp> TRCE starting to handle 'entry_point' state
@@ -585,7 +612,7 @@ fn correct_config_remote_install() {
     <timestamp> DEBG successfuly uncompressed metadata file
     <timestamp> INFO update package extracted: fake-test-package-01 (9fbf06c2ad11c611f1d5601d5daa13ea6b6f7bfd2ec32c935991684a80d6e1d0)
     <timestamp> TRCE starting to handle 'validation' state
-    <timestamp> INFO no signature key available on device, ignoring signature validation
+    <timestamp> WARN no signature key available on device, ignoring signature validation
     <timestamp> TRCE starting to handle 'install' state
     <timestamp> INFO installing update: fake-test-package-01 (9fbf06c2ad11c611f1d5601d5daa13ea6b6f7bfd2ec32c935991684a80d6e1d0)
     <timestamp> INFO using installation set as target 1
@@ -595,6 +622,8 @@ fn correct_config_remote_install() {
     <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> INFO swapping active installation set
     <timestamp> INFO update installed successfully
+    <timestamp> DEBG recording Success as last update result of 9fbf06c2ad11c611f1d5601d5daa13ea6b6f7bfd2ec32c935991684a80d6e1d0
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'reboot' state
     <timestamp> INFO triggering reboot
     <timestamp> TRCE starting to handle 'entry_point' state
@@ -645,6 +674,8 @@ exit 0
     <timestamp> INFO validate callback has exit with success
     <timestamp> DEBG reseting installation settings
     <timestamp> DEBG saved runtime settings to "<file>"
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> INFO triggering Probe to finish update
     <timestamp> DEBG disabling foce poll
@@ -667,6 +698,8 @@ exit 0
     <timestamp> INFO validate callback has exit with success
     <timestamp> DEBG reseting installation settings
     <timestamp> DEBG saved runtime settings to "<file>"
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> INFO triggering Probe to finish update
     <timestamp> DEBG disabling foce poll
@@ -779,6 +812,8 @@ UpgradeToInstallation=1
     <timestamp> WARN confirming active installation as update has been rollback
     <timestamp> DEBG reseting installation settings
     <timestamp> DEBG saved runtime settings to "<file>"
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> INFO triggering Probe to finish update
     <timestamp> DEBG disabling foce poll
@@ -801,6 +836,8 @@ UpgradeToInstallation=1
     <timestamp> WARN confirming active installation as update has been rollback
     <timestamp> DEBG reseting installation settings
     <timestamp> DEBG saved runtime settings to "<file>"
+    <timestamp> DEBG updating boot count to 1
+    <timestamp> DEBG saved runtime settings to "<file>"
     <timestamp> TRCE starting to handle 'entry_point' state
     <timestamp> INFO triggering Probe to finish update
     <timestamp> DEBG disabling foce poll